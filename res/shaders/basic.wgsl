@@ -1,42 +1,199 @@
 struct CameraUniform {
     view_proj : mat4x4<f32>,
+    inverse_view_proj : mat4x4<f32>,
+    position : vec4<f32>,
 };
 @group(0) @binding(0)
 var<uniform> camera : CameraUniform;
 
 struct TransformationUniform {
     matrix : mat4x4<f32>,
+    lod_fade : vec4<f32>,
 };
 @group(2) @binding(0)
 var<uniform> transformation : TransformationUniform;
 
+// Per draw data of the GPU driven path, indexed by the draw's first instance
+struct DrawData {
+    model : mat4x4<f32>,
+    aabb_min : vec4<f32>,
+    aabb_max : vec4<f32>,
+    first_index : u32,
+    index_count : u32,
+    base_vertex : i32,
+    _padding : u32,
+};
+@group(2) @binding(1)
+var<storage, read> draws : array<DrawData>;
+
 struct VertexInput {
     @location(0) position : vec3<f32>,
     @location(1) tex_coords : vec2<f32>,
+    @location(2) normal : vec3<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0 : vec4<f32>,
+    @location(6) model_matrix_1 : vec4<f32>,
+    @location(7) model_matrix_2 : vec4<f32>,
+    @location(8) model_matrix_3 : vec4<f32>,
+    @location(9) color : vec4<f32>,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position : vec4<f32>,
     @location(0) tex_coords : vec2<f32>,
+    @location(1) world_position : vec3<f32>,
+    @location(2) world_normal : vec3<f32>,
+    @location(3) color : vec4<f32>,
+    // Share of pixels kept and whether this draw keeps the complement
+    @location(4) @interpolate(flat) lod_fade : vec2<f32>,
 }
 
-@vertex
-fn vs_main(model : VertexInput) -> VertexOutput {
+fn transform_vertex(model : VertexInput, matrix : mat4x4<f32>, color : vec4<f32>) -> VertexOutput {
     var out : VertexOutput;
     out.tex_coords = model.tex_coords;
 
-    let world_position = transformation.matrix * vec4<f32>(model.position, 1.0);
+    let world_position = matrix * vec4<f32>(model.position, 1.0);
     out.clip_position = camera.view_proj * world_position;
+    out.world_position = world_position.xyz;
+    out.world_normal = (matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.color = color;
+    out.lod_fade = vec2<f32>(1.0, 0.0);
 
     return out;
 }
 
+// Cross-fading levels of detail are drawn as instance 0 and 1 of the same draw
+@vertex
+fn vs_main(model : VertexInput, @builtin(instance_index) instance : u32) -> VertexOutput {
+    var out = transform_vertex(model, transformation.matrix, vec4<f32>(1.0));
+    out.lod_fade = vec2<f32>(transformation.lod_fade.x, f32(instance));
+    return out;
+}
+
+// Instance transforms are relative to the transformation of the whole batch
+@vertex
+fn vs_instanced(model : VertexInput, instance : InstanceInput) -> VertexOutput {
+    let instance_matrix = mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+    return transform_vertex(model, transformation.matrix * instance_matrix, instance.color);
+}
+
+@vertex
+fn vs_gpu_driven(model : VertexInput, @builtin(instance_index) draw_index : u32) -> VertexOutput {
+    return transform_vertex(model, draws[draw_index].model, vec4<f32>(1.0));
+}
+
 @group(1) @binding(0)
 var t_diffuse : texture_2d<f32>;
 @group(1) @binding(1)
 var s_diffuse : sampler;
 
+struct EnvironmentUniform {
+    intensity : f32,
+    enabled : u32,
+    max_reflection_lod : f32,
+    _padding : u32,
+};
+
+@group(3) @binding(1)
+var t_irradiance : texture_cube<f32>;
+@group(3) @binding(2)
+var t_prefiltered : texture_cube<f32>;
+@group(3) @binding(3)
+var t_brdf_lut : texture_2d<f32>;
+@group(3) @binding(4)
+var s_environment : sampler;
+@group(3) @binding(5)
+var<uniform> environment : EnvironmentUniform;
+
+// Materials don't carry surface parameters yet
+const ROUGHNESS : f32 = 0.5;
+const METALLIC : f32 = 0.0;
+
+fn fresnel_schlick_roughness(cos_theta : f32, f0 : vec3<f32>, roughness : f32) -> vec3<f32> {
+    return f0 + (max(vec3<f32>(1.0 - roughness), f0) - f0) * pow(clamp(1.0 - cos_theta, 0.0, 1.0), 5.0);
+}
+
+const BAYER_4X4 = array<f32, 16>(
+    0.0, 8.0, 2.0, 10.0,
+    12.0, 4.0, 14.0, 6.0,
+    3.0, 11.0, 1.0, 9.0,
+    15.0, 7.0, 13.0, 5.0,
+);
+
+fn dither(position : vec2<f32>) -> f32 {
+    var bayer = BAYER_4X4;
+    let pixel = vec2<u32>(position) % 4u;
+    return (bayer[pixel.y * 4u + pixel.x] + 0.5) / 16.0;
+}
+
+fn shade(in : VertexOutput) -> vec4<f32> {
+    if (in.lod_fade.x < 1.0 && (dither(in.clip_position.xy) < in.lod_fade.x) == (in.lod_fade.y > 0.5)) {
+        discard;
+    }
+
+    let albedo = textureSample(t_diffuse, s_diffuse, in.tex_coords) * in.color;
+
+    // Models without normals are left unlit
+    if (environment.enabled == 0u || dot(in.world_normal, in.world_normal) < 1e-6) {
+        return albedo;
+    }
+
+    let normal = normalize(in.world_normal);
+    let view = normalize(camera.position.xyz - in.world_position);
+    let n_dot_v = max(dot(normal, view), 0.0);
+    let reflected = reflect(-view, normal);
+
+    let f0 = mix(vec3<f32>(0.04), albedo.rgb, METALLIC);
+    let fresnel = fresnel_schlick_roughness(n_dot_v, f0, ROUGHNESS);
+    let diffuse_weight = (1.0 - fresnel) * (1.0 - METALLIC);
+
+    let irradiance = textureSampleLevel(t_irradiance, s_environment, normal, 0.0).rgb;
+    let prefiltered = textureSampleLevel(
+        t_prefiltered,
+        s_environment,
+        reflected,
+        ROUGHNESS * environment.max_reflection_lod,
+    ).rgb;
+    let brdf = textureSampleLevel(t_brdf_lut, s_environment, vec2<f32>(n_dot_v, ROUGHNESS), 0.0).rg;
+
+    let diffuse = diffuse_weight * irradiance * albedo.rgb;
+    let specular = prefiltered * (fresnel * brdf.x + brdf.y);
+
+    return vec4<f32>((diffuse + specular) * environment.intensity, albedo.a);
+}
+
 @fragment
 fn fs_main(in : VertexOutput) -> @location(0) vec4<f32> {
-    return textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    return shade(in);
+}
+
+struct OitOutput {
+    @location(0) accum : vec4<f32>,
+    @location(1) revealage : f32,
+}
+
+// Weighted blended transparency (McGuire and Bavoil 2013), nearer and more
+// opaque surfaces weigh more
+fn oit_output(color : vec4<f32>, depth : f32) -> OitOutput {
+    let weight = clamp(
+        pow(min(1.0, color.a * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - depth * 0.9, 3.0),
+        1e-2,
+        3e3,
+    );
+    var out : OitOutput;
+    out.accum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.revealage = color.a;
+    return out;
+}
+
+@fragment
+fn fs_oit(in : VertexOutput) -> OitOutput {
+    return oit_output(shade(in), in.clip_position.z);
 }
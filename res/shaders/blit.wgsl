@@ -0,0 +1,24 @@
+@group(0) @binding(0)
+var t_source : texture_2d<f32>;
+@group(0) @binding(1)
+var s_source : sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position : vec4<f32>,
+    @location(0) uv : vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index : u32) -> VertexOutput {
+    var out : VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// A bilinear tap in the middle of each 2x2 block of the previous mip is a box filter
+@fragment
+fn fs_main(in : VertexOutput) -> @location(0) vec4<f32> {
+    return textureSampleLevel(t_source, s_source, in.uv, 0.0);
+}
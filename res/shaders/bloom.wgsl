@@ -0,0 +1,91 @@
+struct BloomUniform {
+    threshold : f32,
+    knee : f32,
+    intensity : f32,
+    filter_radius : f32,
+};
+
+@group(0) @binding(0)
+var t_source : texture_2d<f32>;
+@group(0) @binding(1)
+var s_source : sampler;
+@group(0) @binding(2)
+var<uniform> params : BloomUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position : vec4<f32>,
+    @location(0) uv : vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index : u32) -> VertexOutput {
+    var out : VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn sample_source(uv : vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0).rgb;
+}
+
+// 13 tap downsample from "Next Generation Post Processing in Call of Duty: Advanced Warfare"
+fn downsample(uv : vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+
+    let a = sample_source(uv + texel * vec2<f32>(-2.0, -2.0));
+    let b = sample_source(uv + texel * vec2<f32>(0.0, -2.0));
+    let c = sample_source(uv + texel * vec2<f32>(2.0, -2.0));
+    let d = sample_source(uv + texel * vec2<f32>(-2.0, 0.0));
+    let e = sample_source(uv);
+    let f = sample_source(uv + texel * vec2<f32>(2.0, 0.0));
+    let g = sample_source(uv + texel * vec2<f32>(-2.0, 2.0));
+    let h = sample_source(uv + texel * vec2<f32>(0.0, 2.0));
+    let i = sample_source(uv + texel * vec2<f32>(2.0, 2.0));
+    let j = sample_source(uv + texel * vec2<f32>(-1.0, -1.0));
+    let k = sample_source(uv + texel * vec2<f32>(1.0, -1.0));
+    let l = sample_source(uv + texel * vec2<f32>(-1.0, 1.0));
+    let m = sample_source(uv + texel * vec2<f32>(1.0, 1.0));
+
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+// Quadratic soft knee around the threshold
+fn threshold(color : vec3<f32>) -> vec3<f32> {
+    let brightness = max(color.r, max(color.g, color.b));
+    var soft = clamp(brightness - params.threshold + params.knee, 0.0, 2.0 * params.knee);
+    soft = soft * soft / (4.0 * params.knee + 1e-5);
+    let contribution = max(soft, brightness - params.threshold) / max(brightness, 1e-5);
+    return color * contribution;
+}
+
+@fragment
+fn fs_prefilter(in : VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(threshold(downsample(in.uv)), 1.0);
+}
+
+@fragment
+fn fs_downsample(in : VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// 3x3 tent filter, blended additively onto the next larger mip
+@fragment
+fn fs_upsample(in : VertexOutput) -> @location(0) vec4<f32> {
+    let r = params.filter_radius;
+
+    var color = sample_source(in.uv) * 4.0;
+    color += (sample_source(in.uv + vec2<f32>(0.0, -r)) + sample_source(in.uv + vec2<f32>(-r, 0.0))
+        + sample_source(in.uv + vec2<f32>(r, 0.0)) + sample_source(in.uv + vec2<f32>(0.0, r))) * 2.0;
+    color += sample_source(in.uv + vec2<f32>(-r, -r)) + sample_source(in.uv + vec2<f32>(r, -r))
+        + sample_source(in.uv + vec2<f32>(-r, r)) + sample_source(in.uv + vec2<f32>(r, r));
+
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+@fragment
+fn fs_composite(in : VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(sample_source(in.uv) * params.intensity, 1.0);
+}
@@ -0,0 +1,122 @@
+struct DrawData {
+    model : mat4x4<f32>,
+    aabb_min : vec4<f32>,
+    aabb_max : vec4<f32>,
+    first_index : u32,
+    index_count : u32,
+    base_vertex : i32,
+    _padding : u32,
+};
+
+struct DrawIndexedIndirect {
+    index_count : u32,
+    instance_count : u32,
+    first_index : u32,
+    base_vertex : i32,
+    first_instance : u32,
+};
+
+struct CullUniform {
+    planes : array<vec4<f32>, 6>,
+    // The depth pyramid is built from the previous frame, so occlusion is
+    // tested with the previous camera
+    previous_view_proj : mat4x4<f32>,
+    hi_z_size : vec2<f32>,
+    draw_count : u32,
+    flags : u32,
+};
+
+const FLAG_FRUSTUM : u32 = 1u;
+const FLAG_OCCLUSION : u32 = 2u;
+
+@group(0) @binding(0)
+var<uniform> params : CullUniform;
+@group(0) @binding(1)
+var<storage, read> draws : array<DrawData>;
+@group(0) @binding(2)
+var<storage, read_write> commands : array<DrawIndexedIndirect>;
+@group(0) @binding(3)
+var t_hi_z : texture_2d<f32>;
+
+fn transform_aabb(draw : DrawData) -> array<vec3<f32>, 2> {
+    let center = (draw.aabb_min.xyz + draw.aabb_max.xyz) * 0.5;
+    let half = (draw.aabb_max.xyz - draw.aabb_min.xyz) * 0.5;
+    let world_center = (draw.model * vec4<f32>(center, 1.0)).xyz;
+    let extents = abs(draw.model[0].xyz) * half.x + abs(draw.model[1].xyz) * half.y
+        + abs(draw.model[2].xyz) * half.z;
+    return array<vec3<f32>, 2>(world_center - extents, world_center + extents);
+}
+
+fn in_frustum(aabb_min : vec3<f32>, aabb_max : vec3<f32>) -> bool {
+    for (var i = 0; i < 6; i++) {
+        let plane = params.planes[i];
+        let corner = select(aabb_min, aabb_max, plane.xyz >= vec3<f32>(0.0));
+        if (dot(plane.xyz, corner) + plane.w < 0.0) {
+            return false;
+        }
+    }
+    return true;
+}
+
+fn occluded(aabb_min : vec3<f32>, aabb_max : vec3<f32>) -> bool {
+    var uv_min = vec2<f32>(1.0);
+    var uv_max = vec2<f32>(0.0);
+    var nearest = 1.0;
+    for (var i = 0u; i < 8u; i++) {
+        let corner = select(aabb_min, aabb_max, vec3<bool>((i & 1u) != 0u, (i & 2u) != 0u, (i & 4u) != 0u));
+        let clip = params.previous_view_proj * vec4<f32>(corner, 1.0);
+        // Boxes crossing the near plane can't be projected reliably
+        if (clip.w <= 0.0) {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = vec2<f32>(ndc.x * 0.5 + 0.5, 0.5 - ndc.y * 0.5);
+        uv_min = min(uv_min, uv);
+        uv_max = max(uv_max, uv);
+        nearest = min(nearest, ndc.z);
+    }
+    uv_min = clamp(uv_min, vec2<f32>(0.0), vec2<f32>(1.0));
+    uv_max = clamp(uv_max, vec2<f32>(0.0), vec2<f32>(1.0));
+
+    // Pick the mip where the box covers at most 2x2 texels
+    let extent = (uv_max - uv_min) * params.hi_z_size;
+    let max_level = f32(textureNumLevels(t_hi_z) - 1u);
+    let level = i32(clamp(ceil(log2(max(max(extent.x, extent.y), 1.0))), 0.0, max_level));
+    let size = vec2<f32>(textureDimensions(t_hi_z, level));
+    let low = vec2<u32>(clamp(uv_min * size, vec2<f32>(0.0), size - 1.0));
+    let high = vec2<u32>(clamp(uv_max * size, vec2<f32>(0.0), size - 1.0));
+
+    let farthest = max(
+        max(textureLoad(t_hi_z, low, level).r, textureLoad(t_hi_z, vec2<u32>(high.x, low.y), level).r),
+        max(textureLoad(t_hi_z, vec2<u32>(low.x, high.y), level).r, textureLoad(t_hi_z, high, level).r),
+    );
+    return nearest > farthest;
+}
+
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id : vec3<u32>) {
+    let index = id.x;
+    if (index >= params.draw_count) {
+        return;
+    }
+    let draw = draws[index];
+    let aabb = transform_aabb(draw);
+
+    var visible = true;
+    if ((params.flags & FLAG_FRUSTUM) != 0u) {
+        visible = in_frustum(aabb[0], aabb[1]);
+    }
+    if (visible && (params.flags & FLAG_OCCLUSION) != 0u) {
+        visible = !occluded(aabb[0], aabb[1]);
+    }
+
+    // Culled draws stay in place with no instances so every material batch
+    // keeps a fixed range of commands
+    commands[index] = DrawIndexedIndirect(
+        draw.index_count,
+        select(0u, 1u, visible),
+        draw.first_index,
+        draw.base_vertex,
+        index,
+    );
+}
@@ -0,0 +1,168 @@
+// Debug views that replace the material shading or draw over the scene
+
+struct CameraUniform {
+    view_proj : mat4x4<f32>,
+    inverse_view_proj : mat4x4<f32>,
+    position : vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera : CameraUniform;
+
+struct DebugUniform {
+    wireframe_color : vec4<f32>,
+    near : f32,
+    far : f32,
+    normal_length : f32,
+    checker_scale : f32,
+};
+@group(1) @binding(0)
+var<uniform> debug : DebugUniform;
+// Mesh buffers read directly by the views that need more than one vertex
+@group(1) @binding(1)
+var<storage, read> vertices : array<f32>;
+@group(1) @binding(2)
+var<storage, read> indices : array<u32>;
+
+struct TransformationUniform {
+    matrix : mat4x4<f32>,
+    lod_fade : vec4<f32>,
+};
+@group(2) @binding(0)
+var<uniform> transformation : TransformationUniform;
+
+struct VertexInput {
+    @location(0) position : vec3<f32>,
+    @location(1) tex_coords : vec2<f32>,
+    @location(2) normal : vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position : vec4<f32>,
+    @location(0) tex_coords : vec2<f32>,
+    @location(1) world_position : vec3<f32>,
+    @location(2) world_normal : vec3<f32>,
+    // Object and submodel, or the barycentric coordinate for the wireframe
+    @location(3) @interpolate(flat) id : u32,
+    @location(4) barycentric : vec3<f32>,
+}
+
+// ModelVertex is 8 tightly packed floats
+const VERTEX_STRIDE : u32 = 8u;
+
+fn vertex_position(index : u32) -> vec3<f32> {
+    let base = index * VERTEX_STRIDE;
+    return vec3<f32>(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+}
+
+fn vertex_normal(index : u32) -> vec3<f32> {
+    let base = index * VERTEX_STRIDE + 5u;
+    return vec3<f32>(vertices[base], vertices[base + 1u], vertices[base + 2u]);
+}
+
+@vertex
+fn vs_main(model : VertexInput, @builtin(instance_index) id : u32) -> VertexOutput {
+    var out : VertexOutput;
+    let world_position = transformation.matrix * vec4<f32>(model.position, 1.0);
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = model.tex_coords;
+    out.world_position = world_position.xyz;
+    out.world_normal = (transformation.matrix * vec4<f32>(model.normal, 0.0)).xyz;
+    out.id = id;
+    out.barycentric = vec3<f32>(0.0);
+    return out;
+}
+
+@fragment
+fn fs_uv_checker(in : VertexOutput) -> @location(0) vec4<f32> {
+    let cell = vec2<i32>(floor(in.tex_coords * debug.checker_scale));
+    let checker = f32((cell.x + cell.y) & 1);
+    // Tint by quadrant so flipped or mirrored UVs stand out
+    let tint = vec3<f32>(fract(in.tex_coords), 0.5);
+    return vec4<f32>(mix(tint * 0.4, tint, checker), 1.0);
+}
+
+@fragment
+fn fs_depth(in : VertexOutput) -> @location(0) vec4<f32> {
+    let distance = length(in.world_position - camera.position.xyz);
+    let depth = clamp((distance - debug.near) / (debug.far - debug.near), 0.0, 1.0);
+    // Square root spreads the near range where most detail is
+    return vec4<f32>(vec3<f32>(1.0 - sqrt(depth)), 1.0);
+}
+
+fn hash(value : u32) -> u32 {
+    var x = value;
+    x = ((x >> 16u) ^ x) * 0x45d9f3bu;
+    x = ((x >> 16u) ^ x) * 0x45d9f3bu;
+    return (x >> 16u) ^ x;
+}
+
+@fragment
+fn fs_false_color(in : VertexOutput) -> @location(0) vec4<f32> {
+    let h = hash(in.id);
+    let color = vec3<f32>(f32(h & 255u), f32((h >> 8u) & 255u), f32((h >> 16u) & 255u)) / 255.0;
+    // A little lighting keeps the shape readable
+    let light = 0.6 + 0.4 * abs(dot(normalize(in.world_normal + vec3<f32>(1e-6)), normalize(vec3<f32>(0.3, 1.0, 0.5))));
+    return vec4<f32>((color * 0.8 + 0.2) * light, 1.0);
+}
+
+// Wireframe drawn with PolygonMode::Line
+@fragment
+fn fs_wireframe(in : VertexOutput) -> @location(0) vec4<f32> {
+    return debug.wireframe_color;
+}
+
+// Fallback without line polygon mode: triangles are drawn unindexed, pulling
+// their vertices from the mesh buffers so each corner gets its own barycentric
+// coordinate. The instance index carries the base vertex.
+@vertex
+fn vs_wireframe_barycentric(
+    @builtin(vertex_index) vertex_index : u32,
+    @builtin(instance_index) base_vertex : u32,
+) -> VertexOutput {
+    let index = indices[vertex_index] + base_vertex;
+    let world_position = transformation.matrix * vec4<f32>(vertex_position(index), 1.0);
+
+    var out : VertexOutput;
+    out.clip_position = camera.view_proj * world_position;
+    out.tex_coords = vec2<f32>(0.0);
+    out.world_position = world_position.xyz;
+    out.world_normal = vec3<f32>(0.0);
+    out.id = 0u;
+    let corner = vertex_index % 3u;
+    out.barycentric = vec3<f32>(f32(corner == 0u), f32(corner == 1u), f32(corner == 2u));
+    return out;
+}
+
+@fragment
+fn fs_wireframe_barycentric(in : VertexOutput) -> @location(0) vec4<f32> {
+    // Distance to the nearest edge in pixels
+    let edge = in.barycentric / fwidth(in.barycentric);
+    let coverage = 1.0 - clamp(min(edge.x, min(edge.y, edge.z)) - 0.5, 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+    return vec4<f32>(debug.wireframe_color.rgb, debug.wireframe_color.a * coverage);
+}
+
+// Two line vertices per mesh vertex, from the position along the normal
+@vertex
+fn vs_normals(@builtin(vertex_index) vertex_index : u32) -> VertexOutput {
+    let index = vertex_index / 2u;
+    let normal = normalize((transformation.matrix * vec4<f32>(vertex_normal(index), 0.0)).xyz + vec3<f32>(1e-6));
+    var world_position = (transformation.matrix * vec4<f32>(vertex_position(index), 1.0)).xyz;
+    world_position += normal * debug.normal_length * f32(vertex_index & 1u);
+
+    var out : VertexOutput;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.tex_coords = vec2<f32>(0.0);
+    out.world_position = world_position;
+    out.world_normal = normal;
+    out.id = 0u;
+    out.barycentric = vec3<f32>(0.0);
+    return out;
+}
+
+@fragment
+fn fs_normals(in : VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.world_normal * 0.5 + 0.5, 1.0);
+}
@@ -37,8 +37,7 @@ const X_AXIS_COLOR: vec3<f32> = vec3<f32>(1.0, 0.0, 0.0);
 const Z_AXIS_COLOR: vec3<f32> = vec3<f32>(0.0, 0.0, 1.0);
 const GRID_COLOR: vec3<f32> = vec3<f32>(0.2, 0.2, 0.2);
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+fn grid_color(in: VertexOutput) -> vec4<f32> {
     let xz = in.world_pos.xz;
 
     let distance_from_origin = length(xz);
@@ -77,3 +76,32 @@ fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
 
     return vec4<f32>(final_color, final_alpha);
 }
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return grid_color(in);
+}
+
+struct OitOutput {
+    @location(0) accum: vec4<f32>,
+    @location(1) revealage: f32,
+}
+
+// Weighted blended transparency (McGuire and Bavoil 2013), nearer and more
+// opaque surfaces weigh more
+fn oit_output(color: vec4<f32>, depth: f32) -> OitOutput {
+    let weight = clamp(
+        pow(min(1.0, color.a * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - depth * 0.9, 3.0),
+        1e-2,
+        3e3,
+    );
+    var out: OitOutput;
+    out.accum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.revealage = color.a;
+    return out;
+}
+
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    return oit_output(grid_color(in), in.clip_position.z);
+}
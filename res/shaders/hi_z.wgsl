@@ -0,0 +1,56 @@
+// Depth pyramid where every texel holds the farthest depth of the texels
+// below it, used for occlusion culling
+
+@group(0) @binding(0)
+var t_depth : texture_depth_2d;
+@group(0) @binding(1)
+var t_depth_multisampled : texture_depth_multisampled_2d;
+@group(0) @binding(2)
+var t_source : texture_2d<f32>;
+@group(0) @binding(3)
+var t_output : texture_storage_2d<r32float, write>;
+
+@compute @workgroup_size(8, 8)
+fn copy_depth(@builtin(global_invocation_id) id : vec3<u32>) {
+    if (any(id.xy >= textureDimensions(t_output))) {
+        return;
+    }
+    let depth = textureLoad(t_depth, id.xy, 0);
+    textureStore(t_output, id.xy, vec4<f32>(depth, 0.0, 0.0, 0.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn copy_depth_multisampled(@builtin(global_invocation_id) id : vec3<u32>) {
+    if (any(id.xy >= textureDimensions(t_output))) {
+        return;
+    }
+    var depth = 0.0;
+    for (var i = 0; i < i32(textureNumSamples(t_depth_multisampled)); i++) {
+        depth = max(depth, textureLoad(t_depth_multisampled, id.xy, i));
+    }
+    textureStore(t_output, id.xy, vec4<f32>(depth, 0.0, 0.0, 0.0));
+}
+
+// Odd sized sources fold their last row and column into the edge texels so
+// no depth is lost
+@compute @workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) id : vec3<u32>) {
+    let size = textureDimensions(t_output);
+    if (any(id.xy >= size)) {
+        return;
+    }
+    let source_size = textureDimensions(t_source);
+    let extra = vec2<u32>(
+        select(0u, 1u, id.x == size.x - 1u && (source_size.x & 1u) != 0u),
+        select(0u, 1u, id.y == size.y - 1u && (source_size.y & 1u) != 0u),
+    );
+
+    var depth = 0.0;
+    for (var y = 0u; y <= 1u + extra.y; y++) {
+        for (var x = 0u; x <= 1u + extra.x; x++) {
+            let texel = min(id.xy * 2u + vec2<u32>(x, y), source_size - 1u);
+            depth = max(depth, textureLoad(t_source, texel, 0).r);
+        }
+    }
+    textureStore(t_output, id.xy, vec4<f32>(depth, 0.0, 0.0, 0.0));
+}
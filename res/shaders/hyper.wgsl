@@ -42,3 +42,27 @@ var s_diffuse : sampler;
 fn fs_main(in : VertexOutput) -> @location(0) vec4<f32> {
     return textureSample(t_diffuse, s_diffuse, in.tex_coords);
 }
+
+struct OitOutput {
+    @location(0) accum : vec4<f32>,
+    @location(1) revealage : f32,
+}
+
+// Weighted blended transparency (McGuire and Bavoil 2013), nearer and more
+// opaque surfaces weigh more
+fn oit_output(color : vec4<f32>, depth : f32) -> OitOutput {
+    let weight = clamp(
+        pow(min(1.0, color.a * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - depth * 0.9, 3.0),
+        1e-2,
+        3e3,
+    );
+    var out : OitOutput;
+    out.accum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.revealage = color.a;
+    return out;
+}
+
+@fragment
+fn fs_oit(in : VertexOutput) -> OitOutput {
+    return oit_output(textureSample(t_diffuse, s_diffuse, in.tex_coords), in.clip_position.z);
+}
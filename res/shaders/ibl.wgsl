@@ -0,0 +1,215 @@
+// Precomputation of the image based lighting maps. Every entry point uses its
+// own bindings so that each pipeline gets a minimal layout.
+
+const PI : f32 = 3.14159265359;
+
+struct FilterUniform {
+    roughness : f32,
+    source_size : f32,
+    sample_count : u32,
+    _padding : u32,
+};
+
+@group(0) @binding(0)
+var t_equirect : texture_2d<f32>;
+@group(0) @binding(1)
+var t_output : texture_storage_2d_array<rgba16float, write>;
+@group(0) @binding(2)
+var t_environment : texture_cube<f32>;
+@group(0) @binding(3)
+var s_environment : sampler;
+@group(0) @binding(4)
+var<uniform> params : FilterUniform;
+@group(0) @binding(5)
+var t_brdf_lut : texture_storage_2d<rgba16float, write>;
+
+// Direction through the center of texel `id` of a cube face, following the
+// usual +X, -X, +Y, -Y, +Z, -Z layer order
+fn cube_direction(id : vec3<u32>, size : u32) -> vec3<f32> {
+    let uv = (vec2<f32>(id.xy) + 0.5) / f32(size) * 2.0 - 1.0;
+    var direction : vec3<f32>;
+    switch id.z {
+        case 0u: { direction = vec3<f32>(1.0, -uv.y, -uv.x); }
+        case 1u: { direction = vec3<f32>(-1.0, -uv.y, uv.x); }
+        case 2u: { direction = vec3<f32>(uv.x, 1.0, uv.y); }
+        case 3u: { direction = vec3<f32>(uv.x, -1.0, -uv.y); }
+        case 4u: { direction = vec3<f32>(uv.x, -uv.y, 1.0); }
+        default: { direction = vec3<f32>(-uv.x, -uv.y, -1.0); }
+    }
+    return normalize(direction);
+}
+
+// The equirectangular map is a 32 bit float texture, which isn't filterable,
+// so it is sampled bilinearly by hand
+fn sample_equirect(direction : vec3<f32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(t_equirect));
+    let uv = vec2<f32>(
+        atan2(direction.z, direction.x) / (2.0 * PI) + 0.5,
+        acos(clamp(direction.y, -1.0, 1.0)) / PI,
+    );
+    let position = uv * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(position));
+    let weight = fract(position);
+
+    var color = vec3<f32>(0.0);
+    for (var i = 0; i < 4; i++) {
+        let offset = vec2<i32>(i & 1, i >> 1u);
+        var texel = base + offset;
+        texel.x = (texel.x + size.x) % size.x;
+        texel.y = clamp(texel.y, 0, size.y - 1);
+        let w = mix(1.0 - weight, weight, vec2<f32>(offset));
+        color += textureLoad(t_equirect, texel, 0).rgb * w.x * w.y;
+    }
+    return color;
+}
+
+@compute @workgroup_size(8, 8, 1)
+fn equirect_to_cube(@builtin(global_invocation_id) id : vec3<u32>) {
+    let size = textureDimensions(t_output).x;
+    if (id.x >= size || id.y >= size) {
+        return;
+    }
+    let color = sample_equirect(cube_direction(id, size));
+    textureStore(t_output, id.xy, id.z, vec4<f32>(color, 1.0));
+}
+
+fn tangent_basis(normal : vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(normal.y) > 0.999) {
+        up = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    let tangent = normalize(cross(up, normal));
+    let bitangent = cross(normal, tangent);
+    return mat3x3<f32>(tangent, bitangent, normal);
+}
+
+// Cosine weighted convolution of the environment over the hemisphere
+@compute @workgroup_size(8, 8, 1)
+fn irradiance(@builtin(global_invocation_id) id : vec3<u32>) {
+    let size = textureDimensions(t_output).x;
+    if (id.x >= size || id.y >= size) {
+        return;
+    }
+    let normal = cube_direction(id, size);
+    let basis = tangent_basis(normal);
+
+    // Sampling a smaller mip keeps the fixed step count from aliasing
+    let level = max(log2(params.source_size / 64.0), 0.0);
+    let step = 0.025;
+    var irradiance = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += step * 2.0) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += step) {
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let sample = textureSampleLevel(t_environment, s_environment, basis * local, level).rgb;
+            irradiance += sample * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+
+    textureStore(t_output, id.xy, id.z, vec4<f32>(PI * irradiance / count, 1.0));
+}
+
+fn radical_inverse(bits_in : u32) -> f32 {
+    var bits = bits_in;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i : u32, count : u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), radical_inverse(i));
+}
+
+fn importance_sample_ggx(xi : vec2<f32>, normal : vec3<f32>, roughness : f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    let half_vector = vec3<f32>(cos(phi) * sin_theta, sin(phi) * sin_theta, cos_theta);
+    return normalize(tangent_basis(normal) * half_vector);
+}
+
+fn distribution_ggx(n_dot_h : f32, roughness : f32) -> f32 {
+    let a = roughness * roughness;
+    let a2 = a * a;
+    let denominator = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denominator * denominator);
+}
+
+// Split sum prefiltering with N = V = R, sampling from lower mips for
+// samples with a low probability to avoid fireflies
+@compute @workgroup_size(8, 8, 1)
+fn prefilter(@builtin(global_invocation_id) id : vec3<u32>) {
+    let size = textureDimensions(t_output).x;
+    if (id.x >= size || id.y >= size) {
+        return;
+    }
+    let normal = cube_direction(id, size);
+    let texel_solid_angle = 4.0 * PI / (6.0 * params.source_size * params.source_size);
+
+    var color = vec3<f32>(0.0);
+    var total_weight = 0.0;
+    for (var i = 0u; i < params.sample_count; i++) {
+        let half_vector = importance_sample_ggx(hammersley(i, params.sample_count), normal, params.roughness);
+        let light = normalize(2.0 * dot(normal, half_vector) * half_vector - normal);
+        let n_dot_l = dot(normal, light);
+        if (n_dot_l > 0.0) {
+            let n_dot_h = max(dot(normal, half_vector), 0.0);
+            let pdf = distribution_ggx(n_dot_h, params.roughness) * 0.25 + 0.0001;
+            let sample_solid_angle = 1.0 / (f32(params.sample_count) * pdf);
+            var level = 0.0;
+            if (params.roughness > 0.0) {
+                level = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+            }
+            color += textureSampleLevel(t_environment, s_environment, light, level).rgb * n_dot_l;
+            total_weight += n_dot_l;
+        }
+    }
+
+    textureStore(t_output, id.xy, id.z, vec4<f32>(color / max(total_weight, 0.0001), 1.0));
+}
+
+fn geometry_schlick_ggx(n_dot_v : f32, roughness : f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+// Scale and bias to F0 of the split sum approximation, indexed by
+// (n_dot_v, roughness)
+@compute @workgroup_size(8, 8, 1)
+fn brdf_lut(@builtin(global_invocation_id) id : vec3<u32>) {
+    let size = textureDimensions(t_brdf_lut);
+    if (id.x >= size.x || id.y >= size.y) {
+        return;
+    }
+    let n_dot_v = max((f32(id.x) + 0.5) / f32(size.x), 0.001);
+    let roughness = (f32(id.y) + 0.5) / f32(size.y);
+
+    let view = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+    let normal = vec3<f32>(0.0, 0.0, 1.0);
+    let sample_count = 512u;
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < sample_count; i++) {
+        let half_vector = importance_sample_ggx(hammersley(i, sample_count), normal, roughness);
+        let light = normalize(2.0 * dot(view, half_vector) * half_vector - view);
+        let n_dot_l = max(light.z, 0.0);
+        let n_dot_h = max(half_vector.z, 0.0);
+        let v_dot_h = max(dot(view, half_vector), 0.0);
+        if (n_dot_l > 0.0) {
+            let geometry = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let visibility = geometry * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fresnel) * visibility;
+            bias += fresnel * visibility;
+        }
+    }
+
+    let result = vec2<f32>(scale, bias) / f32(sample_count);
+    textureStore(t_brdf_lut, id.xy, vec4<f32>(result, 0.0, 1.0));
+}
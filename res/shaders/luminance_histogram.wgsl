@@ -0,0 +1,80 @@
+struct HistogramUniform {
+    min_log_luminance : f32,
+    inverse_log_luminance_range : f32,
+    log_luminance_range : f32,
+    time_coefficient : f32,
+    pixel_count : u32,
+};
+
+@group(0) @binding(0)
+var t_hdr : texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram : array<atomic<u32>, 256>;
+@group(0) @binding(2)
+var<uniform> params : HistogramUniform;
+@group(0) @binding(3)
+var<storage, read_write> average_luminance : f32;
+
+var<workgroup> local_bins : array<atomic<u32>, 256>;
+// Summed as floats, a 4K frame in the top bin would overflow a u32
+var<workgroup> weighted_bins : array<f32, 256>;
+
+// Bin 0 holds (near) black pixels, the remaining 255 bins split the log range evenly
+fn luminance_bin(color : vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if (luminance < 0.005) {
+        return 0u;
+    }
+    let log_luminance = clamp(
+        (log2(luminance) - params.min_log_luminance) * params.inverse_log_luminance_range,
+        0.0,
+        1.0,
+    );
+    return u32(log_luminance * 254.0 + 1.0);
+}
+
+@compute @workgroup_size(16, 16)
+fn build_histogram(
+    @builtin(global_invocation_id) global_id : vec3<u32>,
+    @builtin(local_invocation_index) local_index : u32,
+) {
+    atomicStore(&local_bins[local_index], 0u);
+    workgroupBarrier();
+
+    let dimensions = textureDimensions(t_hdr);
+    if (global_id.x < dimensions.x && global_id.y < dimensions.y) {
+        let color = textureLoad(t_hdr, vec2<i32>(global_id.xy), 0).rgb;
+        atomicAdd(&local_bins[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[local_index], atomicLoad(&local_bins[local_index]));
+}
+
+@compute @workgroup_size(256)
+fn average_luminance_main(@builtin(local_invocation_index) local_index : u32) {
+    let count = atomicLoad(&histogram[local_index]);
+    weighted_bins[local_index] = f32(count) * f32(local_index);
+    workgroupBarrier();
+
+    // Clear the bin for the next frame
+    atomicStore(&histogram[local_index], 0u);
+
+    for (var cutoff = 128u; cutoff > 0u; cutoff >>= 1u) {
+        if (local_index < cutoff) {
+            weighted_bins[local_index] += weighted_bins[local_index + cutoff];
+        }
+        workgroupBarrier();
+    }
+
+    if (local_index == 0u) {
+        // `count` is the number of black pixels here, which are left out of the average
+        let lit_pixels = max(f32(params.pixel_count) - f32(count), 1.0);
+        let weighted_log_average = weighted_bins[0] / lit_pixels - 1.0;
+        let target_luminance = exp2(
+            weighted_log_average / 254.0 * params.log_luminance_range + params.min_log_luminance
+        );
+        let previous = average_luminance;
+        average_luminance = previous + (target_luminance - previous) * params.time_coefficient;
+    }
+}
@@ -0,0 +1,25 @@
+// Resolves weighted blended transparency over the opaque scene
+
+@group(0) @binding(0)
+var t_accum : texture_2d<f32>;
+@group(0) @binding(1)
+var t_revealage : texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index : u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+}
+
+@fragment
+fn fs_main(@builtin(position) position : vec4<f32>) -> @location(0) vec4<f32> {
+    let texel = vec2<u32>(position.xy);
+    let revealage = textureLoad(t_revealage, texel, 0).r;
+    // Nothing transparent covers this pixel
+    if (revealage >= 0.9999) {
+        discard;
+    }
+    let accum = textureLoad(t_accum, texel, 0);
+    let color = accum.rgb / clamp(accum.a, 1e-4, 5e4);
+    return vec4<f32>(color, 1.0 - revealage);
+}
@@ -0,0 +1,80 @@
+// Silhouette outline around the selection mask. A jump flood stores the pixel
+// of the nearest mask pixel found so far, or -1 where none is known yet.
+
+struct SelectionUniform {
+    outline_color : vec4<f32>,
+    hover_color : vec4<f32>,
+    tint : vec4<f32>,
+    outline_width : f32,
+};
+@group(0) @binding(0)
+var<uniform> selection : SelectionUniform;
+@group(1) @binding(0)
+var t_mask : texture_2d<f32>;
+@group(1) @binding(1)
+var t_seeds : texture_2d<f32>;
+
+struct FullscreenOutput {
+    @builtin(position) clip_position : vec4<f32>,
+    // Pixel distance between the samples of a jump flood step
+    @location(0) @interpolate(flat) step : u32,
+}
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index : u32, @builtin(instance_index) step : u32) -> FullscreenOutput {
+    var out : FullscreenOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.step = step;
+    return out;
+}
+
+@fragment
+fn fs_jump_flood_init(in : FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    if (textureLoad(t_mask, pixel, 0).r > 0.0) {
+        return vec4<f32>(vec2<f32>(pixel), 0.0, 0.0);
+    }
+    return vec4<f32>(-1.0, -1.0, 0.0, 0.0);
+}
+
+@fragment
+fn fs_jump_flood_step(in : FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let size = vec2<i32>(textureDimensions(t_seeds));
+    var nearest = vec2<f32>(-1.0);
+    var nearest_distance = 1e20;
+    for (var y = -1; y <= 1; y++) {
+        for (var x = -1; x <= 1; x++) {
+            let sample_pixel = pixel + vec2<i32>(x, y) * i32(in.step);
+            if (any(sample_pixel < vec2<i32>(0)) || any(sample_pixel >= size)) {
+                continue;
+            }
+            let seed = textureLoad(t_seeds, sample_pixel, 0).xy;
+            if (seed.x < 0.0) {
+                continue;
+            }
+            let seed_distance = distance(seed, vec2<f32>(pixel));
+            if (seed_distance < nearest_distance) {
+                nearest = seed;
+                nearest_distance = seed_distance;
+            }
+        }
+    }
+    return vec4<f32>(nearest, 0.0, 0.0);
+}
+
+@fragment
+fn fs_composite(in : FullscreenOutput) -> @location(0) vec4<f32> {
+    let pixel = vec2<i32>(in.clip_position.xy);
+    let seed = textureLoad(t_seeds, pixel, 0).xy;
+    // The outline only grows outwards from the silhouette
+    if (seed.x < 0.0 || textureLoad(t_mask, pixel, 0).r > 0.0) {
+        discard;
+    }
+    // One pixel of falloff at the outer edge
+    let coverage = clamp(selection.outline_width + 1.0 - distance(seed, vec2<f32>(pixel)), 0.0, 1.0);
+    let selected = textureLoad(t_mask, vec2<i32>(seed), 0).r > 0.75;
+    let color = select(selection.hover_color, selection.outline_color, selected);
+    return vec4<f32>(color.rgb, color.a * coverage);
+}
@@ -0,0 +1,49 @@
+// Screen space quads, images and text drawn over the finished frame
+
+struct ScreenUniform {
+    size : vec2<f32>,
+};
+@group(0) @binding(0)
+var<uniform> screen : ScreenUniform;
+
+@group(1) @binding(0)
+var t_texture : texture_2d<f32>;
+@group(1) @binding(1)
+var s_texture : sampler;
+
+struct VertexInput {
+    // In pixels from the top left corner
+    @location(0) position : vec2<f32>,
+    @location(1) uv : vec2<f32>,
+    @location(2) color : vec4<f32>,
+    // 0 multiplies the color by the texture, 1 uses the red channel of the
+    // glyph atlas as coverage
+    @location(3) mode : u32,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position : vec4<f32>,
+    @location(0) uv : vec2<f32>,
+    @location(1) color : vec4<f32>,
+    @location(2) @interpolate(flat) mode : u32,
+}
+
+@vertex
+fn vs_main(in : VertexInput) -> VertexOutput {
+    var out : VertexOutput;
+    let ndc = in.position / screen.size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    out.clip_position = vec4<f32>(ndc, 0.0, 1.0);
+    out.uv = in.uv;
+    out.color = in.color;
+    out.mode = in.mode;
+    return out;
+}
+
+@fragment
+fn fs_main(in : VertexOutput) -> @location(0) vec4<f32> {
+    let texel = textureSample(t_texture, s_texture, in.uv);
+    if (in.mode == 1u) {
+        return vec4<f32>(in.color.rgb, in.color.a * texel.r);
+    }
+    return in.color * texel;
+}
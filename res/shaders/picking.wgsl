@@ -0,0 +1,47 @@
+// Object and submodel ids with their depth, read back for mouse picking
+
+struct CameraUniform {
+    view_proj : mat4x4<f32>,
+    inverse_view_proj : mat4x4<f32>,
+    position : vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera : CameraUniform;
+
+struct TransformationUniform {
+    matrix : mat4x4<f32>,
+    lod_fade : vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> transformation : TransformationUniform;
+
+struct VertexInput {
+    @location(0) position : vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position : vec4<f32>,
+    // The instance index carries the id, 0 is left for empty pixels
+    @location(0) @interpolate(flat) id : u32,
+}
+
+struct FragmentOutput {
+    @location(0) id : u32,
+    @location(1) depth : f32,
+}
+
+@vertex
+fn vs_main(model : VertexInput, @builtin(instance_index) id : u32) -> VertexOutput {
+    var out : VertexOutput;
+    out.clip_position = camera.view_proj * transformation.matrix * vec4<f32>(model.position, 1.0);
+    out.id = id;
+    return out;
+}
+
+@fragment
+fn fs_main(in : VertexOutput) -> FragmentOutput {
+    var out : FragmentOutput;
+    out.id = in.id;
+    out.depth = in.clip_position.z;
+    return out;
+}
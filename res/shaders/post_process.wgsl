@@ -0,0 +1,139 @@
+struct PostProcessUniform {
+    fxaa_edge_threshold : f32,
+    fxaa_edge_threshold_min : f32,
+    fxaa_span_max : f32,
+    fxaa_reduce_mul : f32,
+    vignette_intensity : f32,
+    vignette_radius : f32,
+    vignette_smoothness : f32,
+    chromatic_aberration_intensity : f32,
+    color_grading_strength : f32,
+    lut_size : f32,
+    linear_input : u32,
+    _padding : u32,
+};
+
+@group(0) @binding(0)
+var t_source : texture_2d<f32>;
+@group(0) @binding(1)
+var s_source : sampler;
+@group(0) @binding(2)
+var<uniform> params : PostProcessUniform;
+@group(0) @binding(3)
+var t_lut : texture_3d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position : vec4<f32>,
+    @location(0) uv : vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index : u32) -> VertexOutput {
+    var out : VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn sample_source(uv : vec2<f32>) -> vec4<f32> {
+    return textureSampleLevel(t_source, s_source, uv, 0.0);
+}
+
+fn luma(color : vec3<f32>) -> f32 {
+    return dot(color, vec3<f32>(0.299, 0.587, 0.114));
+}
+
+@fragment
+fn fs_fxaa(in : VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+
+    let center = sample_source(in.uv);
+    let luma_nw = luma(sample_source(in.uv + vec2<f32>(-1.0, -1.0) * texel).rgb);
+    let luma_ne = luma(sample_source(in.uv + vec2<f32>(1.0, -1.0) * texel).rgb);
+    let luma_sw = luma(sample_source(in.uv + vec2<f32>(-1.0, 1.0) * texel).rgb);
+    let luma_se = luma(sample_source(in.uv + vec2<f32>(1.0, 1.0) * texel).rgb);
+    let luma_m = luma(center.rgb);
+
+    let luma_min = min(luma_m, min(min(luma_nw, luma_ne), min(luma_sw, luma_se)));
+    let luma_max = max(luma_m, max(max(luma_nw, luma_ne), max(luma_sw, luma_se)));
+
+    if (luma_max - luma_min < max(params.fxaa_edge_threshold_min, luma_max * params.fxaa_edge_threshold)) {
+        return center;
+    }
+
+    var direction = vec2<f32>(
+        -((luma_nw + luma_ne) - (luma_sw + luma_se)),
+        (luma_nw + luma_sw) - (luma_ne + luma_se),
+    );
+    let direction_reduce = max(
+        (luma_nw + luma_ne + luma_sw + luma_se) * 0.25 * params.fxaa_reduce_mul,
+        1.0 / 128.0,
+    );
+    let inverse_direction_min = 1.0 / (min(abs(direction.x), abs(direction.y)) + direction_reduce);
+    direction = clamp(
+        direction * inverse_direction_min,
+        vec2<f32>(-params.fxaa_span_max),
+        vec2<f32>(params.fxaa_span_max),
+    ) * texel;
+
+    let color_a = 0.5 * (sample_source(in.uv + direction * (1.0 / 3.0 - 0.5)).rgb
+        + sample_source(in.uv + direction * (2.0 / 3.0 - 0.5)).rgb);
+    let color_b = color_a * 0.5 + 0.25 * (sample_source(in.uv - direction * 0.5).rgb
+        + sample_source(in.uv + direction * 0.5).rgb);
+
+    let luma_b = luma(color_b);
+    if (luma_b < luma_min || luma_b > luma_max) {
+        return vec4<f32>(color_a, center.a);
+    }
+    return vec4<f32>(color_b, center.a);
+}
+
+@fragment
+fn fs_vignette(in : VertexOutput) -> @location(0) vec4<f32> {
+    let color = sample_source(in.uv);
+    let distance = length(in.uv - vec2<f32>(0.5)) * 1.41421356;
+    let falloff = smoothstep(params.vignette_radius, params.vignette_radius - params.vignette_smoothness, distance);
+    return vec4<f32>(color.rgb * mix(1.0 - params.vignette_intensity, 1.0, falloff), color.a);
+}
+
+@fragment
+fn fs_chromatic_aberration(in : VertexOutput) -> @location(0) vec4<f32> {
+    let offset = (in.uv - vec2<f32>(0.5)) * params.chromatic_aberration_intensity;
+    let center = sample_source(in.uv);
+    let r = sample_source(in.uv - offset).r;
+    let b = sample_source(in.uv + offset).b;
+    return vec4<f32>(r, center.g, b, center.a);
+}
+
+fn linear_to_srgb(color : vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+fn srgb_to_linear(color : vec3<f32>) -> vec3<f32> {
+    let low = color / 12.92;
+    let high = pow((color + 0.055) / 1.055, vec3<f32>(2.4));
+    return select(high, low, color <= vec3<f32>(0.04045));
+}
+
+// LUTs are authored against display referred (sRGB encoded) values
+@fragment
+fn fs_color_grading(in : VertexOutput) -> @location(0) vec4<f32> {
+    let color = sample_source(in.uv);
+
+    var encoded = clamp(color.rgb, vec3<f32>(0.0), vec3<f32>(1.0));
+    if (params.linear_input != 0u) {
+        encoded = linear_to_srgb(encoded);
+    }
+
+    let scale = (params.lut_size - 1.0) / params.lut_size;
+    let offset = 0.5 / params.lut_size;
+    var graded = textureSampleLevel(t_lut, s_source, encoded * scale + offset, 0.0).rgb;
+    if (params.linear_input != 0u) {
+        graded = srgb_to_linear(graded);
+    }
+
+    return vec4<f32>(mix(color.rgb, graded, params.color_grading_strength), color.a);
+}
@@ -0,0 +1,61 @@
+// Selected and hovered objects, drawn into the outline mask and as a tint over
+// their visible surfaces
+
+struct CameraUniform {
+    view_proj : mat4x4<f32>,
+    inverse_view_proj : mat4x4<f32>,
+    position : vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera : CameraUniform;
+
+struct TransformationUniform {
+    matrix : mat4x4<f32>,
+    lod_fade : vec4<f32>,
+};
+@group(1) @binding(0)
+var<uniform> transformation : TransformationUniform;
+
+struct SelectionUniform {
+    outline_color : vec4<f32>,
+    hover_color : vec4<f32>,
+    // Strength in alpha
+    tint : vec4<f32>,
+    outline_width : f32,
+};
+@group(2) @binding(0)
+var<uniform> selection : SelectionUniform;
+
+const SELECTED : u32 = 1u;
+// Mask values, hovered objects get the lower one
+const MASK_SELECTED : f32 = 1.0;
+const MASK_HOVERED : f32 = 0.5;
+
+struct VertexInput {
+    @location(0) position : vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position : vec4<f32>,
+    // The instance index carries the selection state
+    @location(0) @interpolate(flat) state : u32,
+}
+
+@vertex
+fn vs_main(model : VertexInput, @builtin(instance_index) state : u32) -> VertexOutput {
+    var out : VertexOutput;
+    out.clip_position = camera.view_proj * transformation.matrix * vec4<f32>(model.position, 1.0);
+    out.state = state;
+    return out;
+}
+
+@fragment
+fn fs_mask(in : VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(select(MASK_HOVERED, MASK_SELECTED, in.state == SELECTED), 0.0, 0.0, 1.0);
+}
+
+@fragment
+fn fs_tint(in : VertexOutput) -> @location(0) vec4<f32> {
+    let strength = select(0.5, 1.0, in.state == SELECTED) * selection.tint.a;
+    return vec4<f32>(selection.tint.rgb, strength);
+}
@@ -0,0 +1,44 @@
+struct CameraUniform {
+    view_proj : mat4x4<f32>,
+    inverse_view_proj : mat4x4<f32>,
+    position : vec4<f32>,
+};
+@group(0) @binding(0)
+var<uniform> camera : CameraUniform;
+
+struct EnvironmentUniform {
+    intensity : f32,
+    enabled : u32,
+    max_reflection_lod : f32,
+    _padding : u32,
+};
+
+@group(1) @binding(0)
+var t_environment : texture_cube<f32>;
+@group(1) @binding(4)
+var s_environment : sampler;
+@group(1) @binding(5)
+var<uniform> environment : EnvironmentUniform;
+
+struct VertexOutput {
+    @builtin(position) clip_position : vec4<f32>,
+    @location(0) ndc : vec2<f32>,
+}
+
+// Full screen triangle on the far plane, so it only covers empty pixels
+@vertex
+fn vs_main(@builtin(vertex_index) index : u32) -> VertexOutput {
+    var out : VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.ndc = uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    out.clip_position = vec4<f32>(out.ndc, 1.0, 1.0);
+    return out;
+}
+
+@fragment
+fn fs_main(in : VertexOutput) -> @location(0) vec4<f32> {
+    let world = camera.inverse_view_proj * vec4<f32>(in.ndc, 1.0, 1.0);
+    let direction = normalize(world.xyz / world.w - camera.position.xyz);
+    let color = textureSampleLevel(t_environment, s_environment, direction, 0.0).rgb;
+    return vec4<f32>(color * environment.intensity, 1.0);
+}
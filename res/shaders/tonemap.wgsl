@@ -0,0 +1,127 @@
+struct ToneMapUniform {
+    tone_operator : u32,
+    auto_exposure : u32,
+    exposure : f32,
+    encode_srgb : u32,
+};
+
+@group(0) @binding(0)
+var t_hdr : texture_2d<f32>;
+@group(0) @binding(1)
+var s_hdr : sampler;
+@group(0) @binding(2)
+var<uniform> params : ToneMapUniform;
+@group(0) @binding(3)
+var<storage, read> average_luminance : f32;
+
+struct VertexOutput {
+    @builtin(position) clip_position : vec4<f32>,
+    @location(0) uv : vec2<f32>,
+}
+
+// Single triangle covering the whole screen
+@vertex
+fn vs_main(@builtin(vertex_index) index : u32) -> VertexOutput {
+    var out : VertexOutput;
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+const OPERATOR_NONE : u32 = 0u;
+const OPERATOR_REINHARD : u32 = 1u;
+const OPERATOR_ACES : u32 = 2u;
+const OPERATOR_AGX : u32 = 3u;
+
+fn reinhard(color : vec3<f32>) -> vec3<f32> {
+    return color / (vec3<f32>(1.0) + color);
+}
+
+// Stephen Hill's fit of the ACES RRT + ODT
+fn aces(color : vec3<f32>) -> vec3<f32> {
+    let input_matrix = mat3x3<f32>(
+        vec3<f32>(0.59719, 0.07600, 0.02840),
+        vec3<f32>(0.35458, 0.90834, 0.13383),
+        vec3<f32>(0.04823, 0.01566, 0.83777),
+    );
+    let output_matrix = mat3x3<f32>(
+        vec3<f32>(1.60475, -0.10208, -0.00327),
+        vec3<f32>(-0.53108, 1.10813, -0.07276),
+        vec3<f32>(-0.07367, -0.00605, 1.07602),
+    );
+
+    let v = input_matrix * color;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(output_matrix * (a / b), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+fn agx_contrast(x : vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+}
+
+// Minimal AgX with the default look, returning linear values
+fn agx(color : vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var c = inset * color;
+    c = clamp(log2(max(c, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    c = (c - min_ev) / (max_ev - min_ev);
+    c = agx_contrast(c);
+    c = outset * c;
+    return pow(max(c, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn linear_to_srgb(color : vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_main(in : VertexOutput) -> @location(0) vec4<f32> {
+    let hdr = textureSample(t_hdr, s_hdr, in.uv);
+
+    var exposure = exp2(params.exposure);
+    if (params.auto_exposure != 0u) {
+        exposure *= 0.18 / max(average_luminance, 1e-4);
+    }
+    let color = hdr.rgb * exposure;
+
+    var mapped : vec3<f32>;
+    switch params.tone_operator {
+        case OPERATOR_REINHARD: {
+            mapped = reinhard(color);
+        }
+        case OPERATOR_ACES: {
+            mapped = aces(color);
+        }
+        case OPERATOR_AGX: {
+            mapped = agx(color);
+        }
+        default: {
+            mapped = clamp(color, vec3<f32>(0.0), vec3<f32>(1.0));
+        }
+    }
+
+    if (params.encode_srgb != 0u) {
+        mapped = linear_to_srgb(mapped);
+    }
+
+    return vec4<f32>(mapped, 1.0);
+}
@@ -3,6 +3,7 @@ use crate::renderer::Renderer;
 use std::sync::Arc;
 
 use winit::dpi::PhysicalSize;
+use winit::keyboard::ModifiersState;
 use winit::window::Window;
 
 pub struct ManifoldApp {
@@ -10,6 +11,12 @@ pub struct ManifoldApp {
     pub size: PhysicalSize<u32>,
     pub renderer: Option<Renderer>,
     pub cursor_position: Option<(f64, f64)>,
+    // Pixel clicked on, kept until the renderer answers the pick
+    pub pick_position: Option<(u32, u32)>,
+    // Pixel the hovered object was last picked at, picked again once the
+    // cursor leaves it
+    pub hover_position: Option<(u32, u32)>,
+    pub modifiers: ModifiersState,
 }
 
 impl ManifoldApp {
@@ -19,6 +26,9 @@ impl ManifoldApp {
             size: PhysicalSize::new(0, 0),
             renderer: None,
             cursor_position: None,
+            pick_position: None,
+            hover_position: None,
+            modifiers: ModifiersState::empty(),
         }
     }
 }
@@ -6,17 +6,28 @@ use winit::event::*;
 use winit::event_loop::ActiveEventLoop;
 use winit::keyboard::{KeyCode, PhysicalKey};
 
-use log::{debug, info, warn};
+use log::{debug, error, info, warn};
+use std::path::Path;
+use std::task::Poll;
 use winit::window::CursorIcon;
 
+// Under `res`, written with F6 and read back with F7
+const QUICKSAVE_SCENE: &str = "scenes/quicksave.scene";
+
 pub trait EventHandler {
     fn handle_window_event(&mut self, event_loop: &ActiveEventLoop, event: WindowEvent);
     fn handle_keyboard_input(&mut self, event_loop: &ActiveEventLoop, key_event: KeyEvent);
+    fn update_selection(&mut self);
 }
 
 impl EventHandler for ManifoldApp {
     fn handle_window_event(&mut self, event_loop: &ActiveEventLoop, event: WindowEvent) {
         debug!("{event:?}");
+        // Input the editor UI used does not reach the scene
+        let consumed = self
+            .renderer
+            .as_mut()
+            .is_some_and(|renderer| renderer.handle_editor_event(&event));
         match event {
             WindowEvent::CloseRequested => {
                 event_loop.exit();
@@ -32,22 +43,55 @@ impl EventHandler for ManifoldApp {
                 let scaled_height = (self.size.height as f64 * scale_factor).floor() as u32;
                 self.resize(scaled_width, scaled_height);
             }
-            WindowEvent::KeyboardInput { event, .. } => {
+            WindowEvent::KeyboardInput { event, .. } if !consumed => {
                 self.handle_keyboard_input(event_loop, event);
             }
             WindowEvent::CursorEntered { .. } | WindowEvent::CursorLeft { .. } => {
                 self.cursor_position = None;
             }
             WindowEvent::CursorMoved { position, .. } => {
-                if let Some(prev) = self.cursor_position {
+                if let Some(prev) = self.cursor_position.filter(|_| !consumed) {
                     let delta = (position.x - prev.0, position.y - prev.1);
                     self.renderer.as_mut().unwrap().handle_mouse_delta(delta);
                 }
+                if !consumed {
+                    // Ctrl flips snapping for the drag
+                    let ctrl = self.modifiers.control_key();
+                    self.renderer
+                        .as_mut()
+                        .unwrap()
+                        .handle_gizmo_motion(position.x, position.y, ctrl);
+                }
                 self.cursor_position = Some((position.x, position.y));
             }
+            WindowEvent::MouseInput {
+                state: ElementState::Pressed,
+                button: MouseButton::Left,
+                ..
+            } if !consumed => {
+                // Gizmo handles sit in front of the objects they move
+                let renderer = self.renderer.as_mut().unwrap();
+                let on_gizmo = self
+                    .cursor_position
+                    .is_some_and(|(x, y)| renderer.handle_gizmo_press(x, y));
+                if !on_gizmo {
+                    self.pick_position = self.cursor_position.map(|(x, y)| (x as u32, y as u32));
+                }
+            }
+            WindowEvent::MouseInput {
+                state: ElementState::Released,
+                button: MouseButton::Left,
+                ..
+            } => {
+                self.renderer.as_mut().unwrap().handle_gizmo_release();
+            }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.modifiers = modifiers.state();
+            }
             WindowEvent::RedrawRequested => {
                 self.window.as_ref().unwrap().request_redraw();
                 self.renderer.as_mut().unwrap().render();
+                self.update_selection();
             }
             _ => (),
         }
@@ -55,16 +99,12 @@ impl EventHandler for ManifoldApp {
 
     fn handle_keyboard_input(&mut self, event_loop: &ActiveEventLoop, key_event: KeyEvent) {
         match key_event.physical_key {
-            PhysicalKey::Code(KeyCode::Space) => {
-                if key_event.state == ElementState::Pressed {
-                    info!("Space!");
-                }
+            PhysicalKey::Code(KeyCode::Space) if key_event.state == ElementState::Pressed => {
+                info!("Space!");
             }
-            PhysicalKey::Code(KeyCode::Escape) => {
-                if key_event.state == ElementState::Pressed {
-                    warn!("Escape pressed, exiting the application.");
-                    event_loop.exit();
-                }
+            PhysicalKey::Code(KeyCode::Escape) if key_event.state == ElementState::Pressed => {
+                warn!("Escape pressed, exiting the application.");
+                event_loop.exit();
             }
             PhysicalKey::Code(KeyCode::ShiftLeft) => {
                 let icon = if key_event.state == ElementState::Pressed {
@@ -87,7 +127,97 @@ impl EventHandler for ManifoldApp {
                     .unwrap()
                     .handle_camera_movement(key_event);
             }
+            PhysicalKey::Code(
+                KeyCode::F1 | KeyCode::F2 | KeyCode::F3 | KeyCode::F4 | KeyCode::F5,
+            ) if key_event.state == ElementState::Pressed && !key_event.repeat => {
+                self.renderer
+                    .as_mut()
+                    .unwrap()
+                    .handle_debug_view_key(key_event);
+            }
+            PhysicalKey::Code(
+                KeyCode::Digit1
+                | KeyCode::Digit2
+                | KeyCode::Digit3
+                | KeyCode::Digit4
+                | KeyCode::KeyQ
+                | KeyCode::KeyX,
+            ) if key_event.state == ElementState::Pressed && !key_event.repeat => {
+                self.renderer.as_mut().unwrap().handle_gizmo_key(key_event);
+            }
+            PhysicalKey::Code(KeyCode::F6)
+                if key_event.state == ElementState::Pressed && !key_event.repeat =>
+            {
+                let renderer = self.renderer.as_ref().unwrap();
+                if let Err(e) = renderer.save_scene(Path::new(QUICKSAVE_SCENE)) {
+                    error!("Failed to save the scene: {e:#}");
+                }
+            }
+            PhysicalKey::Code(KeyCode::F7)
+                if key_event.state == ElementState::Pressed && !key_event.repeat =>
+            {
+                let renderer = self.renderer.as_mut().unwrap();
+                if let Err(e) = renderer.load_scene(Path::new(QUICKSAVE_SCENE)) {
+                    error!("Failed to load the scene: {e:#}");
+                }
+            }
+            PhysicalKey::Code(KeyCode::F8)
+                if key_event.state == ElementState::Pressed && !key_event.repeat =>
+            {
+                let renderer = self.renderer.as_mut().unwrap();
+                if let Err(e) = renderer.toggle_gpu_demo() {
+                    error!("Failed to toggle the GPU driven demo: {e:#}");
+                }
+            }
+            PhysicalKey::Code(KeyCode::F12)
+                if key_event.state == ElementState::Pressed && !key_event.repeat =>
+            {
+                self.renderer.as_mut().unwrap().toggle_editor();
+            }
             _ => (),
         }
     }
+
+    // Clicks select the object under the cursor, with ctrl adding to or
+    // removing from the selection; otherwise the cursor hovers objects
+    fn update_selection(&mut self) {
+        let renderer = self.renderer.as_mut().unwrap();
+        if let Some((x, y)) = self.pick_position {
+            if let Poll::Ready(hit) = renderer.pick(x, y) {
+                let ctrl = self.modifiers.control_key();
+                match hit {
+                    Some(hit) => {
+                        info!(
+                            "Picked object {} submodel {} at {:?}, depth {}",
+                            hit.object, hit.submodel, hit.position, hit.depth
+                        );
+                        if ctrl {
+                            renderer.selection.toggle(hit.object);
+                        } else {
+                            renderer.selection.select_only(hit.object);
+                        }
+                    }
+                    None if !ctrl => renderer.selection.clear(),
+                    None => (),
+                }
+                self.pick_position = None;
+                self.hover_position = None;
+            }
+        } else if renderer.is_dragging_gizmo() {
+            renderer.selection.set_hovered(None);
+            self.hover_position = None;
+        } else if let Some((x, y)) = self.cursor_position {
+            // Asking again for the pixel in flight does not render another pick
+            let position = (x as u32, y as u32);
+            if self.hover_position != Some(position) {
+                if let Poll::Ready(hit) = renderer.pick(position.0, position.1) {
+                    renderer.selection.set_hovered(hit.map(|hit| hit.object));
+                    self.hover_position = Some(position);
+                }
+            }
+        } else {
+            renderer.selection.set_hovered(None);
+            self.hover_position = None;
+        }
+    }
 }
@@ -2,5 +2,6 @@ pub mod event_handler;
 pub mod window_management;
 pub mod winit_handler;
 
+#[allow(clippy::module_inception)]
 pub mod app;
 pub use app::ManifoldApp;
@@ -0,0 +1,479 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex, Weak};
+use std::thread;
+
+use anyhow::{bail, Result};
+use log::{error, info};
+
+use super::arena::{Arena, Handle};
+use super::context::Context;
+use super::material::MaterialStore;
+use super::model::{parse_model, upload_model, Model, ModelData};
+use super::object::ObjectManager;
+use super::texture::{decode_texture, DecodedTexture, TextureId, TextureStore};
+
+const MAX_LOADER_THREADS: usize = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    // The error, the placeholder or previous version stays in use
+    Failed(String),
+}
+
+struct AssetEntry<T> {
+    // Relative to `res`
+    path: PathBuf,
+    state: LoadState,
+    // A placeholder until loaded, the previous version while reloading
+    value: T,
+    refs: Weak<()>,
+    // Bumped for every load started, results of older loads are dropped
+    generation: u32,
+}
+
+// Keeps an asset loaded for as long as any clone of it is alive
+pub struct AssetHandle<T> {
+    id: Handle<AssetEntry<T>>,
+    refs: Arc<()>,
+}
+
+impl<T> Clone for AssetHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id,
+            refs: Arc::clone(&self.refs),
+        }
+    }
+}
+
+impl<T> PartialEq for AssetHandle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl<T> Eq for AssetHandle<T> {}
+
+impl<T> fmt::Debug for AssetHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "AssetHandle({})", self.id)
+    }
+}
+
+pub type ModelHandle = AssetHandle<Model>;
+// The texture lives in the `TextureStore`, under an id that stays the same
+// from placeholder to loaded texture
+pub type TextureHandle = AssetHandle<TextureId>;
+
+// Assets of one type, one entry per path
+pub struct Assets<T> {
+    entries: Arena<AssetEntry<T>>,
+    by_path: HashMap<PathBuf, Handle<AssetEntry<T>>>,
+}
+
+impl<T> Default for Assets<T> {
+    fn default() -> Self {
+        Self {
+            entries: Arena::new(),
+            by_path: HashMap::new(),
+        }
+    }
+}
+
+impl<T> Assets<T> {
+    fn find(&mut self, path: &Path) -> Option<AssetHandle<T>> {
+        let id = *self.by_path.get(path)?;
+        let entry = self.entries.get_mut(id)?;
+        // Entries whose last handle was dropped stay until the next unload
+        let refs = entry.refs.upgrade().unwrap_or_else(|| {
+            let refs = Arc::new(());
+            entry.refs = Arc::downgrade(&refs);
+            refs
+        });
+        Some(AssetHandle { id, refs })
+    }
+
+    fn insert(&mut self, path: &Path, value: T) -> AssetHandle<T> {
+        let refs = Arc::new(());
+        let id = self.entries.insert(AssetEntry {
+            path: path.to_path_buf(),
+            state: LoadState::Loading,
+            value,
+            refs: Arc::downgrade(&refs),
+            generation: 0,
+        });
+        self.by_path.insert(path.to_path_buf(), id);
+        AssetHandle { id, refs }
+    }
+
+    // Marks the entry as loading again and returns the generation to tag
+    // the new load with
+    fn start_load(&mut self, id: Handle<AssetEntry<T>>) -> u32 {
+        let entry = self
+            .entries
+            .get_mut(id)
+            .expect("Loads are only started for live entries");
+        entry.state = LoadState::Loading;
+        entry.generation = entry.generation.wrapping_add(1);
+        entry.generation
+    }
+
+    fn entry(&self, handle: &AssetHandle<T>) -> &AssetEntry<T> {
+        self.entries
+            .get(handle.id)
+            .expect("Asset entries live as long as their handles")
+    }
+
+    // Finished entries nothing holds a handle to
+    fn unused(&self) -> Vec<Handle<AssetEntry<T>>> {
+        self.entries
+            .iter()
+            .filter(|(_, entry)| {
+                entry.refs.strong_count() == 0 && entry.state != LoadState::Loading
+            })
+            .map(|(id, _)| id)
+            .collect()
+    }
+
+    fn remove(&mut self, id: Handle<AssetEntry<T>>) -> Option<AssetEntry<T>> {
+        let entry = self.entries.remove(id)?;
+        self.by_path.remove(&entry.path);
+        Some(entry)
+    }
+}
+
+pub trait Asset: Sized + 'static {
+    fn assets(server: &AssetServer) -> &Assets<Self>;
+}
+
+impl Asset for Model {
+    fn assets(server: &AssetServer) -> &Assets<Self> {
+        &server.models
+    }
+}
+
+impl Asset for TextureId {
+    fn assets(server: &AssetServer) -> &Assets<Self> {
+        &server.textures
+    }
+}
+
+// CPU work finished on a loader thread, uploaded in `AssetServer::update`
+enum Loaded {
+    Model(Handle<AssetEntry<Model>>, u32, Result<ModelData>),
+    Texture(Handle<AssetEntry<TextureId>>, u32, Result<DecodedTexture>),
+}
+
+type Job = Box<dyn FnOnce() -> Loaded + Send>;
+
+struct LoaderPool {
+    jobs: Option<mpsc::Sender<Job>>,
+    results: mpsc::Receiver<Loaded>,
+    threads: Vec<thread::JoinHandle<()>>,
+}
+
+impl LoaderPool {
+    fn new(thread_count: usize) -> Self {
+        let (jobs, job_receiver) = mpsc::channel::<Job>();
+        let (result_sender, results) = mpsc::channel();
+        let job_receiver = Arc::new(Mutex::new(job_receiver));
+        let threads = (0..thread_count)
+            .map(|i| {
+                let job_receiver = Arc::clone(&job_receiver);
+                let result_sender = result_sender.clone();
+                thread::Builder::new()
+                    .name(format!("asset loader {i}"))
+                    .spawn(move || loop {
+                        // The lock is only held while waiting for the next job
+                        let job = match job_receiver.lock() {
+                            Ok(receiver) => receiver.recv(),
+                            Err(_) => break,
+                        };
+                        let Ok(job) = job else {
+                            break;
+                        };
+                        if result_sender.send(job()).is_err() {
+                            break;
+                        }
+                    })
+                    .expect("Failed to spawn an asset loader thread")
+            })
+            .collect();
+        Self {
+            jobs: Some(jobs),
+            results,
+            threads,
+        }
+    }
+
+    fn spawn(&self, job: Job) {
+        if let Some(jobs) = &self.jobs {
+            // Only fails once every thread is gone, which only happens on drop
+            let _ = jobs.send(job);
+        }
+    }
+}
+
+impl Drop for LoaderPool {
+    fn drop(&mut self) {
+        // Closing the channel lets the threads finish their current job and exit
+        self.jobs = None;
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Loads models and textures from `res`, textures and reloads on background
+// threads. Each path is loaded once and shared through handles; a
+// placeholder stands in until the load finishes and assets are dropped once
+// no handle is left.
+pub struct AssetServer {
+    models: Assets<Model>,
+    textures: Assets<TextureId>,
+    pool: LoaderPool,
+}
+
+impl Default for AssetServer {
+    fn default() -> Self {
+        let thread_count = thread::available_parallelism()
+            .map_or(1, |count| count.get())
+            .min(MAX_LOADER_THREADS);
+        Self {
+            models: Assets::default(),
+            textures: Assets::default(),
+            pool: LoaderPool::new(thread_count),
+        }
+    }
+}
+
+impl AssetServer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Loads on this thread, for callers that need the submodels right away.
+    // A background load of the same path is superseded.
+    pub fn load_model_now(
+        &mut self,
+        path: &Path,
+        device: &wgpu::Device,
+        material_store: &mut MaterialStore,
+    ) -> Result<ModelHandle> {
+        let handle = match self.models.find(path) {
+            Some(handle) => handle,
+            None => self.models.insert(path, Model { data: Vec::new() }),
+        };
+        match self.load_state(&handle) {
+            LoadState::Loaded => return Ok(handle),
+            LoadState::Failed(e) => bail!("{e}"),
+            LoadState::Loading => {}
+        }
+        self.models.start_load(handle.id);
+        let entry = self.models.entries.get_mut(handle.id).unwrap();
+        match parse_model(path) {
+            Ok(data) => {
+                entry.value = upload_model(&data, path, device, material_store);
+                entry.state = LoadState::Loaded;
+                info!("Loaded model {}", path.display());
+                Ok(handle)
+            }
+            Err(e) => {
+                entry.state = LoadState::Failed(format!("{e:#}"));
+                Err(e)
+            }
+        }
+    }
+
+    // Starts loading in the background. Until then the id points at a grey
+    // placeholder.
+    pub fn load_texture(
+        &mut self,
+        path: &Path,
+        texture_store: &mut TextureStore,
+        context: &Context<'_>,
+    ) -> TextureHandle {
+        if let Some(handle) = self.textures.find(path) {
+            return handle;
+        }
+        let placeholder = texture_store.create_placeholder(context);
+        let texture_id = texture_store.add_texture(placeholder, Some(path.to_path_buf()));
+        let handle = self.textures.insert(path, texture_id);
+        self.spawn_texture(handle.id, context);
+        handle
+    }
+
+    // Loads a file again in the background and swaps the result in place of
+    // the current version. Returns whether the path is a loaded asset.
+    pub fn reload(&mut self, path: &Path, context: &Context<'_>) -> bool {
+        if let Some(&id) = self.models.by_path.get(path) {
+            self.spawn_model(id);
+            return true;
+        }
+        if let Some(&id) = self.textures.by_path.get(path) {
+            self.spawn_texture(id, context);
+            return true;
+        }
+        false
+    }
+
+    pub fn get<T: Asset>(&self, handle: &AssetHandle<T>) -> &T {
+        &T::assets(self).entry(handle).value
+    }
+
+    pub fn load_state<T: Asset>(&self, handle: &AssetHandle<T>) -> &LoadState {
+        &T::assets(self).entry(handle).state
+    }
+
+    pub fn path<T: Asset>(&self, handle: &AssetHandle<T>) -> &Path {
+        &T::assets(self).entry(handle).path
+    }
+
+    // Uploads what the loader threads finished, puts it in place of the
+    // placeholders and drops assets without handles
+    pub fn update(
+        &mut self,
+        context: &Context<'_>,
+        texture_store: &mut TextureStore,
+        material_store: &mut MaterialStore,
+        object_manager: &mut ObjectManager,
+    ) {
+        while let Ok(loaded) = self.pool.results.try_recv() {
+            match loaded {
+                Loaded::Model(id, generation, result) => self.finish_model(
+                    id,
+                    generation,
+                    result,
+                    context,
+                    material_store,
+                    object_manager,
+                ),
+                Loaded::Texture(id, generation, result) => {
+                    self.finish_texture(id, generation, result, context, texture_store)
+                }
+            }
+        }
+        self.unload_unused(texture_store, material_store);
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn finish_model(
+        &mut self,
+        id: Handle<AssetEntry<Model>>,
+        generation: u32,
+        result: Result<ModelData>,
+        context: &Context<'_>,
+        material_store: &mut MaterialStore,
+        object_manager: &mut ObjectManager,
+    ) {
+        // Superseded by a later reload or by `load_model_now`
+        let Some(entry) = self
+            .models
+            .entries
+            .get_mut(id)
+            .filter(|entry| entry.generation == generation)
+        else {
+            return;
+        };
+        match result {
+            Ok(data) => {
+                entry.value = upload_model(&data, &entry.path, &context.device, material_store);
+                entry.state = LoadState::Loaded;
+                info!("Loaded model {}", entry.path.display());
+                for object in object_manager.iter_mut() {
+                    if object.model_asset().is_some_and(|handle| handle.id == id) {
+                        // Materials assigned to the object survive a reload
+                        let mut model = entry.value.clone();
+                        for (submodel, old) in model.data.iter_mut().zip(&object.model().data) {
+                            submodel.material_id = old.material_id;
+                        }
+                        object.set_model(model);
+                    }
+                }
+            }
+            Err(e) => {
+                error!("{e:#}");
+                entry.state = LoadState::Failed(format!("{e:#}"));
+            }
+        }
+    }
+
+    fn finish_texture(
+        &mut self,
+        id: Handle<AssetEntry<TextureId>>,
+        generation: u32,
+        result: Result<DecodedTexture>,
+        context: &Context<'_>,
+        texture_store: &mut TextureStore,
+    ) {
+        let Some(entry) = self
+            .textures
+            .entries
+            .get_mut(id)
+            .filter(|entry| entry.generation == generation)
+        else {
+            return;
+        };
+        // Keeps a sampler set on the placeholder or the previous version
+        let sampler = texture_store.get_texture(entry.value).sampler.clone();
+        let texture = result.and_then(|decoded| {
+            texture_store.create_texture(&decoded, &entry.path, sampler, context)
+        });
+        match texture {
+            Ok(texture) => {
+                texture_store.replace_texture(entry.value, texture);
+                entry.state = LoadState::Loaded;
+                info!("Loaded texture {}", entry.path.display());
+            }
+            Err(e) => {
+                error!("{e:#}");
+                entry.state = LoadState::Failed(format!("{e:#}"));
+            }
+        }
+    }
+
+    // Textures still assigned to a material are kept, materials refer to
+    // them by id rather than by handle
+    pub fn unload_unused(
+        &mut self,
+        texture_store: &mut TextureStore,
+        material_store: &MaterialStore,
+    ) {
+        for id in self.models.unused() {
+            if let Some(entry) = self.models.remove(id) {
+                info!("Unloaded model {}", entry.path.display());
+            }
+        }
+        for id in self.textures.unused() {
+            let texture_id = self.textures.entries.get(id).unwrap().value;
+            if material_store.uses_texture(texture_id) {
+                continue;
+            }
+            if let Some(entry) = self.textures.remove(id) {
+                texture_store.remove_texture(texture_id);
+                info!("Unloaded texture {}", entry.path.display());
+            }
+        }
+    }
+
+    fn spawn_model(&mut self, id: Handle<AssetEntry<Model>>) {
+        let generation = self.models.start_load(id);
+        let path = self.models.entries.get(id).unwrap().path.clone();
+        self.pool.spawn(Box::new(move || {
+            Loaded::Model(id, generation, parse_model(&path))
+        }));
+    }
+
+    fn spawn_texture(&mut self, id: Handle<AssetEntry<TextureId>>, context: &Context<'_>) {
+        let generation = self.textures.start_load(id);
+        let path = self.textures.entries.get(id).unwrap().path.clone();
+        let features = context.device.features();
+        self.pool.spawn(Box::new(move || {
+            Loaded::Texture(id, generation, decode_texture(&path, features))
+        }));
+    }
+}
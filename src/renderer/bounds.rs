@@ -0,0 +1,208 @@
+use cgmath::{InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Vector3<f32>,
+    pub max: Vector3<f32>,
+}
+
+#[allow(dead_code)]
+impl Aabb {
+    pub fn from_points(points: impl IntoIterator<Item = Vector3<f32>>) -> Option<Self> {
+        let mut points = points.into_iter();
+        let first = points.next()?;
+        Some(points.fold(
+            Self {
+                min: first,
+                max: first,
+            },
+            |aabb, point| Self {
+                min: min(aabb.min, point),
+                max: max(aabb.max, point),
+            },
+        ))
+    }
+
+    pub fn union(&self, other: &Self) -> Self {
+        Self {
+            min: min(self.min, other.min),
+            max: max(self.max, other.max),
+        }
+    }
+
+    pub fn center(&self) -> Vector3<f32> {
+        (self.min + self.max) * 0.5
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    // Box enclosing the transformed box (Arvo's method)
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        let center = (matrix * self.center().extend(1.0)).truncate();
+        let half = self.half_extents();
+        let extents = Vector3::new(
+            matrix.x.x.abs() * half.x + matrix.y.x.abs() * half.y + matrix.z.x.abs() * half.z,
+            matrix.x.y.abs() * half.x + matrix.y.y.abs() * half.y + matrix.z.y.abs() * half.z,
+            matrix.x.z.abs() * half.x + matrix.y.z.abs() * half.y + matrix.z.z.abs() * half.z,
+        );
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BoundingSphere {
+    pub center: Vector3<f32>,
+    pub radius: f32,
+}
+
+#[allow(dead_code)]
+impl BoundingSphere {
+    // Sphere around the box center reaching the farthest point
+    pub fn from_points(points: &[Vector3<f32>], aabb: &Aabb) -> Self {
+        let center = aabb.center();
+        let radius = points
+            .iter()
+            .map(|point| (point - center).magnitude2())
+            .fold(0.0, f32::max)
+            .sqrt();
+        Self { center, radius }
+    }
+
+    pub fn transformed(&self, matrix: &Matrix4<f32>) -> Self {
+        let scale = matrix
+            .x
+            .truncate()
+            .magnitude2()
+            .max(matrix.y.truncate().magnitude2())
+            .max(matrix.z.truncate().magnitude2())
+            .sqrt();
+        Self {
+            center: (matrix * self.center.extend(1.0)).truncate(),
+            radius: self.radius * scale,
+        }
+    }
+}
+
+// Planes point inwards, a point is inside when `dot(plane.xyz, p) + plane.w >= 0`
+// for all of them
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    // Gribb-Hartmann extraction for a wgpu style projection with depth in 0..1
+    pub fn from_view_projection(matrix: &Matrix4<f32>) -> Self {
+        let row = |i: usize| Vector4::new(matrix.x[i], matrix.y[i], matrix.z[i], matrix.w[i]);
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2]
+            .map(|plane| plane / plane.truncate().magnitude());
+        Self { planes }
+    }
+
+    pub fn planes(&self) -> &[Vector4<f32>; 6] {
+        &self.planes
+    }
+
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    // Tests the corner farthest along each plane normal
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        self.planes.iter().all(|plane| {
+            let pick = |normal: f32, min: f32, max: f32| if normal >= 0.0 { max } else { min };
+            let corner = Vector3::new(
+                pick(plane.x, aabb.min.x, aabb.max.x),
+                pick(plane.y, aabb.min.y, aabb.max.y),
+                pick(plane.z, aabb.min.z, aabb.max.z),
+            );
+            plane.truncate().dot(corner) + plane.w >= 0.0
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Ray {
+    pub origin: Vector3<f32>,
+    // Unit length
+    pub direction: Vector3<f32>,
+}
+
+impl Ray {
+    // Through a pixel of the window, from the near plane outwards
+    pub fn from_screen(
+        view_projection: &Matrix4<f32>,
+        pixel: [f32; 2],
+        size: [u32; 2],
+    ) -> Option<Self> {
+        let inverse = view_projection.invert()?;
+        let x = pixel[0] / size[0] as f32 * 2.0 - 1.0;
+        let y = 1.0 - pixel[1] / size[1] as f32 * 2.0;
+        let unproject = |z: f32| {
+            let point = inverse * Vector4::new(x, y, z, 1.0);
+            point.truncate() / point.w
+        };
+        let near = unproject(0.0);
+        let far = unproject(1.0);
+        Some(Self {
+            origin: near,
+            direction: (far - near).normalize(),
+        })
+    }
+
+    pub fn at(&self, t: f32) -> Vector3<f32> {
+        self.origin + self.direction * t
+    }
+
+    // Distance along the ray to the plane, if it hits it in front
+    pub fn intersect_plane(&self, point: Vector3<f32>, normal: Vector3<f32>) -> Option<f32> {
+        let denominator = self.direction.dot(normal);
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        let t = (point - self.origin).dot(normal) / denominator;
+        (t >= 0.0).then_some(t)
+    }
+
+    // Parameters of the closest points on the ray and on the line through
+    // `point` along `direction`, in that order
+    pub fn closest_to_line(
+        &self,
+        point: Vector3<f32>,
+        direction: Vector3<f32>,
+    ) -> Option<(f32, f32)> {
+        let offset = self.origin - point;
+        let b = self.direction.dot(direction);
+        let c = direction.dot(direction);
+        let d = self.direction.dot(offset);
+        let e = direction.dot(offset);
+        let denominator = c - b * b;
+        if denominator.abs() < 1e-6 {
+            return None;
+        }
+        Some(((b * e - c * d) / denominator, (e - b * d) / denominator))
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CullingStats {
+    pub visible: u32,
+    pub culled: u32,
+}
+
+fn min(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z))
+}
+
+fn max(a: Vector3<f32>, b: Vector3<f32>) -> Vector3<f32> {
+    Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z))
+}
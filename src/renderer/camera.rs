@@ -1,3 +1,5 @@
+use super::bounds::Frustum;
+
 use cgmath::{
     perspective, Deg, InnerSpace, Matrix4, Point3, Quaternion, Rad, Rotation3, SquareMatrix,
     Vector3,
@@ -73,12 +75,12 @@ impl Camera {
         });
 
         Self {
-            eye: eye,
-            uniform: uniform,
-            controller: controller,
-            bind_group_layout: bind_group_layout,
-            bind_group: bind_group,
-            buffer: buffer,
+            eye,
+            uniform,
+            controller,
+            bind_group_layout,
+            bind_group,
+            buffer,
         }
     }
 
@@ -101,19 +103,25 @@ pub struct CameraEye {
 }
 
 impl CameraEye {
-    fn build_view_projection_matrix(&self) -> Matrix4<f32> {
+    pub fn build_view_projection_matrix(&self) -> Matrix4<f32> {
         let forward = self.orientation * -Vector3::unit_z();
         let view = Matrix4::look_at_rh(self.position, self.position + forward, self.up);
         let proj = perspective(Deg(self.fov), self.aspect, self.near, self.far);
 
         OPENGL_TO_WGPU_MATRIX * proj * view
     }
+
+    pub fn frustum(&self) -> Frustum {
+        Frustum::from_view_projection(&self.build_view_projection_matrix())
+    }
 }
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct CameraUniform {
     view_proj: [[f32; 4]; 4],
+    inverse_view_proj: [[f32; 4]; 4],
+    position: [f32; 4],
 }
 
 impl CameraUniform {
@@ -121,12 +129,16 @@ impl CameraUniform {
         use SquareMatrix;
         Self {
             view_proj: Matrix4::identity().into(),
+            inverse_view_proj: Matrix4::identity().into(),
+            position: [0.0; 4],
         }
     }
 
     pub fn update_view_proj(&mut self, camera: &CameraEye) {
         let proj = camera.build_view_projection_matrix();
         self.view_proj = proj.into();
+        self.inverse_view_proj = proj.invert().unwrap_or(Matrix4::identity()).into();
+        self.position = camera.position.to_homogeneous().into();
     }
 }
 
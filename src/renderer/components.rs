@@ -0,0 +1,229 @@
+use std::collections::HashSet;
+
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Point3, Quaternion, Rotation3, Vector3};
+
+use super::camera::CameraEye;
+use super::debug_draw::debug_draw;
+use super::ecs::{Entity, World};
+use super::material::MaterialId;
+use super::object::{Object, ObjectId, ObjectManager, Transform};
+
+// Meshes, lights and cameras are entities with a `Transform`. For meshes it
+// is relative to the parent object, lights and cameras sit in world space.
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Name(pub String);
+
+// The object drawn for an entity. GPU resources stay with the object manager.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MeshInstance {
+    pub object: ObjectId,
+}
+
+// One per submodel of the mesh, in model order
+#[derive(Clone, Debug, PartialEq)]
+pub struct Materials(pub Vec<MaterialId>);
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    // Shines along the transform's -Z
+    Directional,
+    Point { range: f32 },
+    // Half angle of the cone in degrees
+    Spot { range: f32, angle: f32 },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Light {
+    pub kind: LightKind,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+// Takes over the view whenever it or its transform changes while active
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraComponent {
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+    pub active: bool,
+}
+
+// Turns the transform around an axis at a constant rate
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Spin {
+    pub axis: Vector3<f32>,
+    pub degrees_per_second: f32,
+}
+
+// Resource updated by the renderer before the schedule runs
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Time {
+    // Seconds
+    pub delta: f32,
+    pub elapsed: f32,
+}
+
+pub fn spin_system(world: &mut World) {
+    let delta = world.resource::<Time>().map_or(0.0, |time| time.delta);
+    let spins: Vec<(Entity, Spin)> = world
+        .query::<Spin>()
+        .map(|(entity, spin)| (entity, *spin))
+        .collect();
+    for (entity, spin) in spins {
+        if spin.axis.magnitude2() == 0.0 {
+            continue;
+        }
+        if let Some(transform) = world.get_mut::<Transform>(entity) {
+            let step = Quaternion::from_axis_angle(
+                spin.axis.normalize(),
+                Deg(spin.degrees_per_second * delta),
+            );
+            transform.rotation = (step * transform.rotation).normalize();
+        }
+    }
+}
+
+pub fn spawn_object(world: &mut World, id: ObjectId, object: &Object) -> Entity {
+    let entity = world.spawn();
+    world.insert(entity, Name(object.name()));
+    world.insert(entity, object.local_transform());
+    world.insert(entity, MeshInstance { object: id });
+    world.insert(entity, Materials(material_ids(object)));
+    entity
+}
+
+fn material_ids(object: &Object) -> Vec<MaterialId> {
+    object
+        .model()
+        .data
+        .iter()
+        .map(|submodel| submodel.material_id)
+        .collect()
+}
+
+// Brings the world up to date with objects added, removed or edited
+// outside of it, by the editor and gizmo for example. Runs before the
+// schedule so systems see the objects as they are drawn.
+pub fn sync_objects(world: &mut World, object_manager: &ObjectManager) {
+    let links: Vec<(Entity, ObjectId)> = world
+        .query::<MeshInstance>()
+        .map(|(entity, mesh)| (entity, mesh.object))
+        .collect();
+    let mut mirrored = HashSet::new();
+    for (entity, id) in links {
+        if !object_manager.contains(id) {
+            world.despawn(entity);
+            continue;
+        }
+        let Some(object) = object_manager.get(id) else {
+            continue;
+        };
+        mirrored.insert(id);
+        if let Some(transform) = world.get_mut_untracked::<Transform>(entity) {
+            *transform = object.local_transform();
+        }
+        if let Some(materials) = world.get_mut_untracked::<Materials>(entity) {
+            materials.0 = material_ids(object);
+        }
+    }
+
+    for (id, object) in object_manager.iter_with_ids() {
+        if !object_manager.is_static(id) && !mirrored.contains(&id) {
+            spawn_object(world, id, object);
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExtractedLight {
+    pub kind: LightKind,
+    pub position: Vector3<f32>,
+    pub direction: Vector3<f32>,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl ExtractedLight {
+    // A marker at the light and a line along its direction or out to its range
+    pub fn draw(&self) {
+        let color = [self.color[0], self.color[1], self.color[2], 1.0];
+        let mut draw = debug_draw();
+        match self.kind {
+            LightKind::Directional => {
+                draw.arrow(self.position, self.position + self.direction, color);
+            }
+            LightKind::Point { range } => {
+                draw.sphere(self.position, range, color);
+            }
+            LightKind::Spot { range, .. } => {
+                draw.arrow(self.position, self.position + self.direction * range, color);
+            }
+        }
+        draw.sphere(self.position, 0.1, color).depth_test(false);
+    }
+}
+
+// Copies what the renderer needs out of the world once the systems ran.
+// Only components changed since the last extraction are written back, so
+// objects edited outside the world keep their edits.
+#[derive(Default)]
+pub struct Extractor {
+    // Tick of the last extraction
+    seen: u64,
+    pub lights: Vec<ExtractedLight>,
+}
+
+impl Extractor {
+    pub fn extract(
+        &mut self,
+        world: &mut World,
+        object_manager: &mut ObjectManager,
+        eye: &mut CameraEye,
+    ) {
+        for (entity, transform) in world.changed::<Transform>(self.seen) {
+            let Some(mesh) = world.get::<MeshInstance>(entity) else {
+                continue;
+            };
+            if let Some(object) = object_manager.get_mut(mesh.object) {
+                object.set_local_transform(*transform);
+            }
+        }
+        for (entity, materials) in world.changed::<Materials>(self.seen) {
+            let Some(mesh) = world.get::<MeshInstance>(entity) else {
+                continue;
+            };
+            if let Some(object) = object_manager.get_mut(mesh.object) {
+                for (submodel, id) in object.model_mut().data.iter_mut().zip(&materials.0) {
+                    submodel.material_id = *id;
+                }
+            }
+        }
+
+        for (entity, camera, transform) in world.query2::<CameraComponent, Transform>() {
+            let changed = world.is_changed::<CameraComponent>(entity, self.seen)
+                || world.is_changed::<Transform>(entity, self.seen);
+            if camera.active && changed {
+                eye.position = Point3::from_vec(transform.position);
+                eye.orientation = transform.rotation;
+                eye.fov = camera.fov;
+                eye.near = camera.near;
+                eye.far = camera.far;
+            }
+        }
+
+        self.lights = world
+            .query2::<Light, Transform>()
+            .map(|(_, light, transform)| ExtractedLight {
+                kind: light.kind,
+                position: transform.position,
+                direction: transform.rotation * -Vector3::unit_z(),
+                color: light.color,
+                intensity: light.intensity,
+            })
+            .collect();
+
+        self.seen = world.advance_tick();
+    }
+}
@@ -3,11 +3,13 @@ use winit::window::Window;
 
 pub struct Context<'a> {
     pub surface: wgpu::Surface<'a>,
+    pub adapter: wgpu::Adapter,
     pub device: wgpu::Device,
     pub queue: wgpu::Queue,
     pub config: wgpu::SurfaceConfiguration,
 }
 
+#[allow(clippy::filter_next, clippy::redundant_field_names)]
 pub async fn init_wgpu(window: Arc<Window>) -> Context<'static> {
     let size = window.inner_size();
 
@@ -27,10 +29,22 @@ pub async fn init_wgpu(window: Arc<Window>) -> Context<'static> {
         .await
         .unwrap();
 
+    // Adapter specific format features report sample counts beyond the 1x/4x
+    // that WebGPU guarantees; compressed formats and indirect draw extensions
+    // are used when available, as is line rasterization for the wireframe view
+    let optional_features = wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES
+        | wgpu::Features::TEXTURE_COMPRESSION_BC
+        | wgpu::Features::TEXTURE_COMPRESSION_ETC2
+        | wgpu::Features::TEXTURE_COMPRESSION_ASTC
+        | wgpu::Features::TEXTURE_COMPRESSION_ASTC_HDR
+        | wgpu::Features::INDIRECT_FIRST_INSTANCE
+        | wgpu::Features::MULTI_DRAW_INDIRECT
+        | wgpu::Features::POLYGON_MODE_LINE;
+
     let (device, queue) = adapter
         .request_device(
             &wgpu::DeviceDescriptor {
-                required_features: wgpu::Features::empty(),
+                required_features: adapter.features() & optional_features,
                 required_limits: wgpu::Limits::default(),
                 memory_hints: wgpu::MemoryHints::Performance,
                 label: None,
@@ -64,8 +78,33 @@ pub async fn init_wgpu(window: Arc<Window>) -> Context<'static> {
 
     Context {
         surface: surface,
+        adapter,
         device: device,
         queue: queue,
         config: config,
     }
 }
+
+impl Context<'_> {
+    pub fn supports_sample_count(&self, format: wgpu::TextureFormat, sample_count: u32) -> bool {
+        if sample_count == 1 {
+            return true;
+        }
+
+        let features = if self
+            .device
+            .features()
+            .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+        {
+            self.adapter.get_texture_format_features(format)
+        } else {
+            format.guaranteed_format_features(self.device.features())
+        };
+
+        let resolvable = format.is_depth_stencil_format()
+            || features
+                .flags
+                .contains(wgpu::TextureFormatFeatureFlags::MULTISAMPLE_RESOLVE);
+        resolvable && features.flags.sample_count_supported(sample_count)
+    }
+}
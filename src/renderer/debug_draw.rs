@@ -0,0 +1,592 @@
+use std::f32::consts::TAU;
+use std::mem;
+use std::ops::Range;
+use std::sync::{Mutex, MutexGuard};
+
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, SquareMatrix, Vector3, Vector4};
+
+use super::{
+    bounds::Aabb, camera::CameraEye, context::Context, hdr::HDR_FORMAT, model::Vertex,
+    shader::ShaderStore, texture::Texture,
+};
+
+const INITIAL_VERTEX_CAPACITY: usize = 1 << 12;
+const CIRCLE_SEGMENTS: usize = 32;
+// Labels keep the same size on screen at any distance
+const TEXT_HEIGHT_PIXELS: f32 = 14.0;
+
+// Shapes queued from anywhere during a frame, drawn and aged by the renderer
+static DEBUG_DRAW: Mutex<DebugDraw> = Mutex::new(DebugDraw::new());
+
+pub fn debug_draw() -> MutexGuard<'static, DebugDraw> {
+    // A panic while drawing leaves nothing inconsistent in the queue
+    DEBUG_DRAW
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Lifetime {
+    // Drawn in this many rendered frames
+    Frames(u32),
+    Seconds(f32),
+}
+
+impl Default for Lifetime {
+    fn default() -> Self {
+        Self::Frames(1)
+    }
+}
+
+enum Shape {
+    Lines(Vec<[Vector3<f32>; 2]>),
+    // Depends on the camera, so it is turned into lines when drawn
+    Text {
+        position: Vector3<f32>,
+        text: String,
+    },
+}
+
+pub struct DebugCommand {
+    shape: Shape,
+    color: [f32; 4],
+    depth_test: bool,
+    lifetime: Lifetime,
+}
+
+#[allow(dead_code)]
+impl DebugCommand {
+    // Depth tested shapes are hidden behind the scene, the rest draw on top
+    pub fn depth_test(&mut self, depth_test: bool) -> &mut Self {
+        self.depth_test = depth_test;
+        self
+    }
+
+    pub fn lifetime(&mut self, lifetime: Lifetime) -> &mut Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    pub fn frames(&mut self, frames: u32) -> &mut Self {
+        self.lifetime(Lifetime::Frames(frames))
+    }
+
+    pub fn seconds(&mut self, seconds: f32) -> &mut Self {
+        self.lifetime(Lifetime::Seconds(seconds))
+    }
+}
+
+// Immediate mode lines, shapes and labels for debugging. Every call queues a
+// command that is drawn for one frame unless given a longer lifetime.
+//
+// debug_draw().aabb(&bounds, [1.0, 0.0, 0.0, 1.0]).depth_test(false).seconds(2.0);
+#[allow(dead_code)]
+pub struct DebugDraw {
+    commands: Vec<DebugCommand>,
+}
+
+#[allow(dead_code)]
+impl DebugDraw {
+    pub const fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+        }
+    }
+
+    fn push(&mut self, shape: Shape, color: [f32; 4]) -> &mut DebugCommand {
+        self.commands.push(DebugCommand {
+            shape,
+            color,
+            depth_test: true,
+            lifetime: Lifetime::default(),
+        });
+        self.commands.last_mut().unwrap()
+    }
+
+    pub fn line(&mut self, a: Vector3<f32>, b: Vector3<f32>, color: [f32; 4]) -> &mut DebugCommand {
+        self.push(Shape::Lines(vec![[a, b]]), color)
+    }
+
+    // Connected line segments through the points
+    pub fn polyline(&mut self, points: &[Vector3<f32>], color: [f32; 4]) -> &mut DebugCommand {
+        let lines = points.windows(2).map(|pair| [pair[0], pair[1]]).collect();
+        self.push(Shape::Lines(lines), color)
+    }
+
+    pub fn aabb(&mut self, aabb: &Aabb, color: [f32; 4]) -> &mut DebugCommand {
+        let corner = |i: usize| {
+            Vector3::new(
+                if i & 1 == 0 { aabb.min.x } else { aabb.max.x },
+                if i & 2 == 0 { aabb.min.y } else { aabb.max.y },
+                if i & 4 == 0 { aabb.min.z } else { aabb.max.z },
+            )
+        };
+        self.push(Shape::Lines(box_edges(corner)), color)
+    }
+
+    // Three great circles around the axes
+    pub fn sphere(
+        &mut self,
+        center: Vector3<f32>,
+        radius: f32,
+        color: [f32; 4],
+    ) -> &mut DebugCommand {
+        let mut lines = Vec::with_capacity(CIRCLE_SEGMENTS * 3);
+        for (u, v) in [
+            (Vector3::unit_x(), Vector3::unit_y()),
+            (Vector3::unit_y(), Vector3::unit_z()),
+            (Vector3::unit_z(), Vector3::unit_x()),
+        ] {
+            circle(&mut lines, center, u * radius, v * radius);
+        }
+        self.push(Shape::Lines(lines), color)
+    }
+
+    // Line with a four sided head at `to`, sized relative to its length
+    pub fn arrow(
+        &mut self,
+        from: Vector3<f32>,
+        to: Vector3<f32>,
+        color: [f32; 4],
+    ) -> &mut DebugCommand {
+        let direction = to - from;
+        let length = direction.magnitude();
+        let mut lines = vec![[from, to]];
+        if length > 0.0 {
+            let forward = direction / length;
+            let (side, up) = perpendicular_basis(forward);
+            let head = length * 0.15;
+            let base = to - forward * head;
+            for offset in [side, -side, up, -up] {
+                lines.push([to, base + offset * head * 0.4]);
+            }
+        }
+        self.push(Shape::Lines(lines), color)
+    }
+
+    // The X, Y and Z axes of a transform in red, green and blue
+    pub fn axes(&mut self, transform: &Matrix4<f32>, size: f32) {
+        let origin = transform.w.truncate();
+        for (axis, color) in [
+            (transform.x.truncate(), [1.0, 0.0, 0.0, 1.0]),
+            (transform.y.truncate(), [0.0, 1.0, 0.0, 1.0]),
+            (transform.z.truncate(), [0.0, 0.0, 1.0, 1.0]),
+        ] {
+            self.arrow(origin, origin + axis * size, color);
+        }
+    }
+
+    // Edges of the camera's view volume between its near and far planes
+    pub fn frustum(&mut self, eye: &CameraEye, color: [f32; 4]) -> &mut DebugCommand {
+        let inverse = eye
+            .build_view_projection_matrix()
+            .invert()
+            .unwrap_or(Matrix4::identity());
+        let corner = |i: usize| {
+            let ndc = Vector4::new(
+                if i & 1 == 0 { -1.0 } else { 1.0 },
+                if i & 2 == 0 { -1.0 } else { 1.0 },
+                if i & 4 == 0 { 0.0 } else { 1.0 },
+                1.0,
+            );
+            let world = inverse * ndc;
+            world.truncate() / world.w
+        };
+        self.push(Shape::Lines(box_edges(corner)), color)
+    }
+
+    // Text facing the camera, centered above `position`. Letters are drawn
+    // uppercase with a segment font covering digits and basic punctuation.
+    pub fn text(
+        &mut self,
+        position: Vector3<f32>,
+        text: &str,
+        color: [f32; 4],
+    ) -> &mut DebugCommand {
+        self.push(
+            Shape::Text {
+                position,
+                text: text.to_string(),
+            },
+            color,
+        )
+    }
+
+    pub fn clear(&mut self) {
+        self.commands.clear();
+    }
+
+    // Writes the lines of every queued command, depth tested ones first, and
+    // drops the commands whose lifetime ran out. Returns the number of depth
+    // tested vertices.
+    fn flush(
+        &mut self,
+        delta_time: f32,
+        eye: &CameraEye,
+        viewport_height: u32,
+        vertices: &mut Vec<LineVertex>,
+    ) -> usize {
+        vertices.clear();
+        let mut depth_tested = 0;
+        for depth_test in [true, false] {
+            for command in self.commands.iter().filter(|c| c.depth_test == depth_test) {
+                let mut emit = |[a, b]: [Vector3<f32>; 2]| {
+                    vertices.push(LineVertex::new(a, command.color));
+                    vertices.push(LineVertex::new(b, command.color));
+                };
+                match &command.shape {
+                    Shape::Lines(lines) => lines.iter().copied().for_each(&mut emit),
+                    Shape::Text { position, text } => {
+                        text_lines(*position, text, eye, viewport_height, &mut emit)
+                    }
+                }
+            }
+            if depth_test {
+                depth_tested = vertices.len();
+            }
+        }
+        self.age(delta_time);
+        depth_tested
+    }
+
+    fn age(&mut self, delta_time: f32) {
+        self.commands
+            .retain_mut(|command| match &mut command.lifetime {
+                Lifetime::Frames(frames) => {
+                    *frames = frames.saturating_sub(1);
+                    *frames > 0
+                }
+                Lifetime::Seconds(seconds) => {
+                    *seconds -= delta_time;
+                    *seconds > 0.0
+                }
+            });
+    }
+}
+
+// The 12 edges between 8 corners indexed by their x, y and z bits
+fn box_edges(corner: impl Fn(usize) -> Vector3<f32>) -> Vec<[Vector3<f32>; 2]> {
+    let mut lines = Vec::with_capacity(12);
+    for i in 0..8 {
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                lines.push([corner(i), corner(i | bit)]);
+            }
+        }
+    }
+    lines
+}
+
+fn circle(
+    lines: &mut Vec<[Vector3<f32>; 2]>,
+    center: Vector3<f32>,
+    u: Vector3<f32>,
+    v: Vector3<f32>,
+) {
+    let point = |i: usize| {
+        let angle = i as f32 / CIRCLE_SEGMENTS as f32 * TAU;
+        center + u * angle.cos() + v * angle.sin()
+    };
+    for i in 0..CIRCLE_SEGMENTS {
+        lines.push([point(i), point(i + 1)]);
+    }
+}
+
+fn perpendicular_basis(forward: Vector3<f32>) -> (Vector3<f32>, Vector3<f32>) {
+    let reference = if forward.y.abs() < 0.99 {
+        Vector3::unit_y()
+    } else {
+        Vector3::unit_x()
+    };
+    let side = forward.cross(reference).normalize();
+    (side, side.cross(forward))
+}
+
+// Segments of an 18 segment display in a cell two units wide and tall:
+// the outline split at its midpoints, the middle bar, the center verticals
+// and diagonals from the center and from the side midpoints to the bottom
+const SEGMENTS: [[(f32, f32); 2]; 18] = [
+    [(0.0, 2.0), (1.0, 2.0)], // top left
+    [(1.0, 2.0), (2.0, 2.0)], // top right
+    [(2.0, 2.0), (2.0, 1.0)], // right upper
+    [(2.0, 1.0), (2.0, 0.0)], // right lower
+    [(2.0, 0.0), (1.0, 0.0)], // bottom right
+    [(1.0, 0.0), (0.0, 0.0)], // bottom left
+    [(0.0, 0.0), (0.0, 1.0)], // left lower
+    [(0.0, 1.0), (0.0, 2.0)], // left upper
+    [(0.0, 1.0), (1.0, 1.0)], // middle left
+    [(1.0, 1.0), (2.0, 1.0)], // middle right
+    [(0.0, 2.0), (1.0, 1.0)], // diagonal upper left
+    [(1.0, 2.0), (1.0, 1.0)], // center upper
+    [(2.0, 2.0), (1.0, 1.0)], // diagonal upper right
+    [(1.0, 1.0), (0.0, 0.0)], // diagonal lower left
+    [(1.0, 1.0), (1.0, 0.0)], // center lower
+    [(1.0, 1.0), (2.0, 0.0)], // diagonal lower right
+    [(0.0, 1.0), (1.0, 0.0)], // side left to bottom
+    [(2.0, 1.0), (1.0, 0.0)], // side right to bottom
+];
+
+const fn segments(indices: &[usize]) -> u32 {
+    let mut mask = 0;
+    let mut i = 0;
+    while i < indices.len() {
+        mask |= 1 << indices[i];
+        i += 1;
+    }
+    mask
+}
+
+const OUTLINE: [usize; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+fn glyph(c: char) -> u32 {
+    match c.to_ascii_uppercase() {
+        '0' => segments(&OUTLINE) | segments(&[12, 13]),
+        '1' => segments(&[2, 3, 12]),
+        '2' => segments(&[0, 1, 2, 9, 8, 6, 5, 4]),
+        '3' => segments(&[0, 1, 2, 3, 4, 5, 9]),
+        '4' => segments(&[7, 8, 9, 2, 3]),
+        '5' => segments(&[0, 1, 7, 8, 9, 3, 4, 5]),
+        '6' => segments(&[0, 1, 7, 6, 5, 4, 3, 8, 9]),
+        '7' => segments(&[0, 1, 2, 3]),
+        '8' => segments(&OUTLINE) | segments(&[8, 9]),
+        '9' => segments(&[0, 1, 2, 3, 4, 5, 7, 8, 9]),
+        'A' => segments(&[0, 1, 2, 3, 6, 7, 8, 9]),
+        'B' => segments(&[0, 1, 2, 3, 4, 5, 11, 14, 9]),
+        'C' => segments(&[0, 1, 7, 6, 5, 4]),
+        'D' => segments(&[0, 1, 2, 3, 4, 5, 11, 14]),
+        'E' => segments(&[0, 1, 7, 6, 5, 4, 8]),
+        'F' => segments(&[0, 1, 7, 6, 8]),
+        'G' => segments(&[0, 1, 7, 6, 5, 4, 3, 9]),
+        'H' => segments(&[7, 6, 2, 3, 8, 9]),
+        'I' => segments(&[0, 1, 11, 14, 5, 4]),
+        'J' => segments(&[2, 3, 4, 5, 6]),
+        'K' => segments(&[7, 6, 8, 12, 15]),
+        'L' => segments(&[7, 6, 5, 4]),
+        'M' => segments(&[7, 6, 2, 3, 10, 12]),
+        'N' => segments(&[7, 6, 2, 3, 10, 15]),
+        'O' => segments(&OUTLINE),
+        'P' => segments(&[0, 1, 2, 7, 6, 8, 9]),
+        'Q' => segments(&OUTLINE) | segments(&[15]),
+        'R' => segments(&[0, 1, 2, 7, 6, 8, 9, 15]),
+        'S' => segments(&[0, 1, 7, 8, 9, 3, 4, 5]),
+        'T' => segments(&[0, 1, 11, 14]),
+        'U' => segments(&[7, 6, 5, 4, 3, 2]),
+        'V' => segments(&[7, 2, 16, 17]),
+        'W' => segments(&[7, 6, 2, 3, 13, 15]),
+        'X' => segments(&[10, 12, 13, 15]),
+        'Y' => segments(&[10, 12, 14]),
+        'Z' => segments(&[0, 1, 12, 13, 5, 4]),
+        '-' => segments(&[8, 9]),
+        '+' => segments(&[8, 9, 11, 14]),
+        '=' => segments(&[8, 9, 5, 4]),
+        '_' => segments(&[5, 4]),
+        '/' => segments(&[12, 13]),
+        '\\' => segments(&[10, 15]),
+        '*' => segments(&[8, 9, 10, 11, 12, 13, 14, 15]),
+        '.' | ',' => segments(&[5]),
+        ':' | '|' => segments(&[11, 14]),
+        '(' | '[' => segments(&[1, 11, 14, 4]),
+        ')' | ']' => segments(&[0, 11, 14, 5]),
+        _ => 0,
+    }
+}
+
+// Lines of a camera facing label, scaled to a constant height in pixels
+fn text_lines(
+    position: Vector3<f32>,
+    text: &str,
+    eye: &CameraEye,
+    viewport_height: u32,
+    mut emit: impl FnMut([Vector3<f32>; 2]),
+) {
+    let right = eye.orientation * Vector3::unit_x();
+    let up = eye.orientation * Vector3::unit_y();
+    let distance = (position - eye.position.to_vec()).magnitude();
+    let pixel = 2.0 * distance * (eye.fov.to_radians() * 0.5).tan() / viewport_height.max(1) as f32;
+    // A glyph is `unit` wide and twice that tall
+    let unit = pixel * TEXT_HEIGHT_PIXELS * 0.5;
+    let advance = unit * 1.5;
+    let width = (text.chars().count().max(1) - 1) as f32 * advance + unit;
+    let origin = position - right * (width * 0.5);
+
+    for (i, c) in text.chars().enumerate() {
+        let mask = glyph(c);
+        let cell = origin + right * (i as f32 * advance);
+        let point = |(x, y): (f32, f32)| cell + right * (x * unit * 0.5) + up * (y * unit);
+        for (segment, [a, b]) in SEGMENTS.iter().enumerate() {
+            if mask & (1 << segment) != 0 {
+                emit([point(*a), point(*b)]);
+            }
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct LineVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl LineVertex {
+    fn new(position: Vector3<f32>, color: [f32; 4]) -> Self {
+        Self {
+            position: position.into(),
+            color,
+        }
+    }
+}
+
+impl Vertex for LineVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+            wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// GPU side of the debug draw queue, refilled every frame
+pub struct DebugDrawBuffer {
+    vertices: Vec<LineVertex>,
+    vertex_buffer: wgpu::Buffer,
+    capacity: usize,
+    depth_tested: Range<u32>,
+    on_top: Range<u32>,
+}
+
+impl DebugDrawBuffer {
+    pub fn new(context: &Context) -> Self {
+        Self {
+            vertices: Vec::new(),
+            vertex_buffer: Self::create_vertex_buffer(&context.device, INITIAL_VERTEX_CAPACITY),
+            capacity: INITIAL_VERTEX_CAPACITY,
+            depth_tested: 0..0,
+            on_top: 0..0,
+        }
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Debug Draw Vertex Buffer"),
+            size: (capacity * mem::size_of::<LineVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Takes this frame's lines out of the global queue
+    pub fn update(&mut self, context: &Context, delta_time: f32, eye: &CameraEye) {
+        let depth_tested =
+            debug_draw().flush(delta_time, eye, context.config.height, &mut self.vertices);
+        let count = self.vertices.len();
+        self.depth_tested = 0..depth_tested as u32;
+        self.on_top = depth_tested as u32..count as u32;
+        if count == 0 {
+            return;
+        }
+        if count > self.capacity {
+            self.capacity = count.next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(&context.device, self.capacity);
+        }
+        context
+            .queue
+            .write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.on_top.end == 0
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        pipelines: &'a DebugDrawPipelines,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.is_empty() {
+            return;
+        }
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        for (pipeline, vertices) in [
+            (&pipelines.depth_tested, self.depth_tested.clone()),
+            (&pipelines.on_top, self.on_top.clone()),
+        ] {
+            if !vertices.is_empty() {
+                render_pass.set_pipeline(pipeline);
+                render_pass.draw(vertices, 0..1);
+            }
+        }
+    }
+}
+
+pub struct DebugDrawPipelines {
+    pub depth_tested: wgpu::RenderPipeline,
+    pub on_top: wgpu::RenderPipeline,
+}
+
+impl DebugDrawPipelines {
+    pub fn new(
+        context: &Context,
+        shader_store: &ShaderStore,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let device = &context.device;
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Draw Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let init = |depth_compare| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some("Debug Draw Pipeline"),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_store.debug_lines,
+                    entry_point: "vs_main",
+                    buffers: &[LineVertex::desc()],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_store.debug_lines,
+                    entry_point: "fs_main",
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: wgpu::PrimitiveTopology::LineList,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: false,
+                    depth_compare,
+                    stencil: wgpu::StencilState::default(),
+                    bias: wgpu::DepthBiasState::default(),
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+
+        Self {
+            depth_tested: init(wgpu::CompareFunction::LessEqual),
+            on_top: init(wgpu::CompareFunction::Always),
+        }
+    }
+}
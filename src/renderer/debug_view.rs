@@ -0,0 +1,438 @@
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+use winit::keyboard::KeyCode;
+
+use super::{
+    camera::CameraEye,
+    context::Context,
+    hdr::HDR_FORMAT,
+    model::{Mesh, ModelVertex, Vertex},
+    object::Object,
+    shader::ShaderStore,
+    texture::Texture,
+};
+
+// Replaces the material shading of regular objects
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DebugShading {
+    #[default]
+    Off,
+    // Checkerboard over the texture coordinates, tinted by their fraction
+    UvChecker,
+    // Linear distance from the camera between its near and far planes
+    Depth,
+    // A distinct color per object and submodel
+    SubmodelColor,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DebugViewSettings {
+    pub shading: DebugShading,
+    // Overlays drawn on top of the shaded scene
+    pub wireframe: bool,
+    pub normals: bool,
+    // Light positions and directions from the world
+    pub lights: bool,
+    pub wireframe_color: [f32; 4],
+    // In world units
+    pub normal_length: f32,
+    // Checker cells per UV unit
+    pub checker_scale: f32,
+}
+
+impl Default for DebugViewSettings {
+    fn default() -> Self {
+        Self {
+            shading: DebugShading::Off,
+            wireframe: false,
+            normals: false,
+            lights: false,
+            wireframe_color: [0.9, 0.9, 0.9, 1.0],
+            normal_length: 0.1,
+            checker_scale: 8.0,
+        }
+    }
+}
+
+impl DebugViewSettings {
+    // F1 and F2 toggle the overlays, F3 to F5 toggle the shading modes.
+    // Returns whether the key was used.
+    pub fn handle_key(&mut self, key: KeyCode) -> bool {
+        let mut toggle_shading = |shading| {
+            self.shading = if self.shading == shading {
+                DebugShading::Off
+            } else {
+                shading
+            };
+        };
+        match key {
+            KeyCode::F1 => self.wireframe = !self.wireframe,
+            KeyCode::F2 => self.normals = !self.normals,
+            KeyCode::F3 => toggle_shading(DebugShading::UvChecker),
+            KeyCode::F4 => toggle_shading(DebugShading::Depth),
+            KeyCode::F5 => toggle_shading(DebugShading::SubmodelColor),
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn has_overlay(&self) -> bool {
+        self.wireframe || self.normals
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct DebugUniform {
+    wireframe_color: [f32; 4],
+    near: f32,
+    far: f32,
+    normal_length: f32,
+    checker_scale: f32,
+}
+
+// Settings uniform and per mesh bind groups shared by the debug pipelines
+pub struct DebugViews {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+}
+
+impl DebugViews {
+    pub fn new(context: &Context) -> Self {
+        let device = &context.device;
+        let storage_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only: true },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1),
+                storage_entry(2),
+            ],
+            label: Some("debug_bind_group_layout"),
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Debug Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[DebugUniform {
+                wireframe_color: [1.0; 4],
+                near: 0.1,
+                far: 100.0,
+                normal_length: 0.1,
+                checker_scale: 8.0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        Self {
+            bind_group_layout,
+            uniform_buffer,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, settings: &DebugViewSettings, eye: &CameraEye) {
+        let uniform = DebugUniform {
+            wireframe_color: settings.wireframe_color,
+            near: eye.near,
+            far: eye.far,
+            normal_length: settings.normal_length,
+            checker_scale: settings.checker_scale,
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // One bind group per submodel of each object, created every frame since
+    // the debug views are rarely on
+    pub fn bind_groups(
+        &self,
+        device: &wgpu::Device,
+        objects: &[&Object],
+    ) -> Vec<Vec<wgpu::BindGroup>> {
+        objects
+            .iter()
+            .map(|object| {
+                object
+                    .model()
+                    .data
+                    .iter()
+                    .map(|data| self.mesh_bind_group(device, &data.mesh))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn mesh_bind_group(&self, device: &wgpu::Device, mesh: &Mesh) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: mesh.vertex_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: mesh.index_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("debug_bind_group"),
+        })
+    }
+}
+
+// Pipelines of the debug views, laid out as camera, debug and object groups
+pub struct DebugPipelines {
+    pub uv_checker: wgpu::RenderPipeline,
+    pub depth: wgpu::RenderPipeline,
+    pub submodel_color: wgpu::RenderPipeline,
+    pub wireframe: wgpu::RenderPipeline,
+    // Without line polygon mode the wireframe is drawn from barycentric
+    // coordinates, pulling vertices from the mesh buffers
+    pub line_polygon_mode: bool,
+    pub normals: wgpu::RenderPipeline,
+}
+
+struct DebugPipelineDesc<'a> {
+    label: &'a str,
+    vertex_entry_point: &'a str,
+    fragment_entry_point: &'a str,
+    vertex_buffers: &'a [wgpu::VertexBufferLayout<'a>],
+    topology: wgpu::PrimitiveTopology,
+    polygon_mode: wgpu::PolygonMode,
+    // Overlays test against the scene depth without writing it
+    overlay: bool,
+}
+
+impl DebugPipelines {
+    pub fn new(
+        context: &Context,
+        shader_store: &ShaderStore,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        sample_count: u32,
+    ) -> Self {
+        let device = &context.device;
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Debug Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
+        });
+        let init = |desc: DebugPipelineDesc| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(desc.label),
+                layout: Some(&layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_store.debug,
+                    entry_point: desc.vertex_entry_point,
+                    buffers: desc.vertex_buffers,
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_store.debug,
+                    entry_point: desc.fragment_entry_point,
+                    targets: &[Some(wgpu::ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend: desc.overlay.then_some(wgpu::BlendState::ALPHA_BLENDING),
+                        write_mask: wgpu::ColorWrites::ALL,
+                    })],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState {
+                    topology: desc.topology,
+                    polygon_mode: desc.polygon_mode,
+                    ..Default::default()
+                },
+                depth_stencil: Some(wgpu::DepthStencilState {
+                    format: Texture::DEPTH_FORMAT,
+                    depth_write_enabled: !desc.overlay,
+                    depth_compare: if desc.overlay {
+                        wgpu::CompareFunction::LessEqual
+                    } else {
+                        wgpu::CompareFunction::Less
+                    },
+                    stencil: wgpu::StencilState::default(),
+                    // Pulls rasterized lines in front of the faces they outline
+                    bias: if desc.polygon_mode == wgpu::PolygonMode::Line {
+                        wgpu::DepthBiasState {
+                            constant: -2,
+                            slope_scale: -1.0,
+                            clamp: 0.0,
+                        }
+                    } else {
+                        wgpu::DepthBiasState::default()
+                    },
+                }),
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+                cache: None,
+            })
+        };
+        let shading = |label, fragment_entry_point| {
+            init(DebugPipelineDesc {
+                label,
+                vertex_entry_point: "vs_main",
+                fragment_entry_point,
+                vertex_buffers: &[ModelVertex::desc()],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                overlay: false,
+            })
+        };
+
+        let line_polygon_mode = device
+            .features()
+            .contains(wgpu::Features::POLYGON_MODE_LINE);
+        let wireframe = if line_polygon_mode {
+            init(DebugPipelineDesc {
+                label: "Wireframe Pipeline",
+                vertex_entry_point: "vs_main",
+                fragment_entry_point: "fs_wireframe",
+                vertex_buffers: &[ModelVertex::desc()],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                polygon_mode: wgpu::PolygonMode::Line,
+                overlay: true,
+            })
+        } else {
+            init(DebugPipelineDesc {
+                label: "Wireframe Pipeline",
+                vertex_entry_point: "vs_wireframe_barycentric",
+                fragment_entry_point: "fs_wireframe_barycentric",
+                vertex_buffers: &[],
+                topology: wgpu::PrimitiveTopology::TriangleList,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                overlay: true,
+            })
+        };
+
+        Self {
+            uv_checker: shading("UV Checker Pipeline", "fs_uv_checker"),
+            depth: shading("Depth View Pipeline", "fs_depth"),
+            submodel_color: shading("Submodel Color Pipeline", "fs_false_color"),
+            wireframe,
+            line_polygon_mode,
+            normals: init(DebugPipelineDesc {
+                label: "Normals Pipeline",
+                vertex_entry_point: "vs_normals",
+                fragment_entry_point: "fs_normals",
+                vertex_buffers: &[],
+                topology: wgpu::PrimitiveTopology::LineList,
+                polygon_mode: wgpu::PolygonMode::Fill,
+                overlay: true,
+            }),
+        }
+    }
+
+    pub fn shading(&self, shading: DebugShading) -> Option<&wgpu::RenderPipeline> {
+        match shading {
+            DebugShading::Off => None,
+            DebugShading::UvChecker => Some(&self.uv_checker),
+            DebugShading::Depth => Some(&self.depth),
+            DebugShading::SubmodelColor => Some(&self.submodel_color),
+        }
+    }
+}
+
+pub trait DrawDebug<'a> {
+    // Draws every submodel at its current level of detail with a shading
+    // pipeline, passing `object_index` and the submodel index as the instance
+    fn draw_debug_shading(
+        &mut self,
+        pipeline: &'a wgpu::RenderPipeline,
+        object: &'a Object,
+        object_index: u32,
+        bind_groups: &'a [wgpu::BindGroup],
+    );
+    fn draw_debug_overlay(
+        &mut self,
+        pipelines: &'a DebugPipelines,
+        settings: &DebugViewSettings,
+        object: &'a Object,
+        bind_groups: &'a [wgpu::BindGroup],
+    );
+}
+
+impl<'a, 'b> DrawDebug<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_debug_shading(
+        &mut self,
+        pipeline: &'b wgpu::RenderPipeline,
+        object: &'b Object,
+        object_index: u32,
+        bind_groups: &'b [wgpu::BindGroup],
+    ) {
+        self.set_pipeline(pipeline);
+        self.set_bind_group(2, object.bind_group(), &[]);
+        for (index, (data, bind_group)) in object.model().data.iter().zip(bind_groups).enumerate() {
+            let mesh = &data.mesh;
+            let lod = &mesh.lods[object.lod().level.min(mesh.lods.len() - 1)];
+            let id = object_index << 8 | index as u32;
+            self.set_bind_group(1, bind_group, &[]);
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            self.draw_indexed(
+                lod.first_index..lod.first_index + lod.index_count,
+                lod.base_vertex,
+                id..id + 1,
+            );
+        }
+    }
+
+    fn draw_debug_overlay(
+        &mut self,
+        pipelines: &'b DebugPipelines,
+        settings: &DebugViewSettings,
+        object: &'b Object,
+        bind_groups: &'b [wgpu::BindGroup],
+    ) {
+        self.set_bind_group(2, object.bind_group(), &[]);
+        for (data, bind_group) in object.model().data.iter().zip(bind_groups) {
+            let mesh = &data.mesh;
+            let lod = &mesh.lods[object.lod().level.min(mesh.lods.len() - 1)];
+            let indices = lod.first_index..lod.first_index + lod.index_count;
+            self.set_bind_group(1, bind_group, &[]);
+
+            if settings.wireframe {
+                self.set_pipeline(&pipelines.wireframe);
+                if pipelines.line_polygon_mode {
+                    self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                    self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                    self.draw_indexed(indices, lod.base_vertex, 0..1);
+                } else {
+                    // The instance carries the base vertex to the shader
+                    let base_vertex = lod.base_vertex as u32;
+                    self.draw(indices, base_vertex..base_vertex + 1);
+                }
+            }
+            if settings.normals {
+                let vertex_count =
+                    (mesh.vertex_buffer.size() / std::mem::size_of::<ModelVertex>() as u64) as u32;
+                self.set_pipeline(&pipelines.normals);
+                self.draw(0..vertex_count * 2, 0..1);
+            }
+        }
+    }
+}
@@ -0,0 +1,258 @@
+use std::any::{Any, TypeId};
+use std::collections::{BTreeMap, HashMap};
+
+use super::arena::{Arena, Handle};
+
+pub struct EntityMarker;
+
+pub type Entity = Handle<EntityMarker>;
+
+// Any 'static type can be a component. Each type gets its own storage, kept
+// in entity order so systems visit entities the same way every frame.
+struct Stored<T> {
+    value: T,
+    // Tick of the last mutable access
+    changed: u64,
+}
+
+trait Storage: Any {
+    fn remove_entity(&mut self, entity: Entity);
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: 'static> Storage for BTreeMap<Entity, Stored<T>> {
+    fn remove_entity(&mut self, entity: Entity) {
+        self.remove(&entity);
+    }
+
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+// Entities with their components plus resources, single values shared by
+// all systems such as the frame time
+pub struct World {
+    entities: Arena<EntityMarker>,
+    storages: HashMap<TypeId, Box<dyn Storage>>,
+    resources: HashMap<TypeId, Box<dyn Any>>,
+    tick: u64,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self {
+            entities: Arena::new(),
+            storages: HashMap::new(),
+            resources: HashMap::new(),
+            // Zero is left for "never seen", see `changed`
+            tick: 1,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl World {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spawn(&mut self) -> Entity {
+        self.entities.insert(EntityMarker)
+    }
+
+    // Drops the entity's components, returning whether it was alive
+    pub fn despawn(&mut self, entity: Entity) -> bool {
+        if self.entities.remove(entity).is_none() {
+            return false;
+        }
+        for storage in self.storages.values_mut() {
+            storage.remove_entity(entity);
+        }
+        true
+    }
+
+    // Removes every entity but keeps the resources
+    pub fn clear(&mut self) {
+        self.entities.clear();
+        self.storages.clear();
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.entities.contains(entity)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entities.is_empty()
+    }
+
+    pub fn entities(&self) -> impl Iterator<Item = Entity> + '_ {
+        self.entities.iter().map(|(entity, _)| entity)
+    }
+
+    // Replaces a component of the same type, returning whether the entity
+    // was alive to receive it
+    pub fn insert<T: 'static>(&mut self, entity: Entity, component: T) -> bool {
+        if !self.entities.contains(entity) {
+            return false;
+        }
+        let tick = self.tick;
+        self.storage_mut::<T>().insert(
+            entity,
+            Stored {
+                value: component,
+                changed: tick,
+            },
+        );
+        true
+    }
+
+    pub fn remove<T: 'static>(&mut self, entity: Entity) -> Option<T> {
+        let stored = self.storage_mut::<T>().remove(&entity)?;
+        Some(stored.value)
+    }
+
+    pub fn has<T: 'static>(&self, entity: Entity) -> bool {
+        self.storage::<T>()
+            .is_some_and(|storage| storage.contains_key(&entity))
+    }
+
+    pub fn get<T: 'static>(&self, entity: Entity) -> Option<&T> {
+        Some(&self.storage::<T>()?.get(&entity)?.value)
+    }
+
+    // Marks the component as changed whether or not it is written to
+    pub fn get_mut<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        let tick = self.tick;
+        let stored = self.storage_mut::<T>().get_mut(&entity)?;
+        stored.changed = tick;
+        Some(&mut stored.value)
+    }
+
+    // For writes that mirror state from elsewhere and should not be picked
+    // up as changes
+    pub fn get_mut_untracked<T: 'static>(&mut self, entity: Entity) -> Option<&mut T> {
+        Some(&mut self.storage_mut::<T>().get_mut(&entity)?.value)
+    }
+
+    pub fn query<T: 'static>(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.storage::<T>()
+            .into_iter()
+            .flatten()
+            .map(|(entity, stored)| (*entity, &stored.value))
+    }
+
+    // Marks every visited component as changed
+    pub fn query_mut<T: 'static>(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        let tick = self.tick;
+        self.storage_mut::<T>()
+            .iter_mut()
+            .map(move |(entity, stored)| {
+                stored.changed = tick;
+                (*entity, &mut stored.value)
+            })
+    }
+
+    // Entities with both components
+    pub fn query2<A: 'static, B: 'static>(&self) -> impl Iterator<Item = (Entity, &A, &B)> {
+        let others = self.storage::<B>();
+        self.query::<A>().filter_map(move |(entity, a)| {
+            let b = &others?.get(&entity)?.value;
+            Some((entity, a, b))
+        })
+    }
+
+    // Components changed after `since`, a tick returned by `advance_tick`
+    pub fn changed<T: 'static>(&self, since: u64) -> impl Iterator<Item = (Entity, &T)> {
+        self.storage::<T>()
+            .into_iter()
+            .flatten()
+            .filter(move |(_, stored)| stored.changed > since)
+            .map(|(entity, stored)| (*entity, &stored.value))
+    }
+
+    pub fn is_changed<T: 'static>(&self, entity: Entity, since: u64) -> bool {
+        self.storage::<T>()
+            .and_then(|storage| storage.get(&entity))
+            .is_some_and(|stored| stored.changed > since)
+    }
+
+    // Ends the current tick, returning it. Changes made from here on are
+    // newer than the returned value.
+    pub fn advance_tick(&mut self) -> u64 {
+        self.tick += 1;
+        self.tick - 1
+    }
+
+    pub fn insert_resource<R: 'static>(&mut self, resource: R) {
+        self.resources.insert(TypeId::of::<R>(), Box::new(resource));
+    }
+
+    pub fn resource<R: 'static>(&self) -> Option<&R> {
+        self.resources.get(&TypeId::of::<R>())?.downcast_ref()
+    }
+
+    pub fn resource_mut<R: 'static>(&mut self) -> Option<&mut R> {
+        self.resources.get_mut(&TypeId::of::<R>())?.downcast_mut()
+    }
+
+    fn storage<T: 'static>(&self) -> Option<&BTreeMap<Entity, Stored<T>>> {
+        self.storages
+            .get(&TypeId::of::<T>())?
+            .as_any()
+            .downcast_ref()
+    }
+
+    fn storage_mut<T: 'static>(&mut self) -> &mut BTreeMap<Entity, Stored<T>> {
+        self.storages
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Box::new(BTreeMap::<Entity, Stored<T>>::new()))
+            .as_any_mut()
+            .downcast_mut()
+            .expect("component storage registered under another type")
+    }
+}
+
+pub type System = Box<dyn FnMut(&mut World)>;
+
+// Systems run in the order they were added, once per frame
+#[derive(Default)]
+pub struct Schedule {
+    systems: Vec<(&'static str, System)>,
+}
+
+#[allow(dead_code)]
+impl Schedule {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_system(&mut self, name: &'static str, system: impl FnMut(&mut World) + 'static) {
+        self.systems.push((name, Box::new(system)));
+    }
+
+    pub fn remove_system(&mut self, name: &str) -> bool {
+        let len = self.systems.len();
+        self.systems.retain(|(system, _)| *system != name);
+        self.systems.len() != len
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.systems.iter().map(|(name, _)| *name)
+    }
+
+    pub fn run(&mut self, world: &mut World) {
+        for (_, system) in &mut self.systems {
+            system(world);
+        }
+    }
+}
@@ -0,0 +1,594 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use cgmath::{Deg, Euler, Quaternion, Rad, Vector3};
+use log::warn;
+use winit::event::WindowEvent;
+use winit::window::Window;
+
+use super::{
+    camera::CameraEye,
+    context::Context,
+    debug_view::{DebugShading, DebugViewSettings},
+    environment::{self, EnvironmentSettings},
+    gizmo::{GizmoMode, GizmoSettings, GizmoSpace},
+    gpu_driven::GpuCullingSettings,
+    hdr::{ExposureMode, ToneMapOperator, ToneMapSettings},
+    lod::LodSettings,
+    material::{BlendMode, MaterialId, MaterialStore},
+    object::{KeepTransform, ObjectId, ObjectManager},
+    post_process::{self, PostProcessSettings},
+    selection::{Selection, SelectionStyle},
+    texture::TextureStore,
+    transparency::TransparencyMode,
+};
+
+// Everything the editor panels can inspect and change, borrowed from the
+// renderer for the duration of one frame
+pub struct EditorScene<'a> {
+    pub object_manager: &'a mut ObjectManager,
+    pub material_store: &'a mut MaterialStore,
+    pub texture_store: &'a TextureStore,
+    pub eye: &'a mut CameraEye,
+    pub selection: &'a mut Selection,
+    pub settings: EditorSettings<'a>,
+}
+
+pub struct EditorSettings<'a> {
+    pub frustum_culling: &'a mut bool,
+    pub level_of_detail: &'a mut LodSettings,
+    pub gpu_culling: &'a mut GpuCullingSettings,
+    pub transparency: &'a mut TransparencyMode,
+    pub debug_view: &'a mut DebugViewSettings,
+    pub tone_mapping: &'a mut ToneMapSettings,
+    pub post_processing: &'a mut PostProcessSettings,
+    pub environment: &'a mut EnvironmentSettings,
+    pub show_fps: &'a mut bool,
+    pub selection_style: &'a mut SelectionStyle,
+    pub gizmo: &'a mut GizmoSettings,
+    // Applied by the renderer after the frame since it rebuilds the pipelines
+    pub msaa_samples: &'a mut u32,
+    // Loaded by the renderer after the frame, `None` clears the map
+    pub environment_map: &'a mut Option<PathBuf>,
+}
+
+// Immediate mode editor UI drawn over the finished frame
+pub struct Editor {
+    pub visible: bool,
+    context: egui::Context,
+    state: egui_winit::State,
+    renderer: egui_wgpu::Renderer,
+    window: Arc<Window>,
+    paint_jobs: Vec<egui::ClippedPrimitive>,
+    screen: egui_wgpu::ScreenDescriptor,
+    textures_to_free: Vec<egui::TextureId>,
+    resource_files: ResourceFiles,
+}
+
+// Files under `res` the settings panel offers, listed once at startup
+struct ResourceFiles {
+    environment_maps: Vec<PathBuf>,
+    luts: Vec<PathBuf>,
+}
+
+impl Editor {
+    pub fn new(context: &Context, window: Arc<Window>) -> Self {
+        let egui_context = egui::Context::default();
+        let state = egui_winit::State::new(
+            egui_context.clone(),
+            egui::ViewportId::ROOT,
+            &window,
+            Some(window.scale_factor() as f32),
+            None,
+            None,
+        );
+        let renderer =
+            egui_wgpu::Renderer::new(&context.device, context.config.format, None, 1, false);
+
+        Self {
+            visible: false,
+            context: egui_context,
+            state,
+            renderer,
+            window,
+            paint_jobs: Vec::new(),
+            screen: egui_wgpu::ScreenDescriptor {
+                size_in_pixels: [context.config.width, context.config.height],
+                pixels_per_point: 1.0,
+            },
+            textures_to_free: Vec::new(),
+            resource_files: ResourceFiles {
+                environment_maps: environment::available_maps(),
+                luts: post_process::available_luts(),
+            },
+        }
+    }
+
+    // Returns whether the UI used the event, in which case the scene should
+    // not react to it
+    pub fn handle_window_event(&mut self, event: &WindowEvent) -> bool {
+        if !self.visible {
+            return false;
+        }
+        self.state.on_window_event(&self.window, event).consumed
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+        self.paint_jobs.clear();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paint_jobs.is_empty()
+    }
+
+    // Runs the UI for this frame and uploads its textures and geometry
+    pub fn prepare(
+        &mut self,
+        context: &Context,
+        encoder: &mut wgpu::CommandEncoder,
+        mut scene: EditorScene,
+    ) {
+        self.paint_jobs.clear();
+        if !self.visible {
+            return;
+        }
+
+        let input = self.state.take_egui_input(&self.window);
+        let resource_files = &self.resource_files;
+        let output = self.context.run(input, |ctx| {
+            scene_panel(ctx, &mut scene, resource_files);
+        });
+        self.state
+            .handle_platform_output(&self.window, output.platform_output);
+
+        self.paint_jobs = self
+            .context
+            .tessellate(output.shapes, output.pixels_per_point);
+        self.screen = egui_wgpu::ScreenDescriptor {
+            size_in_pixels: [context.config.width, context.config.height],
+            pixels_per_point: output.pixels_per_point,
+        };
+        for (id, delta) in &output.textures_delta.set {
+            self.renderer
+                .update_texture(&context.device, &context.queue, *id, delta);
+        }
+        self.textures_to_free = output.textures_delta.free;
+
+        let command_buffers = self.renderer.update_buffers(
+            &context.device,
+            &context.queue,
+            encoder,
+            &self.paint_jobs,
+            &self.screen,
+        );
+        // Only user paint callbacks produce these, the editor has none
+        if !command_buffers.is_empty() {
+            context.queue.submit(command_buffers);
+        }
+    }
+
+    pub fn draw(&self, render_pass: &mut wgpu::RenderPass<'static>) {
+        self.renderer
+            .render(render_pass, &self.paint_jobs, &self.screen);
+    }
+
+    // Textures can only be freed once the frame using them is recorded
+    pub fn finish(&mut self) {
+        for id in self.textures_to_free.drain(..) {
+            self.renderer.free_texture(&id);
+        }
+    }
+}
+
+fn scene_panel(ctx: &egui::Context, scene: &mut EditorScene, resource_files: &ResourceFiles) {
+    egui::SidePanel::left("editor_scene")
+        .default_width(280.0)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                egui::CollapsingHeader::new("Objects")
+                    .default_open(true)
+                    .show(ui, |ui| {
+                        let roots = scene.object_manager.roots().collect::<Vec<_>>();
+                        for root in roots {
+                            object_tree(ui, scene.object_manager, scene.selection, root);
+                        }
+                    });
+
+                // The parent, local transform and materials of the primary
+                // selection
+                if let Some(id) = scene.selection.primary() {
+                    parent_editor(ui, scene.object_manager, id);
+                    match scene.object_manager.get_mut(id) {
+                        Some(object) => {
+                            egui::CollapsingHeader::new("Transform")
+                                .default_open(true)
+                                .show(ui, |ui| {
+                                    let mut position = object.position();
+                                    if vector_row(ui, "Position", &mut position, 0.05) {
+                                        object.set_position(position);
+                                    }
+                                    let euler = Euler::from(object.rotation());
+                                    let mut rotation = Vector3::new(
+                                        Deg::from(euler.x).0,
+                                        Deg::from(euler.y).0,
+                                        Deg::from(euler.z).0,
+                                    );
+                                    if vector_row(ui, "Rotation", &mut rotation, 0.5) {
+                                        object.set_rotation(Quaternion::from(Euler::new(
+                                            Rad::from(Deg(rotation.x)),
+                                            Rad::from(Deg(rotation.y)),
+                                            Rad::from(Deg(rotation.z)),
+                                        )));
+                                    }
+                                    let mut scale = object.scale();
+                                    if vector_row(ui, "Scale", &mut scale, 0.01) {
+                                        object.set_scale(scale);
+                                    }
+                                    let mut hyper = object.hyper();
+                                    let mut rotation = hyper.rotation.map(f32::to_degrees);
+                                    let w = ui
+                                        .horizontal(|ui| {
+                                            ui.label("W");
+                                            let w = ui.add(
+                                                egui::DragValue::new(&mut hyper.w).speed(0.05),
+                                            );
+                                            let scale = ui.add(
+                                                egui::DragValue::new(&mut hyper.scale_w)
+                                                    .speed(0.01)
+                                                    .prefix("scale "),
+                                            );
+                                            w.changed() || scale.changed()
+                                        })
+                                        .inner;
+                                    if vector_row(ui, "XW YW ZW", &mut rotation, 0.5) || w {
+                                        hyper.rotation = rotation.map(f32::to_radians);
+                                        object.set_hyper(hyper);
+                                    }
+                                });
+
+                            egui::CollapsingHeader::new("Materials")
+                                .default_open(true)
+                                .show(ui, |ui| {
+                                    for (submodel_index, submodel) in
+                                        object.model_mut().data.iter_mut().enumerate()
+                                    {
+                                        material_editor(
+                                            ui,
+                                            submodel_index,
+                                            &mut submodel.material_id,
+                                            scene.material_store,
+                                            scene.texture_store,
+                                        );
+                                    }
+                                });
+                        }
+                        None => scene.selection.deselect(id),
+                    }
+                }
+
+                egui::CollapsingHeader::new("Camera").show(ui, |ui| {
+                    let mut position = Vector3::new(
+                        scene.eye.position.x,
+                        scene.eye.position.y,
+                        scene.eye.position.z,
+                    );
+                    if vector_row(ui, "Position", &mut position, 0.05) {
+                        scene.eye.position =
+                            cgmath::Point3::new(position.x, position.y, position.z);
+                    }
+                    ui.add(egui::Slider::new(&mut scene.eye.fov, 10.0..=120.0).text("FOV"));
+                    ui.add(
+                        egui::DragValue::new(&mut scene.eye.near)
+                            .speed(0.01)
+                            .range(0.001..=scene.eye.far)
+                            .prefix("Near "),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut scene.eye.far)
+                            .speed(1.0)
+                            .range(scene.eye.near..=f32::MAX)
+                            .prefix("Far "),
+                    );
+                });
+            });
+        });
+
+    egui::SidePanel::right("editor_settings")
+        .default_width(260.0)
+        .show(ctx, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| {
+                settings_panel(ui, &mut scene.settings, resource_files);
+            });
+        });
+}
+
+fn settings_panel(
+    ui: &mut egui::Ui,
+    settings: &mut EditorSettings,
+    resource_files: &ResourceFiles,
+) {
+    egui::CollapsingHeader::new("Rendering")
+        .default_open(true)
+        .show(ui, |ui| {
+            ui.checkbox(settings.show_fps, "Show FPS");
+            ui.checkbox(settings.frustum_culling, "Frustum culling");
+            ui.checkbox(&mut settings.gpu_culling.frustum, "GPU frustum culling");
+            ui.checkbox(&mut settings.gpu_culling.occlusion, "GPU occlusion culling");
+            egui::ComboBox::from_label("MSAA")
+                .selected_text(format!("{}x", settings.msaa_samples))
+                .show_ui(ui, |ui| {
+                    for samples in [1, 2, 4, 8] {
+                        ui.selectable_value(settings.msaa_samples, samples, format!("{samples}x"));
+                    }
+                });
+            egui::ComboBox::from_label("Transparency")
+                .selected_text(format!("{:?}", settings.transparency))
+                .show_ui(ui, |ui| {
+                    for mode in [TransparencyMode::WeightedBlended, TransparencyMode::Sorted] {
+                        ui.selectable_value(settings.transparency, mode, format!("{mode:?}"));
+                    }
+                });
+        });
+
+    egui::CollapsingHeader::new("Selection").show(ui, |ui| {
+        let style = &mut *settings.selection_style;
+        ui.horizontal(|ui| {
+            ui.color_edit_button_rgba_unmultiplied(&mut style.outline_color);
+            ui.label("Outline");
+        });
+        ui.horizontal(|ui| {
+            ui.color_edit_button_rgba_unmultiplied(&mut style.hover_color);
+            ui.label("Hover");
+        });
+        ui.horizontal(|ui| {
+            ui.color_edit_button_rgba_unmultiplied(&mut style.tint);
+            ui.label("Tint");
+        });
+        ui.add(egui::Slider::new(&mut style.outline_width, 1.0..=16.0).text("Outline width"));
+    });
+
+    egui::CollapsingHeader::new("Gizmo").show(ui, |ui| {
+        let gizmo = &mut *settings.gizmo;
+        ui.horizontal(|ui| {
+            for mode in [GizmoMode::Translate, GizmoMode::Rotate, GizmoMode::Scale] {
+                ui.selectable_value(&mut gizmo.mode, mode, format!("{mode:?}"));
+            }
+        });
+        ui.horizontal(|ui| {
+            for space in [GizmoSpace::World, GizmoSpace::Local] {
+                ui.selectable_value(&mut gizmo.space, space, format!("{space:?}"));
+            }
+        });
+        ui.checkbox(&mut gizmo.four_d, "W handles");
+        ui.checkbox(&mut gizmo.snapping, "Snapping");
+        ui.add(egui::Slider::new(&mut gizmo.translate_snap, 0.05..=2.0).text("Move step"));
+        ui.add(egui::Slider::new(&mut gizmo.rotate_snap, 1.0..=90.0).text("Rotate step"));
+        ui.add(egui::Slider::new(&mut gizmo.scale_snap, 0.01..=1.0).text("Scale step"));
+        ui.add(egui::Slider::new(&mut gizmo.size, 0.05..=0.5).text("Size"));
+    });
+
+    egui::CollapsingHeader::new("Level of detail").show(ui, |ui| {
+        let lod = &mut *settings.level_of_detail;
+        ui.checkbox(&mut lod.enabled, "Enabled");
+        ui.add(egui::Slider::new(&mut lod.bias, 0.1..=4.0).text("Bias"));
+        ui.add(egui::Slider::new(&mut lod.crossfade, 0.0..=1.0).text("Crossfade"));
+    });
+
+    egui::CollapsingHeader::new("Debug view").show(ui, |ui| {
+        let debug_view = &mut *settings.debug_view;
+        egui::ComboBox::from_label("Shading")
+            .selected_text(format!("{:?}", debug_view.shading))
+            .show_ui(ui, |ui| {
+                for shading in [
+                    DebugShading::Off,
+                    DebugShading::UvChecker,
+                    DebugShading::Depth,
+                    DebugShading::SubmodelColor,
+                ] {
+                    ui.selectable_value(&mut debug_view.shading, shading, format!("{shading:?}"));
+                }
+            });
+        ui.checkbox(&mut debug_view.wireframe, "Wireframe");
+        ui.checkbox(&mut debug_view.normals, "Normals");
+        ui.checkbox(&mut debug_view.lights, "Lights");
+        ui.add(egui::Slider::new(&mut debug_view.normal_length, 0.01..=1.0).text("Normal length"));
+    });
+
+    egui::CollapsingHeader::new("Tone mapping").show(ui, |ui| {
+        let tone_mapping = &mut *settings.tone_mapping;
+        egui::ComboBox::from_label("Operator")
+            .selected_text(format!("{:?}", tone_mapping.operator))
+            .show_ui(ui, |ui| {
+                for operator in [
+                    ToneMapOperator::None,
+                    ToneMapOperator::Reinhard,
+                    ToneMapOperator::Aces,
+                    ToneMapOperator::AgX,
+                ] {
+                    ui.selectable_value(
+                        &mut tone_mapping.operator,
+                        operator,
+                        format!("{operator:?}"),
+                    );
+                }
+            });
+        egui::ComboBox::from_label("Exposure mode")
+            .selected_text(format!("{:?}", tone_mapping.exposure_mode))
+            .show_ui(ui, |ui| {
+                for mode in [ExposureMode::Manual, ExposureMode::Auto] {
+                    ui.selectable_value(&mut tone_mapping.exposure_mode, mode, format!("{mode:?}"));
+                }
+            });
+        ui.add(egui::Slider::new(&mut tone_mapping.exposure, -8.0..=8.0).text("Exposure"));
+    });
+
+    egui::CollapsingHeader::new("Post processing").show(ui, |ui| {
+        let post_processing = &mut *settings.post_processing;
+        ui.checkbox(&mut post_processing.bloom.enabled, "Bloom");
+        ui.add(
+            egui::Slider::new(&mut post_processing.bloom.threshold, 0.0..=4.0)
+                .text("Bloom threshold"),
+        );
+        ui.add(
+            egui::Slider::new(&mut post_processing.bloom.intensity, 0.0..=1.0)
+                .text("Bloom intensity"),
+        );
+        ui.checkbox(&mut post_processing.fxaa.enabled, "FXAA");
+        ui.checkbox(&mut post_processing.vignette.enabled, "Vignette");
+        ui.checkbox(
+            &mut post_processing.chromatic_aberration.enabled,
+            "Chromatic aberration",
+        );
+        ui.checkbox(&mut post_processing.color_grading.enabled, "Color grading");
+        path_combo(
+            ui,
+            "LUT",
+            &mut post_processing.color_grading.lut,
+            &resource_files.luts,
+        );
+    });
+
+    egui::CollapsingHeader::new("Environment").show(ui, |ui| {
+        let environment = &mut *settings.environment;
+        ui.add(egui::Slider::new(&mut environment.intensity, 0.0..=4.0).text("Intensity"));
+        ui.checkbox(&mut environment.show_skybox, "Skybox");
+        path_combo(
+            ui,
+            "Map",
+            settings.environment_map,
+            &resource_files.environment_maps,
+        );
+    });
+}
+
+// Picks one of `choices`, or `None`
+fn path_combo(ui: &mut egui::Ui, label: &str, value: &mut Option<PathBuf>, choices: &[PathBuf]) {
+    let name = |path: &Option<PathBuf>| match path {
+        Some(path) => path.display().to_string(),
+        None => "None".to_string(),
+    };
+    egui::ComboBox::from_label(label)
+        .selected_text(name(value))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(value, None, "None");
+            for choice in choices {
+                let choice = Some(choice.clone());
+                let text = name(&choice);
+                ui.selectable_value(value, choice, text);
+            }
+        });
+}
+
+fn material_editor(
+    ui: &mut egui::Ui,
+    submodel_index: usize,
+    material_id: &mut MaterialId,
+    material_store: &mut MaterialStore,
+    texture_store: &TextureStore,
+) {
+    ui.push_id(submodel_index, |ui| {
+        let material_ids = material_store.ids();
+        egui::ComboBox::from_label(format!("Submodel {submodel_index}"))
+            .selected_text(material_store.get_material(*material_id).name.clone())
+            .show_ui(ui, |ui| {
+                for id in material_ids {
+                    let name = material_store.get_material(id).name.clone();
+                    ui.selectable_value(material_id, id, format!("{id}: {name}"));
+                }
+            });
+
+        let Some(material) = material_store.get_material_mut(*material_id) else {
+            return;
+        };
+        let texture_ids = texture_store
+            .textures
+            .iter()
+            .map(|(id, _)| id)
+            .collect::<Vec<_>>();
+        egui::ComboBox::from_label("Diffuse texture")
+            .selected_text(format!("Texture {}", material.diffuse_texture_id))
+            .show_ui(ui, |ui| {
+                for id in texture_ids {
+                    ui.selectable_value(
+                        &mut material.diffuse_texture_id,
+                        id,
+                        format!("Texture {id}"),
+                    );
+                }
+            });
+        egui::ComboBox::from_label("Blend mode")
+            .selected_text(format!("{:?}", material.blend_mode))
+            .show_ui(ui, |ui| {
+                for mode in [BlendMode::Opaque, BlendMode::Transparent] {
+                    ui.selectable_value(&mut material.blend_mode, mode, format!("{mode:?}"));
+                }
+            });
+    });
+}
+
+// An object and, indented below it, its children
+fn object_tree(
+    ui: &mut egui::Ui,
+    object_manager: &ObjectManager,
+    selection: &mut Selection,
+    id: ObjectId,
+) {
+    let Some(object) = object_manager.get(id) else {
+        return;
+    };
+    let label = format!("{}: {}", id.index(), object.name());
+    let response = ui.selectable_label(selection.is_selected(id), label);
+    // Ctrl adds to or removes from the selection
+    if response.clicked() && ui.input(|input| input.modifiers.ctrl) {
+        selection.toggle(id);
+    } else if response.clicked() {
+        selection.select_only(id);
+    }
+    if !object.children().is_empty() {
+        ui.indent(id, |ui| {
+            for &child in object.children() {
+                object_tree(ui, object_manager, selection, child);
+            }
+        });
+    }
+}
+
+// Re-parents the object in place, keeping where it is in the world
+fn parent_editor(ui: &mut egui::Ui, object_manager: &mut ObjectManager, id: ObjectId) {
+    let name = |id: Option<ObjectId>| {
+        id.and_then(|id| object_manager.get(id).map(|object| (id, object)))
+            .map_or("None".to_string(), |(id, object)| {
+                format!("{}: {}", id.index(), object.name())
+            })
+    };
+    let current = object_manager.get(id).and_then(|object| object.parent());
+    let mut parent = current;
+    egui::ComboBox::from_label("Parent")
+        .selected_text(name(current))
+        .show_ui(ui, |ui| {
+            ui.selectable_value(&mut parent, None, "None");
+            for (other, _) in object_manager.iter_with_ids() {
+                if other != id {
+                    ui.selectable_value(&mut parent, Some(other), name(Some(other)));
+                }
+            }
+        });
+    if parent != current {
+        if let Err(e) = object_manager.set_parent(id, parent, KeepTransform::World) {
+            warn!("Failed to change the parent of object {id}: {e}");
+        }
+    }
+}
+
+// Three drag values on one line, returning whether any of them changed
+fn vector_row(ui: &mut egui::Ui, label: &str, vector: &mut Vector3<f32>, speed: f32) -> bool {
+    ui.horizontal(|ui| {
+        ui.label(label);
+        let x = ui.add(egui::DragValue::new(&mut vector.x).speed(speed));
+        let y = ui.add(egui::DragValue::new(&mut vector.y).speed(speed));
+        let z = ui.add(egui::DragValue::new(&mut vector.z).speed(speed));
+        x.changed() || y.changed() || z.changed()
+    })
+    .inner
+}
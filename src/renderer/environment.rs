@@ -0,0 +1,648 @@
+use super::context::Context;
+use super::hdr::HDR_FORMAT;
+use super::mipmap::{mip_level_count, MipmapGenerator};
+use super::shader::ShaderStore;
+use super::util::resources;
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use std::path::PathBuf;
+
+// Directory under `res` holding the maps offered by the editor
+const MAP_DIRECTORY: &str = "environments";
+const ENVIRONMENT_SIZE: u32 = 512;
+const IRRADIANCE_SIZE: u32 = 32;
+const PREFILTERED_SIZE: u32 = 128;
+const PREFILTERED_MIP_COUNT: u32 = 5;
+const PREFILTER_SAMPLE_COUNT: u32 = 256;
+const BRDF_LUT_SIZE: u32 = 256;
+const WORKGROUP_SIZE: u32 = 8;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvironmentSettings {
+    // Scales both the skybox and the image based lighting
+    pub intensity: f32,
+    pub show_skybox: bool,
+}
+
+impl Default for EnvironmentSettings {
+    fn default() -> Self {
+        Self {
+            intensity: 1.0,
+            show_skybox: true,
+        }
+    }
+}
+
+// Paths relative to `res` of the maps that ship with the app
+pub fn available_maps() -> Vec<PathBuf> {
+    resources::list_resources(MAP_DIRECTORY, &["hdr", "exr"])
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct EnvironmentUniform {
+    intensity: f32,
+    enabled: u32,
+    max_reflection_lod: f32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FilterUniform {
+    roughness: f32,
+    source_size: f32,
+    sample_count: u32,
+    _padding: u32,
+}
+
+struct Cubemap {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+}
+
+impl Cubemap {
+    fn new(
+        device: &wgpu::Device,
+        label: &str,
+        size: u32,
+        mip_level_count: u32,
+        usage: wgpu::TextureUsages,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
+                width: size,
+                height: size,
+                depth_or_array_layers: 6,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: usage | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+
+        Self { texture, view }
+    }
+
+    // All six faces of one mip as a storage target
+    fn storage_view(&self, mip: u32) -> wgpu::TextureView {
+        self.texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::D2Array),
+            base_mip_level: mip,
+            mip_level_count: Some(1),
+            ..Default::default()
+        })
+    }
+}
+
+// The cubemaps derived from one environment map
+#[allow(dead_code)]
+struct EnvironmentMaps {
+    environment: Cubemap,
+    irradiance: Cubemap,
+    prefiltered: Cubemap,
+}
+
+// Skybox and image based lighting from an equirectangular HDR environment map.
+// The cubemap conversion and the irradiance, prefiltered specular and BRDF
+// lookup maps are all computed on the GPU.
+pub struct Environment {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    pub bind_group: wgpu::BindGroup,
+    maps: Option<EnvironmentMaps>,
+    placeholder: Cubemap,
+    brdf_lut: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    equirect_layout: wgpu::BindGroupLayout,
+    filter_layout: wgpu::BindGroupLayout,
+    equirect_pipeline: wgpu::ComputePipeline,
+    irradiance_pipeline: wgpu::ComputePipeline,
+    prefilter_pipeline: wgpu::ComputePipeline,
+}
+
+fn compute_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+) -> wgpu::ComputePipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(label),
+        layout: Some(&layout),
+        module: shader,
+        entry_point,
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
+
+fn storage_entry(
+    binding: u32,
+    view_dimension: wgpu::TextureViewDimension,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: HDR_FORMAT,
+            view_dimension,
+        },
+        count: None,
+    }
+}
+
+fn texture_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+    view_dimension: wgpu::TextureViewDimension,
+    filterable: bool,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension,
+            sample_type: wgpu::TextureSampleType::Float { filterable },
+        },
+        count: None,
+    }
+}
+
+fn uniform_entry(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Uniform,
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn dispatch(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    pipeline: &wgpu::ComputePipeline,
+    bind_group: &wgpu::BindGroup,
+    size: (u32, u32, u32),
+) {
+    let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+        label: Some(label),
+        timestamp_writes: None,
+    });
+    pass.set_pipeline(pipeline);
+    pass.set_bind_group(0, bind_group, &[]);
+    pass.dispatch_workgroups(
+        size.0.div_ceil(WORKGROUP_SIZE),
+        size.1.div_ceil(WORKGROUP_SIZE),
+        size.2,
+    );
+}
+
+impl Environment {
+    pub fn new(context: &Context, shader_store: &ShaderStore) -> Self {
+        let device = &context.device;
+        let shader = &shader_store.ibl;
+        let compute = wgpu::ShaderStages::COMPUTE;
+        let fragment = wgpu::ShaderStages::FRAGMENT;
+        let cube = wgpu::TextureViewDimension::Cube;
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, fragment, cube, true),
+                texture_entry(1, fragment, cube, true),
+                texture_entry(2, fragment, cube, true),
+                texture_entry(3, fragment, wgpu::TextureViewDimension::D2, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 4,
+                    visibility: fragment,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                uniform_entry(5, fragment),
+            ],
+            label: Some("environment_bind_group_layout"),
+        });
+        let equirect_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, compute, wgpu::TextureViewDimension::D2, false),
+                storage_entry(1, wgpu::TextureViewDimension::D2Array),
+            ],
+            label: Some("equirect_bind_group_layout"),
+        });
+        let filter_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                storage_entry(1, wgpu::TextureViewDimension::D2Array),
+                texture_entry(2, compute, cube, true),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: compute,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                uniform_entry(4, compute),
+            ],
+            label: Some("environment_filter_bind_group_layout"),
+        });
+        let brdf_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[storage_entry(5, wgpu::TextureViewDimension::D2)],
+            label: Some("brdf_lut_bind_group_layout"),
+        });
+
+        let equirect_pipeline = compute_pipeline(
+            device,
+            "Equirect To Cube Pipeline",
+            &equirect_layout,
+            shader,
+            "equirect_to_cube",
+        );
+        let irradiance_pipeline = compute_pipeline(
+            device,
+            "Irradiance Pipeline",
+            &filter_layout,
+            shader,
+            "irradiance",
+        );
+        let prefilter_pipeline = compute_pipeline(
+            device,
+            "Prefilter Pipeline",
+            &filter_layout,
+            shader,
+            "prefilter",
+        );
+        let brdf_pipeline = compute_pipeline(
+            device,
+            "BRDF LUT Pipeline",
+            &brdf_layout,
+            shader,
+            "brdf_lut",
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("Environment Sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[EnvironmentUniform {
+                intensity: 1.0,
+                enabled: 0,
+                max_reflection_lod: (PREFILTERED_MIP_COUNT - 1) as f32,
+                _padding: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        // The BRDF lookup only depends on the BRDF, so it is computed once
+        let brdf_lut = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("BRDF LUT"),
+            size: wgpu::Extent3d {
+                width: BRDF_LUT_SIZE,
+                height: BRDF_LUT_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let brdf_lut = brdf_lut.create_view(&wgpu::TextureViewDescriptor::default());
+        let brdf_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &brdf_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 5,
+                resource: wgpu::BindingResource::TextureView(&brdf_lut),
+            }],
+            label: Some("BRDF LUT Bind Group"),
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("BRDF LUT Encoder"),
+        });
+        dispatch(
+            &mut encoder,
+            "BRDF LUT",
+            &brdf_pipeline,
+            &brdf_bind_group,
+            (BRDF_LUT_SIZE, BRDF_LUT_SIZE, 1),
+        );
+        context.queue.submit(std::iter::once(encoder.finish()));
+
+        // Bound in place of the real maps until an environment is loaded
+        let placeholder = Cubemap::new(
+            device,
+            "Environment Placeholder",
+            1,
+            1,
+            wgpu::TextureUsages::empty(),
+        );
+        let bind_group = Self::create_bind_group(
+            device,
+            &bind_group_layout,
+            [&placeholder.view, &placeholder.view, &placeholder.view],
+            &brdf_lut,
+            &sampler,
+            &uniform_buffer,
+        );
+
+        Self {
+            bind_group_layout,
+            bind_group,
+            maps: None,
+            placeholder,
+            brdf_lut,
+            sampler,
+            uniform_buffer,
+            equirect_layout,
+            filter_layout,
+            equirect_pipeline,
+            irradiance_pipeline,
+            prefilter_pipeline,
+        }
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        cubemaps: [&wgpu::TextureView; 3],
+        brdf_lut: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(cubemaps[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(cubemaps[1]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(cubemaps[2]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(brdf_lut),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 5,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("Environment Bind Group"),
+        })
+    }
+
+    pub fn is_loaded(&self) -> bool {
+        self.maps.is_some()
+    }
+
+    // Loads an equirectangular `.hdr` or `.exr` map and derives all cubemaps from it
+    pub fn load(
+        &mut self,
+        relative_path: &PathBuf,
+        context: &Context,
+        mipmap_generator: &MipmapGenerator,
+    ) -> Result<()> {
+        let device = &context.device;
+        let bytes = resources::load_resource(relative_path)?;
+        let image = image::load_from_memory(&bytes)?.to_rgba32f();
+        let (width, height) = image.dimensions();
+        let limit = device.limits().max_texture_dimension_2d;
+        if width > limit || height > limit {
+            bail!("Environment map is {width}x{height}, the device allows at most {limit}");
+        }
+
+        let equirect = device.create_texture_with_data(
+            &context.queue,
+            &wgpu::TextureDescriptor {
+                label: Some("Equirectangular Environment"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            wgpu::util::TextureDataOrder::LayerMajor,
+            bytemuck::cast_slice(image.as_raw()),
+        );
+        let equirect = equirect.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let environment = Cubemap::new(
+            device,
+            "Environment Cubemap",
+            ENVIRONMENT_SIZE,
+            mip_level_count(ENVIRONMENT_SIZE, ENVIRONMENT_SIZE),
+            wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::RENDER_ATTACHMENT,
+        );
+        let irradiance = Cubemap::new(
+            device,
+            "Irradiance Cubemap",
+            IRRADIANCE_SIZE,
+            1,
+            wgpu::TextureUsages::STORAGE_BINDING,
+        );
+        let prefiltered = Cubemap::new(
+            device,
+            "Prefiltered Cubemap",
+            PREFILTERED_SIZE,
+            PREFILTERED_MIP_COUNT,
+            wgpu::TextureUsages::STORAGE_BINDING,
+        );
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Encoder"),
+        });
+
+        let environment_storage = environment.storage_view(0);
+        let equirect_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.equirect_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&equirect),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&environment_storage),
+                },
+            ],
+            label: Some("Equirect Bind Group"),
+        });
+        dispatch(
+            &mut encoder,
+            "Equirect To Cube",
+            &self.equirect_pipeline,
+            &equirect_bind_group,
+            (ENVIRONMENT_SIZE, ENVIRONMENT_SIZE, 6),
+        );
+        // Filtering reads from the mips to keep the sample counts low
+        mipmap_generator.generate(device, &mut encoder, &environment.texture)?;
+
+        let irradiance_storage = irradiance.storage_view(0);
+        let irradiance_bind_group =
+            self.create_filter_bind_group(device, &irradiance_storage, &environment.view, 0.0, 0);
+        dispatch(
+            &mut encoder,
+            "Irradiance",
+            &self.irradiance_pipeline,
+            &irradiance_bind_group,
+            (IRRADIANCE_SIZE, IRRADIANCE_SIZE, 6),
+        );
+
+        for mip in 0..PREFILTERED_MIP_COUNT {
+            let size = (PREFILTERED_SIZE >> mip).max(1);
+            let roughness = mip as f32 / (PREFILTERED_MIP_COUNT - 1) as f32;
+            let storage = prefiltered.storage_view(mip);
+            let bind_group = self.create_filter_bind_group(
+                device,
+                &storage,
+                &environment.view,
+                roughness,
+                PREFILTER_SAMPLE_COUNT,
+            );
+            dispatch(
+                &mut encoder,
+                "Prefilter",
+                &self.prefilter_pipeline,
+                &bind_group,
+                (size, size, 6),
+            );
+        }
+
+        context.queue.submit(std::iter::once(encoder.finish()));
+
+        self.bind_group = Self::create_bind_group(
+            device,
+            &self.bind_group_layout,
+            [&environment.view, &irradiance.view, &prefiltered.view],
+            &self.brdf_lut,
+            &self.sampler,
+            &self.uniform_buffer,
+        );
+        self.maps = Some(EnvironmentMaps {
+            environment,
+            irradiance,
+            prefiltered,
+        });
+
+        Ok(())
+    }
+
+    fn create_filter_bind_group(
+        &self,
+        device: &wgpu::Device,
+        output: &wgpu::TextureView,
+        environment: &wgpu::TextureView,
+        roughness: f32,
+        sample_count: u32,
+    ) -> wgpu::BindGroup {
+        let uniform = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Environment Filter Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[FilterUniform {
+                roughness,
+                source_size: ENVIRONMENT_SIZE as f32,
+                sample_count,
+                _padding: 0,
+            }]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.filter_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(output),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(environment),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 4,
+                    resource: uniform.as_entire_binding(),
+                },
+            ],
+            label: Some("Environment Filter Bind Group"),
+        })
+    }
+
+    // Drops the loaded maps, leaving the scene unlit over the clear color
+    pub fn unload(&mut self, context: &Context) {
+        self.maps = None;
+        self.bind_group = Self::create_bind_group(
+            &context.device,
+            &self.bind_group_layout,
+            [
+                &self.placeholder.view,
+                &self.placeholder.view,
+                &self.placeholder.view,
+            ],
+            &self.brdf_lut,
+            &self.sampler,
+            &self.uniform_buffer,
+        );
+    }
+
+    pub fn update(&self, context: &Context, settings: &EnvironmentSettings) {
+        let uniform = EnvironmentUniform {
+            intensity: settings.intensity,
+            enabled: self.is_loaded() as u32,
+            max_reflection_lod: (PREFILTERED_MIP_COUNT - 1) as f32,
+            _padding: 0,
+        };
+        context
+            .queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+}
@@ -0,0 +1,533 @@
+use std::f32::consts::{PI, TAU};
+
+use cgmath::{Deg, EuclideanSpace, InnerSpace, Quaternion, Rad, Rotation, Rotation3, Vector3};
+use serde::{Deserialize, Serialize};
+use winit::keyboard::KeyCode;
+
+use super::{
+    bounds::Ray,
+    camera::CameraEye,
+    debug_draw::debug_draw,
+    object::{HyperTransform, Transform},
+};
+
+// X, Y, Z and W
+const AXIS_COLORS: [[f32; 4]; 4] = [
+    [1.0, 0.2, 0.2, 1.0],
+    [0.2, 1.0, 0.2, 1.0],
+    [0.3, 0.4, 1.0, 1.0],
+    [1.0, 0.3, 1.0, 1.0],
+];
+const UNIFORM_COLOR: [f32; 4] = [0.9, 0.9, 0.9, 1.0];
+const ACTIVE_COLOR: [f32; 4] = [1.0, 1.0, 0.2, 1.0];
+// In fractions of the gizmo size
+const HANDLE_START: f32 = 0.15;
+const HIT_RADIUS: f32 = 0.08;
+const PLANE_HANDLE: [f32; 2] = [0.3, 0.5];
+const SCALE_KNOB: f32 = 0.06;
+const UNIFORM_KNOB: f32 = 0.12;
+const RING_SEGMENTS: usize = 48;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GizmoMode {
+    #[default]
+    Translate,
+    Rotate,
+    Scale,
+}
+
+// Scale handles always follow the object's own axes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum GizmoSpace {
+    #[default]
+    World,
+    Local,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GizmoSettings {
+    pub mode: GizmoMode,
+    pub space: GizmoSpace,
+    pub snapping: bool,
+    pub translate_snap: f32,
+    // Degrees
+    pub rotate_snap: f32,
+    // Steps of the scale factor
+    pub scale_snap: f32,
+    // Share of the window height the axes cover
+    pub size: f32,
+    // Adds the W axis and the XW, YW and ZW plane handles
+    pub four_d: bool,
+}
+
+impl Default for GizmoSettings {
+    fn default() -> Self {
+        Self {
+            mode: GizmoMode::default(),
+            space: GizmoSpace::default(),
+            snapping: false,
+            translate_snap: 0.25,
+            rotate_snap: 15.0,
+            scale_snap: 0.1,
+            size: 0.15,
+            four_d: false,
+        }
+    }
+}
+
+impl GizmoSettings {
+    pub fn handle_key(&mut self, key: KeyCode) -> bool {
+        match key {
+            KeyCode::Digit1 => self.mode = GizmoMode::Translate,
+            KeyCode::Digit2 => self.mode = GizmoMode::Rotate,
+            KeyCode::Digit3 => self.mode = GizmoMode::Scale,
+            KeyCode::Digit4 => self.four_d = !self.four_d,
+            KeyCode::KeyQ => {
+                self.space = match self.space {
+                    GizmoSpace::World => GizmoSpace::Local,
+                    GizmoSpace::Local => GizmoSpace::World,
+                }
+            }
+            KeyCode::KeyX => self.snapping = !self.snapping,
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GizmoHandle {
+    // X, Y, Z or W; the rings around X, Y and Z in rotate mode
+    Axis(usize),
+    // The plane of X, Y or Z with W
+    WPlane(usize),
+    // Scales X, Y and Z together
+    Uniform,
+}
+
+// The world transform a gizmo edits
+#[derive(Clone, Copy, Debug)]
+pub struct GizmoTarget {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+    pub hyper: HyperTransform,
+}
+
+impl GizmoTarget {
+    pub fn new(transform: Transform, hyper: HyperTransform) -> Self {
+        Self {
+            position: transform.position,
+            rotation: transform.rotation,
+            scale: transform.scale,
+            hyper,
+        }
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform {
+            position: self.position,
+            rotation: self.rotation,
+            scale: self.scale,
+        }
+    }
+}
+
+// Where the handles are for a target and camera
+#[derive(Clone, Copy, Debug)]
+struct GizmoFrame {
+    center: Vector3<f32>,
+    // Unit X, Y and Z in the gizmo space. W has no direction in 3D, so its
+    // handles point diagonally up and right across the screen.
+    axes: [Vector3<f32>; 4],
+    view_direction: Vector3<f32>,
+    // World size that covers the configured share of the screen
+    size: f32,
+}
+
+impl GizmoFrame {
+    fn new(settings: &GizmoSettings, target: &GizmoTarget, eye: &CameraEye) -> Self {
+        let local = settings.space == GizmoSpace::Local || settings.mode == GizmoMode::Scale;
+        let axis = |unit: Vector3<f32>| {
+            if local {
+                target.rotation.rotate_vector(unit)
+            } else {
+                unit
+            }
+        };
+        let right = eye.orientation.rotate_vector(Vector3::unit_x());
+        let up = eye.orientation.rotate_vector(Vector3::unit_y());
+        let distance = (target.position - eye.position.to_vec()).magnitude();
+        let view_height = 2.0 * distance * (Rad::from(Deg(eye.fov)).0 * 0.5).tan();
+
+        Self {
+            center: target.position,
+            axes: [
+                axis(Vector3::unit_x()),
+                axis(Vector3::unit_y()),
+                axis(Vector3::unit_z()),
+                (right + up).normalize(),
+            ],
+            view_direction: eye.orientation.rotate_vector(-Vector3::unit_z()),
+            size: view_height * settings.size,
+        }
+    }
+
+    // Two vectors spanning the plane of a rotation ring
+    fn ring_basis(&self, axis: usize) -> (Vector3<f32>, Vector3<f32>) {
+        let normal = self.axes[axis];
+        let helper = if normal.x.abs() < 0.9 {
+            Vector3::unit_x()
+        } else {
+            Vector3::unit_y()
+        };
+        let u = normal.cross(helper).normalize();
+        (u, normal.cross(u))
+    }
+
+    // Distance along the ray to the plane through the center spanned by `a`
+    // and `b`, and where it crosses the plane in multiples of them
+    fn plane_coordinates(
+        &self,
+        ray: &Ray,
+        a: Vector3<f32>,
+        b: Vector3<f32>,
+    ) -> Option<(f32, [f32; 2])> {
+        let normal = a.cross(b);
+        if normal.magnitude2() < 1e-8 {
+            return None;
+        }
+        let t = ray.intersect_plane(self.center, normal.normalize())?;
+        let point = ray.at(t) - self.center;
+        // The W direction is not orthogonal to the axes
+        let (aa, ab, bb) = (a.dot(a), a.dot(b), b.dot(b));
+        let determinant = aa * bb - ab * ab;
+        let (pa, pb) = (point.dot(a), point.dot(b));
+        Some((
+            t,
+            [
+                (pa * bb - pb * ab) / determinant,
+                (pb * aa - pa * ab) / determinant,
+            ],
+        ))
+    }
+
+    fn hit_test(&self, settings: &GizmoSettings, ray: &Ray) -> Option<GizmoHandle> {
+        let (center, size) = (self.center, self.size);
+        let radius = HIT_RADIUS * size;
+        let mut nearest: Option<(f32, GizmoHandle)> = None;
+        let mut consider = |t: f32, handle| {
+            if nearest.is_none_or(|(nearest_t, _)| t < nearest_t) {
+                nearest = Some((t, handle));
+            }
+        };
+
+        if settings.four_d {
+            for axis in 0..3 {
+                let hit = self.plane_coordinates(ray, self.axes[axis] * size, self.axes[3] * size);
+                if let Some((t, coordinates)) = hit {
+                    let range = PLANE_HANDLE[0]..=PLANE_HANDLE[1];
+                    if coordinates.iter().all(|c| range.contains(c)) {
+                        consider(t, GizmoHandle::WPlane(axis));
+                    }
+                }
+            }
+        }
+
+        match settings.mode {
+            GizmoMode::Rotate => {
+                for axis in 0..3 {
+                    if let Some(t) = ray.intersect_plane(center, self.axes[axis]) {
+                        let distance = (ray.at(t) - center).magnitude();
+                        if (distance - size).abs() < radius {
+                            consider(t, GizmoHandle::Axis(axis));
+                        }
+                    }
+                }
+            }
+            GizmoMode::Translate | GizmoMode::Scale => {
+                let axis_count = if settings.four_d { 4 } else { 3 };
+                for axis in 0..axis_count {
+                    let direction = self.axes[axis];
+                    let Some((t, along)) = ray.closest_to_line(center, direction) else {
+                        continue;
+                    };
+                    let on_handle =
+                        (HANDLE_START * size..=(1.0 + HIT_RADIUS) * size).contains(&along);
+                    if t >= 0.0
+                        && on_handle
+                        && (ray.at(t) - (center + direction * along)).magnitude() < radius
+                    {
+                        consider(t, GizmoHandle::Axis(axis));
+                    }
+                }
+                if settings.mode == GizmoMode::Scale {
+                    let t = (center - ray.origin).dot(ray.direction);
+                    if t >= 0.0 && (ray.at(t) - center).magnitude() < UNIFORM_KNOB * size {
+                        consider(t, GizmoHandle::Uniform);
+                    }
+                }
+            }
+        }
+        nearest.map(|(_, handle)| handle)
+    }
+
+    // What a drag of the handle measures where the ray points: a distance
+    // along an axis, an angle around it, coordinates in a W plane or the
+    // distance from the center
+    fn drag_value(&self, mode: GizmoMode, handle: GizmoHandle, ray: &Ray) -> Option<[f32; 2]> {
+        match (mode, handle) {
+            (_, GizmoHandle::WPlane(axis)) => self
+                .plane_coordinates(ray, self.axes[axis], self.axes[3])
+                .map(|(_, coordinates)| coordinates),
+            (GizmoMode::Rotate, GizmoHandle::Axis(axis)) => {
+                let t = ray.intersect_plane(self.center, self.axes[axis])?;
+                let point = ray.at(t) - self.center;
+                let (u, v) = self.ring_basis(axis);
+                Some([point.dot(v).atan2(point.dot(u)), 0.0])
+            }
+            (_, GizmoHandle::Axis(axis)) => ray
+                .closest_to_line(self.center, self.axes[axis])
+                .map(|(_, along)| [along, 0.0]),
+            (_, GizmoHandle::Uniform) => {
+                let t = ray.intersect_plane(self.center, self.view_direction)?;
+                Some([(ray.at(t) - self.center).magnitude(), 0.0])
+            }
+        }
+    }
+}
+
+struct Drag {
+    handle: GizmoHandle,
+    mode: GizmoMode,
+    // Held fixed so the handles do not move under the cursor while dragging
+    frame: GizmoFrame,
+    start: GizmoTarget,
+    start_value: [f32; 2],
+    // Ring angles are accumulated so a drag can turn past half a circle
+    last_value: [f32; 2],
+    angle: f32,
+}
+
+// Translate, rotate and scale handles drawn over the primary selection
+#[derive(Default)]
+pub struct Gizmo {
+    hovered: Option<GizmoHandle>,
+    drag: Option<Drag>,
+}
+
+impl Gizmo {
+    pub fn is_dragging(&self) -> bool {
+        self.drag.is_some()
+    }
+
+    // Highlights the handle under the cursor
+    pub fn hover(
+        &mut self,
+        settings: &GizmoSettings,
+        target: Option<&GizmoTarget>,
+        eye: &CameraEye,
+        ray: &Ray,
+    ) {
+        self.hovered = target
+            .and_then(|target| GizmoFrame::new(settings, target, eye).hit_test(settings, ray));
+    }
+
+    // Starts a drag if the ray hits a handle, returning whether it did
+    pub fn begin_drag(
+        &mut self,
+        settings: &GizmoSettings,
+        target: &GizmoTarget,
+        eye: &CameraEye,
+        ray: &Ray,
+    ) -> bool {
+        let frame = GizmoFrame::new(settings, target, eye);
+        let Some(handle) = frame.hit_test(settings, ray) else {
+            return false;
+        };
+        let Some(value) = frame.drag_value(settings.mode, handle, ray) else {
+            return false;
+        };
+        self.drag = Some(Drag {
+            handle,
+            mode: settings.mode,
+            frame,
+            start: *target,
+            start_value: value,
+            last_value: value,
+            angle: 0.0,
+        });
+        true
+    }
+
+    // The dragged transform for the ray, snapped unless `invert_snapping`
+    // flips the setting
+    pub fn drag(
+        &mut self,
+        settings: &GizmoSettings,
+        ray: &Ray,
+        invert_snapping: bool,
+    ) -> Option<GizmoTarget> {
+        let drag = self.drag.as_mut()?;
+        let value = drag.frame.drag_value(drag.mode, drag.handle, ray)?;
+        let delta = [
+            value[0] - drag.start_value[0],
+            value[1] - drag.start_value[1],
+        ];
+        let step = value[0] - drag.last_value[0];
+        drag.angle += step - TAU * ((step + PI) / TAU).floor();
+        drag.last_value = value;
+
+        let snapping = settings.snapping != invert_snapping;
+        let snap = |value: f32, increment: f32| {
+            if snapping && increment > 0.0 {
+                (value / increment).round() * increment
+            } else {
+                value
+            }
+        };
+        let ratio = |current: f32, start: f32| {
+            let factor = if start.abs() > 1e-4 {
+                current / start
+            } else {
+                1.0
+            };
+            // Scales do not collapse or flip
+            snap(factor, settings.scale_snap).max(0.01)
+        };
+
+        let start = drag.start;
+        let axes = drag.frame.axes;
+        let mut target = start;
+        match (drag.mode, drag.handle) {
+            (GizmoMode::Translate, GizmoHandle::Axis(3)) => {
+                target.hyper.w = start.hyper.w + snap(delta[0], settings.translate_snap);
+            }
+            (GizmoMode::Translate, GizmoHandle::Axis(axis)) => {
+                target.position =
+                    start.position + axes[axis] * snap(delta[0], settings.translate_snap);
+            }
+            (GizmoMode::Translate, GizmoHandle::WPlane(axis)) => {
+                target.position =
+                    start.position + axes[axis] * snap(delta[0], settings.translate_snap);
+                target.hyper.w = start.hyper.w + snap(delta[1], settings.translate_snap);
+            }
+            (GizmoMode::Rotate, GizmoHandle::Axis(axis)) => {
+                let angle = snap(drag.angle.to_degrees(), settings.rotate_snap);
+                target.rotation =
+                    Quaternion::from_axis_angle(axes[axis], Deg(angle)) * start.rotation;
+            }
+            (GizmoMode::Rotate, GizmoHandle::WPlane(axis)) => {
+                // One radian per gizmo size dragged along the handle's diagonal
+                let angle = (delta[0] + delta[1]) / drag.frame.size;
+                let angle = snap(angle.to_degrees(), settings.rotate_snap).to_radians();
+                target.hyper.rotation[axis] = start.hyper.rotation[axis] + angle;
+            }
+            (GizmoMode::Scale, GizmoHandle::Axis(3)) => {
+                target.hyper.scale_w = start.hyper.scale_w * ratio(value[0], drag.start_value[0]);
+            }
+            (GizmoMode::Scale, GizmoHandle::Axis(axis)) => {
+                target.scale[axis] = start.scale[axis] * ratio(value[0], drag.start_value[0]);
+            }
+            (GizmoMode::Scale, GizmoHandle::WPlane(axis)) => {
+                let factor = ratio(
+                    value[0] + value[1],
+                    drag.start_value[0] + drag.start_value[1],
+                );
+                target.scale[axis] = start.scale[axis] * factor;
+                target.hyper.scale_w = start.hyper.scale_w * factor;
+            }
+            (_, GizmoHandle::Uniform) => {
+                target.scale = start.scale * ratio(value[0], drag.start_value[0]);
+            }
+        }
+        Some(target)
+    }
+
+    pub fn end_drag(&mut self) {
+        self.drag = None;
+    }
+
+    // Queues the handles as debug lines on top of the scene
+    pub fn draw(&self, settings: &GizmoSettings, target: &GizmoTarget, eye: &CameraEye) {
+        let frame = GizmoFrame::new(settings, target, eye);
+        let active = self.drag.as_ref().map(|drag| drag.handle).or(self.hovered);
+        let color = |handle, base| {
+            if active == Some(handle) {
+                ACTIVE_COLOR
+            } else {
+                base
+            }
+        };
+        let (center, size, axes) = (frame.center, frame.size, frame.axes);
+        let axis_count = if settings.four_d { 4 } else { 3 };
+        let mut draw = debug_draw();
+
+        match settings.mode {
+            GizmoMode::Translate => {
+                for axis in 0..axis_count {
+                    let handle = GizmoHandle::Axis(axis);
+                    draw.arrow(
+                        center + axes[axis] * HANDLE_START * size,
+                        center + axes[axis] * size,
+                        color(handle, AXIS_COLORS[axis]),
+                    )
+                    .depth_test(false);
+                }
+            }
+            GizmoMode::Rotate => {
+                for (axis, base) in AXIS_COLORS.into_iter().enumerate().take(3) {
+                    let (u, v) = frame.ring_basis(axis);
+                    let points = (0..=RING_SEGMENTS)
+                        .map(|i| {
+                            let angle = i as f32 / RING_SEGMENTS as f32 * TAU;
+                            center + (u * angle.cos() + v * angle.sin()) * size
+                        })
+                        .collect::<Vec<_>>();
+                    draw.polyline(&points, color(GizmoHandle::Axis(axis), base))
+                        .depth_test(false);
+                }
+                if settings.four_d {
+                    // The W direction the plane handles are drawn against
+                    draw.line(center, center + axes[3] * size, AXIS_COLORS[3])
+                        .depth_test(false);
+                }
+            }
+            GizmoMode::Scale => {
+                for axis in 0..axis_count {
+                    let handle_color = color(GizmoHandle::Axis(axis), AXIS_COLORS[axis]);
+                    let tip = center + axes[axis] * size;
+                    draw.line(center + axes[axis] * HANDLE_START * size, tip, handle_color)
+                        .depth_test(false);
+                    draw.sphere(tip, SCALE_KNOB * size, handle_color)
+                        .depth_test(false);
+                }
+                draw.sphere(
+                    center,
+                    UNIFORM_KNOB * size,
+                    color(GizmoHandle::Uniform, UNIFORM_COLOR),
+                )
+                .depth_test(false);
+            }
+        }
+
+        if settings.four_d {
+            let [near, far] = PLANE_HANDLE.map(|offset| offset * size);
+            for axis in 0..3 {
+                let corner = |a: f32, w: f32| center + axes[axis] * a + axes[3] * w;
+                draw.polyline(
+                    &[
+                        corner(near, near),
+                        corner(far, near),
+                        corner(far, far),
+                        corner(near, far),
+                        corner(near, near),
+                    ],
+                    color(GizmoHandle::WPlane(axis), AXIS_COLORS[axis]),
+                )
+                .depth_test(false);
+            }
+        }
+    }
+}
@@ -0,0 +1,929 @@
+use std::mem;
+
+use cgmath::{Matrix4, SquareMatrix};
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use super::{
+    arena::{Arena, Handle},
+    bounds::{Aabb, Frustum},
+    context::Context,
+    material::MaterialId,
+    model::{Model, ModelVertex},
+    shader::ShaderStore,
+};
+
+const INITIAL_VERTEX_CAPACITY: u64 = 1 << 16;
+const INITIAL_INDEX_CAPACITY: u64 = 1 << 18;
+const INITIAL_DRAW_CAPACITY: usize = 256;
+const CULL_WORKGROUP_SIZE: u32 = 64;
+const HI_Z_WORKGROUP_SIZE: u32 = 8;
+const HI_Z_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+
+const FLAG_FRUSTUM: u32 = 1;
+const FLAG_OCCLUSION: u32 = 2;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GpuCullingSettings {
+    pub frustum: bool,
+    // Tests against the depth of the previous frame, so objects appearing from
+    // behind an occluder may show up one frame late
+    pub occlusion: bool,
+}
+
+impl Default for GpuCullingSettings {
+    fn default() -> Self {
+        Self {
+            frustum: true,
+            occlusion: true,
+        }
+    }
+}
+
+// One submodel of one object; `first_instance` of its indirect command points
+// back at it so the vertex shader can find its transform
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct DrawData {
+    model: [[f32; 4]; 4],
+    aabb_min: [f32; 4],
+    aabb_max: [f32; 4],
+    first_index: u32,
+    index_count: u32,
+    base_vertex: i32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    previous_view_proj: [[f32; 4]; 4],
+    hi_z_size: [f32; 2],
+    draw_count: u32,
+    flags: u32,
+}
+
+// Matches wgpu's `DrawIndexedIndirectArgs`
+const INDIRECT_COMMAND_SIZE: u64 = 5 * mem::size_of::<u32>() as u64;
+
+#[derive(Clone, Copy, Debug)]
+struct MeshRange {
+    first_index: u32,
+    index_count: u32,
+    base_vertex: i32,
+    aabb: Aabb,
+}
+
+struct GpuModel {
+    // Mesh index and material id of every submodel
+    submodels: Vec<(usize, MaterialId)>,
+}
+
+pub struct GpuObject {
+    model: usize,
+    transform: Matrix4<f32>,
+}
+
+pub type GpuObjectId = Handle<GpuObject>;
+
+// A run of draws sharing a material, drawn with one multi draw call
+#[derive(Clone, Copy, Debug)]
+pub struct GpuBatch {
+    pub material_id: MaterialId,
+    pub first_draw: u32,
+    pub draw_count: u32,
+}
+
+// Depth pyramid of the last frame, mip 0 matches the screen
+struct HiZ {
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    downsample_bind_groups: Vec<wgpu::BindGroup>,
+    mip_views: Vec<wgpu::TextureView>,
+    width: u32,
+    height: u32,
+}
+
+// Scene whose meshes live in shared vertex and index buffers and whose draw
+// calls are written by a culling compute shader. Every draw uses the basic
+// shader.
+#[allow(dead_code)]
+pub struct GpuScene {
+    vertex_buffer: wgpu::Buffer,
+    index_buffer: wgpu::Buffer,
+    vertex_capacity: u64,
+    index_capacity: u64,
+    vertex_count: u64,
+    index_count: u64,
+    meshes: Vec<MeshRange>,
+    models: Vec<GpuModel>,
+    objects: Arena<GpuObject>,
+    batches: Vec<GpuBatch>,
+    draws: Vec<DrawData>,
+    draw_count: u32,
+    draw_capacity: usize,
+    dirty: bool,
+    draw_buffer: wgpu::Buffer,
+    indirect_buffer: wgpu::Buffer,
+    cull_buffer: wgpu::Buffer,
+    pub draw_bind_group_layout: wgpu::BindGroupLayout,
+    pub draw_bind_group: wgpu::BindGroup,
+    cull_bind_group_layout: wgpu::BindGroupLayout,
+    cull_bind_group: wgpu::BindGroup,
+    copy_depth_layout: wgpu::BindGroupLayout,
+    copy_depth_multisampled_layout: wgpu::BindGroupLayout,
+    downsample_layout: wgpu::BindGroupLayout,
+    pipelines: CullPipelines,
+    hi_z: HiZ,
+    // Whether the pyramid holds a frame drawn with `previous_view_proj`
+    hi_z_valid: bool,
+    build_hi_z: bool,
+    previous_view_proj: Matrix4<f32>,
+    // Indirect draws need `first_instance` to find their draw data
+    pub indirect_supported: bool,
+    pub multi_draw_supported: bool,
+}
+
+#[allow(dead_code)]
+impl GpuScene {
+    pub fn new(context: &Context, shader_store: &ShaderStore) -> Self {
+        let device = &context.device;
+        let features = device.features();
+
+        let draw_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("gpu_draw_bind_group_layout"),
+                entries: &[storage_entry(1, wgpu::ShaderStages::VERTEX, true)],
+            });
+        let cull_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("gpu_cull_bind_group_layout"),
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    storage_entry(1, wgpu::ShaderStages::COMPUTE, true),
+                    storage_entry(2, wgpu::ShaderStages::COMPUTE, false),
+                    texture_entry(
+                        3,
+                        wgpu::TextureSampleType::Float { filterable: false },
+                        false,
+                    ),
+                ],
+            });
+        let copy_depth_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("hi_z_copy_depth_bind_group_layout"),
+            entries: &[
+                texture_entry(0, wgpu::TextureSampleType::Depth, false),
+                output_entry(3),
+            ],
+        });
+        let copy_depth_multisampled_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                label: Some("hi_z_copy_depth_multisampled_bind_group_layout"),
+                entries: &[
+                    texture_entry(1, wgpu::TextureSampleType::Depth, true),
+                    output_entry(3),
+                ],
+            });
+        let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("hi_z_downsample_bind_group_layout"),
+            entries: &[
+                texture_entry(
+                    2,
+                    wgpu::TextureSampleType::Float { filterable: false },
+                    false,
+                ),
+                output_entry(3),
+            ],
+        });
+
+        let pipelines = CullPipelines::new(
+            device,
+            shader_store,
+            &cull_bind_group_layout,
+            &copy_depth_layout,
+            &copy_depth_multisampled_layout,
+            &downsample_layout,
+        );
+
+        let cull_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("GPU Cull Uniform Buffer"),
+            size: mem::size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let draw_buffer = create_draw_buffer(device, INITIAL_DRAW_CAPACITY);
+        let indirect_buffer = create_indirect_buffer(device, INITIAL_DRAW_CAPACITY);
+        let hi_z = create_hi_z(
+            device,
+            &downsample_layout,
+            context.config.width,
+            context.config.height,
+        );
+
+        let draw_bind_group = create_draw_bind_group(device, &draw_bind_group_layout, &draw_buffer);
+        let cull_bind_group = create_cull_bind_group(
+            device,
+            &cull_bind_group_layout,
+            &cull_buffer,
+            &draw_buffer,
+            &indirect_buffer,
+            &hi_z.view,
+        );
+
+        Self {
+            vertex_buffer: create_mega_buffer(
+                device,
+                "Mega Vertex Buffer",
+                INITIAL_VERTEX_CAPACITY * mem::size_of::<ModelVertex>() as u64,
+                wgpu::BufferUsages::VERTEX,
+            ),
+            index_buffer: create_mega_buffer(
+                device,
+                "Mega Index Buffer",
+                INITIAL_INDEX_CAPACITY * mem::size_of::<u32>() as u64,
+                wgpu::BufferUsages::INDEX,
+            ),
+            vertex_capacity: INITIAL_VERTEX_CAPACITY,
+            index_capacity: INITIAL_INDEX_CAPACITY,
+            vertex_count: 0,
+            index_count: 0,
+            meshes: Vec::new(),
+            models: Vec::new(),
+            objects: Arena::new(),
+            batches: Vec::new(),
+            draws: Vec::new(),
+            draw_count: 0,
+            draw_capacity: INITIAL_DRAW_CAPACITY,
+            dirty: false,
+            draw_buffer,
+            indirect_buffer,
+            cull_buffer,
+            draw_bind_group_layout,
+            draw_bind_group,
+            cull_bind_group_layout,
+            cull_bind_group,
+            copy_depth_layout,
+            copy_depth_multisampled_layout,
+            downsample_layout,
+            pipelines,
+            hi_z,
+            hi_z_valid: false,
+            build_hi_z: false,
+            previous_view_proj: Matrix4::identity(),
+            indirect_supported: features.contains(wgpu::Features::INDIRECT_FIRST_INSTANCE),
+            multi_draw_supported: features.contains(wgpu::Features::MULTI_DRAW_INDIRECT),
+        }
+    }
+
+    // Picks up changed culling and Hi-Z shaders, keeping the scene
+    pub fn rebuild_pipelines(&mut self, device: &wgpu::Device, shader_store: &ShaderStore) {
+        self.pipelines = CullPipelines::new(
+            device,
+            shader_store,
+            &self.cull_bind_group_layout,
+            &self.copy_depth_layout,
+            &self.copy_depth_multisampled_layout,
+            &self.downsample_layout,
+        );
+    }
+
+    // Copies the model's meshes into the shared buffers on the GPU and returns
+    // an index for `add_object`. The model itself can be dropped afterwards.
+    pub fn add_model(&mut self, context: &Context, model: &Model) -> usize {
+        let device = &context.device;
+        let vertex_size = mem::size_of::<ModelVertex>() as u64;
+        let index_size = mem::size_of::<u32>() as u64;
+
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Mega Buffer Upload Encoder"),
+        });
+
+        let mut submodels = Vec::with_capacity(model.data.len());
+        for data in &model.data {
+            let mesh = &data.mesh;
+            let vertex_count = mesh.vertex_buffer.size() / vertex_size;
+            let index_count = mesh.num_elements as u64;
+
+            self.reserve(device, &mut encoder, vertex_count, index_count);
+            encoder.copy_buffer_to_buffer(
+                &mesh.vertex_buffer,
+                0,
+                &self.vertex_buffer,
+                self.vertex_count * vertex_size,
+                vertex_count * vertex_size,
+            );
+            encoder.copy_buffer_to_buffer(
+                &mesh.index_buffer,
+                0,
+                &self.index_buffer,
+                self.index_count * index_size,
+                index_count * index_size,
+            );
+
+            self.meshes.push(MeshRange {
+                first_index: self.index_count as u32,
+                index_count: index_count as u32,
+                base_vertex: self.vertex_count as i32,
+                aabb: mesh.aabb,
+            });
+            submodels.push((self.meshes.len() - 1, data.material_id));
+            self.vertex_count += vertex_count;
+            self.index_count += index_count;
+        }
+
+        context.queue.submit(Some(encoder.finish()));
+        self.models.push(GpuModel { submodels });
+        self.models.len() - 1
+    }
+
+    // Grows the shared buffers to fit the given amount of new data, keeping
+    // what is already uploaded
+    fn reserve(
+        &mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        vertex_count: u64,
+        index_count: u64,
+    ) {
+        let vertex_size = mem::size_of::<ModelVertex>() as u64;
+        let index_size = mem::size_of::<u32>() as u64;
+
+        if self.vertex_count + vertex_count > self.vertex_capacity {
+            self.vertex_capacity = (self.vertex_count + vertex_count).next_power_of_two();
+            let buffer = create_mega_buffer(
+                device,
+                "Mega Vertex Buffer",
+                self.vertex_capacity * vertex_size,
+                wgpu::BufferUsages::VERTEX,
+            );
+            encoder.copy_buffer_to_buffer(
+                &self.vertex_buffer,
+                0,
+                &buffer,
+                0,
+                self.vertex_count * vertex_size,
+            );
+            self.vertex_buffer = buffer;
+        }
+        if self.index_count + index_count > self.index_capacity {
+            self.index_capacity = (self.index_count + index_count).next_power_of_two();
+            let buffer = create_mega_buffer(
+                device,
+                "Mega Index Buffer",
+                self.index_capacity * index_size,
+                wgpu::BufferUsages::INDEX,
+            );
+            encoder.copy_buffer_to_buffer(
+                &self.index_buffer,
+                0,
+                &buffer,
+                0,
+                self.index_count * index_size,
+            );
+            self.index_buffer = buffer;
+        }
+    }
+
+    pub fn add_object(&mut self, model: usize, transform: Matrix4<f32>) -> GpuObjectId {
+        self.dirty = true;
+        self.objects.insert(GpuObject { model, transform })
+    }
+
+    // Returns whether the object still exists
+    pub fn set_transform(&mut self, id: GpuObjectId, transform: Matrix4<f32>) -> bool {
+        let Some(object) = self.objects.get_mut(id) else {
+            return false;
+        };
+        object.transform = transform;
+        self.dirty = true;
+        true
+    }
+
+    pub fn remove_object(&mut self, id: GpuObjectId) -> bool {
+        let removed = self.objects.remove(id).is_some();
+        self.dirty |= removed;
+        removed
+    }
+
+    pub fn object_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    pub fn draw_count(&self) -> u32 {
+        self.draw_count
+    }
+
+    pub fn batches(&self) -> &[GpuBatch] {
+        &self.batches
+    }
+
+    pub fn vertex_buffer(&self) -> &wgpu::Buffer {
+        &self.vertex_buffer
+    }
+
+    pub fn index_buffer(&self) -> &wgpu::Buffer {
+        &self.index_buffer
+    }
+
+    pub fn indirect_buffer(&self) -> &wgpu::Buffer {
+        &self.indirect_buffer
+    }
+
+    pub fn hi_z_texture(&self) -> (&wgpu::Texture, &wgpu::TextureView) {
+        (&self.hi_z.texture, &self.hi_z.view)
+    }
+
+    // Whether the render graph should rebuild the depth pyramid this frame
+    pub fn builds_hi_z(&self) -> bool {
+        self.build_hi_z && self.indirect_supported && self.draw_count > 0
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, width: u32, height: u32) {
+        self.hi_z = create_hi_z(device, &self.downsample_layout, width, height);
+        self.hi_z_valid = false;
+        self.create_cull_bind_group(device);
+    }
+
+    pub fn update(
+        &mut self,
+        context: &Context,
+        frustum: &Frustum,
+        view_proj: Matrix4<f32>,
+        settings: &GpuCullingSettings,
+    ) {
+        if self.dirty {
+            self.dirty = false;
+            self.upload_draws(context);
+        }
+
+        let mut flags = 0;
+        if settings.frustum {
+            flags |= FLAG_FRUSTUM;
+        }
+        if settings.occlusion && self.hi_z_valid {
+            flags |= FLAG_OCCLUSION;
+        }
+        let uniform = CullUniform {
+            planes: frustum.planes().map(|plane| plane.into()),
+            previous_view_proj: self.previous_view_proj.into(),
+            hi_z_size: [self.hi_z.width as f32, self.hi_z.height as f32],
+            draw_count: self.draw_count,
+            flags,
+        };
+        context
+            .queue
+            .write_buffer(&self.cull_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        // This frame's depth becomes next frame's occluders
+        self.build_hi_z = settings.occlusion;
+        self.hi_z_valid = self.builds_hi_z();
+        self.previous_view_proj = view_proj;
+    }
+
+    // Flattens objects into one draw per submodel, grouped by material
+    fn upload_draws(&mut self, context: &Context) {
+        let mut draws = self
+            .objects
+            .values()
+            .flat_map(|object| {
+                self.models[object.model]
+                    .submodels
+                    .iter()
+                    .map(|&(mesh, material_id)| {
+                        let mesh = &self.meshes[mesh];
+                        let draw = DrawData {
+                            model: object.transform.into(),
+                            aabb_min: mesh.aabb.min.extend(1.0).into(),
+                            aabb_max: mesh.aabb.max.extend(1.0).into(),
+                            first_index: mesh.first_index,
+                            index_count: mesh.index_count,
+                            base_vertex: mesh.base_vertex,
+                            _padding: 0,
+                        };
+                        (material_id, draw)
+                    })
+            })
+            .collect::<Vec<_>>();
+        draws.sort_by_key(|(material_id, _)| *material_id);
+
+        self.batches.clear();
+        for (index, (material_id, _)) in draws.iter().enumerate() {
+            match self.batches.last_mut() {
+                Some(batch) if batch.material_id == *material_id => batch.draw_count += 1,
+                _ => self.batches.push(GpuBatch {
+                    material_id: *material_id,
+                    first_draw: index as u32,
+                    draw_count: 1,
+                }),
+            }
+        }
+        self.draw_count = draws.len() as u32;
+
+        let device = &context.device;
+        if draws.len() > self.draw_capacity {
+            self.draw_capacity = draws.len().next_power_of_two();
+            self.draw_buffer = create_draw_buffer(device, self.draw_capacity);
+            self.indirect_buffer = create_indirect_buffer(device, self.draw_capacity);
+            self.draw_bind_group =
+                create_draw_bind_group(device, &self.draw_bind_group_layout, &self.draw_buffer);
+            self.create_cull_bind_group(device);
+        }
+
+        self.draws = draws.into_iter().map(|(_, draw)| draw).collect();
+        context
+            .queue
+            .write_buffer(&self.draw_buffer, 0, bytemuck::cast_slice(&self.draws));
+    }
+
+    fn create_cull_bind_group(&mut self, device: &wgpu::Device) {
+        self.cull_bind_group = create_cull_bind_group(
+            device,
+            &self.cull_bind_group_layout,
+            &self.cull_buffer,
+            &self.draw_buffer,
+            &self.indirect_buffer,
+            &self.hi_z.view,
+        );
+    }
+
+    // Writes one indirect command per draw, culled draws get no instances
+    pub fn cull(&self, encoder: &mut wgpu::CommandEncoder) {
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("GPU Cull Pass"),
+            timestamp_writes: None,
+        });
+        pass.set_pipeline(&self.pipelines.cull);
+        pass.set_bind_group(0, &self.cull_bind_group, &[]);
+        pass.dispatch_workgroups(self.draw_count.div_ceil(CULL_WORKGROUP_SIZE), 1, 1);
+    }
+
+    // Rebuilds the depth pyramid from the scene depth buffer
+    pub fn build_hi_z(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        depth: &wgpu::TextureView,
+        sample_count: u32,
+    ) {
+        let (layout, pipeline, binding) = if sample_count > 1 {
+            (
+                &self.copy_depth_multisampled_layout,
+                &self.pipelines.copy_depth_multisampled,
+                1,
+            )
+        } else {
+            (&self.copy_depth_layout, &self.pipelines.copy_depth, 0)
+        };
+        let copy_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("hi_z_copy_depth_bind_group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding,
+                    resource: wgpu::BindingResource::TextureView(depth),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.hi_z.mip_views[0]),
+                },
+            ],
+        });
+
+        let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Hi-Z Pass"),
+            timestamp_writes: None,
+        });
+        let dispatch = |pass: &mut wgpu::ComputePass, level: u32| {
+            let width = (self.hi_z.width >> level).max(1);
+            let height = (self.hi_z.height >> level).max(1);
+            pass.dispatch_workgroups(
+                width.div_ceil(HI_Z_WORKGROUP_SIZE),
+                height.div_ceil(HI_Z_WORKGROUP_SIZE),
+                1,
+            );
+        };
+
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &copy_bind_group, &[]);
+        dispatch(&mut pass, 0);
+
+        pass.set_pipeline(&self.pipelines.downsample);
+        for (index, bind_group) in self.hi_z.downsample_bind_groups.iter().enumerate() {
+            pass.set_bind_group(0, bind_group, &[]);
+            dispatch(&mut pass, index as u32 + 1);
+        }
+    }
+
+    // Draws every batch, one call per batch when multi draw is available. Without
+    // indirect support nothing is culled and the draws are issued directly.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        texture_bind_group: impl Fn(MaterialId) -> &'a wgpu::BindGroup,
+    ) {
+        if self.draw_count == 0 {
+            return;
+        }
+
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.set_index_buffer(self.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        render_pass.set_bind_group(2, &self.draw_bind_group, &[]);
+
+        for batch in &self.batches {
+            render_pass.set_bind_group(1, texture_bind_group(batch.material_id), &[]);
+            let first = batch.first_draw as u64;
+            if !self.indirect_supported {
+                for index in batch.first_draw..batch.first_draw + batch.draw_count {
+                    let draw = &self.draws[index as usize];
+                    render_pass.draw_indexed(
+                        draw.first_index..draw.first_index + draw.index_count,
+                        draw.base_vertex,
+                        index..index + 1,
+                    );
+                }
+            } else if self.multi_draw_supported {
+                render_pass.multi_draw_indexed_indirect(
+                    &self.indirect_buffer,
+                    first * INDIRECT_COMMAND_SIZE,
+                    batch.draw_count,
+                );
+            } else {
+                for draw in 0..batch.draw_count as u64 {
+                    render_pass.draw_indexed_indirect(
+                        &self.indirect_buffer,
+                        (first + draw) * INDIRECT_COMMAND_SIZE,
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn storage_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+    read_only: bool,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Buffer {
+            ty: wgpu::BufferBindingType::Storage { read_only },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    }
+}
+
+fn texture_entry(
+    binding: u32,
+    sample_type: wgpu::TextureSampleType,
+    multisampled: bool,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::Texture {
+            sample_type,
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled,
+        },
+        count: None,
+    }
+}
+
+fn output_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::COMPUTE,
+        ty: wgpu::BindingType::StorageTexture {
+            access: wgpu::StorageTextureAccess::WriteOnly,
+            format: HI_Z_FORMAT,
+            view_dimension: wgpu::TextureViewDimension::D2,
+        },
+        count: None,
+    }
+}
+
+struct CullPipelines {
+    cull: wgpu::ComputePipeline,
+    copy_depth: wgpu::ComputePipeline,
+    copy_depth_multisampled: wgpu::ComputePipeline,
+    downsample: wgpu::ComputePipeline,
+}
+
+impl CullPipelines {
+    fn new(
+        device: &wgpu::Device,
+        shader_store: &ShaderStore,
+        cull_layout: &wgpu::BindGroupLayout,
+        copy_depth_layout: &wgpu::BindGroupLayout,
+        copy_depth_multisampled_layout: &wgpu::BindGroupLayout,
+        downsample_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        Self {
+            cull: compute_pipeline(
+                device,
+                "GPU Cull Pipeline",
+                cull_layout,
+                &shader_store.cull,
+                "cull",
+            ),
+            copy_depth: compute_pipeline(
+                device,
+                "Hi-Z Copy Depth Pipeline",
+                copy_depth_layout,
+                &shader_store.hi_z,
+                "copy_depth",
+            ),
+            copy_depth_multisampled: compute_pipeline(
+                device,
+                "Hi-Z Copy Multisampled Depth Pipeline",
+                copy_depth_multisampled_layout,
+                &shader_store.hi_z,
+                "copy_depth_multisampled",
+            ),
+            downsample: compute_pipeline(
+                device,
+                "Hi-Z Downsample Pipeline",
+                downsample_layout,
+                &shader_store.hi_z,
+                "downsample",
+            ),
+        }
+    }
+}
+
+fn compute_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+) -> wgpu::ComputePipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+        label: Some(label),
+        layout: Some(&layout),
+        module: shader,
+        entry_point,
+        compilation_options: wgpu::PipelineCompilationOptions::default(),
+        cache: None,
+    })
+}
+
+fn create_mega_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    size: u64,
+    usage: wgpu::BufferUsages,
+) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size,
+        usage: usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_draw_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("GPU Draw Buffer"),
+        size: (capacity * mem::size_of::<DrawData>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_indirect_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some("GPU Indirect Buffer"),
+        contents: &vec![0; capacity * INDIRECT_COMMAND_SIZE as usize],
+        usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+    })
+}
+
+fn create_draw_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    draw_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("gpu_draw_bind_group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 1,
+            resource: draw_buffer.as_entire_binding(),
+        }],
+    })
+}
+
+fn create_cull_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    cull_buffer: &wgpu::Buffer,
+    draw_buffer: &wgpu::Buffer,
+    indirect_buffer: &wgpu::Buffer,
+    hi_z: &wgpu::TextureView,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("gpu_cull_bind_group"),
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: cull_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: draw_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 2,
+                resource: indirect_buffer.as_entire_binding(),
+            },
+            wgpu::BindGroupEntry {
+                binding: 3,
+                resource: wgpu::BindingResource::TextureView(hi_z),
+            },
+        ],
+    })
+}
+
+fn create_hi_z(
+    device: &wgpu::Device,
+    downsample_layout: &wgpu::BindGroupLayout,
+    width: u32,
+    height: u32,
+) -> HiZ {
+    let (width, height) = (width.max(1), height.max(1));
+    let mip_level_count = super::mipmap::mip_level_count(width, height);
+    let texture = device.create_texture(&wgpu::TextureDescriptor {
+        label: Some("Hi-Z Texture"),
+        size: wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HI_Z_FORMAT,
+        usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    });
+    let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+    let mip_views = (0..mip_level_count)
+        .map(|level| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                base_mip_level: level,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        })
+        .collect::<Vec<_>>();
+    let downsample_bind_groups = mip_views
+        .windows(2)
+        .map(|views| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some("hi_z_downsample_bind_group"),
+                layout: downsample_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(&views[0]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::TextureView(&views[1]),
+                    },
+                ],
+            })
+        })
+        .collect();
+
+    HiZ {
+        texture,
+        view,
+        downsample_bind_groups,
+        mip_views,
+        width,
+        height,
+    }
+}
@@ -0,0 +1,464 @@
+use anyhow::{bail, Result};
+use log::warn;
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+
+// Pooled textures that are not used for this many frames get released.
+const MAX_IDLE_FRAMES: u64 = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ResourceId(usize);
+
+#[derive(Clone, Debug)]
+pub struct TextureDesc {
+    pub label: &'static str,
+    pub size: wgpu::Extent3d,
+    pub mip_level_count: u32,
+    pub sample_count: u32,
+    pub dimension: wgpu::TextureDimension,
+    pub format: wgpu::TextureFormat,
+    pub usage: wgpu::TextureUsages,
+}
+
+impl TextureDesc {
+    pub fn new(
+        label: &'static str,
+        width: u32,
+        height: u32,
+        format: wgpu::TextureFormat,
+        usage: wgpu::TextureUsages,
+    ) -> Self {
+        Self {
+            label,
+            size: wgpu::Extent3d {
+                width: width.max(1),
+                height: height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+        }
+    }
+
+    fn key(&self) -> TextureKey {
+        TextureKey {
+            size: self.size,
+            mip_level_count: self.mip_level_count,
+            sample_count: self.sample_count,
+            dimension: self.dimension,
+            format: self.format,
+            usage: self.usage,
+        }
+    }
+}
+
+// Everything that decides whether two transient textures may share memory.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+struct TextureKey {
+    size: wgpu::Extent3d,
+    mip_level_count: u32,
+    sample_count: u32,
+    dimension: wgpu::TextureDimension,
+    format: wgpu::TextureFormat,
+    usage: wgpu::TextureUsages,
+}
+
+struct PooledTexture {
+    key: TextureKey,
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    last_used_frame: u64,
+}
+
+#[derive(Default)]
+pub struct TransientPool {
+    textures: Vec<PooledTexture>,
+    frame: u64,
+}
+
+impl TransientPool {
+    pub fn clear(&mut self) {
+        self.textures.clear();
+    }
+
+    fn acquire(
+        &mut self,
+        device: &wgpu::Device,
+        desc: &TextureDesc,
+        busy: &mut Vec<bool>,
+    ) -> usize {
+        let key = desc.key();
+        let free = self
+            .textures
+            .iter()
+            .enumerate()
+            .position(|(i, t)| t.key == key && !busy.get(i).copied().unwrap_or(false));
+
+        let slot = match free {
+            Some(slot) => slot,
+            None => {
+                let texture = device.create_texture(&wgpu::TextureDescriptor {
+                    label: Some(desc.label),
+                    size: desc.size,
+                    mip_level_count: desc.mip_level_count,
+                    sample_count: desc.sample_count,
+                    dimension: desc.dimension,
+                    format: desc.format,
+                    usage: desc.usage,
+                    view_formats: &[],
+                });
+                let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+                self.textures.push(PooledTexture {
+                    key,
+                    texture,
+                    view,
+                    last_used_frame: self.frame,
+                });
+                self.textures.len() - 1
+            }
+        };
+
+        if busy.len() < self.textures.len() {
+            busy.resize(self.textures.len(), false);
+        }
+        busy[slot] = true;
+        self.textures[slot].last_used_frame = self.frame;
+        slot
+    }
+
+    fn end_frame(&mut self) {
+        let frame = self.frame;
+        self.textures
+            .retain(|t| frame - t.last_used_frame <= MAX_IDLE_FRAMES);
+        self.frame += 1;
+    }
+}
+
+enum Resource<'a> {
+    Transient(TextureDesc),
+    Imported {
+        texture: &'a wgpu::Texture,
+        view: &'a wgpu::TextureView,
+    },
+    Buffer(&'a wgpu::Buffer),
+}
+
+type ExecuteFn<'a> = Box<dyn FnOnce(&mut wgpu::CommandEncoder, &PassResources) + 'a>;
+
+struct PassNode<'a> {
+    name: &'static str,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    side_effect: bool,
+    execute: ExecuteFn<'a>,
+}
+
+impl PassNode<'_> {
+    fn touches(&self) -> impl Iterator<Item = &ResourceId> {
+        self.reads.iter().chain(self.writes.iter())
+    }
+}
+
+pub struct PassBuilder<'g, 'a> {
+    graph: &'g mut RenderGraph<'a>,
+    name: &'static str,
+    reads: Vec<ResourceId>,
+    writes: Vec<ResourceId>,
+    side_effect: bool,
+}
+
+impl<'a> PassBuilder<'_, 'a> {
+    pub fn read(mut self, id: ResourceId) -> Self {
+        self.reads.push(id);
+        self
+    }
+
+    pub fn write(mut self, id: ResourceId) -> Self {
+        self.writes.push(id);
+        self
+    }
+
+    // Keeps the pass alive even if nothing in the graph consumes its outputs.
+    #[allow(dead_code)]
+    pub fn side_effect(mut self) -> Self {
+        self.side_effect = true;
+        self
+    }
+
+    pub fn execute<F>(self, execute: F)
+    where
+        F: FnOnce(&mut wgpu::CommandEncoder, &PassResources) + 'a,
+    {
+        self.graph.passes.push(PassNode {
+            name: self.name,
+            reads: self.reads,
+            writes: self.writes,
+            side_effect: self.side_effect,
+            execute: Box::new(execute),
+        });
+    }
+}
+
+pub struct PassResources<'r> {
+    resources: &'r [Resource<'r>],
+    pool: &'r TransientPool,
+    slots: &'r [Option<usize>],
+}
+
+impl PassResources<'_> {
+    pub fn texture(&self, id: ResourceId) -> &wgpu::Texture {
+        match &self.resources[id.0] {
+            Resource::Imported { texture, .. } => texture,
+            Resource::Transient(_) => &self.pooled(id).texture,
+            Resource::Buffer(_) => panic!("Resource {id:?} is a buffer, not a texture"),
+        }
+    }
+
+    pub fn view(&self, id: ResourceId) -> &wgpu::TextureView {
+        match &self.resources[id.0] {
+            Resource::Imported { view, .. } => view,
+            Resource::Transient(_) => &self.pooled(id).view,
+            Resource::Buffer(_) => panic!("Resource {id:?} is a buffer, not a texture"),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn buffer(&self, id: ResourceId) -> &wgpu::Buffer {
+        match &self.resources[id.0] {
+            Resource::Buffer(buffer) => buffer,
+            _ => panic!("Resource {id:?} is a texture, not a buffer"),
+        }
+    }
+
+    fn pooled(&self, id: ResourceId) -> &PooledTexture {
+        let slot =
+            self.slots[id.0].expect("Transient resource used outside of its declared passes");
+        &self.pool.textures[slot]
+    }
+}
+
+// A per-frame description of the passes that make up a frame. Passes declare the
+// resources they read and write; the graph derives their order from that, culls
+// passes whose results are never used, allocates transient textures from a
+// persistent pool (reusing memory between resources whose lifetimes do not
+// overlap) and records everything into a single command encoder.
+pub struct RenderGraph<'a> {
+    resources: Vec<Resource<'a>>,
+    passes: Vec<PassNode<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new() -> Self {
+        Self {
+            resources: Vec::new(),
+            passes: Vec::new(),
+        }
+    }
+
+    pub fn create_texture(&mut self, desc: TextureDesc) -> ResourceId {
+        self.resources.push(Resource::Transient(desc));
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub fn import_texture(
+        &mut self,
+        texture: &'a wgpu::Texture,
+        view: &'a wgpu::TextureView,
+    ) -> ResourceId {
+        self.resources.push(Resource::Imported { texture, view });
+        ResourceId(self.resources.len() - 1)
+    }
+
+    // Buffers are only tracked so that passes touching them get ordered; they are
+    // never allocated by the graph.
+    pub fn import_buffer(&mut self, buffer: &'a wgpu::Buffer) -> ResourceId {
+        self.resources.push(Resource::Buffer(buffer));
+        ResourceId(self.resources.len() - 1)
+    }
+
+    pub fn add_pass<'g>(&'g mut self, name: &'static str) -> PassBuilder<'g, 'a> {
+        PassBuilder {
+            graph: self,
+            name,
+            reads: Vec::new(),
+            writes: Vec::new(),
+            side_effect: false,
+        }
+    }
+
+    // Writers of a resource run in the order they were added, and every pass that
+    // only reads a resource runs after all of its writers.
+    fn dependencies(&self) -> Vec<Vec<usize>> {
+        let mut writers: Vec<Vec<usize>> = vec![Vec::new(); self.resources.len()];
+        for (index, pass) in self.passes.iter().enumerate() {
+            for id in &pass.writes {
+                if !writers[id.0].contains(&index) {
+                    writers[id.0].push(index);
+                }
+            }
+        }
+
+        self.passes
+            .iter()
+            .enumerate()
+            .map(|(index, pass)| {
+                let mut dependencies = Vec::new();
+                for id in &pass.writes {
+                    let previous = writers[id.0].iter().take_while(|&&w| w != index);
+                    dependencies.extend(previous);
+                }
+                for id in &pass.reads {
+                    if pass.writes.contains(id) {
+                        continue;
+                    }
+                    dependencies.extend(&writers[id.0]);
+                }
+                dependencies.sort_unstable();
+                dependencies.dedup();
+                dependencies
+            })
+            .collect()
+    }
+
+    fn compile(&self) -> Result<Vec<usize>> {
+        let dependencies = self.dependencies();
+
+        let mut alive = vec![false; self.passes.len()];
+        let mut stack: Vec<usize> = self
+            .passes
+            .iter()
+            .enumerate()
+            .filter(|(_, pass)| {
+                pass.side_effect
+                    || pass
+                        .writes
+                        .iter()
+                        .any(|id| !matches!(self.resources[id.0], Resource::Transient(_)))
+            })
+            .map(|(index, _)| index)
+            .collect();
+        while let Some(index) = stack.pop() {
+            if alive[index] {
+                continue;
+            }
+            alive[index] = true;
+            stack.extend(&dependencies[index]);
+        }
+
+        let mut remaining = vec![0usize; self.passes.len()];
+        let mut dependents: Vec<Vec<usize>> = vec![Vec::new(); self.passes.len()];
+        for (index, deps) in dependencies.iter().enumerate() {
+            if !alive[index] {
+                continue;
+            }
+            remaining[index] = deps.len();
+            for &dependency in deps {
+                dependents[dependency].push(index);
+            }
+        }
+
+        // Kahn's algorithm, preferring insertion order among ready passes.
+        let mut ready: BinaryHeap<Reverse<usize>> = (0..self.passes.len())
+            .filter(|&index| alive[index] && remaining[index] == 0)
+            .map(Reverse)
+            .collect();
+        let mut order = Vec::new();
+        while let Some(Reverse(index)) = ready.pop() {
+            order.push(index);
+            for &dependent in &dependents[index] {
+                remaining[dependent] -= 1;
+                if remaining[dependent] == 0 {
+                    ready.push(Reverse(dependent));
+                }
+            }
+        }
+
+        if order.len() != alive.iter().filter(|&&a| a).count() {
+            let stuck = (0..self.passes.len())
+                .filter(|&index| alive[index] && !order.contains(&index))
+                .map(|index| self.passes[index].name)
+                .collect::<Vec<_>>();
+            bail!("Render graph has a dependency cycle between passes {stuck:?}");
+        }
+
+        Ok(order)
+    }
+
+    pub fn execute(
+        mut self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        pool: &mut TransientPool,
+    ) -> Result<()> {
+        let order = self.compile()?;
+
+        let mut first_use = vec![None; self.resources.len()];
+        let mut last_use = vec![0; self.resources.len()];
+        for (step, &index) in order.iter().enumerate() {
+            for id in self.passes[index].touches() {
+                first_use[id.0].get_or_insert(step);
+                last_use[id.0] = step;
+            }
+        }
+
+        for (id, resource) in self.resources.iter().enumerate() {
+            let Resource::Transient(desc) = resource else {
+                continue;
+            };
+            let Some(first) = first_use[id] else {
+                continue;
+            };
+            if !self.passes[order[first]].writes.contains(&ResourceId(id)) {
+                warn!(
+                    "Transient texture {:?} is read before anything writes to it",
+                    desc.label
+                );
+            }
+        }
+
+        // Walk the passes in execution order, handing out pool slots when a transient
+        // is first touched and returning them after its last use, so later resources
+        // with a matching description alias the same texture.
+        let mut slots = vec![None; self.resources.len()];
+        let mut busy = Vec::new();
+        for step in 0..order.len() {
+            for (id, resource) in self.resources.iter().enumerate() {
+                if let Resource::Transient(desc) = resource {
+                    if first_use[id] == Some(step) {
+                        slots[id] = Some(pool.acquire(device, desc, &mut busy));
+                    }
+                }
+            }
+            for (id, slot) in slots.iter().enumerate() {
+                if let Some(slot) = slot {
+                    if first_use[id].is_some() && last_use[id] == step {
+                        busy[*slot] = false;
+                    }
+                }
+            }
+        }
+
+        let mut passes = std::mem::take(&mut self.passes)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let resources = PassResources {
+            resources: &self.resources,
+            pool,
+            slots: &slots,
+        };
+        for index in order {
+            let pass = passes[index].take().unwrap();
+            encoder.push_debug_group(pass.name);
+            (pass.execute)(encoder, &resources);
+            encoder.pop_debug_group();
+        }
+
+        pool.end_frame();
+        Ok(())
+    }
+}
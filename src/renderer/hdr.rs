@@ -0,0 +1,400 @@
+use super::context::Context;
+use super::pipeline::init_fullscreen_pipeline;
+use super::shader::ShaderStore;
+
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+const HISTOGRAM_BINS: u64 = 256;
+const HISTOGRAM_WORKGROUP_SIZE: u32 = 16;
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToneMapOperator {
+    None,
+    Reinhard,
+    Aces,
+    AgX,
+}
+
+impl ToneMapOperator {
+    fn index(self) -> u32 {
+        match self {
+            ToneMapOperator::None => 0,
+            ToneMapOperator::Reinhard => 1,
+            ToneMapOperator::Aces => 2,
+            ToneMapOperator::AgX => 3,
+        }
+    }
+}
+
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExposureMode {
+    Manual,
+    // Exposure follows the average scene luminance from the histogram pass
+    Auto,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ToneMapSettings {
+    pub operator: ToneMapOperator,
+    pub exposure_mode: ExposureMode,
+    // Exposure in stops; the fixed exposure in manual mode and a compensation on
+    // top of the metered exposure in auto mode
+    pub exposure: f32,
+    // Luminance range (log2) covered by the histogram
+    pub min_log_luminance: f32,
+    pub max_log_luminance: f32,
+    // How quickly auto exposure adapts to a new luminance, per second
+    pub adaptation_rate: f32,
+}
+
+impl Default for ToneMapSettings {
+    fn default() -> Self {
+        Self {
+            operator: ToneMapOperator::Aces,
+            exposure_mode: ExposureMode::Auto,
+            exposure: 0.0,
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+            adaptation_rate: 1.5,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ToneMapUniform {
+    operator: u32,
+    auto_exposure: u32,
+    exposure: f32,
+    encode_srgb: u32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct HistogramUniform {
+    min_log_luminance: f32,
+    inverse_log_luminance_range: f32,
+    log_luminance_range: f32,
+    time_coefficient: f32,
+    pixel_count: u32,
+    _padding: [u32; 3],
+}
+
+pub struct ToneMapper {
+    pipeline: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    sampler: wgpu::Sampler,
+    uniform_buffer: wgpu::Buffer,
+    histogram_pipeline: wgpu::ComputePipeline,
+    average_pipeline: wgpu::ComputePipeline,
+    histogram_bind_group_layout: wgpu::BindGroupLayout,
+    histogram_buffer: wgpu::Buffer,
+    histogram_uniform_buffer: wgpu::Buffer,
+    pub luminance_buffer: wgpu::Buffer,
+}
+
+impl ToneMapper {
+    pub fn new(context: &Context, shader_store: &ShaderStore) -> Self {
+        let device = &context.device;
+
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("tonemap_bind_group_layout"),
+        });
+
+        let pipeline = init_fullscreen_pipeline(
+            device,
+            "Tone Mapping Pipeline",
+            &[&bind_group_layout],
+            &shader_store.tonemap,
+            "fs_main",
+            context.config.format,
+            None,
+        );
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Tone Mapping Uniform Buffer"),
+            size: std::mem::size_of::<ToneMapUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let histogram_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 2,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 3,
+                        visibility: wgpu::ShaderStages::COMPUTE,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Storage { read_only: false },
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                ],
+                label: Some("histogram_bind_group_layout"),
+            });
+
+        let histogram_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Luminance Histogram Pipeline Layout"),
+                bind_group_layouts: &[&histogram_bind_group_layout],
+                push_constant_ranges: &[],
+            });
+        let histogram_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Luminance Histogram Pipeline"),
+            layout: Some(&histogram_pipeline_layout),
+            module: &shader_store.luminance_histogram,
+            entry_point: "build_histogram",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+        let average_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Average Luminance Pipeline"),
+            layout: Some(&histogram_pipeline_layout),
+            module: &shader_store.luminance_histogram,
+            entry_point: "average_luminance_main",
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+            cache: None,
+        });
+
+        let histogram_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Histogram Buffer"),
+            size: HISTOGRAM_BINS * std::mem::size_of::<u32>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::STORAGE,
+            mapped_at_creation: false,
+        });
+        let histogram_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Luminance Histogram Uniform Buffer"),
+            size: std::mem::size_of::<HistogramUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let luminance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Average Luminance Buffer"),
+            contents: bytemuck::cast_slice(&[0.18f32]),
+            usage: wgpu::BufferUsages::STORAGE,
+        });
+
+        Self {
+            pipeline,
+            bind_group_layout,
+            sampler,
+            uniform_buffer,
+            histogram_pipeline,
+            average_pipeline,
+            histogram_bind_group_layout,
+            histogram_buffer,
+            histogram_uniform_buffer,
+            luminance_buffer,
+        }
+    }
+
+    pub fn update(&self, context: &Context, settings: &ToneMapSettings, delta_time: f32) {
+        let uniform = ToneMapUniform {
+            operator: settings.operator.index(),
+            auto_exposure: (settings.exposure_mode == ExposureMode::Auto) as u32,
+            exposure: settings.exposure,
+            encode_srgb: !context.config.format.is_srgb() as u32,
+        };
+        context
+            .queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let log_luminance_range =
+            (settings.max_log_luminance - settings.min_log_luminance).max(0.01);
+        let histogram_uniform = HistogramUniform {
+            min_log_luminance: settings.min_log_luminance,
+            inverse_log_luminance_range: 1.0 / log_luminance_range,
+            log_luminance_range,
+            time_coefficient: (1.0 - (-delta_time * settings.adaptation_rate).exp())
+                .clamp(0.0, 1.0),
+            pixel_count: context.config.width * context.config.height,
+            _padding: [0; 3],
+        };
+        context.queue.write_buffer(
+            &self.histogram_uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[histogram_uniform]),
+        );
+    }
+
+    // Meters the HDR scene into the luminance buffer read by `apply`
+    pub fn compute_exposure(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &wgpu::TextureView,
+        width: u32,
+        height: u32,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.histogram_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: self.histogram_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.histogram_uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.luminance_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("histogram_bind_group"),
+        });
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Auto Exposure Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.set_pipeline(&self.histogram_pipeline);
+        compute_pass.dispatch_workgroups(
+            width.div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+            height.div_ceil(HISTOGRAM_WORKGROUP_SIZE),
+            1,
+        );
+        compute_pass.set_pipeline(&self.average_pipeline);
+        compute_pass.dispatch_workgroups(1, 1, 1);
+    }
+
+    pub fn apply(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(hdr),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.luminance_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("tonemap_bind_group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Tone Mapping Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,78 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+
+use log::warn;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::util::resources;
+
+// Editors save in several writes, a file is reported once it stayed quiet
+// this long
+const SETTLE_TIME: Duration = Duration::from_millis(100);
+
+// Watches `res` for files changed on disk
+pub struct HotReloader {
+    // Watching stops when dropped
+    _watcher: RecommendedWatcher,
+    events: mpsc::Receiver<notify::Result<notify::Event>>,
+    root: PathBuf,
+    // Last change seen for files not reported yet
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl HotReloader {
+    pub fn new() -> notify::Result<Self> {
+        let root = resources::res_path(&PathBuf::new());
+        // Events carry the full path as the OS sees it
+        let root = root.canonicalize().unwrap_or(root);
+        let (sender, events) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(sender)?;
+        watcher.watch(&root, RecursiveMode::Recursive)?;
+        Ok(Self {
+            _watcher: watcher,
+            events,
+            root,
+            pending: HashMap::new(),
+        })
+    }
+
+    // Files that changed and settled since the last call, relative to `res`
+    pub fn changed_files(&mut self) -> Vec<PathBuf> {
+        let now = Instant::now();
+        while let Ok(result) = self.events.try_recv() {
+            match result {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    for path in event.paths {
+                        if let Some(relative_path) = self.relative_path(&path) {
+                            self.pending.insert(relative_path, now);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Error watching {}: {e}", self.root.display()),
+            }
+        }
+
+        let mut changed = Vec::new();
+        self.pending.retain(|path, changed_at| {
+            let settled = now - *changed_at >= SETTLE_TIME;
+            if settled {
+                changed.push(path.clone());
+            }
+            !settled
+        });
+        changed.sort();
+        changed
+    }
+
+    fn relative_path(&self, path: &Path) -> Option<PathBuf> {
+        if !path.is_file() {
+            return None;
+        }
+        path.strip_prefix(&self.root)
+            .ok()
+            .map(|relative_path| relative_path.to_path_buf())
+    }
+}
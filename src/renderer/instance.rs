@@ -0,0 +1,276 @@
+use std::path::Path;
+
+use cgmath::{Deg, Matrix4, Quaternion, Rotation3, Vector3};
+use wgpu::util::DeviceExt;
+
+use super::{
+    arena::Handle,
+    bounds::{Aabb, Frustum},
+    context::Context,
+    material::MaterialStore,
+    model::{load_model, Model, Vertex},
+    object::TransformUniform,
+};
+
+const INITIAL_CAPACITY: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Instance {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+    // Multiplied with the material's diffuse color
+    pub color: [f32; 4],
+}
+
+impl Default for Instance {
+    fn default() -> Self {
+        Self {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0)),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+            color: [1.0; 4],
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Instance {
+    pub fn new(position: Vector3<f32>) -> Self {
+        Self {
+            position,
+            ..Default::default()
+        }
+    }
+
+    fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    fn to_raw(self) -> InstanceRaw {
+        InstanceRaw {
+            model: self.matrix().into(),
+            color: self.color,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct InstanceRaw {
+    model: [[f32; 4]; 4],
+    color: [f32; 4],
+}
+
+impl Vertex for InstanceRaw {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        use std::mem;
+        // Locations 0-4 are left for the per vertex attributes
+        const ATTRIBUTES: [wgpu::VertexAttribute; 5] = wgpu::vertex_attr_array![
+            5 => Float32x4,
+            6 => Float32x4,
+            7 => Float32x4,
+            8 => Float32x4,
+            9 => Float32x4,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<InstanceRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+// Many copies of one model drawn with a single instanced draw call per
+// submodel. The object transform applies to the whole batch and instance
+// transforms are relative to it.
+#[allow(dead_code)]
+pub struct InstancedObject {
+    pub model: Model,
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+    transform_uniform: TransformUniform,
+    uniform_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    instances: Vec<Instance>,
+    instance_buffer: wgpu::Buffer,
+    capacity: usize,
+    dirty: bool,
+    // Bounds of all instances relative to the batch and in world space
+    aabb: Aabb,
+    instances_aabb: Option<Aabb>,
+    world_aabb: Option<Aabb>,
+    visible: bool,
+}
+
+pub type InstancedObjectId = Handle<InstancedObject>;
+
+#[allow(dead_code)]
+impl InstancedObject {
+    pub fn new(
+        model: Model,
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let transform_uniform = TransformUniform::new();
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Instanced Object Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[transform_uniform]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("instanced_object_bind_group"),
+        });
+
+        let aabb = model.aabb();
+
+        Self {
+            model,
+            position: Vector3::new(0.0, 0.0, 0.0),
+            rotation: Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0)),
+            scale: Vector3::new(1.0, 1.0, 1.0),
+            transform_uniform,
+            uniform_buffer,
+            bind_group,
+            instances: Vec::new(),
+            instance_buffer: Self::create_instance_buffer(device, INITIAL_CAPACITY),
+            capacity: INITIAL_CAPACITY,
+            dirty: false,
+            aabb,
+            instances_aabb: None,
+            world_aabb: None,
+            visible: true,
+        }
+    }
+
+    pub async fn from_model_path(
+        model_path: &Path,
+        context: &Context<'_>,
+        material_store: &mut MaterialStore,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let model = load_model(model_path, &context.device, material_store)
+            .await
+            .unwrap();
+
+        Self::new(model, &context.device, bind_group_layout)
+    }
+
+    fn create_instance_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    pub fn add_instance(&mut self, instance: Instance) -> usize {
+        self.instances.push(instance);
+        self.dirty = true;
+        self.instances.len() - 1
+    }
+
+    pub fn update_instance(&mut self, index: usize, instance: Instance) {
+        self.instances[index] = instance;
+        self.dirty = true;
+    }
+
+    // The last instance is moved into the freed slot to keep the buffer dense
+    pub fn remove_instance(&mut self, index: usize) -> Instance {
+        self.dirty = true;
+        self.instances.swap_remove(index)
+    }
+
+    pub fn clear_instances(&mut self) {
+        self.instances.clear();
+        self.dirty = true;
+    }
+
+    pub fn instance(&self, index: usize) -> &Instance {
+        &self.instances[index]
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    pub fn instance_count(&self) -> u32 {
+        self.instances.len() as u32
+    }
+
+    pub fn instance_buffer(&self) -> &wgpu::Buffer {
+        &self.instance_buffer
+    }
+
+    pub fn world_aabb(&self) -> Option<&Aabb> {
+        self.world_aabb.as_ref()
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn has_transparent(&self, material_store: &MaterialStore) -> bool {
+        self.model.data.iter().any(|data| {
+            material_store
+                .get_material(data.material_id)
+                .is_transparent()
+        })
+    }
+
+    // The whole batch is culled at once, individual instances are always drawn
+    pub fn cull(&mut self, frustum: Option<&Frustum>) -> bool {
+        self.visible = match (frustum, &self.world_aabb) {
+            (None, _) => true,
+            (Some(frustum), Some(aabb)) => frustum.intersects_aabb(aabb),
+            (Some(_), None) => false,
+        };
+        self.visible
+    }
+
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        self.transform_uniform
+            .calculate(self.position, self.rotation, self.scale);
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.transform_uniform]),
+        );
+
+        if self.dirty {
+            self.dirty = false;
+            self.instances_aabb = self
+                .instances
+                .iter()
+                .map(|instance| self.aabb.transformed(&instance.matrix()))
+                .reduce(|a, b| a.union(&b));
+            self.upload_instances(device, queue);
+        }
+
+        let matrix = self.transform_uniform.matrix();
+        self.world_aabb = self.instances_aabb.map(|aabb| aabb.transformed(&matrix));
+    }
+
+    fn upload_instances(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        if self.instances.len() > self.capacity {
+            self.capacity = self.instances.len().next_power_of_two();
+            self.instance_buffer = Self::create_instance_buffer(device, self.capacity);
+        }
+        let raw = self
+            .instances
+            .iter()
+            .map(|instance| instance.to_raw())
+            .collect::<Vec<_>>();
+        queue.write_buffer(&self.instance_buffer, 0, bytemuck::cast_slice(&raw));
+    }
+}
@@ -0,0 +1,83 @@
+use cgmath::{EuclideanSpace, InnerSpace};
+use serde::{Deserialize, Serialize};
+
+use super::{bounds::BoundingSphere, camera::CameraEye};
+
+// Level 1 takes over below half the screen height and every further level at
+// half the size of the previous one
+const FIRST_LOD_SCREEN_SIZE: f32 = 0.5;
+const MAX_LOD_LEVEL: usize = 16;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LodSettings {
+    pub enabled: bool,
+    // Multiplies the projected size, values above 1 keep finer levels longer
+    pub bias: f32,
+    // Fraction of a level's size range over which it dithers into the next
+    // level, 0 switches instantly
+    pub crossfade: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            bias: 1.0,
+            crossfade: 0.25,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodSelection {
+    pub level: usize,
+    // Share of pixels drawn with `level`, the rest are drawn with `level + 1`
+    pub fade: f32,
+}
+
+impl Default for LodSelection {
+    fn default() -> Self {
+        Self {
+            level: 0,
+            fade: 1.0,
+        }
+    }
+}
+
+impl LodSelection {
+    pub fn is_fading(&self) -> bool {
+        self.fade < 1.0
+    }
+}
+
+// Height of the sphere on screen as a fraction of the viewport height
+pub fn screen_size(sphere: &BoundingSphere, eye: &CameraEye) -> f32 {
+    let distance = (sphere.center - eye.position.to_vec()).magnitude();
+    if distance <= sphere.radius {
+        return f32::INFINITY;
+    }
+    sphere.radius / (distance * (eye.fov.to_radians() * 0.5).tan())
+}
+
+pub fn select_lod(screen_size: f32, settings: &LodSettings) -> LodSelection {
+    let size = screen_size * settings.bias;
+    if !settings.enabled {
+        return LodSelection::default();
+    }
+
+    let level = if size >= FIRST_LOD_SCREEN_SIZE {
+        0
+    } else {
+        ((FIRST_LOD_SCREEN_SIZE / size).log2().floor() as usize + 1).min(MAX_LOD_LEVEL)
+    };
+    // Fade from the bottom of this level's range into the next level
+    let next_threshold = FIRST_LOD_SCREEN_SIZE * 0.5f32.powi(level as i32);
+    let band = next_threshold * settings.crossfade.clamp(0.0, 1.0);
+    let fade = if band > 0.0 && level < MAX_LOD_LEVEL {
+        ((size - next_threshold) / band).clamp(0.0, 1.0)
+    } else {
+        1.0
+    };
+    LodSelection { level, fade }
+}
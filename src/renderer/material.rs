@@ -1,58 +1,113 @@
+use super::arena::{Arena, Handle};
 use super::shader::ShaderType;
-use std::collections::HashMap;
+use super::texture::TextureId;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlendMode {
+    #[default]
+    Opaque,
+    // Drawn after all opaque surfaces without writing depth
+    Transparent,
+}
 
 #[allow(dead_code)]
 pub struct Material {
     pub name: String,
     pub shader_type: ShaderType,
-    pub diffuse_texture_id: u32,
+    pub diffuse_texture_id: TextureId,
+    pub blend_mode: BlendMode,
 }
 
+impl Material {
+    pub fn is_transparent(&self) -> bool {
+        self.blend_mode == BlendMode::Transparent
+    }
+}
+
+pub type MaterialId = Handle<Material>;
+
 pub struct MaterialStore {
     pub default_material: Material,
-    materials: HashMap<u32, Material>,
-    next_id: u32,
+    // Given to submodels until something else is assigned
+    pub basic_material: MaterialId,
+    pub grid_material: MaterialId,
+    materials: Arena<Material>,
 }
 
 impl MaterialStore {
-    pub fn new() -> Self {
+    // Materials without a texture of their own sample `default_texture`
+    pub fn new(default_texture: TextureId) -> Self {
         let default_material = Material {
             name: ShaderType::Basic.to_string(),
             shader_type: ShaderType::Basic,
-            diffuse_texture_id: 0,
+            diffuse_texture_id: default_texture,
+            blend_mode: BlendMode::Opaque,
         };
         let basic_material = Material {
             name: ShaderType::Basic.to_string(),
             shader_type: ShaderType::Basic,
-            diffuse_texture_id: 0,
+            diffuse_texture_id: default_texture,
+            blend_mode: BlendMode::Opaque,
         };
         let grid_material = Material {
             name: ShaderType::Grid.to_string(),
             shader_type: ShaderType::Grid,
-            diffuse_texture_id: 0,
+            diffuse_texture_id: default_texture,
+            blend_mode: BlendMode::Transparent,
         };
 
-        let materials: HashMap<u32, Material> = vec![(0, basic_material), (1, grid_material)]
-            .into_iter()
-            .collect();
-
-        let next_id = materials.len() as u32 + 1;
+        let mut materials = Arena::new();
+        let basic_material = materials.insert(basic_material);
+        let grid_material = materials.insert(grid_material);
 
         Self {
             default_material,
+            basic_material,
+            grid_material,
             materials,
-            next_id,
         }
     }
 
-    pub fn add_material(&mut self, material: Material) -> u32 {
-        let id = self.next_id;
-        self.next_id += 1;
-        self.materials.insert(id, material);
-        id
+    pub fn add_material(&mut self, material: Material) -> MaterialId {
+        self.materials.insert(material)
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_material(&mut self, id: MaterialId) -> Option<Material> {
+        self.materials.remove(id)
+    }
+
+    pub fn get_material(&self, id: MaterialId) -> &Material {
+        self.materials.get(id).unwrap_or(&self.default_material)
+    }
+
+    #[allow(dead_code)]
+    pub fn get_material_mut(&mut self, id: MaterialId) -> Option<&mut Material> {
+        self.materials.get_mut(id)
+    }
+
+    pub fn find_by_name(&self, name: &str) -> Option<MaterialId> {
+        self.materials
+            .iter()
+            .find(|(_, material)| material.name == name)
+            .map(|(id, _)| id)
+    }
+
+    pub fn uses_texture(&self, id: TextureId) -> bool {
+        self.default_material.diffuse_texture_id == id
+            || self
+                .materials
+                .values()
+                .any(|material| material.diffuse_texture_id == id)
     }
 
-    pub fn get_material(&self, id: u32) -> &Material {
-        self.materials.get(&id).unwrap_or(&self.default_material)
+    // Ids of all materials in ascending order
+    #[allow(dead_code)]
+    pub fn ids(&self) -> Vec<MaterialId> {
+        let mut ids = self.materials.iter().map(|(id, _)| id).collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
     }
 }
@@ -1,13 +1,40 @@
 pub mod util;
 
+pub mod arena;
+pub mod asset;
+pub mod bounds;
 pub mod camera;
+pub mod components;
 pub mod context;
+pub mod debug_draw;
+pub mod debug_view;
+pub mod ecs;
+pub mod editor;
+pub mod environment;
+pub mod gizmo;
+pub mod gpu_driven;
+pub mod graph;
+pub mod hdr;
+pub mod hot_reload;
+pub mod instance;
+pub mod lod;
 pub mod material;
+pub mod mipmap;
 pub mod model;
 pub mod object;
+pub mod overlay;
+pub mod picking;
 pub mod pipeline;
+pub mod post_process;
+pub mod scene;
+pub mod selection;
 pub mod shader;
+pub mod simplify;
+pub mod text;
 pub mod texture;
+pub mod texture_data;
+pub mod transparency;
 
+#[allow(clippy::module_inception)]
 pub mod renderer;
 pub use renderer::Renderer;
@@ -1,11 +1,20 @@
-use super::material::{Material, MaterialStore};
+use super::bounds::{Aabb, BoundingSphere};
+use super::material::{BlendMode, Material, MaterialId, MaterialStore};
 use super::shader::ShaderType;
+use super::simplify::simplify;
 use super::util::resources;
 
+use anyhow::Context;
+use cgmath::{InnerSpace, Vector3};
 use wgpu::util::DeviceExt;
 
 use std::io::{BufReader, Cursor};
-use std::path::{Path, PathBuf};
+use std::path::Path;
+use std::sync::Arc;
+
+const MAX_LOD_COUNT: usize = 4;
+// Error budget of the first simplified level relative to the mesh extent
+const LOD_ERROR: f32 = 0.005;
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
@@ -46,116 +55,348 @@ impl Vertex for ModelVertex {
     }
 }
 
+// A range of the mesh's index buffer drawing one level of detail
+#[allow(dead_code)]
+#[derive(Clone, Copy, Debug)]
+pub struct MeshLod {
+    pub first_index: u32,
+    pub index_count: u32,
+    pub base_vertex: i32,
+    // Largest distance the simplifier moved the surface, in model units
+    pub error: f32,
+}
+
 #[allow(dead_code)]
 pub struct Mesh {
     pub name: String,
     pub vertex_buffer: wgpu::Buffer,
     pub index_buffer: wgpu::Buffer,
     pub num_elements: u32,
+    // Finest level first; `num_elements` covers level 0
+    pub lods: Vec<MeshLod>,
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
 }
 
+// Models cloned from the same upload share their meshes, but each clone has
+// its own materials
+#[derive(Clone)]
 pub struct SubModel {
-    pub mesh: Mesh,
-    pub material_id: u32,
+    pub mesh: Arc<Mesh>,
+    pub material_id: MaterialId,
 }
 
+#[derive(Clone)]
 pub struct Model {
     pub data: Vec<SubModel>,
 }
 
+impl Model {
+    pub fn aabb(&self) -> Aabb {
+        self.data
+            .iter()
+            .map(|submodel| submodel.mesh.aabb)
+            .reduce(|a, b| a.union(&b))
+            .unwrap_or(Aabb {
+                min: Vector3::new(0.0, 0.0, 0.0),
+                max: Vector3::new(0.0, 0.0, 0.0),
+            })
+    }
+
+    // Sphere around the model's box center enclosing every mesh sphere
+    pub fn bounding_sphere(&self) -> BoundingSphere {
+        let center = self.aabb().center();
+        let radius = self
+            .data
+            .iter()
+            .map(|submodel| {
+                let sphere = submodel.mesh.bounding_sphere;
+                (sphere.center - center).magnitude() + sphere.radius
+            })
+            .fold(0.0, f32::max);
+        BoundingSphere { center, radius }
+    }
+}
+
+// CPU side of a model file, parsed off the render thread
+pub struct ModelData {
+    pub submodels: Vec<SubModelData>,
+    // Name and blend mode of each material in the MTL files
+    pub materials: Vec<(String, BlendMode)>,
+}
+
+pub struct SubModelData {
+    // Index into `ModelData::materials`
+    pub material: Option<usize>,
+    pub vertices: Vec<ModelVertex>,
+    pub indices: Vec<u32>,
+    pub lods: Vec<MeshLod>,
+    pub aabb: Aabb,
+    pub bounding_sphere: BoundingSphere,
+}
+
 pub async fn load_model(
-    file_path: &PathBuf,
+    file_path: &Path,
     device: &wgpu::Device,
     material_store: &mut MaterialStore,
 ) -> anyhow::Result<Model> {
-    let obj_text = resources::load_resource(file_path).unwrap();
-    let obj_cursor = Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
+    let data = parse_model(file_path)?;
+    Ok(upload_model(&data, file_path, device, material_store))
+}
+
+// Reads and triangulates the OBJ with its materials and builds the levels of
+// detail. Touches no GPU state, so it can run on any thread.
+pub fn parse_model(file_path: &Path) -> anyhow::Result<ModelData> {
+    let obj_text = resources::load_resource(&file_path.to_path_buf())
+        .with_context(|| format!("Failed to read {}", file_path.display()))?;
+    let mut obj_reader = BufReader::new(Cursor::new(obj_text));
 
-    let (models, obj_materials) = tobj::load_obj_buf_async(
+    let directory = file_path.parent().unwrap_or(Path::new(""));
+    let (models, obj_materials) = tobj::load_obj_buf(
         &mut obj_reader,
         &tobj::LoadOptions {
             triangulate: true,
             single_index: true,
             ..Default::default()
         },
-        |p| async move {
-            let directory = Path::new(file_path).parent().unwrap();
-            let mat_text = resources::load_resource(&directory.join(p)).unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
+        |p| match resources::load_resource(&directory.join(p)) {
+            Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+            Err(_) => Err(tobj::LoadError::OpenFileFailed),
         },
     )
-    .await?;
+    .with_context(|| format!("Failed to parse {}", file_path.display()))?;
 
-    for m in obj_materials? {
-        material_store.add_material(Material {
-            name: m.name,
-            shader_type: ShaderType::Basic,
-            diffuse_texture_id: 0,
-        });
-    }
-
-    let submodels = models
+    let materials = obj_materials
+        .with_context(|| format!("Failed to load the materials of {}", file_path.display()))?
         .into_iter()
         .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| {
-                    if m.mesh.normals.is_empty() {
-                        ModelVertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coords: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
-                            normal: [0.0, 0.0, 0.0],
-                        }
-                    } else {
-                        ModelVertex {
-                            position: [
-                                m.mesh.positions[i * 3],
-                                m.mesh.positions[i * 3 + 1],
-                                m.mesh.positions[i * 3 + 2],
-                            ],
-                            tex_coords: [
-                                m.mesh.texcoords[i * 2],
-                                1.0 - m.mesh.texcoords[i * 2 + 1],
-                            ],
-                            normal: [
-                                m.mesh.normals[i * 3],
-                                m.mesh.normals[i * 3 + 1],
-                                m.mesh.normals[i * 3 + 2],
-                            ],
-                        }
+            let blend_mode = match m.dissolve {
+                Some(dissolve) if dissolve < 1.0 => BlendMode::Transparent,
+                _ => BlendMode::Opaque,
+            };
+            (m.name, blend_mode)
+        })
+        .collect();
+
+    // Meshes named `<name>_LOD<n>` are levels of detail of `<name>` and share
+    // its material
+    let mut groups: Vec<(String, Option<usize>, Vec<SourceLod>)> = Vec::new();
+    for m in models {
+        let (name, level) = split_lod_suffix(&m.name);
+        let vertices = (0..m.mesh.positions.len() / 3)
+            .map(|i| {
+                if m.mesh.normals.is_empty() {
+                    ModelVertex {
+                        position: [
+                            m.mesh.positions[i * 3],
+                            m.mesh.positions[i * 3 + 1],
+                            m.mesh.positions[i * 3 + 2],
+                        ],
+                        tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
+                        normal: [0.0, 0.0, 0.0],
                     }
-                })
+                } else {
+                    ModelVertex {
+                        position: [
+                            m.mesh.positions[i * 3],
+                            m.mesh.positions[i * 3 + 1],
+                            m.mesh.positions[i * 3 + 2],
+                        ],
+                        tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
+                        normal: [
+                            m.mesh.normals[i * 3],
+                            m.mesh.normals[i * 3 + 1],
+                            m.mesh.normals[i * 3 + 2],
+                        ],
+                    }
+                }
+            })
+            .collect::<Vec<_>>();
+
+        match groups.iter_mut().find(|(group, _, _)| *group == name) {
+            Some((_, material, levels)) => {
+                *material = material.or(m.mesh.material_id);
+                levels.push((level, vertices, m.mesh.indices));
+            }
+            None => groups.push((
+                name.to_string(),
+                m.mesh.material_id,
+                vec![(level, vertices, m.mesh.indices)],
+            )),
+        }
+    }
+
+    let submodels = groups
+        .into_iter()
+        .map(|(_, material, mut levels)| {
+            levels.sort_by_key(|(level, _, _)| *level);
+            let (vertices, indices, lods) = if levels.len() > 1 {
+                merge_lods(levels)
+            } else {
+                let (_, vertices, indices) = levels.pop().unwrap();
+                let (indices, lods) = generate_lods(&vertices, indices);
+                (vertices, indices, lods)
+            };
+
+            let positions = vertices
+                .iter()
+                .map(|vertex| Vector3::from(vertex.position))
                 .collect::<Vec<_>>();
+            let aabb = Aabb::from_points(positions.iter().copied()).unwrap_or(Aabb {
+                min: Vector3::new(0.0, 0.0, 0.0),
+                max: Vector3::new(0.0, 0.0, 0.0),
+            });
+            let bounding_sphere = BoundingSphere::from_points(&positions, &aabb);
+            SubModelData {
+                material,
+                vertices,
+                indices,
+                lods,
+                aabb,
+                bounding_sphere,
+            }
+        })
+        .collect();
+
+    Ok(ModelData {
+        submodels,
+        materials,
+    })
+}
+
+// Creates the buffers and registers the file's materials. Submodels without
+// a material in the MTL get the basic material.
+pub fn upload_model(
+    data: &ModelData,
+    file_path: &Path,
+    device: &wgpu::Device,
+    material_store: &mut MaterialStore,
+) -> Model {
+    // Reloads and files sharing an MTL do not add duplicates
+    let material_ids = data
+        .materials
+        .iter()
+        .map(|(name, blend_mode)| {
+            material_store.find_by_name(name).unwrap_or_else(|| {
+                material_store.add_material(Material {
+                    name: name.clone(),
+                    shader_type: ShaderType::Basic,
+                    diffuse_texture_id: material_store.default_material.diffuse_texture_id,
+                    blend_mode: *blend_mode,
+                })
+            })
+        })
+        .collect::<Vec<_>>();
 
+    let submodels = data
+        .submodels
+        .iter()
+        .map(|submodel| {
             let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Vertex Buffer", file_path)),
-                contents: bytemuck::cast_slice(&vertices),
-                usage: wgpu::BufferUsages::VERTEX,
+                contents: bytemuck::cast_slice(&submodel.vertices),
+                // Copied into the shared buffers of the GPU driven scene and
+                // read as storage by the debug views
+                usage: wgpu::BufferUsages::VERTEX
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::STORAGE,
             });
             let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
                 label: Some(&format!("{:?} Index Buffer", file_path)),
-                contents: bytemuck::cast_slice(&m.mesh.indices),
-                usage: wgpu::BufferUsages::INDEX,
+                contents: bytemuck::cast_slice(&submodel.indices),
+                usage: wgpu::BufferUsages::INDEX
+                    | wgpu::BufferUsages::COPY_SRC
+                    | wgpu::BufferUsages::STORAGE,
             });
 
             SubModel {
-                mesh: Mesh {
-                    name: file_path.to_str().unwrap().to_string(),
+                mesh: Arc::new(Mesh {
+                    name: file_path.to_string_lossy().into_owned(),
                     vertex_buffer,
                     index_buffer,
-                    num_elements: m.mesh.indices.len() as u32,
-                },
-                material_id: 0,
+                    num_elements: submodel.lods[0].index_count,
+                    lods: submodel.lods.clone(),
+                    aabb: submodel.aabb,
+                    bounding_sphere: submodel.bounding_sphere,
+                }),
+                material_id: submodel
+                    .material
+                    .and_then(|index| material_ids.get(index).copied())
+                    .unwrap_or(material_store.basic_material),
             }
         })
         .collect::<Vec<_>>();
 
-    Ok(Model { data: submodels })
+    Model { data: submodels }
+}
+
+// Level number, vertices and indices of one mesh in the source file
+type SourceLod = (u32, Vec<ModelVertex>, Vec<u32>);
+
+fn split_lod_suffix(name: &str) -> (&str, u32) {
+    if let Some((base, level)) = name
+        .rsplit_once("_LOD")
+        .or_else(|| name.rsplit_once("_lod"))
+    {
+        if let Ok(level) = level.parse() {
+            return (base, level);
+        }
+    }
+    (name, 0)
+}
+
+// Levels from the source file are appended into one vertex and index buffer
+fn merge_lods(levels: Vec<SourceLod>) -> (Vec<ModelVertex>, Vec<u32>, Vec<MeshLod>) {
+    let mut vertices = Vec::new();
+    let mut indices = Vec::new();
+    let mut lods = Vec::new();
+    for (_, level_vertices, level_indices) in levels {
+        lods.push(MeshLod {
+            first_index: indices.len() as u32,
+            index_count: level_indices.len() as u32,
+            base_vertex: vertices.len() as i32,
+            error: 0.0,
+        });
+        vertices.extend(level_vertices);
+        indices.extend(level_indices);
+    }
+    (vertices, indices, lods)
+}
+
+// Simplified levels share the full vertex buffer, each halving the triangle
+// count of the previous one while staying within an error budget that
+// doubles with every level. Stops early once a level barely shrinks.
+fn generate_lods(vertices: &[ModelVertex], indices: Vec<u32>) -> (Vec<u32>, Vec<MeshLod>) {
+    let positions = vertices
+        .iter()
+        .map(|vertex| vertex.position)
+        .collect::<Vec<_>>();
+    let extent = Aabb::from_points(positions.iter().map(|&position| Vector3::from(position)))
+        .map(|aabb| (aabb.max - aabb.min).magnitude())
+        .unwrap_or(0.0);
+
+    let mut lods = vec![MeshLod {
+        first_index: 0,
+        index_count: indices.len() as u32,
+        base_vertex: 0,
+        error: 0.0,
+    }];
+    let mut all_indices = indices.clone();
+    let mut previous = indices;
+    for level in 1..MAX_LOD_COUNT {
+        let max_error = extent * LOD_ERROR * (1 << level) as f32;
+        let (simplified, error) = simplify(&positions, &previous, previous.len() / 2, max_error);
+        if simplified.is_empty() || simplified.len() as f32 > previous.len() as f32 * 0.9 {
+            break;
+        }
+        lods.push(MeshLod {
+            first_index: all_indices.len() as u32,
+            index_count: simplified.len() as u32,
+            base_vertex: 0,
+            error,
+        });
+        all_indices.extend_from_slice(&simplified);
+        previous = simplified;
+    }
+    (all_indices, lods)
 }
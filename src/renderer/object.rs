@@ -1,14 +1,23 @@
-use std::path::PathBuf;
+use std::collections::HashSet;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
-use cgmath::{Deg, Matrix4, Quaternion, Rotation3, SquareMatrix, Vector3};
+use anyhow::bail;
+use cgmath::{Deg, InnerSpace, Matrix3, Matrix4, Quaternion, Rotation3, SquareMatrix, Vector3};
+use log::warn;
 use wgpu::util::DeviceExt;
 
 use super::{
+    arena::{Arena, Handle},
+    asset::{AssetServer, ModelHandle},
+    bounds::{Aabb, BoundingSphere, CullingStats, Frustum},
+    camera::CameraEye,
     context::Context,
-    material::MaterialStore,
-    model::{load_model, Mesh, Model},
-    pipeline::PipelineStore,
-    shader::ShaderType,
+    instance::{InstancedObject, InstancedObjectId},
+    lod::{screen_size, select_lod, LodSelection, LodSettings},
+    material::{MaterialId, MaterialStore},
+    model::{load_model, Mesh, MeshLod, Model},
+    pipeline::{Blending, PipelineStore},
     texture::TextureStore,
 };
 
@@ -16,12 +25,15 @@ use super::{
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct TransformUniform {
     matrix: [[f32; 4]; 4],
+    // x is the share of pixels drawn by the first of two cross-fading levels
+    lod_fade: [f32; 4],
 }
 
 impl TransformUniform {
     pub fn new() -> Self {
         Self {
             matrix: Matrix4::identity().into(),
+            lod_fade: [1.0, 0.0, 0.0, 0.0],
         }
     }
 
@@ -36,17 +48,128 @@ impl TransformUniform {
             * Matrix4::from_nonuniform_scale(scale.x, scale.y, scale.z))
         .into();
     }
+
+    pub fn set_matrix(&mut self, matrix: Matrix4<f32>) {
+        self.matrix = matrix.into();
+    }
+
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from(self.matrix)
+    }
+
+    pub fn set_lod_fade(&mut self, fade: f32) {
+        self.lod_fade[0] = fade;
+    }
+}
+
+// Translation, rotation and scale relative to a parent or the world
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+    pub scale: Vector3<f32>,
+}
+
+impl Transform {
+    pub fn matrix(&self) -> Matrix4<f32> {
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.rotation)
+            * Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z)
+    }
+
+    // Shear from non-uniformly scaled, rotated parents cannot be expressed
+    // and is dropped
+    pub fn from_matrix(matrix: &Matrix4<f32>) -> Self {
+        let axes = [
+            matrix.x.truncate(),
+            matrix.y.truncate(),
+            matrix.z.truncate(),
+        ];
+        let mut scale = Vector3::new(
+            axes[0].magnitude(),
+            axes[1].magnitude(),
+            axes[2].magnitude(),
+        );
+        // A mirrored basis flips one axis instead of the rotation
+        if Matrix3::from_cols(axes[0], axes[1], axes[2]).determinant() < 0.0 {
+            scale.x = -scale.x;
+        }
+        let unit = |axis: Vector3<f32>, length: f32| {
+            if length.abs() > f32::EPSILON {
+                axis / length
+            } else {
+                axis
+            }
+        };
+        let rotation = Matrix3::from_cols(
+            unit(axes[0], scale.x),
+            unit(axes[1], scale.y),
+            unit(axes[2], scale.z),
+        );
+
+        Self {
+            position: matrix.w.truncate(),
+            rotation: Quaternion::from(rotation).normalize(),
+            scale,
+        }
+    }
+}
+
+// Which transform to keep when an object moves to another parent
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum KeepTransform {
+    // Stays where it is in the world
+    World,
+    // Moves along with the new parent
+    Local,
+}
+
+// Placement along the fourth axis for the 4D shader; the 3D projection does
+// not use it yet
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct HyperTransform {
+    pub w: f32,
+    pub scale_w: f32,
+    // Angles in radians in the XW, YW and ZW planes
+    pub rotation: Vector3<f32>,
+}
+
+impl Default for HyperTransform {
+    fn default() -> Self {
+        Self {
+            w: 0.0,
+            scale_w: 1.0,
+            rotation: Vector3::new(0.0, 0.0, 0.0),
+        }
+    }
 }
 
 #[allow(dead_code)]
 pub struct Object {
     model: Model,
+    // Relative to `res`, for objects loaded from a file
+    model_path: Option<PathBuf>,
+    // Keeps the shared model loaded, and swaps it in once a background load
+    // finishes
+    model_asset: Option<ModelHandle>,
     position: Vector3<f32>,
     rotation: Quaternion<f32>,
     scale: Vector3<f32>,
+    hyper: HyperTransform,
     transform_uniform: TransformUniform,
     uniform_buffer: wgpu::Buffer,
     bind_group: wgpu::BindGroup,
+    // Model space bounds and their world space counterparts after `update`
+    aabb: Aabb,
+    bounding_sphere: BoundingSphere,
+    world_aabb: Aabb,
+    world_bounding_sphere: BoundingSphere,
+    visible: bool,
+    lod: LodSelection,
+    parent: Option<ObjectId>,
+    children: Vec<ObjectId>,
+    // The local transform changed since the world matrix was last computed
+    dirty: bool,
 }
 
 impl Object {
@@ -68,7 +191,7 @@ impl Object {
         });
 
         let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: &bind_group_layout,
+            layout: bind_group_layout,
             entries: &[wgpu::BindGroupEntry {
                 binding: 0,
                 resource: uniform_buffer.as_entire_binding(),
@@ -76,19 +199,35 @@ impl Object {
             label: Some("object_bind_group"),
         });
 
+        let aabb = model.aabb();
+        let bounding_sphere = model.bounding_sphere();
+        let matrix = transform_uniform.matrix();
+
         Self {
             model,
+            model_path: None,
+            model_asset: None,
             position,
             rotation,
             scale,
+            hyper: HyperTransform::default(),
             transform_uniform,
             uniform_buffer,
             bind_group,
+            aabb,
+            bounding_sphere,
+            world_aabb: aabb.transformed(&matrix),
+            world_bounding_sphere: bounding_sphere.transformed(&matrix),
+            visible: true,
+            lod: LodSelection::default(),
+            parent: None,
+            children: Vec::new(),
+            dirty: true,
         }
     }
 
     pub async fn from_model_path(
-        model_path: &PathBuf,
+        model_path: &Path,
         context: &Context<'_>,
         material_store: &mut MaterialStore,
         bind_group_layout: &wgpu::BindGroupLayout,
@@ -97,19 +236,22 @@ impl Object {
             .await
             .unwrap();
 
-        Self::new(
+        let mut object = Self::new(
             model,
             Vector3::new(0.0, 0.0, 0.0),
             Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0)),
             Vector3::new(1.0, 1.0, 1.0),
             &context.device,
-            &bind_group_layout,
-        )
+            bind_group_layout,
+        );
+        object.model_path = Some(model_path.to_path_buf());
+        object
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
-        self.transform_uniform
-            .calculate(self.position, self.rotation, self.scale);
+    // Expects the world matrix to be propagated already
+    pub fn update(&mut self, queue: &wgpu::Queue, eye: &CameraEye, lod_settings: &LodSettings) {
+        self.lod = select_lod(screen_size(&self.world_bounding_sphere, eye), lod_settings);
+        self.transform_uniform.set_lod_fade(self.lod.fade);
 
         queue.write_buffer(
             &self.uniform_buffer,
@@ -117,29 +259,181 @@ impl Object {
             bytemuck::cast_slice(&[self.transform_uniform]),
         );
     }
+
+    fn set_world_matrix(&mut self, matrix: Matrix4<f32>) {
+        self.transform_uniform.set_matrix(matrix);
+        self.world_aabb = self.aabb.transformed(&matrix);
+        self.world_bounding_sphere = self.bounding_sphere.transformed(&matrix);
+        self.dirty = false;
+    }
+}
+
+#[allow(dead_code)]
+impl Object {
+    pub fn world_aabb(&self) -> &Aabb {
+        &self.world_aabb
+    }
+
+    pub fn world_bounding_sphere(&self) -> &BoundingSphere {
+        &self.world_bounding_sphere
+    }
+
+    pub fn is_visible(&self) -> bool {
+        self.visible
+    }
+
+    pub fn lod(&self) -> LodSelection {
+        self.lod
+    }
+
+    pub fn model(&self) -> &Model {
+        &self.model
+    }
+
+    pub fn model_mut(&mut self) -> &mut Model {
+        &mut self.model
+    }
+
+    pub fn model_path(&self) -> Option<&PathBuf> {
+        self.model_path.as_ref()
+    }
+
+    pub fn model_asset(&self) -> Option<&ModelHandle> {
+        self.model_asset.as_ref()
+    }
+
+    // Replaces the model while keeping the transform
+    pub fn set_model(&mut self, model: Model) {
+        self.aabb = model.aabb();
+        self.bounding_sphere = model.bounding_sphere();
+        self.model = model;
+        self.lod = LodSelection::default();
+        self.dirty = true;
+    }
+
+    // File name of the model the object was loaded from
+    pub fn name(&self) -> String {
+        self.model_path
+            .clone()
+            .or_else(|| {
+                let data = self.model.data.first()?;
+                Some(PathBuf::from(&data.mesh.name))
+            })
+            .and_then(|path| {
+                path.file_stem()
+                    .map(|stem| stem.to_string_lossy().into_owned())
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn position(&self) -> Vector3<f32> {
+        self.position
+    }
+
+    pub fn rotation(&self) -> Quaternion<f32> {
+        self.rotation
+    }
+
+    pub fn scale(&self) -> Vector3<f32> {
+        self.scale
+    }
+
+    // Relative to the parent. Changes reach the children and the GPU in the
+    // next `ObjectManager::update`.
+    pub fn local_transform(&self) -> Transform {
+        Transform {
+            position: self.position,
+            rotation: self.rotation,
+            scale: self.scale,
+        }
+    }
+
+    pub fn set_local_transform(&mut self, transform: Transform) {
+        self.position = transform.position;
+        self.rotation = transform.rotation;
+        self.scale = transform.scale;
+        self.dirty = true;
+    }
+
+    pub fn set_position(&mut self, position: Vector3<f32>) {
+        self.position = position;
+        self.dirty = true;
+    }
+
+    pub fn set_rotation(&mut self, rotation: Quaternion<f32>) {
+        self.rotation = rotation;
+        self.dirty = true;
+    }
+
+    pub fn set_scale(&mut self, scale: Vector3<f32>) {
+        self.scale = scale;
+        self.dirty = true;
+    }
+
+    // As of the last `ObjectManager::update`
+    pub fn world_matrix(&self) -> Matrix4<f32> {
+        self.transform_uniform.matrix()
+    }
+
+    pub fn parent(&self) -> Option<ObjectId> {
+        self.parent
+    }
+
+    pub fn children(&self) -> &[ObjectId] {
+        &self.children
+    }
+
+    pub fn hyper(&self) -> HyperTransform {
+        self.hyper
+    }
+
+    pub fn set_hyper(&mut self, hyper: HyperTransform) {
+        self.hyper = hyper;
+    }
+
+    pub fn bind_group(&self) -> &wgpu::BindGroup {
+        &self.bind_group
+    }
+
+    pub fn has_transparent(&self, material_store: &MaterialStore) -> bool {
+        self.model.data.iter().any(|data| {
+            material_store
+                .get_material(data.material_id)
+                .is_transparent()
+        })
+    }
+
+    // The sphere test is cheaper and rejects most objects, the box is tighter
+    fn cull(&mut self, frustum: &Frustum) -> bool {
+        self.visible = frustum.intersects_sphere(&self.world_bounding_sphere)
+            && frustum.intersects_aabb(&self.world_aabb);
+        self.visible
+    }
 }
 
 fn deduce_pipeline<'a>(
-    material_id: u32,
+    material_id: MaterialId,
     material_store: &'a MaterialStore,
     pipeline_store: &'a PipelineStore,
+    blending: Blending,
 ) -> &'a wgpu::RenderPipeline {
     let material = material_store.get_material(material_id);
-    match material.shader_type {
-        ShaderType::Basic => &pipeline_store.basic,
-        ShaderType::Grid => &pipeline_store.grid,
-        ShaderType::Hyper => &pipeline_store.hyper,
-    }
+    pipeline_store
+        .material_pipelines(blending)
+        .get(&material.shader_type)
 }
 
 pub trait DrawObject<'a> {
     fn draw_mesh(
         &mut self,
         mesh: &'a Mesh,
+        lod: &MeshLod,
+        instances: Range<u32>,
         camera_bind_group: &'a wgpu::BindGroup,
         diffuse_bind_group: &'a wgpu::BindGroup,
         translation_bind_group: &'a wgpu::BindGroup,
     );
+    // Draws the submodels whose materials belong to the given kind of pass
     fn draw_object(
         &mut self,
         object: &'a Object,
@@ -147,6 +441,16 @@ pub trait DrawObject<'a> {
         material_store: &'a MaterialStore,
         texture_store: &'a TextureStore,
         pipeline_store: &'a PipelineStore,
+        blending: Blending,
+    );
+    fn draw_instanced_object(
+        &mut self,
+        object: &'a InstancedObject,
+        camera_bind_group: &'a wgpu::BindGroup,
+        material_store: &'a MaterialStore,
+        texture_store: &'a TextureStore,
+        pipeline_store: &'a PipelineStore,
+        blending: Blending,
     );
 }
 
@@ -157,16 +461,22 @@ where
     fn draw_mesh(
         &mut self,
         mesh: &'b Mesh,
+        lod: &MeshLod,
+        instances: Range<u32>,
         camera_bind_group: &'b wgpu::BindGroup,
         diffuse_bind_group: &'b wgpu::BindGroup,
         translation_bind_group: &'b wgpu::BindGroup,
     ) {
         self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
         self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
-        self.set_bind_group(0, &camera_bind_group, &[]);
-        self.set_bind_group(1, &diffuse_bind_group, &[]);
-        self.set_bind_group(2, &translation_bind_group, &[]);
-        self.draw_indexed(0..mesh.num_elements, 0, 0..1);
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(1, diffuse_bind_group, &[]);
+        self.set_bind_group(2, translation_bind_group, &[]);
+        self.draw_indexed(
+            lod.first_index..lod.first_index + lod.index_count,
+            lod.base_vertex,
+            instances,
+        );
     }
 
     fn draw_object(
@@ -176,30 +486,102 @@ where
         material_store: &'a MaterialStore,
         texture_store: &'a TextureStore,
         pipeline_store: &'a PipelineStore,
+        blending: Blending,
     ) {
         for data in &object.model.data {
             let mesh = &data.mesh;
             let material = material_store.get_material(data.material_id);
+            if material.is_transparent() == (blending == Blending::Opaque) {
+                continue;
+            }
             let diffuse = texture_store.get_texture(material.diffuse_texture_id);
             self.set_pipeline(deduce_pipeline(
                 data.material_id,
                 material_store,
                 pipeline_store,
+                blending,
             ));
+
+            // While cross-fading the finer level covers its share of pixels as
+            // instance 0 and the coarser one the rest as instance 1; meshes
+            // with fewer levels draw their last level both ways
+            let lod = object.lod;
+            let last = mesh.lods.len() - 1;
+            let level = &mesh.lods[lod.level.min(last)];
+            if !lod.is_fading() {
+                self.draw_mesh(
+                    mesh,
+                    level,
+                    0..1,
+                    camera_bind_group,
+                    diffuse.bind_group.as_ref().unwrap(),
+                    &object.bind_group,
+                );
+                continue;
+            }
+            let next = &mesh.lods[(lod.level + 1).min(last)];
             self.draw_mesh(
                 mesh,
+                level,
+                0..1,
+                camera_bind_group,
+                diffuse.bind_group.as_ref().unwrap(),
+                &object.bind_group,
+            );
+            self.draw_mesh(
+                mesh,
+                next,
+                1..2,
                 camera_bind_group,
                 diffuse.bind_group.as_ref().unwrap(),
                 &object.bind_group,
             );
         }
     }
+
+    // Only the basic shader has an instanced variant, so the material's
+    // shader type is ignored but its blend mode is not
+    fn draw_instanced_object(
+        &mut self,
+        object: &'a InstancedObject,
+        camera_bind_group: &'a wgpu::BindGroup,
+        material_store: &'a MaterialStore,
+        texture_store: &'a TextureStore,
+        pipeline_store: &'a PipelineStore,
+        blending: Blending,
+    ) {
+        if object.instance_count() == 0 {
+            return;
+        }
+
+        self.set_pipeline(pipeline_store.basic_instanced(blending));
+        self.set_vertex_buffer(1, object.instance_buffer().slice(..));
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(2, &object.bind_group, &[]);
+        for data in &object.model.data {
+            let mesh = &data.mesh;
+            let material = material_store.get_material(data.material_id);
+            if material.is_transparent() == (blending == Blending::Opaque) {
+                continue;
+            }
+            let diffuse = texture_store.get_texture(material.diffuse_texture_id);
+            self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+            self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+            self.set_bind_group(1, diffuse.bind_group.as_ref().unwrap(), &[]);
+            self.draw_indexed(0..mesh.num_elements, 0, 0..object.instance_count());
+        }
+    }
 }
 
+pub type ObjectId = Handle<Object>;
+
 pub struct ObjectManager {
     pub bind_group_layout: wgpu::BindGroupLayout,
-    actors: Vec<Object>,            // User-defined objects
-    immutable_objects: [Object; 1], // Static objects
+    // User-defined and static objects share one arena so every object has
+    // an id, but only actors can be removed
+    objects: Arena<Object>,
+    immutable_objects: [ObjectId; 1],
+    instanced_objects: Arena<InstancedObject>,
 }
 
 #[allow(dead_code)]
@@ -230,48 +612,305 @@ impl<'a> ObjectManager {
         )
         .await;
         grid.scale = Vector3::new(100.0, 100.0, 100.0);
-        grid.model.data[0].material_id = 1;
+        grid.model.data[0].material_id = material_store.grid_material;
+
+        let mut objects = Arena::new();
+        let grid = objects.insert(grid);
 
         Self {
-            bind_group_layout: bind_group_layout,
-            actors: Vec::new(),
+            bind_group_layout,
+            objects,
             immutable_objects: [grid],
+            instanced_objects: Arena::new(),
+        }
+    }
+
+    pub fn add_actor(&mut self, object: Object) -> ObjectId {
+        self.objects.insert(object)
+    }
+
+    // Returns None for stale ids and static objects. Children move up to the
+    // removed object's parent and stay where they are in the world.
+    pub fn remove_actor(&mut self, id: ObjectId) -> Option<Object> {
+        if self.immutable_objects.contains(&id) {
+            warn!("Object {id} is static and cannot be removed");
+            return None;
+        }
+        let object = self.objects.get(id)?;
+        let (parent, children) = (object.parent, object.children.clone());
+        for child in children {
+            if let Err(e) = self.set_parent(child, parent, KeepTransform::World) {
+                warn!("Failed to move child {child} of removed object {id}: {e}");
+            }
+        }
+        self.set_parent(id, None, KeepTransform::Local).ok()?;
+        self.objects.remove(id)
+    }
+
+    // Attaches `child` to `parent`, or makes it a root with None
+    pub fn set_parent(
+        &mut self,
+        child: ObjectId,
+        parent: Option<ObjectId>,
+        keep: KeepTransform,
+    ) -> anyhow::Result<()> {
+        if !self.objects.contains(child) {
+            bail!("Object {child} does not exist");
+        }
+        if let Some(parent) = parent {
+            if !self.objects.contains(parent) {
+                bail!("Parent {parent} does not exist");
+            }
+            if self.ancestors(parent).any(|ancestor| ancestor == child) || parent == child {
+                bail!("Object {child} cannot be parented to its own descendant {parent}");
+            }
+        }
+
+        let world = self.world_transform(child);
+        if let Some(old_parent) = self.objects.get(child).and_then(|object| object.parent) {
+            if let Some(old_parent) = self.objects.get_mut(old_parent) {
+                old_parent.children.retain(|&id| id != child);
+            }
+        }
+        if let Some(parent) = parent.and_then(|parent| self.objects.get_mut(parent)) {
+            parent.children.push(child);
+        }
+        let object = self.objects.get_mut(child).unwrap();
+        object.parent = parent;
+        object.dirty = true;
+
+        if let (KeepTransform::World, Some(world)) = (keep, world) {
+            self.set_world_transform(child, world);
+        }
+        Ok(())
+    }
+
+    // From the parent up to the root
+    pub fn ancestors(&self, id: ObjectId) -> impl Iterator<Item = ObjectId> + '_ {
+        std::iter::successors(
+            self.objects.get(id).and_then(|object| object.parent),
+            |&parent| self.objects.get(parent).and_then(|object| object.parent),
+        )
+    }
+
+    // Composed from the local transforms, so it is current even before the
+    // next `update`
+    pub fn world_matrix(&self, id: ObjectId) -> Option<Matrix4<f32>> {
+        let mut matrix = self.objects.get(id)?.local_transform().matrix();
+        for ancestor in self.ancestors(id) {
+            matrix = self.objects.get(ancestor)?.local_transform().matrix() * matrix;
+        }
+        Some(matrix)
+    }
+
+    pub fn world_transform(&self, id: ObjectId) -> Option<Transform> {
+        self.world_matrix(id)
+            .map(|matrix| Transform::from_matrix(&matrix))
+    }
+
+    // Sets the local transform that puts the object at `transform` in the world
+    pub fn set_world_transform(&mut self, id: ObjectId, transform: Transform) {
+        let parent_matrix = self
+            .objects
+            .get(id)
+            .and_then(|object| object.parent)
+            .and_then(|parent| self.world_matrix(parent))
+            .unwrap_or(Matrix4::identity());
+        let local = parent_matrix
+            .invert()
+            .map(|inverse| Transform::from_matrix(&(inverse * transform.matrix())))
+            .unwrap_or(transform);
+        if let Some(object) = self.objects.get_mut(id) {
+            object.set_local_transform(local);
+        }
+    }
+
+    // Objects without a parent
+    pub fn roots(&self) -> impl Iterator<Item = ObjectId> + '_ {
+        self.objects
+            .iter()
+            .filter(|(_, object)| object.parent.is_none())
+            .map(|(id, _)| id)
+    }
+
+    // Parents come before their children, so every parent's world matrix is
+    // current when its children are composed with it. Subtrees below clean
+    // objects with clean ancestors are skipped.
+    fn propagate_transforms(&mut self) {
+        let mut stack = self
+            .roots()
+            .map(|id| (id, Matrix4::identity(), false))
+            .collect::<Vec<_>>();
+        while let Some((id, parent_matrix, parent_moved)) = stack.pop() {
+            let Some(object) = self.objects.get_mut(id) else {
+                continue;
+            };
+            let moved = parent_moved || object.dirty;
+            if moved {
+                object.set_world_matrix(parent_matrix * object.local_transform().matrix());
+            }
+            let matrix = object.world_matrix();
+            stack.extend(object.children.iter().map(|&child| (child, matrix, moved)));
         }
     }
 
-    pub fn add_actor(&mut self, object: Object) {
-        self.actors.push(object);
+    // Every object that is not static
+    pub fn actor_ids(&self) -> Vec<ObjectId> {
+        self.objects
+            .iter()
+            .map(|(id, _)| id)
+            .filter(|id| !self.immutable_objects.contains(id))
+            .collect()
+    }
+
+    // Removes a whole group of objects at once, such as a scene, along with
+    // any parent and child links to them. Static objects are skipped.
+    pub fn remove_actors(&mut self, ids: &[ObjectId]) {
+        let removed = ids
+            .iter()
+            .copied()
+            .filter(|&id| {
+                !self.immutable_objects.contains(&id) && self.objects.remove(id).is_some()
+            })
+            .collect::<HashSet<_>>();
+        for object in self.objects.values_mut() {
+            object.children.retain(|child| !removed.contains(child));
+            if object
+                .parent
+                .is_some_and(|parent| removed.contains(&parent))
+            {
+                object.parent = None;
+                object.dirty = true;
+            }
+        }
+    }
+
+    // Static objects are part of every scene and cannot be removed
+    pub fn is_static(&self, id: ObjectId) -> bool {
+        self.immutable_objects.contains(&id)
+    }
+
+    pub fn contains(&self, id: ObjectId) -> bool {
+        self.objects.contains(id)
+    }
+
+    pub fn get(&self, id: ObjectId) -> Option<&Object> {
+        self.objects.get(id)
     }
 
-    pub fn remove_actor(&mut self, index: usize) {
-        self.actors.remove(index);
+    pub fn get_mut(&mut self, id: ObjectId) -> Option<&mut Object> {
+        self.objects.get_mut(id)
     }
 
-    pub async fn create_actor(
+    // Shares the model with every other object loaded from the same path,
+    // loading it first if needed
+    pub fn create_actor(
         &mut self,
-        model_path: &PathBuf,
+        model_path: &Path,
         context: &Context<'_>,
         material_store: &mut MaterialStore,
-    ) {
-        let actor =
-            Object::from_model_path(model_path, context, material_store, &self.bind_group_layout)
-                .await;
-        self.add_actor(actor);
+        asset_server: &mut AssetServer,
+    ) -> anyhow::Result<ObjectId> {
+        let handle = asset_server.load_model_now(model_path, &context.device, material_store)?;
+        let mut object = Object::new(
+            asset_server.get(&handle).clone(),
+            Vector3::new(0.0, 0.0, 0.0),
+            Quaternion::from_axis_angle(Vector3::unit_z(), Deg(0.0)),
+            Vector3::new(1.0, 1.0, 1.0),
+            &context.device,
+            &self.bind_group_layout,
+        );
+        object.model_path = Some(asset_server.path(&handle).to_path_buf());
+        object.model_asset = Some(handle);
+        Ok(self.add_actor(object))
+    }
+
+    pub fn add_instanced(&mut self, object: InstancedObject) -> InstancedObjectId {
+        self.instanced_objects.insert(object)
+    }
+
+    pub fn remove_instanced(&mut self, id: InstancedObjectId) -> Option<InstancedObject> {
+        self.instanced_objects.remove(id)
+    }
+
+    pub async fn create_instanced(
+        &mut self,
+        model_path: &Path,
+        context: &Context<'_>,
+        material_store: &mut MaterialStore,
+    ) -> InstancedObjectId {
+        let object = InstancedObject::from_model_path(
+            model_path,
+            context,
+            material_store,
+            &self.bind_group_layout,
+        )
+        .await;
+        self.add_instanced(object)
+    }
+
+    pub fn instanced(&self, id: InstancedObjectId) -> Option<&InstancedObject> {
+        self.instanced_objects.get(id)
+    }
+
+    pub fn instanced_mut(&mut self, id: InstancedObjectId) -> Option<&mut InstancedObject> {
+        self.instanced_objects.get_mut(id)
+    }
+
+    pub fn iter_instanced(&self) -> impl Iterator<Item = &InstancedObject> {
+        self.instanced_objects.values()
     }
 
     pub fn iter(&self) -> impl Iterator<Item = &Object> {
-        self.actors.iter().chain(self.immutable_objects.iter())
+        self.objects.values()
     }
 
     pub fn iter_mut(&mut self) -> impl Iterator<Item = &mut Object> {
-        self.actors
-            .iter_mut()
-            .chain(self.immutable_objects.iter_mut())
+        self.objects.values_mut()
     }
 
-    pub fn update(&mut self, queue: &wgpu::Queue) {
+    pub fn iter_with_ids(&self) -> impl Iterator<Item = (ObjectId, &Object)> {
+        self.objects.iter()
+    }
+
+    // Marks objects outside the frustum as invisible, or everything as visible
+    // without a frustum
+    pub fn cull(&mut self, frustum: Option<&Frustum>) -> CullingStats {
+        let mut stats = CullingStats::default();
+        let mut count = |visible: bool| match visible {
+            true => stats.visible += 1,
+            false => stats.culled += 1,
+        };
+
+        for object in self.objects.values_mut() {
+            count(match frustum {
+                Some(frustum) => object.cull(frustum),
+                None => {
+                    object.visible = true;
+                    true
+                }
+            });
+        }
+        for object in self.instanced_objects.values_mut() {
+            count(object.cull(frustum));
+        }
+
+        stats
+    }
+
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        eye: &CameraEye,
+        lod_settings: &LodSettings,
+    ) {
+        self.propagate_transforms();
         for object in self.iter_mut() {
-            object.update(queue);
+            object.update(queue, eye, lod_settings);
+        }
+        for object in self.instanced_objects.values_mut() {
+            object.update(device, queue);
         }
     }
 }
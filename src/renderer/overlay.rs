@@ -0,0 +1,375 @@
+use std::mem;
+use std::ops::Range;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use wgpu::util::DeviceExt;
+
+use super::{
+    context::Context,
+    model::Vertex,
+    shader::ShaderStore,
+    text::{FontId, GlyphAtlas, TextStyle},
+    texture::{SamplerSettings, Texture, TextureId, TextureStore},
+};
+
+const INITIAL_VERTEX_CAPACITY: usize = 1 << 12;
+const MODE_TEXTURE: u32 = 0;
+const MODE_GLYPH: u32 = 1;
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
+struct OverlayVertex {
+    position: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+    mode: u32,
+}
+
+impl Vertex for OverlayVertex {
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        const ATTRIBUTES: [wgpu::VertexAttribute; 4] = wgpu::vertex_attr_array![
+            0 => Float32x2,
+            1 => Float32x2,
+            2 => Float32x4,
+            3 => Uint32,
+        ];
+        wgpu::VertexBufferLayout {
+            array_stride: mem::size_of::<OverlayVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &ATTRIBUTES,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum OverlayTexture {
+    // Plain colored quads sample a white texel
+    White,
+    GlyphAtlas,
+    // A texture of the texture store
+    Image(TextureId),
+}
+
+// Consecutive quads sharing a texture, drawn with one call
+struct OverlayBatch {
+    texture: OverlayTexture,
+    vertices: Range<u32>,
+}
+
+// 2D layer drawn over the finished frame, in pixels from the top left corner
+// of the window. Everything queued is drawn in order in the next frame and
+// then cleared, so HUDs are queued again every frame.
+pub struct Overlay {
+    atlas: GlyphAtlas,
+    atlas_bind_group: wgpu::BindGroup,
+    white: Texture,
+    screen_buffer: wgpu::Buffer,
+    screen_bind_group_layout: wgpu::BindGroupLayout,
+    screen_bind_group: wgpu::BindGroup,
+    pipeline: wgpu::RenderPipeline,
+    vertices: Vec<OverlayVertex>,
+    batches: Vec<OverlayBatch>,
+    vertex_buffer: wgpu::Buffer,
+    capacity: usize,
+}
+
+impl Overlay {
+    pub fn new(
+        context: &Context,
+        shader_store: &ShaderStore,
+        texture_store: &mut TextureStore,
+    ) -> Self {
+        let device = &context.device;
+        let sampler = texture_store.get_sampler(
+            device,
+            SamplerSettings {
+                anisotropy: 1,
+                ..Default::default()
+            },
+        );
+        let atlas = GlyphAtlas::new(context);
+        let atlas_bind_group = Texture::create_bind_group(
+            device,
+            &texture_store.bind_group_layout,
+            &atlas.view,
+            &sampler,
+        );
+        let white = Texture::from_image(
+            device,
+            &context.queue,
+            &texture_store.bind_group_layout,
+            &image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+                1,
+                1,
+                image::Rgba([255; 4]),
+            )),
+            Some("Overlay White Texture"),
+            Arc::clone(&sampler),
+            None,
+        )
+        .unwrap();
+
+        let screen_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Overlay Screen Buffer"),
+            contents: bytemuck::cast_slice(&[
+                context.config.width as f32,
+                context.config.height as f32,
+            ]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let screen_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                }],
+                label: Some("overlay_screen_bind_group_layout"),
+            });
+        let screen_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &screen_bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: screen_buffer.as_entire_binding(),
+            }],
+            label: Some("overlay_screen_bind_group"),
+        });
+
+        let pipeline = create_pipeline(
+            context,
+            shader_store,
+            &screen_bind_group_layout,
+            &texture_store.bind_group_layout,
+        );
+
+        Self {
+            atlas,
+            atlas_bind_group,
+            white,
+            screen_buffer,
+            screen_bind_group_layout,
+            screen_bind_group,
+            pipeline,
+            vertices: Vec::new(),
+            batches: Vec::new(),
+            vertex_buffer: Self::create_vertex_buffer(device, INITIAL_VERTEX_CAPACITY),
+            capacity: INITIAL_VERTEX_CAPACITY,
+        }
+    }
+
+    // Picks up a changed overlay shader
+    pub fn rebuild_pipeline(
+        &mut self,
+        context: &Context,
+        shader_store: &ShaderStore,
+        texture_layout: &wgpu::BindGroupLayout,
+    ) {
+        self.pipeline = create_pipeline(
+            context,
+            shader_store,
+            &self.screen_bind_group_layout,
+            texture_layout,
+        );
+    }
+
+    fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Overlay Vertex Buffer"),
+            size: (capacity * mem::size_of::<OverlayVertex>()) as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn quad(
+        &mut self,
+        texture: OverlayTexture,
+        position: [f32; 2],
+        size: [f32; 2],
+        uv: [[f32; 2]; 2],
+        color: [f32; 4],
+        mode: u32,
+    ) {
+        let start = self.vertices.len() as u32;
+        match self.batches.last_mut() {
+            Some(batch) if batch.texture == texture => batch.vertices.end += 6,
+            _ => self.batches.push(OverlayBatch {
+                texture,
+                vertices: start..start + 6,
+            }),
+        }
+        let [x0, y0] = position;
+        let [x1, y1] = [x0 + size[0], y0 + size[1]];
+        let [[u0, v0], [u1, v1]] = uv;
+        let vertex = |position, uv| OverlayVertex {
+            position,
+            uv,
+            color,
+            mode,
+        };
+        self.vertices.extend_from_slice(&[
+            vertex([x0, y0], [u0, v0]),
+            vertex([x0, y1], [u0, v1]),
+            vertex([x1, y1], [u1, v1]),
+            vertex([x0, y0], [u0, v0]),
+            vertex([x1, y1], [u1, v1]),
+            vertex([x1, y0], [u1, v0]),
+        ]);
+    }
+
+    pub fn rect(&mut self, position: [f32; 2], size: [f32; 2], color: [f32; 4]) {
+        self.quad(
+            OverlayTexture::White,
+            position,
+            size,
+            [[0.0, 0.0], [1.0, 1.0]],
+            color,
+            MODE_TEXTURE,
+        );
+    }
+
+    // Draws a texture of the texture store, tinted by `color`
+    #[allow(dead_code)]
+    pub fn image(
+        &mut self,
+        texture_id: TextureId,
+        position: [f32; 2],
+        size: [f32; 2],
+        color: [f32; 4],
+    ) {
+        self.quad(
+            OverlayTexture::Image(texture_id),
+            position,
+            size,
+            [[0.0, 0.0], [1.0, 1.0]],
+            color,
+            MODE_TEXTURE,
+        );
+    }
+
+    // Draws text with its top left corner at `position` and returns its size
+    pub fn text(&mut self, position: [f32; 2], text: &str, style: &TextStyle) -> [f32; 2] {
+        let mut glyphs = Vec::new();
+        let size = self
+            .atlas
+            .layout_text(text, position, style, |glyph| glyphs.push(glyph));
+        for glyph in glyphs {
+            self.quad(
+                OverlayTexture::GlyphAtlas,
+                glyph.position,
+                glyph.size,
+                [glyph.uv_min, glyph.uv_max],
+                style.color,
+                MODE_GLYPH,
+            );
+        }
+        size
+    }
+
+    pub fn measure_text(&mut self, text: &str, style: &TextStyle) -> [f32; 2] {
+        self.atlas.measure(text, style)
+    }
+
+    #[allow(dead_code)]
+    pub fn load_font(&mut self, relative_path: &PathBuf) -> anyhow::Result<FontId> {
+        self.atlas.load_font(relative_path)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+        self.batches.clear();
+    }
+
+    // Uploads new glyphs, the window size and this frame's quads
+    pub fn prepare(&mut self, context: &Context) {
+        self.atlas.upload(&context.queue);
+        if self.vertices.is_empty() {
+            return;
+        }
+        context.queue.write_buffer(
+            &self.screen_buffer,
+            0,
+            bytemuck::cast_slice(&[context.config.width as f32, context.config.height as f32]),
+        );
+        if self.vertices.len() > self.capacity {
+            self.capacity = self.vertices.len().next_power_of_two();
+            self.vertex_buffer = Self::create_vertex_buffer(&context.device, self.capacity);
+        }
+        context
+            .queue
+            .write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&self.vertices));
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        texture_store: &'a TextureStore,
+    ) {
+        if self.vertices.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &self.screen_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        for batch in &self.batches {
+            let bind_group = match batch.texture {
+                OverlayTexture::White => self.white.bind_group.as_ref(),
+                OverlayTexture::GlyphAtlas => Some(&self.atlas_bind_group),
+                OverlayTexture::Image(id) => texture_store.get_texture(id).bind_group.as_ref(),
+            };
+            if let Some(bind_group) = bind_group {
+                render_pass.set_bind_group(1, bind_group, &[]);
+                render_pass.draw(batch.vertices.clone(), 0..1);
+            }
+        }
+    }
+}
+
+fn create_pipeline(
+    context: &Context,
+    shader_store: &ShaderStore,
+    screen_layout: &wgpu::BindGroupLayout,
+    texture_layout: &wgpu::BindGroupLayout,
+) -> wgpu::RenderPipeline {
+    let device = &context.device;
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Overlay Pipeline Layout"),
+        bind_group_layouts: &[screen_layout, texture_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Overlay Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader_store.overlay,
+            entry_point: "vs_main",
+            buffers: &[OverlayVertex::desc()],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader_store.overlay,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: context.config.format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+        cache: None,
+    })
+}
@@ -0,0 +1,315 @@
+use std::sync::{Arc, OnceLock};
+
+use cgmath::{EuclideanSpace, Matrix4, Point3, SquareMatrix, Vector4};
+use log::warn;
+
+use super::{
+    context::Context,
+    model::{ModelVertex, Vertex},
+    object::{ObjectId, ObjectManager},
+    shader::ShaderStore,
+    texture::Texture,
+};
+
+pub const PICKING_ID_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Uint;
+pub const PICKING_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+// Ids pack the object's position in the request above the submodel, with 0 left for empty pixels
+const SUBMODEL_BITS: u32 = 12;
+const MAX_SUBMODEL: u32 = (1 << SUBMODEL_BITS) - 1;
+const MAX_OBJECT: u32 = (1 << (32 - SUBMODEL_BITS)) - 2;
+// Buffer copies start on this alignment, so the id and the depth get a row each
+const READBACK_ROW: wgpu::BufferAddress = wgpu::COPY_BYTES_PER_ROW_ALIGNMENT as wgpu::BufferAddress;
+
+#[derive(Clone, Copy, Debug)]
+pub struct PickHit {
+    pub object: ObjectId,
+    pub submodel: usize,
+    // Normalized device depth, 0 at the near plane
+    pub depth: f32,
+    pub position: Point3<f32>,
+}
+
+// A pixel to pick with the camera and window size it is rendered with
+#[derive(Clone, Debug)]
+pub struct PickRequest {
+    pub pixel: [u32; 2],
+    size: [u32; 2],
+    inverse_view_projection: Matrix4<f32>,
+    // Visible objects when the pick was issued, ids refer to their position
+    // here so an object replaced before the readback is not picked instead
+    objects: Vec<ObjectId>,
+}
+
+enum Readback {
+    Idle,
+    // Set once the buffer is mapped or mapping failed
+    Mapping(
+        PickRequest,
+        Arc<OnceLock<Result<(), wgpu::BufferAsyncError>>>,
+    ),
+}
+
+// Renders object and submodel ids under a single pixel and reads them back
+// without stalling, so a pick is answered a frame or two after it is asked
+pub struct Picker {
+    pipeline: wgpu::RenderPipeline,
+    readback_buffer: wgpu::Buffer,
+    requested: Option<[u32; 2]>,
+    readback: Readback,
+    result: Option<([u32; 2], Option<PickHit>)>,
+}
+
+impl Picker {
+    pub fn new(
+        context: &Context,
+        shader_store: &ShaderStore,
+        camera_layout: &wgpu::BindGroupLayout,
+        object_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let device = &context.device;
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Picking Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, object_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Picking Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader_store.picking,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_store.picking,
+                entry_point: "fs_main",
+                targets: &[
+                    Some(PICKING_ID_FORMAT.into()),
+                    Some(PICKING_DEPTH_FORMAT.into()),
+                ],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+        let readback_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Picking Readback Buffer"),
+            size: 2 * READBACK_ROW,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            readback_buffer,
+            requested: None,
+            readback: Readback::Idle,
+            result: None,
+        }
+    }
+
+    // Asks for the pixel to be rendered in the next frame that has no
+    // readback in flight
+    pub fn request(&mut self, pixel: [u32; 2]) {
+        if let Readback::Mapping(request, _) = &self.readback {
+            if request.pixel == pixel {
+                return;
+            }
+        }
+        self.requested = Some(pixel);
+    }
+
+    // The answer for the pixel, once its readback finished. Results for
+    // other pixels are stale and dropped.
+    pub fn take_result(&mut self, pixel: [u32; 2]) -> Option<Option<PickHit>> {
+        match self.result.take() {
+            Some((result_pixel, hit)) if result_pixel == pixel => Some(hit),
+            _ => None,
+        }
+    }
+
+    // The pick to render this frame, if any
+    pub fn next_request(
+        &mut self,
+        size: [u32; 2],
+        view_projection: Matrix4<f32>,
+        objects: &ObjectManager,
+    ) -> Option<PickRequest> {
+        if !matches!(self.readback, Readback::Idle) {
+            return None;
+        }
+        let pixel = self.requested.take()?;
+        Some(PickRequest {
+            pixel,
+            size,
+            inverse_view_projection: view_projection.invert()?,
+            objects: objects
+                .iter_with_ids()
+                .filter(|(_, object)| object.is_visible())
+                .map(|(id, _)| id)
+                .collect(),
+        })
+    }
+
+    pub fn readback_buffer(&self) -> &wgpu::Buffer {
+        &self.readback_buffer
+    }
+
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        objects: &'a ObjectManager,
+        request: &PickRequest,
+    ) {
+        let [x, y] = request.pixel;
+        render_pass.set_scissor_rect(x, y, 1, 1);
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        for (position, &object_id) in request.objects.iter().enumerate() {
+            let Some(object) = objects.get(object_id) else {
+                continue;
+            };
+            let Some(object_bits) = encode_object(position) else {
+                warn!(
+                    "Only the first {} visible objects can be picked",
+                    MAX_OBJECT + 1
+                );
+                break;
+            };
+            render_pass.set_bind_group(1, object.bind_group(), &[]);
+            for (index, data) in object.model().data.iter().enumerate() {
+                let mesh = &data.mesh;
+                let lod = &mesh.lods[object.lod().level.min(mesh.lods.len() - 1)];
+                // Submodels past the last id still pick the right object
+                let id = object_bits | (index as u32).min(MAX_SUBMODEL);
+                render_pass.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                render_pass
+                    .set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                render_pass.draw_indexed(
+                    lod.first_index..lod.first_index + lod.index_count,
+                    lod.base_vertex,
+                    id..id + 1,
+                );
+            }
+        }
+    }
+
+    // Copies the picked pixel of both targets into the readback buffer
+    pub fn copy_pixel(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        ids: &wgpu::Texture,
+        depths: &wgpu::Texture,
+        request: &PickRequest,
+    ) {
+        let [x, y] = request.pixel;
+        for (row, texture) in [ids, depths].into_iter().enumerate() {
+            encoder.copy_texture_to_buffer(
+                wgpu::ImageCopyTexture {
+                    texture,
+                    mip_level: 0,
+                    origin: wgpu::Origin3d { x, y, z: 0 },
+                    aspect: wgpu::TextureAspect::All,
+                },
+                wgpu::ImageCopyBuffer {
+                    buffer: &self.readback_buffer,
+                    layout: wgpu::ImageDataLayout {
+                        offset: row as wgpu::BufferAddress * READBACK_ROW,
+                        bytes_per_row: None,
+                        rows_per_image: None,
+                    },
+                },
+                wgpu::Extent3d {
+                    width: 1,
+                    height: 1,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+    }
+
+    // Starts mapping the readback buffer once the copy is submitted
+    pub fn map_readback(&mut self, request: PickRequest) {
+        let done = Arc::new(OnceLock::new());
+        let callback_done = Arc::clone(&done);
+        self.readback_buffer
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                let _ = callback_done.set(result);
+            });
+        self.readback = Readback::Mapping(request, done);
+    }
+
+    // Collects a finished readback without waiting for the GPU
+    pub fn poll(&mut self, device: &wgpu::Device, objects: &ObjectManager) {
+        let Readback::Mapping(_, done) = &self.readback else {
+            return;
+        };
+        device.poll(wgpu::Maintain::Poll);
+        let Some(result) = done.get().cloned() else {
+            return;
+        };
+        let Readback::Mapping(request, _) = std::mem::replace(&mut self.readback, Readback::Idle)
+        else {
+            return;
+        };
+        if let Err(e) = result {
+            warn!("Failed to read back the picking buffer: {e}");
+            return;
+        }
+
+        let (id, depth) = {
+            let data = self.readback_buffer.slice(..).get_mapped_range();
+            let row = READBACK_ROW as usize;
+            (
+                bytemuck::pod_read_unaligned::<u32>(&data[..4]),
+                bytemuck::pod_read_unaligned::<f32>(&data[row..row + 4]),
+            )
+        };
+        self.readback_buffer.unmap();
+        self.result = Some((request.pixel, decode(id, depth, &request, objects)));
+    }
+}
+
+fn encode_object(position: usize) -> Option<u32> {
+    let position = u32::try_from(position).ok().filter(|&p| p <= MAX_OBJECT)?;
+    Some((position + 1) << SUBMODEL_BITS)
+}
+
+fn decode(id: u32, depth: f32, request: &PickRequest, objects: &ObjectManager) -> Option<PickHit> {
+    if id == 0 {
+        return None;
+    }
+    let object = *request.objects.get(((id >> SUBMODEL_BITS) - 1) as usize)?;
+    // Removed since the pick was issued
+    if !objects.contains(object) {
+        return None;
+    }
+    // Back from the pixel center through normalized device coordinates
+    let [x, y] = request.pixel;
+    let [width, height] = request.size;
+    let ndc = Vector4::new(
+        (x as f32 + 0.5) / width as f32 * 2.0 - 1.0,
+        1.0 - (y as f32 + 0.5) / height as f32 * 2.0,
+        depth,
+        1.0,
+    );
+    let world = request.inverse_view_projection * ndc;
+    Some(PickHit {
+        object,
+        submodel: (id & MAX_SUBMODEL) as usize,
+        depth,
+        position: Point3::from_vec(world.truncate() / world.w),
+    })
+}
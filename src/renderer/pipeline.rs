@@ -1,43 +1,101 @@
 use super::{
     context::Context,
+    debug_draw::DebugDrawPipelines,
+    debug_view::DebugPipelines,
+    hdr::HDR_FORMAT,
+    instance::InstanceRaw,
     model::{ModelVertex, Vertex},
-    shader::ShaderStore,
+    selection::create_tint_pipeline,
+    shader::{ShaderStore, ShaderType},
     texture::Texture,
+    transparency::{OIT_ACCUM_FORMAT, OIT_REVEALAGE_FORMAT},
 };
 
+// How a scene pipeline writes color. Transparent surfaces test against the
+// opaque depth without writing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Blending {
+    Opaque,
+    // Alpha blended straight into the scene, needs back to front order
+    Sorted,
+    // Accumulated into the weighted blended OIT targets from `fs_oit`
+    WeightedBlended,
+}
+
 fn init_pipeline(
     context: &Context,
     bind_group_layouts: &[&wgpu::BindGroupLayout],
     shader: &wgpu::ShaderModule,
+    vertex_entry_point: &str,
+    vertex_buffers: &[wgpu::VertexBufferLayout],
+    blending: Blending,
+    sample_count: u32,
 ) -> wgpu::RenderPipeline {
+    let hdr_target = |blend| {
+        Some(wgpu::ColorTargetState {
+            format: HDR_FORMAT,
+            blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        })
+    };
+    let (fragment_entry_point, targets) = match blending {
+        Blending::Opaque => ("fs_main", vec![hdr_target(None)]),
+        Blending::Sorted => (
+            "fs_main",
+            vec![hdr_target(Some(wgpu::BlendState::ALPHA_BLENDING))],
+        ),
+        Blending::WeightedBlended => (
+            "fs_oit",
+            vec![
+                Some(wgpu::ColorTargetState {
+                    format: OIT_ACCUM_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: ADDITIVE,
+                        alpha: ADDITIVE,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+                // Multiplies the destination by one minus each coverage
+                Some(wgpu::ColorTargetState {
+                    format: OIT_REVEALAGE_FORMAT,
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                            operation: wgpu::BlendOperation::Add,
+                        },
+                        alpha: wgpu::BlendComponent::REPLACE,
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                }),
+            ],
+        ),
+    };
+
     let render_pipeline_layout =
         context
             .device
             .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
-                bind_group_layouts: bind_group_layouts,
+                bind_group_layouts,
                 push_constant_ranges: &[],
             });
 
-    let render_pipeline = context
+    context
         .device
         .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
             label: Some("Render Pipeline"),
             layout: Some(&render_pipeline_layout),
             vertex: wgpu::VertexState {
-                module: &shader,
-                entry_point: "vs_main",
-                buffers: &[ModelVertex::desc()],
+                module: shader,
+                entry_point: vertex_entry_point,
+                buffers: vertex_buffers,
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             },
             fragment: Some(wgpu::FragmentState {
-                module: &shader,
-                entry_point: "fs_main",
-                targets: &[Some(wgpu::ColorTargetState {
-                    format: context.config.format,
-                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
-                    write_mask: wgpu::ColorWrites::ALL,
-                })],
+                module: shader,
+                entry_point: fragment_entry_point,
+                targets: &targets,
                 compilation_options: wgpu::PipelineCompilationOptions::default(),
             }),
             primitive: wgpu::PrimitiveState {
@@ -51,40 +109,332 @@ fn init_pipeline(
             },
             depth_stencil: Some(wgpu::DepthStencilState {
                 format: Texture::DEPTH_FORMAT,
-                depth_write_enabled: true,
+                depth_write_enabled: blending == Blending::Opaque,
                 depth_compare: wgpu::CompareFunction::Less,
                 stencil: wgpu::StencilState::default(),
                 bias: wgpu::DepthBiasState::default(),
             }),
             multisample: wgpu::MultisampleState {
-                count: 1,
+                count: sample_count,
                 mask: !0,
                 alpha_to_coverage_enabled: false,
             },
             multiview: None,
             cache: None,
+        })
+}
+
+const ADDITIVE: wgpu::BlendComponent = wgpu::BlendComponent {
+    src_factor: wgpu::BlendFactor::One,
+    dst_factor: wgpu::BlendFactor::One,
+    operation: wgpu::BlendOperation::Add,
+};
+
+// Pipeline for shaders that draw a single full screen triangle from `vs_main`
+// without any vertex buffers, used by the screen space passes.
+pub fn init_fullscreen_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    shader: &wgpu::ShaderModule,
+    fragment_entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    init_multisampled_fullscreen_pipeline(
+        device,
+        label,
+        bind_group_layouts,
+        shader,
+        fragment_entry_point,
+        format,
+        blend,
+        1,
+    )
+}
+
+// Full screen pass into a multisampled target, every sample gets the same
+// fragment
+#[allow(clippy::too_many_arguments)]
+pub fn init_multisampled_fullscreen_pipeline(
+    device: &wgpu::Device,
+    label: &str,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    shader: &wgpu::ShaderModule,
+    fragment_entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some(label),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(label),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point: fragment_entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+// Draws the environment behind everything else, only passing where the depth
+// buffer is still cleared to the far plane
+fn init_skybox_pipeline(
+    context: &Context,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    shader: &wgpu::ShaderModule,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let layout = context
+        .device
+        .create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Skybox Pipeline Layout"),
+            bind_group_layouts,
+            push_constant_ranges: &[],
         });
 
-    render_pipeline
+    context
+        .device
+        .create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Skybox Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: shader,
+                entry_point: "vs_main",
+                buffers: &[],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: false,
+                depth_compare: wgpu::CompareFunction::LessEqual,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState {
+                count: sample_count,
+                mask: !0,
+                alpha_to_coverage_enabled: false,
+            },
+            multiview: None,
+            cache: None,
+        })
+}
+
+// Bind group layouts used by the scene pipelines
+pub struct SceneLayouts<'a> {
+    pub camera: &'a wgpu::BindGroupLayout,
+    pub texture: &'a wgpu::BindGroupLayout,
+    pub object: &'a wgpu::BindGroupLayout,
+    pub environment: &'a wgpu::BindGroupLayout,
+    pub gpu_draws: &'a wgpu::BindGroupLayout,
+    pub debug: &'a wgpu::BindGroupLayout,
+    pub selection: &'a wgpu::BindGroupLayout,
 }
 
-#[allow(unused)]
-pub struct PipelineStore {
+// One pipeline per material shader
+pub struct MaterialPipelines {
     pub grid: wgpu::RenderPipeline,
     pub basic: wgpu::RenderPipeline,
     pub hyper: wgpu::RenderPipeline,
 }
 
+impl MaterialPipelines {
+    fn new(
+        context: &Context,
+        shader_store: &ShaderStore,
+        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        blending: Blending,
+        sample_count: u32,
+    ) -> Self {
+        let init = |shader| {
+            init_pipeline(
+                context,
+                bind_group_layouts,
+                shader,
+                "vs_main",
+                &[ModelVertex::desc()],
+                blending,
+                sample_count,
+            )
+        };
+        Self {
+            grid: init(&shader_store.grid),
+            basic: init(&shader_store.basic),
+            hyper: init(&shader_store.hyper),
+        }
+    }
+
+    pub fn get(&self, shader_type: &ShaderType) -> &wgpu::RenderPipeline {
+        match shader_type {
+            ShaderType::Basic => &self.basic,
+            ShaderType::Grid => &self.grid,
+            ShaderType::Hyper => &self.hyper,
+        }
+    }
+}
+
+// One pipeline per way of writing color
+struct MaterialBlendings {
+    opaque: wgpu::RenderPipeline,
+    sorted: wgpu::RenderPipeline,
+    weighted_blended: wgpu::RenderPipeline,
+}
+
+#[allow(unused)]
+pub struct PipelineStore {
+    pub opaque: MaterialPipelines,
+    pub sorted: MaterialPipelines,
+    pub weighted_blended: MaterialPipelines,
+    basic_instanced: MaterialBlendings,
+    pub skybox: wgpu::RenderPipeline,
+    pub basic_gpu_driven: wgpu::RenderPipeline,
+    pub debug: DebugPipelines,
+    pub debug_draw: DebugDrawPipelines,
+    pub selection_tint: wgpu::RenderPipeline,
+    pub sample_count: u32,
+}
+
 impl PipelineStore {
+    // Only the basic shader has an instanced variant
+    pub fn basic_instanced(&self, blending: Blending) -> &wgpu::RenderPipeline {
+        match blending {
+            Blending::Opaque => &self.basic_instanced.opaque,
+            Blending::Sorted => &self.basic_instanced.sorted,
+            Blending::WeightedBlended => &self.basic_instanced.weighted_blended,
+        }
+    }
+
+    pub fn material_pipelines(&self, blending: Blending) -> &MaterialPipelines {
+        match blending {
+            Blending::Opaque => &self.opaque,
+            Blending::Sorted => &self.sorted,
+            Blending::WeightedBlended => &self.weighted_blended,
+        }
+    }
+
     pub fn new(
         context: &Context,
         shader_store: &ShaderStore,
-        bind_group_layouts: &[&wgpu::BindGroupLayout],
+        layouts: &SceneLayouts,
+        sample_count: u32,
     ) -> Self {
-        let grid = init_pipeline(context, bind_group_layouts, &shader_store.grid);
-        let basic = init_pipeline(context, bind_group_layouts, &shader_store.basic);
-        let hyper = init_pipeline(context, bind_group_layouts, &shader_store.hyper);
+        let bind_group_layouts = &[
+            layouts.camera,
+            layouts.texture,
+            layouts.object,
+            layouts.environment,
+        ];
+        let material_pipelines = |blending| {
+            MaterialPipelines::new(
+                context,
+                shader_store,
+                bind_group_layouts,
+                blending,
+                sample_count,
+            )
+        };
+        let opaque = material_pipelines(Blending::Opaque);
+        let sorted = material_pipelines(Blending::Sorted);
+        let weighted_blended = material_pipelines(Blending::WeightedBlended);
+        let instanced = |blending| {
+            init_pipeline(
+                context,
+                bind_group_layouts,
+                &shader_store.basic,
+                "vs_instanced",
+                &[ModelVertex::desc(), InstanceRaw::desc()],
+                blending,
+                sample_count,
+            )
+        };
+        let basic_instanced = MaterialBlendings {
+            opaque: instanced(Blending::Opaque),
+            sorted: instanced(Blending::Sorted),
+            weighted_blended: instanced(Blending::WeightedBlended),
+        };
+        let skybox = init_skybox_pipeline(
+            context,
+            &[layouts.camera, layouts.environment],
+            &shader_store.skybox,
+            sample_count,
+        );
+        let basic_gpu_driven = init_pipeline(
+            context,
+            &[
+                layouts.camera,
+                layouts.texture,
+                layouts.gpu_draws,
+                layouts.environment,
+            ],
+            &shader_store.basic,
+            "vs_gpu_driven",
+            &[ModelVertex::desc()],
+            Blending::Opaque,
+            sample_count,
+        );
+        let debug = DebugPipelines::new(
+            context,
+            shader_store,
+            &[layouts.camera, layouts.debug, layouts.object],
+            sample_count,
+        );
+        let debug_draw =
+            DebugDrawPipelines::new(context, shader_store, layouts.camera, sample_count);
+        let selection_tint = create_tint_pipeline(
+            context,
+            shader_store,
+            &[layouts.camera, layouts.object, layouts.selection],
+            sample_count,
+        );
 
-        Self { grid, basic, hyper }
+        Self {
+            opaque,
+            sorted,
+            weighted_blended,
+            basic_instanced,
+            skybox,
+            basic_gpu_driven,
+            debug,
+            debug_draw,
+            selection_tint,
+            sample_count,
+        }
     }
 }
@@ -0,0 +1,676 @@
+use super::context::Context;
+use super::hdr::HDR_FORMAT;
+use super::pipeline::init_fullscreen_pipeline;
+use super::shader::ShaderStore;
+use super::util::resources;
+
+use anyhow::{anyhow, bail, Result};
+use serde::{Deserialize, Serialize};
+
+use std::path::PathBuf;
+
+// Directory under `res` holding the LUTs offered by the editor
+const LUT_DIRECTORY: &str = "luts";
+const IDENTITY_LUT_SIZE: u32 = 16;
+// Already 64 MiB of RGBA8 texels
+const MAX_LUT_SIZE: usize = 256;
+
+// Screen space effects that run on the tone mapped image, in the order given by
+// `PostProcessSettings::order`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PostEffect {
+    ChromaticAberration,
+    ColorGrading,
+    Vignette,
+    Fxaa,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct BloomSettings {
+    pub enabled: bool,
+    pub threshold: f32,
+    pub knee: f32,
+    pub intensity: f32,
+    // Radius of the upsampling filter in UV units
+    pub filter_radius: f32,
+    pub max_mip_count: u32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct FxaaSettings {
+    pub enabled: bool,
+    pub edge_threshold: f32,
+    pub edge_threshold_min: f32,
+    pub span_max: f32,
+    pub reduce_mul: f32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct VignetteSettings {
+    pub enabled: bool,
+    pub intensity: f32,
+    pub radius: f32,
+    pub smoothness: f32,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct ChromaticAberrationSettings {
+    pub enabled: bool,
+    pub intensity: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ColorGradingSettings {
+    pub enabled: bool,
+    pub strength: f32,
+    // `.cube` file under `res`, the identity LUT when `None`
+    #[serde(default)]
+    pub lut: Option<PathBuf>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PostProcessSettings {
+    pub bloom: BloomSettings,
+    pub order: Vec<PostEffect>,
+    pub fxaa: FxaaSettings,
+    pub vignette: VignetteSettings,
+    pub chromatic_aberration: ChromaticAberrationSettings,
+    pub color_grading: ColorGradingSettings,
+}
+
+impl PostProcessSettings {
+    pub fn is_enabled(&self, effect: PostEffect) -> bool {
+        match effect {
+            PostEffect::ChromaticAberration => self.chromatic_aberration.enabled,
+            PostEffect::ColorGrading => self.color_grading.enabled,
+            PostEffect::Vignette => self.vignette.enabled,
+            PostEffect::Fxaa => self.fxaa.enabled,
+        }
+    }
+
+    pub fn enabled_effects(&self) -> Vec<PostEffect> {
+        self.order
+            .iter()
+            .copied()
+            .filter(|&effect| self.is_enabled(effect))
+            .collect()
+    }
+}
+
+impl Default for PostProcessSettings {
+    fn default() -> Self {
+        Self {
+            bloom: BloomSettings {
+                enabled: true,
+                threshold: 1.0,
+                knee: 0.5,
+                intensity: 0.05,
+                filter_radius: 0.005,
+                max_mip_count: 6,
+            },
+            order: vec![
+                PostEffect::ChromaticAberration,
+                PostEffect::ColorGrading,
+                PostEffect::Vignette,
+                PostEffect::Fxaa,
+            ],
+            fxaa: FxaaSettings {
+                enabled: true,
+                edge_threshold: 0.125,
+                edge_threshold_min: 0.0312,
+                span_max: 8.0,
+                reduce_mul: 1.0 / 8.0,
+            },
+            vignette: VignetteSettings {
+                enabled: false,
+                intensity: 0.35,
+                radius: 1.0,
+                smoothness: 0.6,
+            },
+            chromatic_aberration: ChromaticAberrationSettings {
+                enabled: false,
+                intensity: 0.005,
+            },
+            color_grading: ColorGradingSettings {
+                enabled: false,
+                strength: 1.0,
+                lut: None,
+            },
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct BloomUniform {
+    threshold: f32,
+    knee: f32,
+    intensity: f32,
+    filter_radius: f32,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostProcessUniform {
+    fxaa_edge_threshold: f32,
+    fxaa_edge_threshold_min: f32,
+    fxaa_span_max: f32,
+    fxaa_reduce_mul: f32,
+    vignette_intensity: f32,
+    vignette_radius: f32,
+    vignette_smoothness: f32,
+    chromatic_aberration_intensity: f32,
+    color_grading_strength: f32,
+    lut_size: f32,
+    linear_input: u32,
+    _padding: u32,
+}
+
+struct Lut {
+    #[allow(dead_code)]
+    texture: wgpu::Texture,
+    view: wgpu::TextureView,
+    size: u32,
+}
+
+impl Lut {
+    fn new(device: &wgpu::Device, queue: &wgpu::Queue, size: u32, rgba: &[u8]) -> Self {
+        let extent = wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: size,
+        };
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Color Grading LUT"),
+            size: extent,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                aspect: wgpu::TextureAspect::All,
+                texture: &texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+            },
+            rgba,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * size),
+                rows_per_image: Some(size),
+            },
+            extent,
+        );
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            texture,
+            view,
+            size,
+        }
+    }
+
+    fn identity(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let size = IDENTITY_LUT_SIZE;
+        let scale = 255.0 / (size - 1) as f32;
+        let mut rgba = Vec::with_capacity((size * size * size * 4) as usize);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    rgba.extend([r, g, b].map(|c| (c as f32 * scale).round() as u8));
+                    rgba.push(255);
+                }
+            }
+        }
+        Self::new(device, queue, size, &rgba)
+    }
+}
+
+// Paths relative to `res` of the LUTs that ship with the app
+pub fn available_luts() -> Vec<PathBuf> {
+    resources::list_resources(LUT_DIRECTORY, &["cube"])
+}
+
+// Parses an Adobe/Resolve `.cube` 3D LUT into RGBA8 texels, red varying fastest
+fn parse_cube_lut(text: &str) -> Result<(u32, Vec<u8>)> {
+    let mut size = None;
+    let mut texel_count = 0;
+    let mut rgba = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(value) = line.strip_prefix("LUT_3D_SIZE") {
+            let value = value.trim().parse::<usize>()?;
+            if !(2..=MAX_LUT_SIZE).contains(&value) {
+                bail!("LUT_3D_SIZE must be between 2 and {MAX_LUT_SIZE}, got {value}");
+            }
+            size = Some(value);
+            texel_count = value * value * value;
+            rgba.reserve(texel_count * 4);
+            continue;
+        }
+        // The shader samples the LUT over 0..1, other domains would need a remap
+        for (keyword, default) in [("DOMAIN_MIN", 0.0), ("DOMAIN_MAX", 1.0)] {
+            if let Some(value) = line.strip_prefix(keyword) {
+                let values = parse_floats(value)?;
+                if values != [default; 3] {
+                    bail!("Only the default {keyword} is supported, got {values:?}");
+                }
+            }
+        }
+        if line.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            // TITLE, LUT_1D_SIZE...
+            if line.starts_with("LUT_1D_SIZE") {
+                bail!("1D LUTs are not supported");
+            }
+            continue;
+        }
+        let values = parse_floats(line)?;
+        if values.len() != 3 {
+            bail!("Malformed LUT entry {line:?}");
+        }
+        rgba.extend(
+            values
+                .iter()
+                .map(|v| (v.clamp(0.0, 1.0) * 255.0).round() as u8),
+        );
+        rgba.push(255);
+    }
+
+    let size = size.ok_or_else(|| anyhow!("Missing LUT_3D_SIZE"))?;
+    if rgba.len() != texel_count * 4 {
+        bail!(
+            "Expected {texel_count} LUT entries, found {}",
+            rgba.len() / 4
+        );
+    }
+    Ok((size as u32, rgba))
+}
+
+fn parse_floats(text: &str) -> Result<Vec<f32>> {
+    Ok(text
+        .split_whitespace()
+        .map(str::parse::<f32>)
+        .collect::<Result<Vec<_>, _>>()?)
+}
+
+pub struct PostProcessor {
+    bloom_bind_group_layout: wgpu::BindGroupLayout,
+    bloom_uniform_buffer: wgpu::Buffer,
+    bloom_prefilter: wgpu::RenderPipeline,
+    bloom_downsample: wgpu::RenderPipeline,
+    bloom_upsample: wgpu::RenderPipeline,
+    bloom_composite: wgpu::RenderPipeline,
+    bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    fxaa: wgpu::RenderPipeline,
+    vignette: wgpu::RenderPipeline,
+    chromatic_aberration: wgpu::RenderPipeline,
+    color_grading: wgpu::RenderPipeline,
+    sampler: wgpu::Sampler,
+    lut: Lut,
+    // Path under `res` of the loaded LUT
+    lut_path: Option<PathBuf>,
+}
+
+impl PostProcessor {
+    pub fn new(context: &Context, shader_store: &ShaderStore) -> Self {
+        let device = &context.device;
+
+        let texture_entry = |binding, view_dimension| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sampler_entry = wgpu::BindGroupLayoutEntry {
+            binding: 1,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+            count: None,
+        };
+        let uniform_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+
+        let bloom_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    texture_entry(0, wgpu::TextureViewDimension::D2),
+                    sampler_entry,
+                    uniform_entry,
+                ],
+                label: Some("bloom_bind_group_layout"),
+            });
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                texture_entry(0, wgpu::TextureViewDimension::D2),
+                sampler_entry,
+                uniform_entry,
+                texture_entry(3, wgpu::TextureViewDimension::D3),
+            ],
+            label: Some("post_process_bind_group_layout"),
+        });
+
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+        let bloom_pipeline = |label, entry_point, blend| {
+            init_fullscreen_pipeline(
+                device,
+                label,
+                &[&bloom_bind_group_layout],
+                &shader_store.bloom,
+                entry_point,
+                HDR_FORMAT,
+                blend,
+            )
+        };
+        let bloom_prefilter = bloom_pipeline("Bloom Prefilter Pipeline", "fs_prefilter", None);
+        let bloom_downsample = bloom_pipeline("Bloom Downsample Pipeline", "fs_downsample", None);
+        let bloom_upsample =
+            bloom_pipeline("Bloom Upsample Pipeline", "fs_upsample", Some(additive));
+        let bloom_composite =
+            bloom_pipeline("Bloom Composite Pipeline", "fs_composite", Some(additive));
+
+        let effect_pipeline = |label, entry_point| {
+            init_fullscreen_pipeline(
+                device,
+                label,
+                &[&bind_group_layout],
+                &shader_store.post_process,
+                entry_point,
+                context.config.format,
+                None,
+            )
+        };
+        let fxaa = effect_pipeline("FXAA Pipeline", "fs_fxaa");
+        let vignette = effect_pipeline("Vignette Pipeline", "fs_vignette");
+        let chromatic_aberration =
+            effect_pipeline("Chromatic Aberration Pipeline", "fs_chromatic_aberration");
+        let color_grading = effect_pipeline("Color Grading Pipeline", "fs_color_grading");
+
+        let bloom_uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Bloom Uniform Buffer"),
+            size: std::mem::size_of::<BloomUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Process Uniform Buffer"),
+            size: std::mem::size_of::<PostProcessUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let lut = Lut::identity(device, &context.queue);
+
+        Self {
+            bloom_bind_group_layout,
+            bloom_uniform_buffer,
+            bloom_prefilter,
+            bloom_downsample,
+            bloom_upsample,
+            bloom_composite,
+            bind_group_layout,
+            uniform_buffer,
+            fxaa,
+            vignette,
+            chromatic_aberration,
+            color_grading,
+            sampler,
+            lut,
+            lut_path: None,
+        }
+    }
+
+    pub fn lut_path(&self) -> Option<&PathBuf> {
+        self.lut_path.as_ref()
+    }
+
+    // Replaces the color grading LUT with a `.cube` file from the resource
+    // directory, or the identity LUT for `None`
+    pub fn load_color_grading_lut(
+        &mut self,
+        relative_path: Option<&PathBuf>,
+        context: &Context,
+    ) -> Result<()> {
+        self.lut = match relative_path {
+            Some(relative_path) => {
+                let bytes = resources::load_resource(relative_path)?;
+                let (size, rgba) = parse_cube_lut(std::str::from_utf8(&bytes)?)?;
+                Lut::new(&context.device, &context.queue, size, &rgba)
+            }
+            None => Lut::identity(&context.device, &context.queue),
+        };
+        self.lut_path = relative_path.cloned();
+        Ok(())
+    }
+
+    pub fn update(&self, context: &Context, settings: &PostProcessSettings) {
+        let bloom = BloomUniform {
+            threshold: settings.bloom.threshold,
+            knee: settings.bloom.knee.max(1e-4),
+            intensity: settings.bloom.intensity,
+            filter_radius: settings.bloom.filter_radius,
+        };
+        context.queue.write_buffer(
+            &self.bloom_uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[bloom]),
+        );
+
+        let uniform = PostProcessUniform {
+            fxaa_edge_threshold: settings.fxaa.edge_threshold,
+            fxaa_edge_threshold_min: settings.fxaa.edge_threshold_min,
+            fxaa_span_max: settings.fxaa.span_max,
+            fxaa_reduce_mul: settings.fxaa.reduce_mul,
+            vignette_intensity: settings.vignette.intensity,
+            vignette_radius: settings.vignette.radius,
+            vignette_smoothness: settings.vignette.smoothness,
+            chromatic_aberration_intensity: settings.chromatic_aberration.intensity,
+            color_grading_strength: settings.color_grading.strength,
+            lut_size: self.lut.size as f32,
+            linear_input: context.config.format.is_srgb() as u32,
+            _padding: 0,
+        };
+        context
+            .queue
+            .write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+    }
+
+    // Number of mips in the half resolution bloom chain for the given screen size
+    pub fn bloom_mip_count(settings: &BloomSettings, width: u32, height: u32) -> u32 {
+        let smallest_side = (width / 2).min(height / 2).max(1);
+        (u32::BITS - smallest_side.leading_zeros())
+            .min(settings.max_mip_count)
+            .max(1)
+    }
+
+    fn bloom_bind_group(
+        &self,
+        device: &wgpu::Device,
+        source: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bloom_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.bloom_uniform_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("bloom_bind_group"),
+        })
+    }
+
+    // Thresholds `hdr` into the mip chain of `bloom`, blurs it by downsampling and
+    // upsampling the chain, then adds the result back onto `hdr`
+    pub fn apply_bloom(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        hdr: &wgpu::TextureView,
+        bloom: &wgpu::Texture,
+    ) {
+        let mips = (0..bloom.mip_level_count())
+            .map(|mip| {
+                bloom.create_view(&wgpu::TextureViewDescriptor {
+                    label: Some("bloom_mip"),
+                    base_mip_level: mip,
+                    mip_level_count: Some(1),
+                    ..Default::default()
+                })
+            })
+            .collect::<Vec<_>>();
+
+        let draw = |encoder: &mut wgpu::CommandEncoder,
+                    pipeline: &wgpu::RenderPipeline,
+                    source: &wgpu::TextureView,
+                    target: &wgpu::TextureView,
+                    load: wgpu::LoadOp<wgpu::Color>| {
+            let bind_group = self.bloom_bind_group(device, source);
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Bloom Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[]);
+            render_pass.draw(0..3, 0..1);
+        };
+
+        let clear = wgpu::LoadOp::Clear(wgpu::Color::BLACK);
+        draw(encoder, &self.bloom_prefilter, hdr, &mips[0], clear);
+        for mip in 1..mips.len() {
+            draw(
+                encoder,
+                &self.bloom_downsample,
+                &mips[mip - 1],
+                &mips[mip],
+                clear,
+            );
+        }
+        for mip in (1..mips.len()).rev() {
+            draw(
+                encoder,
+                &self.bloom_upsample,
+                &mips[mip],
+                &mips[mip - 1],
+                wgpu::LoadOp::Load,
+            );
+        }
+        draw(
+            encoder,
+            &self.bloom_composite,
+            &mips[0],
+            hdr,
+            wgpu::LoadOp::Load,
+        );
+    }
+
+    pub fn apply_effect(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        effect: PostEffect,
+        source: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+    ) {
+        let pipeline = match effect {
+            PostEffect::ChromaticAberration => &self.chromatic_aberration,
+            PostEffect::ColorGrading => &self.color_grading,
+            PostEffect::Vignette => &self.vignette,
+            PostEffect::Fxaa => &self.fxaa,
+        };
+
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.uniform_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(&self.lut.view),
+                },
+            ],
+            label: Some("post_process_bind_group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Post Process Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
@@ -1,74 +1,377 @@
+use super::asset::AssetServer;
+use super::bounds::{CullingStats, Ray};
 use super::camera::Camera;
+use super::components::{spin_system, sync_objects, Extractor, Time};
 use super::context;
+use super::debug_draw::DebugDrawBuffer;
+use super::debug_view::{DebugViewSettings, DebugViews, DrawDebug};
+use super::ecs::{Schedule, World};
+use super::editor::{Editor, EditorScene, EditorSettings};
+use super::environment::{Environment, EnvironmentSettings};
+use super::gizmo::{Gizmo, GizmoSettings, GizmoTarget};
+use super::gpu_driven::{GpuCullingSettings, GpuObjectId, GpuScene};
+use super::graph::{PassResources, RenderGraph, ResourceId, TextureDesc, TransientPool};
+use super::hdr::{ExposureMode, ToneMapSettings, ToneMapper, HDR_FORMAT};
+use super::hot_reload::HotReloader;
+use super::instance::{InstancedObject, InstancedObjectId};
+use super::lod::LodSettings;
 use super::material::MaterialStore;
-use super::object::{DrawObject, ObjectManager};
-use super::pipeline::PipelineStore;
+use super::mipmap::MipmapGenerator;
+use super::model::{parse_model, upload_model};
+use super::object::{DrawObject, Object, ObjectManager};
+use super::overlay::Overlay;
+use super::picking::{PickHit, PickRequest, Picker, PICKING_DEPTH_FORMAT, PICKING_ID_FORMAT};
+use super::pipeline::{Blending, PipelineStore, SceneLayouts};
+use super::post_process::{PostProcessSettings, PostProcessor};
+use super::scene::{Scene, SceneSettings};
+use super::selection::{
+    DrawSelection, Selection, SelectionRenderer, SelectionStyle, JUMP_FLOOD_FORMAT,
+    SELECTION_MASK_FORMAT,
+};
 use super::shader::ShaderStore;
-use super::texture::TextureStore;
+use super::text::TextStyle;
+use super::texture::{Texture, TextureStore};
+use super::transparency::{
+    OitCompositor, TransparencyMode, OIT_ACCUM_FORMAT, OIT_REVEALAGE_FORMAT,
+};
 
-use std::path::PathBuf;
+use anyhow::bail;
+use cgmath::{EuclideanSpace, InnerSpace, Matrix4, Vector3};
+use log::{error, info, warn};
+
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::task::Poll;
+use std::time::Instant;
 use winit::window::Window;
 
+pub const DEFAULT_MSAA_SAMPLES: u32 = 4;
+const DEFAULT_SCENE: &str = "scenes/default.json";
+// Field of cubes drawn through GPU culling, toggled with `toggle_gpu_demo`
+const GPU_DEMO_MODEL: &str = "models/cube.obj";
+const GPU_DEMO_SIDE: i32 = 32;
+const GPU_DEMO_SPACING: f32 = 3.0;
+
 pub struct Renderer {
     context: context::Context<'static>,
     camera: Camera,
-    #[allow(unused)]
     shader_store: ShaderStore,
     texture_store: TextureStore,
     material_store: MaterialStore,
+    asset_server: AssetServer,
+    // None when `res` could not be watched
+    hot_reloader: Option<HotReloader>,
     object_manager: ObjectManager,
+    // Scene data and behavior, extracted into the object manager each frame
+    pub world: World,
+    pub schedule: Schedule,
+    extractor: Extractor,
     pipeline_store: PipelineStore,
+    pub frustum_culling: bool,
+    // Objects drawn and skipped by frustum culling, shown with the FPS
+    culling_stats: CullingStats,
+    pub level_of_detail: LodSettings,
+    gpu_scene: GpuScene,
+    pub gpu_culling: GpuCullingSettings,
+    gpu_demo: Vec<GpuObjectId>,
+    // Loaded into the GPU scene the first time the demo is shown
+    gpu_demo_model: Option<usize>,
+    environment: Environment,
+    pub environment_settings: EnvironmentSettings,
+    // Path under `res` of the loaded map
+    environment_map: Option<PathBuf>,
+    oit_compositor: OitCompositor,
+    pub transparency: TransparencyMode,
+    debug_views: DebugViews,
+    pub debug_view: DebugViewSettings,
+    debug_draw_buffer: DebugDrawBuffer,
+    overlay: Overlay,
+    editor: Editor,
+    picker: Picker,
+    selection_renderer: SelectionRenderer,
+    pub selection: Selection,
+    pub selection_style: SelectionStyle,
+    gizmo: Gizmo,
+    pub gizmo_settings: GizmoSettings,
+    pub show_fps: bool,
+    // Smoothed over recent frames for the FPS counter
+    frame_time: f32,
+    transient_pool: TransientPool,
+    tone_mapper: ToneMapper,
+    pub tone_mapping: ToneMapSettings,
+    post_processor: PostProcessor,
+    pub post_processing: PostProcessSettings,
+    last_update: Instant,
 }
 
 impl Renderer {
     pub async fn setup(window: Arc<Window>) -> Self {
-        let context = context::init_wgpu(window).await;
+        let context = context::init_wgpu(Arc::clone(&window)).await;
 
         let camera = Camera::new(&context.device, &context.config);
         let shader_store = ShaderStore::new(&context);
-        let mut texture_store = TextureStore::new(&context);
-        texture_store
+        let mut texture_store = TextureStore::new(&context, &shader_store);
+        let default_texture = texture_store
             .load_texture_from_file(PathBuf::from("textures/rickroll.jpg"), &context)
-            .await;
-        let mut material_store = MaterialStore::new();
-        let mut object_manager = ObjectManager::new(&context, &mut material_store).await;
+            .await
+            .expect("Failed to load the default texture");
+        let mut material_store = MaterialStore::new(default_texture);
+        let object_manager = ObjectManager::new(&context, &mut material_store).await;
+        let environment = Environment::new(&context, &shader_store);
+        let gpu_scene = GpuScene::new(&context, &shader_store);
+        let debug_views = DebugViews::new(&context);
+        let debug_draw_buffer = DebugDrawBuffer::new(&context);
+        let selection_renderer = SelectionRenderer::new(
+            &context,
+            &shader_store,
+            &camera.bind_group_layout,
+            &object_manager.bind_group_layout,
+        );
 
+        let sample_count = if Self::supports_msaa(&context, DEFAULT_MSAA_SAMPLES) {
+            DEFAULT_MSAA_SAMPLES
+        } else {
+            1
+        };
         let pipeline_store = PipelineStore::new(
             &context,
             &shader_store,
-            &[
-                &camera.bind_group_layout,
-                &texture_store.bind_group_layout,
-                &object_manager.bind_group_layout,
-            ],
+            &SceneLayouts {
+                camera: &camera.bind_group_layout,
+                texture: &texture_store.bind_group_layout,
+                object: &object_manager.bind_group_layout,
+                environment: &environment.bind_group_layout,
+                gpu_draws: &gpu_scene.draw_bind_group_layout,
+                debug: &debug_views.bind_group_layout,
+                selection: &selection_renderer.bind_group_layout,
+            },
+            sample_count,
         );
 
-        object_manager
-            .create_actor(
-                &PathBuf::from("models/cube.obj"),
-                &context,
-                &mut material_store,
-            )
-            .await;
+        let tone_mapper = ToneMapper::new(&context, &shader_store);
+        let post_processor = PostProcessor::new(&context, &shader_store);
+        let oit_compositor = OitCompositor::new(&context, &shader_store, sample_count);
+        let overlay = Overlay::new(&context, &shader_store, &mut texture_store);
+        let editor = Editor::new(&context, window);
+        let picker = Picker::new(
+            &context,
+            &shader_store,
+            &camera.bind_group_layout,
+            &object_manager.bind_group_layout,
+        );
 
-        Self {
+        let hot_reloader = match HotReloader::new() {
+            Ok(hot_reloader) => Some(hot_reloader),
+            Err(e) => {
+                warn!("Hot reloading is off, failed to watch the resource directory: {e}");
+                None
+            }
+        };
+
+        let mut world = World::new();
+        world.insert_resource(Time::default());
+        let mut schedule = Schedule::new();
+        schedule.add_system("spin", spin_system);
+
+        let mut renderer = Self {
             context,
             camera,
             shader_store,
             texture_store,
             material_store,
+            asset_server: AssetServer::new(),
+            hot_reloader,
             object_manager,
+            world,
+            schedule,
+            extractor: Extractor::default(),
             pipeline_store,
+            frustum_culling: true,
+            culling_stats: CullingStats::default(),
+            level_of_detail: LodSettings::default(),
+            gpu_scene,
+            gpu_culling: GpuCullingSettings::default(),
+            gpu_demo: Vec::new(),
+            gpu_demo_model: None,
+            environment,
+            environment_settings: EnvironmentSettings::default(),
+            environment_map: None,
+            oit_compositor,
+            transparency: TransparencyMode::default(),
+            debug_views,
+            debug_view: DebugViewSettings::default(),
+            debug_draw_buffer,
+            overlay,
+            editor,
+            picker,
+            selection_renderer,
+            selection: Selection::default(),
+            selection_style: SelectionStyle::default(),
+            gizmo: Gizmo::default(),
+            gizmo_settings: GizmoSettings::default(),
+            show_fps: false,
+            frame_time: 0.0,
+            transient_pool: TransientPool::default(),
+            tone_mapper,
+            tone_mapping: ToneMapSettings::default(),
+            post_processor,
+            post_processing: PostProcessSettings::default(),
+            last_update: Instant::now(),
+        };
+        if let Err(e) = renderer.load_scene(Path::new(DEFAULT_SCENE)) {
+            error!("Failed to load the default scene: {e:#}");
         }
+        renderer
     }
 
     fn update(&mut self) {
+        let now = Instant::now();
+        let delta_time = (now - self.last_update).as_secs_f32();
+        self.last_update = now;
+
+        self.hot_reload();
+        self.asset_server.update(
+            &self.context,
+            &mut self.texture_store,
+            &mut self.material_store,
+            &mut self.object_manager,
+        );
+        if let Some(time) = self.world.resource_mut::<Time>() {
+            time.delta = delta_time;
+            time.elapsed += delta_time;
+        }
+        sync_objects(&mut self.world, &self.object_manager);
+        self.schedule.run(&mut self.world);
+        self.extractor.extract(
+            &mut self.world,
+            &mut self.object_manager,
+            &mut self.camera.eye,
+        );
+
         self.camera.update(&self.context.queue);
-        self.object_manager.update(&self.context.queue);
+        self.object_manager.update(
+            &self.context.device,
+            &self.context.queue,
+            &self.camera.eye,
+            &self.level_of_detail,
+        );
+        let frustum = self.frustum_culling.then(|| self.camera.eye.frustum());
+        self.culling_stats = self.object_manager.cull(frustum.as_ref());
+        self.gpu_scene.update(
+            &self.context,
+            &self.camera.eye.frustum(),
+            self.camera.eye.build_view_projection_matrix(),
+            &self.gpu_culling,
+        );
+        self.debug_views
+            .update(&self.context.queue, &self.debug_view, &self.camera.eye);
+        self.picker.poll(&self.context.device, &self.object_manager);
+        self.selection.retain(|id| self.object_manager.contains(id));
+        self.selection_renderer
+            .update(&self.context.queue, &self.selection_style);
+        if let Some(target) = self.gizmo_target() {
+            self.gizmo
+                .draw(&self.gizmo_settings, &target, &self.camera.eye);
+        }
+        if self.debug_view.lights {
+            for light in &self.extractor.lights {
+                light.draw();
+            }
+        }
+        self.debug_draw_buffer
+            .update(&self.context, delta_time, &self.camera.eye);
+        self.environment
+            .update(&self.context, &self.environment_settings);
+        self.tone_mapper
+            .update(&self.context, &self.tone_mapping, delta_time);
+        let lut = self.post_processing.color_grading.lut.as_ref();
+        if lut != self.post_processor.lut_path() {
+            if let Err(e) = self
+                .post_processor
+                .load_color_grading_lut(lut, &self.context)
+            {
+                error!("Failed to load color grading LUT: {e:#}");
+                // Keeps the previous LUT instead of retrying every frame
+                self.post_processing.color_grading.lut = self.post_processor.lut_path().cloned();
+            }
+        }
+        self.post_processor
+            .update(&self.context, &self.post_processing);
+
+        self.frame_time += (delta_time - self.frame_time) * 0.05;
+        if self.show_fps && self.frame_time > 0.0 {
+            let text = format!(
+                "{:.0} FPS  {:.2} ms\n{} visible  {} culled",
+                1.0 / self.frame_time,
+                self.frame_time * 1000.0,
+                self.culling_stats.visible,
+                self.culling_stats.culled
+            );
+            let style = TextStyle::default();
+            let size = self.overlay.measure_text(&text, &style);
+            self.overlay.rect(
+                [4.0, 4.0],
+                [size[0] + 8.0, size[1] + 8.0],
+                [0.0, 0.0, 0.0, 0.5],
+            );
+            self.overlay.text([8.0, 8.0], &text, &style);
+        }
+        self.overlay.prepare(&self.context);
     }
 
     pub fn render(&mut self) {
+        let mut encoder =
+            self.context
+                .device
+                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Render Encoder"),
+                });
+
+        // The editor runs first so its changes show up in this frame
+        let mut msaa_samples = self.pipeline_store.sample_count;
+        let mut environment_map = self.environment_map.clone();
+        self.editor.prepare(
+            &self.context,
+            &mut encoder,
+            EditorScene {
+                object_manager: &mut self.object_manager,
+                material_store: &mut self.material_store,
+                texture_store: &self.texture_store,
+                eye: &mut self.camera.eye,
+                selection: &mut self.selection,
+                settings: EditorSettings {
+                    frustum_culling: &mut self.frustum_culling,
+                    level_of_detail: &mut self.level_of_detail,
+                    gpu_culling: &mut self.gpu_culling,
+                    transparency: &mut self.transparency,
+                    debug_view: &mut self.debug_view,
+                    tone_mapping: &mut self.tone_mapping,
+                    post_processing: &mut self.post_processing,
+                    environment: &mut self.environment_settings,
+                    show_fps: &mut self.show_fps,
+                    selection_style: &mut self.selection_style,
+                    gizmo: &mut self.gizmo_settings,
+                    msaa_samples: &mut msaa_samples,
+                    environment_map: &mut environment_map,
+                },
+            },
+        );
+        if msaa_samples != self.pipeline_store.sample_count {
+            if let Err(e) = self.set_msaa_samples(msaa_samples) {
+                error!("Failed to change MSAA: {e}");
+            }
+        }
+        if environment_map != self.environment_map {
+            match environment_map {
+                Some(path) => {
+                    if let Err(e) = self.load_environment(&path) {
+                        error!("Failed to load environment map {}: {e:#}", path.display());
+                    }
+                }
+                None => self.unload_environment(),
+            }
+        }
+
         self.update();
 
         let frame = match self.context.surface.get_current_texture() {
@@ -98,19 +401,119 @@ impl Renderer {
             .texture
             .create_view(&wgpu::TextureViewDescriptor::default());
 
-        let mut encoder =
-            self.context
-                .device
-                .create_command_encoder(&wgpu::CommandEncoderDescriptor {
-                    label: Some("Render Encoder"),
-                });
+        let pick = self.picker.next_request(
+            [self.context.config.width, self.context.config.height],
+            self.camera.eye.build_view_projection_matrix(),
+            &self.object_manager,
+        );
 
-        {
+        // The graph borrows the renderer, so the pool is moved out while it records
+        let mut transient_pool = std::mem::take(&mut self.transient_pool);
+        let mut graph = RenderGraph::new();
+        let surface = graph.import_texture(&frame.texture, &view);
+        let hdr = self.add_scene_passes(&mut graph);
+        self.add_post_process_passes(&mut graph, hdr, surface);
+        if !self.selection.is_empty() {
+            self.add_outline_passes(&mut graph, surface);
+        }
+        if !self.overlay.is_empty() {
+            self.add_overlay_pass(&mut graph, surface);
+        }
+        if !self.editor.is_empty() {
+            self.add_editor_pass(&mut graph, surface);
+        }
+        if let Some(request) = &pick {
+            self.add_picking_pass(&mut graph, request);
+        }
+        let result = graph.execute(&self.context.device, &mut encoder, &mut transient_pool);
+        self.transient_pool = transient_pool;
+        self.overlay.clear();
+        self.editor.finish();
+
+        if let Err(e) = result {
+            error!("Failed to execute render graph: {e}");
+            return;
+        }
+
+        self.context.queue.submit(Some(encoder.finish()));
+        if let Some(request) = pick {
+            self.picker.map_readback(request);
+        }
+        frame.present();
+    }
+
+    // Draws the scene into an HDR target and returns it
+    fn add_scene_passes<'a>(&'a self, graph: &mut RenderGraph<'a>) -> ResourceId {
+        let (width, height) = (self.context.config.width, self.context.config.height);
+        let sample_count = self.pipeline_store.sample_count;
+
+        let hdr = graph.create_texture(TextureDesc::new(
+            "hdr_scene",
+            width,
+            height,
+            HDR_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        ));
+        let depth = graph.create_texture(TextureDesc {
+            sample_count,
+            ..TextureDesc::new(
+                "depth_texture",
+                width,
+                height,
+                Texture::DEPTH_FORMAT,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            )
+        });
+        // With MSAA the scene is drawn into a multisampled target and resolved into `hdr`
+        let multisampled = (sample_count > 1).then(|| {
+            graph.create_texture(TextureDesc {
+                sample_count,
+                ..TextureDesc::new(
+                    "hdr_scene_multisampled",
+                    width,
+                    height,
+                    HDR_FORMAT,
+                    wgpu::TextureUsages::RENDER_ATTACHMENT,
+                )
+            })
+        });
+
+        // GPU driven draws get their indirect commands from a culling pass
+        // before the main pass
+        let gpu_scene = &self.gpu_scene;
+        let indirect = (gpu_scene.indirect_supported && gpu_scene.draw_count() > 0).then(|| {
+            let indirect = graph.import_buffer(gpu_scene.indirect_buffer());
+            graph
+                .add_pass("gpu_culling")
+                .write(indirect)
+                .execute(move |encoder, _| gpu_scene.cull(encoder));
+            indirect
+        });
+
+        let mut pass = graph.add_pass("main").write(hdr).write(depth);
+        if let Some(multisampled) = multisampled {
+            pass = pass.write(multisampled);
+        }
+        if let Some(indirect) = indirect {
+            pass = pass.read(indirect);
+        }
+        let device = &self.context.device;
+        let debug_shading = self.pipeline_store.debug.shading(self.debug_view.shading);
+        pass.execute(move |encoder, resources| {
+            let (view, resolve_target) = match multisampled {
+                Some(multisampled) => (resources.view(multisampled), Some(resources.view(hdr))),
+                None => (resources.view(hdr), None),
+            };
+            let objects = self.object_manager.iter().collect::<Vec<_>>();
+            let debug_bind_groups = match debug_shading {
+                Some(_) => self.debug_views.bind_groups(device, &objects),
+                None => Vec::new(),
+            };
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view,
+                    resolve_target,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
                             r: 0.01,
@@ -124,7 +527,7 @@ impl Renderer {
                 timestamp_writes: None,
                 occlusion_query_set: None,
                 depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: &self.texture_store.depth_texture.view,
+                    view: resources.view(depth),
                     depth_ops: Some(wgpu::Operations {
                         load: wgpu::LoadOp::Clear(1.0),
                         store: wgpu::StoreOp::Store,
@@ -133,20 +536,1043 @@ impl Renderer {
                 }),
             });
 
-            for object in self.object_manager.iter() {
-                render_pass.set_pipeline(&self.pipeline_store.basic);
-                render_pass.draw_object(
+            render_pass.set_bind_group(3, &self.environment.bind_group, &[]);
+            if let Some(pipeline) = debug_shading {
+                // Debug shading draws transparent materials as opaque too
+                render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
+                for (index, (object, bind_groups)) in
+                    objects.iter().zip(&debug_bind_groups).enumerate()
+                {
+                    if object.is_visible() {
+                        render_pass.draw_debug_shading(pipeline, object, index as u32, bind_groups);
+                    }
+                }
+                render_pass.set_bind_group(3, &self.environment.bind_group, &[]);
+            } else {
+                for object in objects.iter().filter(|o| o.is_visible()) {
+                    render_pass.draw_object(
+                        object,
+                        &self.camera.bind_group,
+                        &self.material_store,
+                        &self.texture_store,
+                        &self.pipeline_store,
+                        Blending::Opaque,
+                    );
+                }
+            }
+            for object in self
+                .object_manager
+                .iter_instanced()
+                .filter(|o| o.is_visible())
+            {
+                render_pass.draw_instanced_object(
                     object,
                     &self.camera.bind_group,
                     &self.material_store,
                     &self.texture_store,
                     &self.pipeline_store,
+                    Blending::Opaque,
                 );
             }
+
+            render_pass.set_pipeline(&self.pipeline_store.basic_gpu_driven);
+            render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
+            gpu_scene.draw(&mut render_pass, |material_id| {
+                let material = self.material_store.get_material(material_id);
+                let diffuse = self.texture_store.get_texture(material.diffuse_texture_id);
+                diffuse.bind_group.as_ref().unwrap()
+            });
+
+            if self.environment.is_loaded() && self.environment_settings.show_skybox {
+                render_pass.set_pipeline(&self.pipeline_store.skybox);
+                render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
+                render_pass.set_bind_group(1, &self.environment.bind_group, &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        });
+
+        if debug_shading.is_none() {
+            self.add_transparent_passes(graph, hdr, depth, multisampled);
+        }
+        if self.debug_view.has_overlay() {
+            self.add_debug_overlay_pass(graph, hdr, depth, multisampled);
+        }
+        if !self.debug_draw_buffer.is_empty() {
+            self.add_debug_draw_pass(graph, hdr, depth, multisampled);
+        }
+        if !self.selection.is_empty() && self.selection_style.tint[3] > 0.0 {
+            self.add_selection_tint_pass(graph, hdr, depth, multisampled);
         }
 
-        self.context.queue.submit(Some(encoder.finish()));
-        frame.present();
+        // The culling pass of the next frame tests against this depth. It reads
+        // the pyramid without declaring it, since it wants the previous frame's.
+        if gpu_scene.builds_hi_z() {
+            let (texture, view) = gpu_scene.hi_z_texture();
+            let hi_z = graph.import_texture(texture, view);
+            let device = &self.context.device;
+            graph
+                .add_pass("hi_z")
+                .read(depth)
+                .write(hi_z)
+                .execute(move |encoder, resources| {
+                    gpu_scene.build_hi_z(device, encoder, resources.view(depth), sample_count);
+                });
+        }
+
+        hdr
+    }
+
+    // Draws transparent materials over the opaque scene in `hdr`, testing
+    // against but not writing its depth
+    fn add_transparent_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        hdr: ResourceId,
+        depth: ResourceId,
+        multisampled: Option<ResourceId>,
+    ) {
+        let mut objects = self
+            .object_manager
+            .iter()
+            .filter(|o| o.is_visible() && o.has_transparent(&self.material_store))
+            .collect::<Vec<_>>();
+        let instanced = self
+            .object_manager
+            .iter_instanced()
+            .filter(|o| o.is_visible() && o.has_transparent(&self.material_store))
+            .collect::<Vec<_>>();
+        if objects.is_empty() && instanced.is_empty() {
+            return;
+        }
+
+        let (width, height) = (self.context.config.width, self.context.config.height);
+        let sample_count = self.pipeline_store.sample_count;
+        if self.transparency == TransparencyMode::Sorted {
+            let eye = self.camera.eye.position.to_vec();
+            let distance =
+                |object: &Object| (object.world_bounding_sphere().center - eye).magnitude2();
+            objects.sort_by(|a, b| distance(b).total_cmp(&distance(a)));
+
+            // With MSAA the multisampled scene is blended into and resolved again
+            let mut pass = graph
+                .add_pass("transparent")
+                .read(depth)
+                .read(hdr)
+                .write(hdr);
+            if let Some(multisampled) = multisampled {
+                pass = pass.read(multisampled).write(multisampled);
+            }
+            pass.execute(move |encoder, resources| {
+                let (view, resolve_target) = match multisampled {
+                    Some(multisampled) => (resources.view(multisampled), Some(resources.view(hdr))),
+                    None => (resources.view(hdr), None),
+                };
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Transparent Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view,
+                        resolve_target,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: read_only_depth(resources.view(depth)),
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                self.draw_transparent(&mut render_pass, &objects, &instanced, Blending::Sorted);
+            });
+            return;
+        }
+
+        let target = |graph: &mut RenderGraph, label, format, sample_count| {
+            let usage = if sample_count > 1 {
+                wgpu::TextureUsages::RENDER_ATTACHMENT
+            } else {
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING
+            };
+            graph.create_texture(TextureDesc {
+                sample_count,
+                ..TextureDesc::new(label, width, height, format, usage)
+            })
+        };
+        let accum = target(graph, "oit_accum", OIT_ACCUM_FORMAT, 1);
+        let revealage = target(graph, "oit_revealage", OIT_REVEALAGE_FORMAT, 1);
+        let multisampled_targets = (sample_count > 1).then(|| {
+            (
+                target(
+                    graph,
+                    "oit_accum_multisampled",
+                    OIT_ACCUM_FORMAT,
+                    sample_count,
+                ),
+                target(
+                    graph,
+                    "oit_revealage_multisampled",
+                    OIT_REVEALAGE_FORMAT,
+                    sample_count,
+                ),
+            )
+        });
+
+        let mut pass = graph
+            .add_pass("transparent")
+            .read(depth)
+            .write(accum)
+            .write(revealage);
+        if let Some((accum, revealage)) = multisampled_targets {
+            pass = pass.write(accum).write(revealage);
+        }
+        pass.execute(move |encoder, resources| {
+            let attachment = |target: ResourceId, resolve: Option<ResourceId>, clear: f64| {
+                Some(wgpu::RenderPassColorAttachment {
+                    view: resources.view(target),
+                    resolve_target: resolve.map(|resolve| resources.view(resolve)),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color {
+                            r: clear,
+                            g: clear,
+                            b: clear,
+                            a: clear,
+                        }),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })
+            };
+            let color_attachments = match multisampled_targets {
+                Some((accum_multisampled, revealage_multisampled)) => [
+                    attachment(accum_multisampled, Some(accum), 0.0),
+                    attachment(revealage_multisampled, Some(revealage), 1.0),
+                ],
+                None => [
+                    attachment(accum, None, 0.0),
+                    attachment(revealage, None, 1.0),
+                ],
+            };
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Transparent Pass"),
+                color_attachments: &color_attachments,
+                depth_stencil_attachment: read_only_depth(resources.view(depth)),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            self.draw_transparent(
+                &mut render_pass,
+                &objects,
+                &instanced,
+                Blending::WeightedBlended,
+            );
+        });
+
+        // Composited into the multisampled scene and resolved, so the passes
+        // after it can keep drawing there without losing the transparent layer
+        let device = &self.context.device;
+        let mut pass = graph
+            .add_pass("oit_composite")
+            .read(accum)
+            .read(revealage)
+            .read(hdr)
+            .write(hdr);
+        if let Some(multisampled) = multisampled {
+            pass = pass.read(multisampled).write(multisampled);
+        }
+        pass.execute(move |encoder, resources| {
+            let (view, resolve_target) = scene_target(resources, hdr, multisampled);
+            self.oit_compositor.composite(
+                device,
+                encoder,
+                resources.view(accum),
+                resources.view(revealage),
+                view,
+                resolve_target,
+            );
+        });
+    }
+
+    // Wireframe and normals drawn over the finished scene
+    fn add_debug_overlay_pass<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        hdr: ResourceId,
+        depth: ResourceId,
+        multisampled: Option<ResourceId>,
+    ) {
+        let device = &self.context.device;
+        let mut pass = graph
+            .add_pass("debug_overlay")
+            .read(depth)
+            .read(hdr)
+            .write(hdr);
+        if let Some(multisampled) = multisampled {
+            pass = pass.read(multisampled).write(multisampled);
+        }
+        pass.execute(move |encoder, resources| {
+            let objects = self
+                .object_manager
+                .iter()
+                .filter(|o| o.is_visible())
+                .collect::<Vec<_>>();
+            let bind_groups = self.debug_views.bind_groups(device, &objects);
+            let (view, resolve_target) = scene_target(resources, hdr, multisampled);
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Debug Overlay Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: read_only_depth(resources.view(depth)),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
+            for (object, bind_groups) in objects.iter().zip(&bind_groups) {
+                render_pass.draw_debug_overlay(
+                    &self.pipeline_store.debug,
+                    &self.debug_view,
+                    object,
+                    bind_groups,
+                );
+            }
+        });
+    }
+
+    // Tints the visible surfaces of selected and hovered objects
+    fn add_selection_tint_pass<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        hdr: ResourceId,
+        depth: ResourceId,
+        multisampled: Option<ResourceId>,
+    ) {
+        let mut pass = graph
+            .add_pass("selection_tint")
+            .read(depth)
+            .read(hdr)
+            .write(hdr);
+        if let Some(multisampled) = multisampled {
+            pass = pass.read(multisampled).write(multisampled);
+        }
+        pass.execute(move |encoder, resources| {
+            // Resolving again keeps the transparent layer, the OIT composite
+            // went through the multisampled target as well
+            let (view, resolve_target) = scene_target(resources, hdr, multisampled);
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Selection Tint Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: read_only_depth(resources.view(depth)),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            render_pass.set_pipeline(&self.pipeline_store.selection_tint);
+            render_pass.set_bind_group(0, &self.camera.bind_group, &[]);
+            render_pass.set_bind_group(2, &self.selection_renderer.bind_group, &[]);
+            render_pass.draw_selection(self.object_manager.iter_with_ids(), &self.selection);
+        });
+    }
+
+    // Lines queued through `debug_draw()` this frame
+    fn add_debug_draw_pass<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        hdr: ResourceId,
+        depth: ResourceId,
+        multisampled: Option<ResourceId>,
+    ) {
+        let mut pass = graph
+            .add_pass("debug_draw")
+            .read(depth)
+            .read(hdr)
+            .write(hdr);
+        if let Some(multisampled) = multisampled {
+            pass = pass.read(multisampled).write(multisampled);
+        }
+        pass.execute(move |encoder, resources| {
+            // Resolving again is safe since the OIT composite went through the
+            // multisampled target as well
+            let (view, resolve_target) = scene_target(resources, hdr, multisampled);
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Debug Draw Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view,
+                    resolve_target,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: read_only_depth(resources.view(depth)),
+                timestamp_writes: None,
+                occlusion_query_set: None,
+            });
+            self.debug_draw_buffer.draw(
+                &mut render_pass,
+                &self.pipeline_store.debug_draw,
+                &self.camera.bind_group,
+            );
+        });
+    }
+
+    // Instanced objects come after the others and are not sorted, so with
+    // `TransparencyMode::Sorted` their instances may blend out of order
+    fn draw_transparent<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        objects: &[&'a Object],
+        instanced: &[&'a InstancedObject],
+        blending: Blending,
+    ) {
+        render_pass.set_bind_group(3, &self.environment.bind_group, &[]);
+        for object in objects {
+            render_pass.draw_object(
+                object,
+                &self.camera.bind_group,
+                &self.material_store,
+                &self.texture_store,
+                &self.pipeline_store,
+                blending,
+            );
+        }
+        for object in instanced {
+            render_pass.draw_instanced_object(
+                object,
+                &self.camera.bind_group,
+                &self.material_store,
+                &self.texture_store,
+                &self.pipeline_store,
+                blending,
+            );
+        }
+    }
+
+    // Bloom, exposure and tone mapping on `hdr`, followed by the screen space
+    // effects, ending in `surface`
+    fn add_post_process_passes<'a>(
+        &'a self,
+        graph: &mut RenderGraph<'a>,
+        hdr: ResourceId,
+        surface: ResourceId,
+    ) {
+        let (width, height) = (self.context.config.width, self.context.config.height);
+        let device = &self.context.device;
+        let luminance = graph.import_buffer(&self.tone_mapper.luminance_buffer);
+
+        if self.post_processing.bloom.enabled {
+            let bloom = graph.create_texture(TextureDesc {
+                mip_level_count: PostProcessor::bloom_mip_count(
+                    &self.post_processing.bloom,
+                    width,
+                    height,
+                ),
+                ..TextureDesc::new(
+                    "bloom",
+                    width / 2,
+                    height / 2,
+                    HDR_FORMAT,
+                    wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+                )
+            });
+            graph
+                .add_pass("bloom")
+                .read(hdr)
+                .write(hdr)
+                .write(bloom)
+                .execute(move |encoder, resources| {
+                    self.post_processor.apply_bloom(
+                        device,
+                        encoder,
+                        resources.view(hdr),
+                        resources.texture(bloom),
+                    );
+                });
+        }
+
+        if self.tone_mapping.exposure_mode == ExposureMode::Auto {
+            graph
+                .add_pass("auto_exposure")
+                .read(hdr)
+                .write(luminance)
+                .execute(move |encoder, resources| {
+                    self.tone_mapper.compute_exposure(
+                        device,
+                        encoder,
+                        resources.view(hdr),
+                        width,
+                        height,
+                    );
+                });
+        }
+
+        // Everything after tone mapping ping-pongs between surface sized textures,
+        // with the last enabled effect writing straight to the surface.
+        let ldr_texture = |graph: &mut RenderGraph| {
+            graph.create_texture(TextureDesc::new(
+                "ldr",
+                width,
+                height,
+                self.context.config.format,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            ))
+        };
+        let effects = self.post_processing.enabled_effects();
+        let mut ldr = if effects.is_empty() {
+            surface
+        } else {
+            ldr_texture(graph)
+        };
+
+        graph
+            .add_pass("tone_mapping")
+            .read(hdr)
+            .read(luminance)
+            .write(ldr)
+            .execute(move |encoder, resources| {
+                self.tone_mapper
+                    .apply(device, encoder, resources.view(hdr), resources.view(ldr));
+            });
+
+        for (index, &effect) in effects.iter().enumerate() {
+            let source = ldr;
+            ldr = if index + 1 == effects.len() {
+                surface
+            } else {
+                ldr_texture(graph)
+            };
+            let target = ldr;
+            graph
+                .add_pass("post_process")
+                .read(source)
+                .write(target)
+                .execute(move |encoder, resources| {
+                    self.post_processor.apply_effect(
+                        device,
+                        encoder,
+                        effect,
+                        resources.view(source),
+                        resources.view(target),
+                    );
+                });
+        }
+    }
+
+    // The 2D overlay on top of everything else
+    fn add_overlay_pass<'a>(&'a self, graph: &mut RenderGraph<'a>, surface: ResourceId) {
+        graph
+            .add_pass("overlay")
+            .read(surface)
+            .write(surface)
+            .execute(move |encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Overlay Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(surface),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                self.overlay.draw(&mut render_pass, &self.texture_store);
+            });
+    }
+
+    // Silhouette outlines around selected and hovered objects: their mask is
+    // grown by a jump flood and everything within the outline width is drawn
+    fn add_outline_passes<'a>(&'a self, graph: &mut RenderGraph<'a>, surface: ResourceId) {
+        let device = &self.context.device;
+        let (width, height) = (self.context.config.width, self.context.config.height);
+        let mask = graph.create_texture(TextureDesc::new(
+            "selection_mask",
+            width,
+            height,
+            SELECTION_MASK_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        ));
+        let mask_depth = graph.create_texture(TextureDesc::new(
+            "selection_mask_depth",
+            width,
+            height,
+            Texture::DEPTH_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        ));
+        graph
+            .add_pass("selection_mask")
+            .write(mask)
+            .write(mask_depth)
+            .execute(move |encoder, resources| {
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Selection Mask Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(mask),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                        view: resources.view(mask_depth),
+                        depth_ops: Some(wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(1.0),
+                            store: wgpu::StoreOp::Discard,
+                        }),
+                        stencil_ops: None,
+                    }),
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                self.selection_renderer.draw_mask(
+                    &mut render_pass,
+                    &self.camera.bind_group,
+                    self.object_manager.iter_with_ids(),
+                    &self.selection,
+                );
+            });
+
+        // Seeds ping-pong through the pool, one transient per step
+        let mut seeds = None;
+        let steps = std::iter::once(None).chain(self.selection_style.jump_flood_steps().map(Some));
+        for step in steps {
+            let next = graph.create_texture(TextureDesc::new(
+                "jump_flood_seeds",
+                width,
+                height,
+                JUMP_FLOOD_FORMAT,
+                wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            ));
+            let mut pass = graph.add_pass("jump_flood").read(mask).write(next);
+            if let Some(previous) = seeds {
+                pass = pass.read(previous);
+            }
+            pass.execute(move |encoder, resources| {
+                let input = seeds.map_or(resources.view(mask), |previous| resources.view(previous));
+                let textures =
+                    self.selection_renderer
+                        .texture_bind_group(device, resources.view(mask), input);
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Jump Flood Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(next),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                self.selection_renderer
+                    .draw_jump_flood(&mut render_pass, &textures, step);
+            });
+            seeds = Some(next);
+        }
+        let seeds = seeds.expect("The jump flood has at least its seeding pass");
+
+        graph
+            .add_pass("outline")
+            .read(mask)
+            .read(seeds)
+            .read(surface)
+            .write(surface)
+            .execute(move |encoder, resources| {
+                let textures = self.selection_renderer.texture_bind_group(
+                    device,
+                    resources.view(mask),
+                    resources.view(seeds),
+                );
+                let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                    label: Some("Outline Pass"),
+                    color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                        view: resources.view(surface),
+                        resolve_target: None,
+                        ops: wgpu::Operations {
+                            load: wgpu::LoadOp::Load,
+                            store: wgpu::StoreOp::Store,
+                        },
+                    })],
+                    depth_stencil_attachment: None,
+                    timestamp_writes: None,
+                    occlusion_query_set: None,
+                });
+                self.selection_renderer
+                    .draw_outline(&mut render_pass, &textures);
+            });
+    }
+
+    // Object ids and depth under the picked pixel, copied into the readback buffer
+    fn add_picking_pass<'a>(&'a self, graph: &mut RenderGraph<'a>, request: &'a PickRequest) {
+        let (width, height) = (self.context.config.width, self.context.config.height);
+        let target_usage = wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC;
+        let ids = graph.create_texture(TextureDesc::new(
+            "picking_ids",
+            width,
+            height,
+            PICKING_ID_FORMAT,
+            target_usage,
+        ));
+        let depths = graph.create_texture(TextureDesc::new(
+            "picking_depths",
+            width,
+            height,
+            PICKING_DEPTH_FORMAT,
+            target_usage,
+        ));
+        let depth = graph.create_texture(TextureDesc::new(
+            "picking_depth_buffer",
+            width,
+            height,
+            Texture::DEPTH_FORMAT,
+            wgpu::TextureUsages::RENDER_ATTACHMENT,
+        ));
+        let readback = graph.import_buffer(self.picker.readback_buffer());
+        graph
+            .add_pass("picking")
+            .write(ids)
+            .write(depths)
+            .write(depth)
+            .write(readback)
+            .execute(move |encoder, resources| {
+                {
+                    let target = |id| {
+                        Some(wgpu::RenderPassColorAttachment {
+                            view: resources.view(id),
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                                store: wgpu::StoreOp::Store,
+                            },
+                        })
+                    };
+                    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("Picking Pass"),
+                        color_attachments: &[target(ids), target(depths)],
+                        depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                            view: resources.view(depth),
+                            depth_ops: Some(wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(1.0),
+                                store: wgpu::StoreOp::Discard,
+                            }),
+                            stencil_ops: None,
+                        }),
+                        timestamp_writes: None,
+                        occlusion_query_set: None,
+                    });
+                    self.picker.draw(
+                        &mut render_pass,
+                        &self.camera.bind_group,
+                        &self.object_manager,
+                        request,
+                    );
+                }
+                self.picker.copy_pixel(
+                    encoder,
+                    resources.texture(ids),
+                    resources.texture(depths),
+                    request,
+                );
+            });
+    }
+
+    // The editor UI goes on top of the overlay
+    fn add_editor_pass<'a>(&'a self, graph: &mut RenderGraph<'a>, surface: ResourceId) {
+        graph
+            .add_pass("editor")
+            .read(surface)
+            .write(surface)
+            .execute(move |encoder, resources| {
+                // egui records into a pass that is not tied to the encoder's borrow
+                let mut render_pass = encoder
+                    .begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("Editor Pass"),
+                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                            view: resources.view(surface),
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Load,
+                                store: wgpu::StoreOp::Store,
+                            },
+                        })],
+                        depth_stencil_attachment: None,
+                        timestamp_writes: None,
+                        occlusion_query_set: None,
+                    })
+                    .forget_lifetime();
+                self.editor.draw(&mut render_pass);
+            });
+    }
+
+    // Loads a model once for drawing many instances of it, returning the id
+    // for `instanced_object_mut`
+    #[allow(dead_code)]
+    pub async fn create_instanced_object(&mut self, model_path: &Path) -> InstancedObjectId {
+        self.object_manager
+            .create_instanced(model_path, &self.context, &mut self.material_store)
+            .await
+    }
+
+    #[allow(dead_code)]
+    pub fn instanced_object_mut(&mut self, id: InstancedObjectId) -> Option<&mut InstancedObject> {
+        self.object_manager.instanced_mut(id)
+    }
+
+    // Loads a model into the shared buffers of the GPU driven scene, returning
+    // the index for `GpuScene::add_object`
+    pub fn load_gpu_model(&mut self, model_path: &Path) -> anyhow::Result<usize> {
+        let data = parse_model(model_path)?;
+        let model = upload_model(
+            &data,
+            model_path,
+            &self.context.device,
+            &mut self.material_store,
+        );
+        Ok(self.gpu_scene.add_model(&self.context, &model))
+    }
+
+    // Fills the GPU driven scene with a field of cubes, or empties it again
+    pub fn toggle_gpu_demo(&mut self) -> anyhow::Result<()> {
+        if !self.gpu_demo.is_empty() {
+            for id in self.gpu_demo.drain(..) {
+                self.gpu_scene.remove_object(id);
+            }
+            info!("Removed the GPU driven demo");
+            return Ok(());
+        }
+
+        let model = match self.gpu_demo_model {
+            Some(model) => model,
+            None => {
+                let model = self.load_gpu_model(Path::new(GPU_DEMO_MODEL))?;
+                self.gpu_demo_model = Some(model);
+                model
+            }
+        };
+        let offset = (GPU_DEMO_SIDE - 1) as f32 * GPU_DEMO_SPACING / 2.0;
+        for x in 0..GPU_DEMO_SIDE {
+            for z in 0..GPU_DEMO_SIDE {
+                let position = Vector3::new(
+                    x as f32 * GPU_DEMO_SPACING - offset,
+                    1.0,
+                    z as f32 * GPU_DEMO_SPACING - offset,
+                );
+                let id = self
+                    .gpu_scene
+                    .add_object(model, Matrix4::from_translation(position));
+                self.gpu_demo.push(id);
+            }
+        }
+        info!(
+            "Added {} objects to the GPU driven scene",
+            self.gpu_demo.len()
+        );
+        if !self.gpu_scene.indirect_supported {
+            warn!("Indirect draws are not supported, the demo is drawn without GPU culling");
+        }
+        Ok(())
+    }
+
+    #[allow(dead_code)]
+    pub fn gpu_scene_mut(&mut self) -> &mut GpuScene {
+        &mut self.gpu_scene
+    }
+
+    // Replaces the clear color with the given equirectangular HDR map and
+    // lights the scene with it
+    pub fn load_environment(&mut self, relative_path: &PathBuf) -> anyhow::Result<()> {
+        self.environment.load(
+            relative_path,
+            &self.context,
+            &self.texture_store.mipmap_generator,
+        )?;
+        self.environment_map = Some(relative_path.clone());
+        Ok(())
+    }
+
+    pub fn unload_environment(&mut self) {
+        self.environment.unload(&self.context);
+        self.environment_map = None;
+    }
+
+    fn supports_msaa(context: &context::Context, sample_count: u32) -> bool {
+        context.supports_sample_count(HDR_FORMAT, sample_count)
+            && context.supports_sample_count(Texture::DEPTH_FORMAT, sample_count)
+            && context.supports_sample_count(OIT_ACCUM_FORMAT, sample_count)
+            && context.supports_sample_count(OIT_REVEALAGE_FORMAT, sample_count)
+    }
+
+    #[allow(dead_code)]
+    pub fn msaa_samples(&self) -> u32 {
+        self.pipeline_store.sample_count
+    }
+
+    // Rebuilds the scene pipelines for the new sample count; the multisampled
+    // targets follow on the next frame since they are render graph transients
+    #[allow(dead_code)]
+    pub fn set_msaa_samples(&mut self, sample_count: u32) -> anyhow::Result<()> {
+        self.check_msaa_samples(sample_count)?;
+        if sample_count == self.pipeline_store.sample_count {
+            return Ok(());
+        }
+
+        info!("Switching to {sample_count}x MSAA");
+        self.pipeline_store = self.create_pipeline_store(sample_count);
+        self.oit_compositor = OitCompositor::new(&self.context, &self.shader_store, sample_count);
+        self.transient_pool.clear();
+        Ok(())
+    }
+
+    fn check_msaa_samples(&self, sample_count: u32) -> anyhow::Result<()> {
+        if !matches!(sample_count, 1 | 2 | 4 | 8) {
+            bail!("MSAA sample count must be 1, 2, 4 or 8, got {sample_count}");
+        }
+        if !Self::supports_msaa(&self.context, sample_count) {
+            bail!("{sample_count}x MSAA is not supported by this adapter");
+        }
+        Ok(())
+    }
+
+    fn create_pipeline_store(&self, sample_count: u32) -> PipelineStore {
+        PipelineStore::new(
+            &self.context,
+            &self.shader_store,
+            &SceneLayouts {
+                camera: &self.camera.bind_group_layout,
+                texture: &self.texture_store.bind_group_layout,
+                object: &self.object_manager.bind_group_layout,
+                environment: &self.environment.bind_group_layout,
+                gpu_draws: &self.gpu_scene.draw_bind_group_layout,
+                debug: &self.debug_views.bind_group_layout,
+                selection: &self.selection_renderer.bind_group_layout,
+            },
+            sample_count,
+        )
+    }
+
+    // Picks up files under `res` changed on disk. Whatever fails to load
+    // stays as it was.
+    fn hot_reload(&mut self) {
+        let changed = match &mut self.hot_reloader {
+            Some(hot_reloader) => hot_reloader.changed_files(),
+            None => return,
+        };
+        for path in changed {
+            if let Err(e) = self.reload_file(&path) {
+                error!("Failed to reload {}: {e:#}", path.display());
+            }
+        }
+    }
+
+    fn reload_file(&mut self, path: &Path) -> anyhow::Result<()> {
+        if ShaderStore::is_shader(path) {
+            return self.reload_shader(path);
+        }
+        if self.asset_server.reload(path, &self.context) {
+            info!("Reloading {}", path.display());
+        } else if let Some(id) = self.texture_store.find_by_source(path) {
+            self.texture_store.reload(id, &self.context)?;
+            info!("Reloaded {}", path.display());
+        }
+        Ok(())
+    }
+
+    // Rebuilds the pipelines that use the new shader. If the shader or any of
+    // them fails to compile, they are rebuilt again from the old shader.
+    fn reload_shader(&mut self, path: &Path) -> anyhow::Result<()> {
+        let module = ShaderStore::compile(&self.context, path)?;
+        let Some(previous) = self.shader_store.replace(path, module) else {
+            return Ok(());
+        };
+
+        self.context
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        self.rebuild_pipelines(path);
+        if let Some(e) = pollster::block_on(self.context.device.pop_error_scope()) {
+            self.shader_store.replace(path, previous);
+            self.rebuild_pipelines(path);
+            bail!("{e}");
+        }
+        info!("Reloaded {}", path.display());
+        Ok(())
+    }
+
+    fn rebuild_pipelines(&mut self, shader_path: &Path) {
+        let sample_count = self.pipeline_store.sample_count;
+        match shader_path.file_name().and_then(|name| name.to_str()) {
+            Some("tonemap.wgsl" | "luminance_histogram.wgsl") => {
+                self.tone_mapper = ToneMapper::new(&self.context, &self.shader_store);
+            }
+            // The LUT is loaded again by `update`
+            Some("bloom.wgsl" | "post_process.wgsl") => {
+                self.post_processor = PostProcessor::new(&self.context, &self.shader_store);
+            }
+            Some("blit.wgsl") => {
+                self.texture_store.mipmap_generator =
+                    MipmapGenerator::new(&self.context.device, &self.shader_store.blit);
+            }
+            // Also bakes the BRDF LUT and the loaded map with the new shader
+            Some("ibl.wgsl") => {
+                self.environment = Environment::new(&self.context, &self.shader_store);
+                if let Some(path) = self.environment_map.clone() {
+                    if let Err(e) = self.load_environment(&path) {
+                        error!("Failed to load environment map {}: {e:#}", path.display());
+                        self.unload_environment();
+                    }
+                }
+                self.pipeline_store = self.create_pipeline_store(sample_count);
+            }
+            Some("cull.wgsl" | "hi_z.wgsl") => {
+                self.gpu_scene
+                    .rebuild_pipelines(&self.context.device, &self.shader_store);
+            }
+            Some("oit_composite.wgsl") => {
+                self.oit_compositor =
+                    OitCompositor::new(&self.context, &self.shader_store, sample_count);
+            }
+            Some("overlay.wgsl") => {
+                self.overlay.rebuild_pipeline(
+                    &self.context,
+                    &self.shader_store,
+                    &self.texture_store.bind_group_layout,
+                );
+            }
+            Some("picking.wgsl") => {
+                self.picker = Picker::new(
+                    &self.context,
+                    &self.shader_store,
+                    &self.camera.bind_group_layout,
+                    &self.object_manager.bind_group_layout,
+                );
+            }
+            // The selection tint in the `PipelineStore` uses its bind group layout
+            Some("selection.wgsl" | "outline.wgsl") => {
+                self.selection_renderer = SelectionRenderer::new(
+                    &self.context,
+                    &self.shader_store,
+                    &self.camera.bind_group_layout,
+                    &self.object_manager.bind_group_layout,
+                );
+                self.pipeline_store = self.create_pipeline_store(sample_count);
+            }
+            // Grid, basic, hyper, skybox and debug shaders
+            _ => self.pipeline_store = self.create_pipeline_store(sample_count),
+        }
     }
 
     pub fn resize(&mut self, width: u32, height: u32) {
@@ -156,14 +1582,231 @@ impl Renderer {
             .surface
             .configure(&self.context.device, &self.context.config);
         self.camera.eye.aspect = width as f32 / height as f32;
-        self.texture_store.depth_texture = TextureStore::create_depth_texture(&self.context);
+        self.gpu_scene.resize(&self.context.device, width, height);
+        self.transient_pool.clear();
     }
 
     pub fn handle_camera_movement(&mut self, key_event: winit::event::KeyEvent) {
         self.camera.controller.process_input_events(&key_event);
     }
 
+    // Toggles the debug views, returning whether the key was one of theirs
+    pub fn handle_debug_view_key(&mut self, key_event: winit::event::KeyEvent) -> bool {
+        match key_event.physical_key {
+            winit::keyboard::PhysicalKey::Code(key) => self.debug_view.handle_key(key),
+            _ => false,
+        }
+    }
+
+    // The object under a pixel of the window. The ids are read back without
+    // stalling, so this stays pending for a frame or two after a pixel is
+    // first asked for; each answer is returned once.
+    pub fn pick(&mut self, x: u32, y: u32) -> Poll<Option<PickHit>> {
+        if x >= self.context.config.width || y >= self.context.config.height {
+            return Poll::Ready(None);
+        }
+        if let Some(hit) = self.picker.take_result([x, y]) {
+            return Poll::Ready(hit);
+        }
+        self.picker.request([x, y]);
+        Poll::Pending
+    }
+
+    fn gizmo_target(&self) -> Option<GizmoTarget> {
+        let id = self.selection.primary()?;
+        let hyper = self.object_manager.get(id)?.hyper();
+        let transform = self.object_manager.world_transform(id)?;
+        Some(GizmoTarget::new(transform, hyper))
+    }
+
+    fn cursor_ray(&self, x: f64, y: f64) -> Option<Ray> {
+        Ray::from_screen(
+            &self.camera.eye.build_view_projection_matrix(),
+            [x as f32, y as f32],
+            [self.context.config.width, self.context.config.height],
+        )
+    }
+
+    // Starts dragging the gizmo handle under the cursor, returning whether
+    // there was one
+    pub fn handle_gizmo_press(&mut self, x: f64, y: f64) -> bool {
+        let (Some(target), Some(ray)) = (self.gizmo_target(), self.cursor_ray(x, y)) else {
+            return false;
+        };
+        self.gizmo
+            .begin_drag(&self.gizmo_settings, &target, &self.camera.eye, &ray)
+    }
+
+    // Moves the selected object with the dragged handle, or highlights the
+    // handle under the cursor
+    pub fn handle_gizmo_motion(&mut self, x: f64, y: f64, invert_snapping: bool) {
+        let Some(ray) = self.cursor_ray(x, y) else {
+            return;
+        };
+        if !self.gizmo.is_dragging() {
+            let target = self.gizmo_target();
+            self.gizmo.hover(
+                &self.gizmo_settings,
+                target.as_ref(),
+                &self.camera.eye,
+                &ray,
+            );
+            return;
+        }
+        let Some(target) = self.gizmo.drag(&self.gizmo_settings, &ray, invert_snapping) else {
+            return;
+        };
+        if let Some(id) = self.selection.primary() {
+            self.object_manager
+                .set_world_transform(id, target.transform());
+            if let Some(object) = self.object_manager.get_mut(id) {
+                object.set_hyper(target.hyper);
+            }
+        }
+    }
+
+    pub fn handle_gizmo_release(&mut self) {
+        self.gizmo.end_drag();
+    }
+
+    pub fn is_dragging_gizmo(&self) -> bool {
+        self.gizmo.is_dragging()
+    }
+
+    // Switches gizmo modes, returning whether the key was one of theirs
+    pub fn handle_gizmo_key(&mut self, key_event: winit::event::KeyEvent) -> bool {
+        match key_event.physical_key {
+            winit::keyboard::PhysicalKey::Code(key) => self.gizmo_settings.handle_key(key),
+            _ => false,
+        }
+    }
+
+    // Replaces the actors, camera and settings with those of a scene file
+    // under `res`. If any of its objects fails to load the current scene
+    // stays as it was.
+    pub fn load_scene(&mut self, path: &Path) -> anyhow::Result<()> {
+        let scene = Scene::read(path)?;
+        // Scenes saved on another machine may ask for MSAA this one lacks
+        let mut msaa_samples = scene.settings.msaa_samples;
+        if let Err(e) = self.check_msaa_samples(msaa_samples) {
+            msaa_samples = self.pipeline_store.sample_count;
+            warn!("{e}, keeping {msaa_samples}x MSAA");
+        }
+        let previous = self.object_manager.actor_ids();
+        scene.instantiate(
+            &self.context,
+            &mut self.object_manager,
+            &mut self.material_store,
+            &mut self.texture_store,
+            &mut self.asset_server,
+        )?;
+        self.object_manager.remove_actors(&previous);
+        self.world.clear();
+        self.selection.clear();
+        if let Some(camera) = &scene.camera {
+            camera.apply(&mut self.camera.eye);
+        }
+
+        let settings = scene.settings;
+        self.frustum_culling = settings.frustum_culling;
+        self.level_of_detail = settings.level_of_detail;
+        self.gpu_culling = settings.gpu_culling;
+        self.transparency = settings.transparency;
+        self.debug_view = settings.debug_view;
+        self.tone_mapping = settings.tone_mapping;
+        self.post_processing = settings.post_processing;
+        self.environment_settings = settings.environment;
+        // A map that fails to load leaves the scene lit by the clear color
+        if settings.environment_map != self.environment_map {
+            match &settings.environment_map {
+                Some(path) => {
+                    if let Err(e) = self.load_environment(path) {
+                        warn!("Failed to load environment map {}: {e:#}", path.display());
+                        self.unload_environment();
+                    }
+                }
+                None => self.unload_environment(),
+            }
+        }
+        self.selection_style = settings.selection_style;
+        self.gizmo_settings = settings.gizmo;
+        self.show_fps = settings.show_fps;
+        self.set_msaa_samples(msaa_samples)?;
+        info!(
+            "Loaded scene {} with {} objects",
+            path.display(),
+            scene.objects.len()
+        );
+        Ok(())
+    }
+
+    // JSON for `.json` paths, binary otherwise
+    pub fn save_scene(&self, path: &Path) -> anyhow::Result<()> {
+        let settings = SceneSettings {
+            frustum_culling: self.frustum_culling,
+            level_of_detail: self.level_of_detail,
+            gpu_culling: self.gpu_culling,
+            transparency: self.transparency,
+            msaa_samples: self.pipeline_store.sample_count,
+            debug_view: self.debug_view,
+            tone_mapping: self.tone_mapping,
+            post_processing: self.post_processing.clone(),
+            environment: self.environment_settings,
+            environment_map: self.environment_map.clone(),
+            selection_style: self.selection_style,
+            gizmo: self.gizmo_settings,
+            show_fps: self.show_fps,
+        };
+        let scene = Scene::capture(
+            &self.object_manager,
+            &self.material_store,
+            &self.texture_store,
+            &self.camera.eye,
+            settings,
+        );
+        scene.write(path)?;
+        info!(
+            "Saved scene {} with {} objects",
+            path.display(),
+            scene.objects.len()
+        );
+        Ok(())
+    }
+
+    // Passes the event to the editor UI, returning whether the UI used it
+    pub fn handle_editor_event(&mut self, event: &winit::event::WindowEvent) -> bool {
+        self.editor.handle_window_event(event)
+    }
+
+    pub fn toggle_editor(&mut self) {
+        self.editor.toggle();
+    }
+
     pub fn handle_mouse_delta(&mut self, delta: (f64, f64)) {
         self.camera.controller.process_mouse_delta(delta);
     }
 }
+
+// Where passes drawing over the scene render: the multisampled target resolved
+// into `hdr` with MSAA, `hdr` without. Only valid while everything drawn into
+// `hdr` so far went through the multisampled target too, the resolve would
+// overwrite anything else.
+fn scene_target<'r>(
+    resources: &'r PassResources,
+    hdr: ResourceId,
+    multisampled: Option<ResourceId>,
+) -> (&'r wgpu::TextureView, Option<&'r wgpu::TextureView>) {
+    match multisampled {
+        Some(multisampled) => (resources.view(multisampled), Some(resources.view(hdr))),
+        None => (resources.view(hdr), None),
+    }
+}
+
+// Depth tested against but neither written nor stored again
+fn read_only_depth(view: &wgpu::TextureView) -> Option<wgpu::RenderPassDepthStencilAttachment<'_>> {
+    Some(wgpu::RenderPassDepthStencilAttachment {
+        view,
+        depth_ops: None,
+        stencil_ops: None,
+    })
+}
@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context as _, Result};
+use cgmath::{Point3, Quaternion, Vector3};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+    asset::AssetServer,
+    camera::CameraEye,
+    context::Context,
+    debug_view::DebugViewSettings,
+    environment::EnvironmentSettings,
+    gizmo::GizmoSettings,
+    gpu_driven::GpuCullingSettings,
+    hdr::ToneMapSettings,
+    lod::LodSettings,
+    material::{BlendMode, Material, MaterialId, MaterialStore},
+    object::{HyperTransform, KeepTransform, ObjectId, ObjectManager, Transform},
+    post_process::PostProcessSettings,
+    renderer::DEFAULT_MSAA_SAMPLES,
+    selection::SelectionStyle,
+    shader::ShaderType,
+    texture::TextureStore,
+    transparency::TransparencyMode,
+    util::resources,
+};
+
+pub const SCENE_VERSION: u32 = 1;
+// Binary scene files start with this, followed by the document as MessagePack
+const BINARY_MAGIC: &[u8; 4] = b"MFSC";
+// Step `i` upgrades a document of version `i + 1` to the next version. Files
+// are migrated as untyped documents, so old layouts need no Rust types.
+const MIGRATIONS: [fn(&mut Value) -> Result<()>; SCENE_VERSION as usize - 1] = [];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SceneFormat {
+    // Pretty printed for reading and diffing
+    Json,
+    Binary,
+}
+
+impl SceneFormat {
+    // `.json` files are text, everything else binary
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("json") => SceneFormat::Json,
+            _ => SceneFormat::Binary,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CameraState {
+    pub position: [f32; 3],
+    // x, y, z, w
+    pub orientation: [f32; 4],
+    // Degrees
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl CameraState {
+    pub fn from_eye(eye: &CameraEye) -> Self {
+        let q = eye.orientation;
+        Self {
+            position: eye.position.into(),
+            orientation: [q.v.x, q.v.y, q.v.z, q.s],
+            fov: eye.fov,
+            near: eye.near,
+            far: eye.far,
+        }
+    }
+
+    // The aspect ratio stays with the window
+    pub fn apply(&self, eye: &mut CameraEye) {
+        let [x, y, z, w] = self.orientation;
+        eye.position = Point3::from(self.position);
+        eye.orientation = Quaternion::new(w, x, y, z);
+        eye.fov = self.fov;
+        eye.near = self.near;
+        eye.far = self.far;
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct MaterialState {
+    pub name: String,
+    pub shader_type: ShaderType,
+    // Relative to `res`, None for the default texture
+    #[serde(default)]
+    pub diffuse_texture: Option<PathBuf>,
+    #[serde(default)]
+    pub blend_mode: BlendMode,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HyperState {
+    pub w: f32,
+    pub scale_w: f32,
+    pub rotation: [f32; 3],
+}
+
+impl Default for HyperState {
+    fn default() -> Self {
+        HyperState::from(HyperTransform::default())
+    }
+}
+
+impl From<HyperTransform> for HyperState {
+    fn from(hyper: HyperTransform) -> Self {
+        Self {
+            w: hyper.w,
+            scale_w: hyper.scale_w,
+            rotation: hyper.rotation.into(),
+        }
+    }
+}
+
+impl From<&HyperState> for HyperTransform {
+    fn from(hyper: &HyperState) -> Self {
+        Self {
+            w: hyper.w,
+            scale_w: hyper.scale_w,
+            rotation: Vector3::from(hyper.rotation),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ObjectState {
+    // Relative to `res`
+    pub model: PathBuf,
+    // Relative to the parent
+    pub position: [f32; 3],
+    // x, y, z, w
+    pub rotation: [f32; 4],
+    pub scale: [f32; 3],
+    #[serde(default)]
+    pub hyper: HyperState,
+    // Index of an earlier object in the scene
+    #[serde(default)]
+    pub parent: Option<usize>,
+    // Index into the scene's materials per submodel
+    #[serde(default)]
+    pub materials: Vec<usize>,
+}
+
+impl ObjectState {
+    fn transform(&self) -> Transform {
+        let [x, y, z, w] = self.rotation;
+        Transform {
+            position: Vector3::from(self.position),
+            rotation: Quaternion::new(w, x, y, z),
+            scale: Vector3::from(self.scale),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SceneSettings {
+    pub frustum_culling: bool,
+    pub level_of_detail: LodSettings,
+    pub gpu_culling: GpuCullingSettings,
+    pub transparency: TransparencyMode,
+    pub msaa_samples: u32,
+    pub debug_view: DebugViewSettings,
+    pub tone_mapping: ToneMapSettings,
+    pub post_processing: PostProcessSettings,
+    pub environment: EnvironmentSettings,
+    // Path under `res` of the map that lights the scene, `None` for the
+    // plain clear color
+    pub environment_map: Option<PathBuf>,
+    pub selection_style: SelectionStyle,
+    pub gizmo: GizmoSettings,
+    pub show_fps: bool,
+}
+
+impl Default for SceneSettings {
+    fn default() -> Self {
+        Self {
+            frustum_culling: true,
+            level_of_detail: LodSettings::default(),
+            gpu_culling: GpuCullingSettings::default(),
+            transparency: TransparencyMode::default(),
+            msaa_samples: DEFAULT_MSAA_SAMPLES,
+            debug_view: DebugViewSettings::default(),
+            tone_mapping: ToneMapSettings::default(),
+            post_processing: PostProcessSettings::default(),
+            environment: EnvironmentSettings::default(),
+            environment_map: None,
+            selection_style: SelectionStyle::default(),
+            gizmo: GizmoSettings::default(),
+            show_fps: false,
+        }
+    }
+}
+
+// Everything needed to rebuild a scene, without static objects like the grid
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Scene {
+    pub version: u32,
+    // Missing cameras leave the current one in place
+    #[serde(default)]
+    pub camera: Option<CameraState>,
+    #[serde(default)]
+    pub materials: Vec<MaterialState>,
+    #[serde(default)]
+    pub objects: Vec<ObjectState>,
+    #[serde(default)]
+    pub settings: SceneSettings,
+}
+
+impl Scene {
+    pub fn capture(
+        object_manager: &ObjectManager,
+        material_store: &MaterialStore,
+        texture_store: &TextureStore,
+        eye: &CameraEye,
+        settings: SceneSettings,
+    ) -> Self {
+        let mut scene = Self {
+            version: SCENE_VERSION,
+            camera: Some(CameraState::from_eye(eye)),
+            materials: Vec::new(),
+            objects: Vec::new(),
+            settings,
+        };
+        let mut material_indices = HashMap::new();
+        let mut object_indices = HashMap::new();
+
+        // Parents before children, so parent indices always point backwards
+        let mut stack = object_manager.roots().collect::<Vec<_>>();
+        stack.reverse();
+        while let Some(id) = stack.pop() {
+            let Some(object) = object_manager.get(id) else {
+                continue;
+            };
+            stack.extend(object.children().iter().rev());
+            if object_manager.is_static(id) {
+                continue;
+            }
+            let Some(model) = object.model_path() else {
+                warn!("Object {id} has no model file and is not saved");
+                continue;
+            };
+
+            let parent = object
+                .parent()
+                .and_then(|parent| object_indices.get(&parent).copied());
+            // Objects whose parent is not saved keep their place in the world
+            let transform = match parent {
+                Some(_) => object.local_transform(),
+                None => object_manager
+                    .world_transform(id)
+                    .unwrap_or(object.local_transform()),
+            };
+            let materials = object
+                .model()
+                .data
+                .iter()
+                .map(|data| {
+                    *material_indices.entry(data.material_id).or_insert_with(|| {
+                        let material = material_store.get_material(data.material_id);
+                        scene.materials.push(MaterialState {
+                            name: material.name.clone(),
+                            shader_type: material.shader_type,
+                            diffuse_texture: texture_store
+                                .source(material.diffuse_texture_id)
+                                .cloned(),
+                            blend_mode: material.blend_mode,
+                        });
+                        scene.materials.len() - 1
+                    })
+                })
+                .collect();
+
+            let q = transform.rotation;
+            object_indices.insert(id, scene.objects.len());
+            scene.objects.push(ObjectState {
+                model: model.clone(),
+                position: transform.position.into(),
+                rotation: [q.v.x, q.v.y, q.v.z, q.s],
+                scale: transform.scale.into(),
+                hyper: object.hyper().into(),
+                parent,
+                materials,
+            });
+        }
+        scene
+    }
+
+    // Adds the scene's objects as actors, returning their ids in scene order
+    pub fn instantiate(
+        &self,
+        context: &Context<'_>,
+        object_manager: &mut ObjectManager,
+        material_store: &mut MaterialStore,
+        texture_store: &mut TextureStore,
+        asset_server: &mut AssetServer,
+    ) -> Result<Vec<ObjectId>> {
+        let mut materials = Vec::with_capacity(self.materials.len());
+        for state in &self.materials {
+            // Materials keep their texture loaded once the handle is dropped
+            let diffuse_texture_id = match &state.diffuse_texture {
+                Some(path) => {
+                    let handle = asset_server.load_texture(path, texture_store, context);
+                    *asset_server.get(&handle)
+                }
+                None => material_store.default_material.diffuse_texture_id,
+            };
+            materials.push(find_or_add_material(
+                material_store,
+                Material {
+                    name: state.name.clone(),
+                    shader_type: state.shader_type,
+                    diffuse_texture_id,
+                    blend_mode: state.blend_mode,
+                },
+            ));
+        }
+
+        // Nothing of the scene is left behind when part of it fails
+        let mut ids = Vec::with_capacity(self.objects.len());
+        let result = self.create_objects(
+            context,
+            object_manager,
+            material_store,
+            asset_server,
+            &materials,
+            &mut ids,
+        );
+        if let Err(e) = result {
+            object_manager.remove_actors(&ids);
+            return Err(e);
+        }
+        Ok(ids)
+    }
+
+    fn create_objects(
+        &self,
+        context: &Context<'_>,
+        object_manager: &mut ObjectManager,
+        material_store: &mut MaterialStore,
+        asset_server: &mut AssetServer,
+        materials: &[MaterialId],
+        ids: &mut Vec<ObjectId>,
+    ) -> Result<()> {
+        for (index, state) in self.objects.iter().enumerate() {
+            let id =
+                object_manager.create_actor(&state.model, context, material_store, asset_server)?;
+            ids.push(id);
+
+            let object = object_manager.get_mut(id).unwrap();
+            object.set_local_transform(state.transform());
+            object.set_hyper(HyperTransform::from(&state.hyper));
+            for (data, &material) in object.model_mut().data.iter_mut().zip(&state.materials) {
+                match materials.get(material) {
+                    Some(&material_id) => data.material_id = material_id,
+                    None => {
+                        warn!("Object {index} uses material {material}, which is not in the scene")
+                    }
+                }
+            }
+
+            if let Some(parent) = state.parent {
+                let Some(&parent_id) = ids.get(parent).filter(|_| parent < index) else {
+                    bail!("Object {index} has parent {parent}, which does not come before it");
+                };
+                object_manager.set_parent(id, Some(parent_id), KeepTransform::Local)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Relative paths are relative to `res`
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = resources::load_resource(&path.to_path_buf())
+            .with_context(|| format!("Failed to read scene {}", path.display()))?;
+        Self::from_bytes(&bytes, SceneFormat::from_path(path))
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let path = resources::res_path(&path.to_path_buf());
+        if let Some(directory) = path.parent() {
+            fs::create_dir_all(directory)?;
+        }
+        fs::write(&path, self.to_bytes(SceneFormat::from_path(&path))?)
+            .with_context(|| format!("Failed to write scene {}", path.display()))
+    }
+
+    pub fn to_bytes(&self, format: SceneFormat) -> Result<Vec<u8>> {
+        Ok(match format {
+            SceneFormat::Json => serde_json::to_vec_pretty(self)?,
+            SceneFormat::Binary => {
+                // Through the same document model the loader migrates
+                let document = serde_json::to_value(self)?;
+                let mut bytes = BINARY_MAGIC.to_vec();
+                bytes.extend(rmp_serde::to_vec(&document)?);
+                bytes
+            }
+        })
+    }
+
+    pub fn from_bytes(bytes: &[u8], format: SceneFormat) -> Result<Self> {
+        let document: Value = match format {
+            SceneFormat::Json => serde_json::from_slice(bytes)?,
+            SceneFormat::Binary => {
+                let Some(payload) = bytes.strip_prefix(BINARY_MAGIC) else {
+                    bail!("Not a binary scene file");
+                };
+                rmp_serde::from_slice(payload)?
+            }
+        };
+        Ok(serde_json::from_value(migrate(document)?)?)
+    }
+}
+
+fn migrate(mut document: Value) -> Result<Value> {
+    let version = document
+        .get("version")
+        .and_then(Value::as_u64)
+        .ok_or_else(|| anyhow!("Scene has no version"))?;
+    if version == 0 || version > SCENE_VERSION as u64 {
+        bail!("Scene version {version} is not supported, the newest is {SCENE_VERSION}");
+    }
+    for step in &MIGRATIONS[version as usize - 1..] {
+        step(&mut document)?;
+    }
+    document["version"] = SCENE_VERSION.into();
+    Ok(document)
+}
+
+// Loading a scene twice reuses its materials instead of piling up copies
+fn find_or_add_material(material_store: &mut MaterialStore, material: Material) -> MaterialId {
+    let existing = material_store.ids().into_iter().find(|&id| {
+        let other = material_store.get_material(id);
+        other.name == material.name
+            && other.shader_type == material.shader_type
+            && other.diffuse_texture_id == material.diffuse_texture_id
+            && other.blend_mode == material.blend_mode
+    });
+    existing.unwrap_or_else(|| material_store.add_material(material))
+}
@@ -0,0 +1,478 @@
+use serde::{Deserialize, Serialize};
+use wgpu::util::DeviceExt;
+
+use super::{
+    context::Context,
+    hdr::HDR_FORMAT,
+    model::{ModelVertex, Vertex},
+    object::{Object, ObjectId},
+    shader::ShaderStore,
+    texture::Texture,
+};
+
+pub const SELECTION_MASK_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+// Pixel coordinates of the nearest mask pixel
+pub const JUMP_FLOOD_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rg32Float;
+
+// Passed to the shaders as the instance index
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SelectionState {
+    Selected = 1,
+    Hovered = 2,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    // In the order they were selected, the last one is the primary selection
+    selected: Vec<ObjectId>,
+    hovered: Option<ObjectId>,
+}
+
+#[allow(dead_code)]
+impl Selection {
+    pub fn select(&mut self, object: ObjectId) {
+        self.selected.retain(|&selected| selected != object);
+        self.selected.push(object);
+    }
+
+    pub fn select_only(&mut self, object: ObjectId) {
+        self.selected.clear();
+        self.selected.push(object);
+    }
+
+    pub fn deselect(&mut self, object: ObjectId) {
+        self.selected.retain(|&selected| selected != object);
+    }
+
+    pub fn toggle(&mut self, object: ObjectId) {
+        if self.is_selected(object) {
+            self.deselect(object);
+        } else {
+            self.select(object);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.selected.clear();
+    }
+
+    pub fn is_selected(&self, object: ObjectId) -> bool {
+        self.selected.contains(&object)
+    }
+
+    pub fn primary(&self) -> Option<ObjectId> {
+        self.selected.last().copied()
+    }
+
+    pub fn selected(&self) -> &[ObjectId] {
+        &self.selected
+    }
+
+    pub fn set_hovered(&mut self, object: Option<ObjectId>) {
+        self.hovered = object;
+    }
+
+    pub fn hovered(&self) -> Option<ObjectId> {
+        self.hovered
+    }
+
+    // Selected wins over hovered
+    pub fn state(&self, object: ObjectId) -> Option<SelectionState> {
+        if self.is_selected(object) {
+            Some(SelectionState::Selected)
+        } else if self.hovered == Some(object) {
+            Some(SelectionState::Hovered)
+        } else {
+            None
+        }
+    }
+
+    // Drops objects that no longer exist
+    pub fn retain(&mut self, mut exists: impl FnMut(ObjectId) -> bool) {
+        self.selected.retain(|&object| exists(object));
+        self.hovered = self.hovered.filter(|&object| exists(object));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.selected.is_empty() && self.hovered.is_none()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SelectionStyle {
+    pub outline_color: [f32; 4],
+    pub hover_color: [f32; 4],
+    // Outline width in pixels
+    pub outline_width: f32,
+    // Blended over the selected surfaces with the strength in alpha, at half
+    // strength for hovered ones
+    pub tint: [f32; 4],
+}
+
+impl Default for SelectionStyle {
+    fn default() -> Self {
+        Self {
+            outline_color: [1.0, 0.55, 0.1, 1.0],
+            hover_color: [0.35, 0.7, 1.0, 0.8],
+            outline_width: 3.0,
+            tint: [1.0, 0.55, 0.1, 0.12],
+        }
+    }
+}
+
+impl SelectionStyle {
+    // Jump flood step sizes that reach as far as the outline, halving down to 1
+    pub fn jump_flood_steps(&self) -> impl Iterator<Item = u32> {
+        let radius = self.outline_width.ceil().max(1.0) as u32;
+        std::iter::successors(Some(radius.next_power_of_two()), |&step| {
+            (step > 1).then_some(step / 2)
+        })
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct SelectionUniform {
+    outline_color: [f32; 4],
+    hover_color: [f32; 4],
+    tint: [f32; 4],
+    outline_width: f32,
+    _padding: [f32; 3],
+}
+
+impl From<&SelectionStyle> for SelectionUniform {
+    fn from(style: &SelectionStyle) -> Self {
+        Self {
+            outline_color: style.outline_color,
+            hover_color: style.hover_color,
+            tint: style.tint,
+            outline_width: style.outline_width,
+            _padding: [0.0; 3],
+        }
+    }
+}
+
+// The mask, jump flood and outline composite; the tint is drawn into the
+// multisampled scene and lives in the pipeline store
+pub struct SelectionRenderer {
+    pub bind_group_layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    pub bind_group: wgpu::BindGroup,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    mask_pipeline: wgpu::RenderPipeline,
+    jump_flood_init: wgpu::RenderPipeline,
+    jump_flood_step: wgpu::RenderPipeline,
+    composite: wgpu::RenderPipeline,
+}
+
+impl SelectionRenderer {
+    pub fn new(
+        context: &Context,
+        shader_store: &ShaderStore,
+        camera_layout: &wgpu::BindGroupLayout,
+        object_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let device = &context.device;
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("selection_bind_group_layout"),
+        });
+        let uniform_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Selection Uniform Buffer"),
+            contents: bytemuck::cast_slice(&[SelectionUniform::from(&SelectionStyle::default())]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("selection_bind_group"),
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[texture_entry(0), texture_entry(1)],
+                label: Some("selection_texture_bind_group_layout"),
+            });
+
+        let mask_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Selection Mask Pipeline Layout"),
+            bind_group_layouts: &[camera_layout, object_layout, &bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        // Drawn without the scene depth, so outlines show through other objects
+        let mask_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Selection Mask Pipeline"),
+            layout: Some(&mask_layout),
+            vertex: wgpu::VertexState {
+                module: &shader_store.selection,
+                entry_point: "vs_main",
+                buffers: &[ModelVertex::desc()],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader_store.selection,
+                entry_point: "fs_mask",
+                targets: &[Some(SELECTION_MASK_FORMAT.into())],
+                compilation_options: wgpu::PipelineCompilationOptions::default(),
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: Some(wgpu::DepthStencilState {
+                format: Texture::DEPTH_FORMAT,
+                depth_write_enabled: true,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            }),
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+            cache: None,
+        });
+
+        let fullscreen_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Outline Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout, &texture_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let fullscreen = |label, entry_point, target: wgpu::ColorTargetState| {
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(&fullscreen_layout),
+                vertex: wgpu::VertexState {
+                    module: &shader_store.outline,
+                    entry_point: "vs_fullscreen",
+                    buffers: &[],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader_store.outline,
+                    entry_point,
+                    targets: &[Some(target)],
+                    compilation_options: wgpu::PipelineCompilationOptions::default(),
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: wgpu::MultisampleState::default(),
+                multiview: None,
+                cache: None,
+            })
+        };
+        let jump_flood_init = fullscreen(
+            "Jump Flood Init Pipeline",
+            "fs_jump_flood_init",
+            JUMP_FLOOD_FORMAT.into(),
+        );
+        let jump_flood_step = fullscreen(
+            "Jump Flood Step Pipeline",
+            "fs_jump_flood_step",
+            JUMP_FLOOD_FORMAT.into(),
+        );
+        let composite = fullscreen(
+            "Outline Composite Pipeline",
+            "fs_composite",
+            wgpu::ColorTargetState {
+                format: context.config.format,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            },
+        );
+
+        Self {
+            bind_group_layout,
+            uniform_buffer,
+            bind_group,
+            texture_bind_group_layout,
+            mask_pipeline,
+            jump_flood_init,
+            jump_flood_step,
+            composite,
+        }
+    }
+
+    pub fn update(&self, queue: &wgpu::Queue, style: &SelectionStyle) {
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[SelectionUniform::from(style)]),
+        );
+    }
+
+    pub fn texture_bind_group(
+        &self,
+        device: &wgpu::Device,
+        mask: &wgpu::TextureView,
+        seeds: &wgpu::TextureView,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.texture_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(mask),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(seeds),
+                },
+            ],
+            label: Some("selection_texture_bind_group"),
+        })
+    }
+
+    pub fn draw_mask<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        objects: impl Iterator<Item = (ObjectId, &'a Object)>,
+        selection: &Selection,
+    ) {
+        render_pass.set_pipeline(&self.mask_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(2, &self.bind_group, &[]);
+        render_pass.draw_selection(objects, selection);
+    }
+
+    // The mask is seeded when `seeds` is None, otherwise one step of
+    // `step` pixels is taken from the previous seeds
+    pub fn draw_jump_flood<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        textures: &'a wgpu::BindGroup,
+        step: Option<u32>,
+    ) {
+        let pipeline = match step {
+            Some(_) => &self.jump_flood_step,
+            None => &self.jump_flood_init,
+        };
+        let step = step.unwrap_or(0);
+        render_pass.set_pipeline(pipeline);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, textures, &[]);
+        render_pass.draw(0..3, step..step + 1);
+    }
+
+    pub fn draw_outline<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        textures: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.composite);
+        render_pass.set_bind_group(0, &self.bind_group, &[]);
+        render_pass.set_bind_group(1, textures, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+// Blended over the visible surfaces of the selection in the scene pass
+pub fn create_tint_pipeline(
+    context: &Context,
+    shader_store: &ShaderStore,
+    bind_group_layouts: &[&wgpu::BindGroupLayout],
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let device = &context.device;
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Selection Tint Pipeline Layout"),
+        bind_group_layouts,
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Selection Tint Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader_store.selection,
+            entry_point: "vs_main",
+            buffers: &[ModelVertex::desc()],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader_store.selection,
+            entry_point: "fs_tint",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: HDR_FORMAT,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+            compilation_options: wgpu::PipelineCompilationOptions::default(),
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+        cache: None,
+    })
+}
+
+pub trait DrawSelection<'a> {
+    // Draws the visible selected and hovered objects with their state as the
+    // instance, expecting the object bind group at 1
+    fn draw_selection(
+        &mut self,
+        objects: impl Iterator<Item = (ObjectId, &'a Object)>,
+        selection: &Selection,
+    );
+}
+
+impl<'a, 'b> DrawSelection<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_selection(
+        &mut self,
+        objects: impl Iterator<Item = (ObjectId, &'b Object)>,
+        selection: &Selection,
+    ) {
+        for (id, object) in objects {
+            let Some(state) = selection.state(id) else {
+                continue;
+            };
+            if !object.is_visible() {
+                continue;
+            }
+            let state = state as u32;
+            self.set_bind_group(1, object.bind_group(), &[]);
+            for data in &object.model().data {
+                let mesh = &data.mesh;
+                let lod = &mesh.lods[object.lod().level.min(mesh.lods.len() - 1)];
+                self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+                self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+                self.draw_indexed(
+                    lod.first_index..lod.first_index + lod.index_count,
+                    lod.base_vertex,
+                    state..state + 1,
+                );
+            }
+        }
+    }
+}
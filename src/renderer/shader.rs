@@ -1,9 +1,39 @@
 use std::fmt;
+use std::mem;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
 
 use super::context;
+use super::util::resources;
 use crate::res_path;
 
+// Files under `res/shaders`, in the order of `ShaderStore::modules_mut`
+const SHADER_FILES: [&str; 19] = [
+    "editor_grid.wgsl",
+    "basic.wgsl",
+    "hyper.wgsl",
+    "tonemap.wgsl",
+    "luminance_histogram.wgsl",
+    "bloom.wgsl",
+    "post_process.wgsl",
+    "blit.wgsl",
+    "skybox.wgsl",
+    "ibl.wgsl",
+    "cull.wgsl",
+    "hi_z.wgsl",
+    "oit_composite.wgsl",
+    "debug.wgsl",
+    "debug_lines.wgsl",
+    "overlay.wgsl",
+    "picking.wgsl",
+    "selection.wgsl",
+    "outline.wgsl",
+];
+
 #[allow(unused)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ShaderType {
     Grid,
     Basic,
@@ -24,6 +54,22 @@ pub struct ShaderStore {
     pub grid: wgpu::ShaderModule,  // editor grid
     pub basic: wgpu::ShaderModule, // 3D
     pub hyper: wgpu::ShaderModule, // 4D
+    pub tonemap: wgpu::ShaderModule,
+    pub luminance_histogram: wgpu::ShaderModule,
+    pub bloom: wgpu::ShaderModule,
+    pub post_process: wgpu::ShaderModule,
+    pub blit: wgpu::ShaderModule,
+    pub skybox: wgpu::ShaderModule,
+    pub ibl: wgpu::ShaderModule,
+    pub cull: wgpu::ShaderModule,
+    pub hi_z: wgpu::ShaderModule,
+    pub oit_composite: wgpu::ShaderModule,
+    pub debug: wgpu::ShaderModule,
+    pub debug_lines: wgpu::ShaderModule,
+    pub overlay: wgpu::ShaderModule,
+    pub picking: wgpu::ShaderModule,
+    pub selection: wgpu::ShaderModule,
+    pub outline: wgpu::ShaderModule,
 }
 
 impl ShaderStore {
@@ -37,7 +83,145 @@ impl ShaderStore {
         let hyper = context
             .device
             .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/hyper.wgsl")));
+        let tonemap = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/tonemap.wgsl")));
+        let luminance_histogram =
+            context
+                .device
+                .create_shader_module(wgpu::include_wgsl!(res_path!(
+                    "shaders/luminance_histogram.wgsl"
+                )));
+        let bloom = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/bloom.wgsl")));
+        let post_process = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/post_process.wgsl")));
+        let blit = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/blit.wgsl")));
+        let skybox = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/skybox.wgsl")));
+        let ibl = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/ibl.wgsl")));
+        let cull = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/cull.wgsl")));
+        let hi_z = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/hi_z.wgsl")));
+        let oit_composite = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/oit_composite.wgsl")));
+        let debug = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/debug.wgsl")));
+        let debug_lines = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/debug_lines.wgsl")));
+        let overlay = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/overlay.wgsl")));
+        let picking = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/picking.wgsl")));
+        let selection = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/selection.wgsl")));
+        let outline = context
+            .device
+            .create_shader_module(wgpu::include_wgsl!(res_path!("shaders/outline.wgsl")));
+
+        Self {
+            grid,
+            basic,
+            hyper,
+            tonemap,
+            luminance_histogram,
+            bloom,
+            post_process,
+            blit,
+            skybox,
+            ibl,
+            cull,
+            hi_z,
+            oit_composite,
+            debug,
+            debug_lines,
+            overlay,
+            picking,
+            selection,
+            outline,
+        }
+    }
+
+    fn modules_mut(&mut self) -> [&mut wgpu::ShaderModule; 19] {
+        [
+            &mut self.grid,
+            &mut self.basic,
+            &mut self.hyper,
+            &mut self.tonemap,
+            &mut self.luminance_histogram,
+            &mut self.bloom,
+            &mut self.post_process,
+            &mut self.blit,
+            &mut self.skybox,
+            &mut self.ibl,
+            &mut self.cull,
+            &mut self.hi_z,
+            &mut self.oit_composite,
+            &mut self.debug,
+            &mut self.debug_lines,
+            &mut self.overlay,
+            &mut self.picking,
+            &mut self.selection,
+            &mut self.outline,
+        ]
+    }
+
+    // Whether a path relative to `res` is one of the store's shaders
+    pub fn is_shader(relative_path: &Path) -> bool {
+        Self::file_index(relative_path).is_some()
+    }
+
+    fn file_index(relative_path: &Path) -> Option<usize> {
+        if relative_path.parent() != Some(Path::new("shaders")) {
+            return None;
+        }
+        let file_name = relative_path.file_name()?.to_str()?;
+        SHADER_FILES.iter().position(|&file| file == file_name)
+    }
+
+    // Compiles the current source of a shader under `res`. Errors that would
+    // otherwise end up in the device's error handler are returned instead.
+    pub fn compile(context: &context::Context, relative_path: &Path) -> Result<wgpu::ShaderModule> {
+        let source = String::from_utf8(resources::load_resource(&relative_path.to_path_buf())?)?;
+        context
+            .device
+            .push_error_scope(wgpu::ErrorFilter::Validation);
+        let module = context
+            .device
+            .create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: relative_path.to_str(),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+        match pollster::block_on(context.device.pop_error_scope()) {
+            Some(error) => Err(anyhow!("{error}")),
+            None => Ok(module),
+        }
+    }
 
-        Self { grid, basic, hyper }
+    // Puts a module in place of the shader loaded from `relative_path`,
+    // returning the old module
+    pub fn replace(
+        &mut self,
+        relative_path: &Path,
+        module: wgpu::ShaderModule,
+    ) -> Option<wgpu::ShaderModule> {
+        let index = Self::file_index(relative_path)?;
+        Some(mem::replace(self.modules_mut()[index], module))
     }
 }
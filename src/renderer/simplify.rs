@@ -0,0 +1,286 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use cgmath::{InnerSpace, Vector3};
+
+// Symmetric 4x4 error quadric of a set of planes, storing the upper triangle
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    fn from_plane(a: f64, b: f64, c: f64, d: f64, weight: f64) -> Self {
+        Self(
+            [
+                a * a,
+                a * b,
+                a * c,
+                a * d,
+                b * b,
+                b * c,
+                b * d,
+                c * c,
+                c * d,
+                d * d,
+            ]
+            .map(|value| value * weight),
+        )
+    }
+
+    fn add(&mut self, other: &Self) {
+        for (value, other) in self.0.iter_mut().zip(other.0) {
+            *value += other;
+        }
+    }
+
+    // Squared distance of the point to the planes
+    fn error(&self, point: Vector3<f32>) -> f64 {
+        let [a2, ab, ac, ad, b2, bc, bd, c2, cd, d2] = self.0;
+        let (x, y, z) = (point.x as f64, point.y as f64, point.z as f64);
+        (a2 * x * x + 2.0 * ab * x * y + 2.0 * ac * x * z + 2.0 * ad * x)
+            + (b2 * y * y + 2.0 * bc * y * z + 2.0 * bd * y)
+            + (c2 * z * z + 2.0 * cd * z)
+            + d2
+    }
+}
+
+// Collapse of position `from` onto position `to`, valid while neither has
+// changed since it was queued
+struct Collapse {
+    cost: f64,
+    from: usize,
+    to: usize,
+    from_version: u32,
+    to_version: u32,
+}
+
+impl PartialEq for Collapse {
+    fn eq(&self, other: &Self) -> bool {
+        self.cost == other.cost
+    }
+}
+
+impl Eq for Collapse {}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// Reversed so the heap pops the cheapest collapse first
+impl Ord for Collapse {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+// Reduces a triangle list to at most `target_index_count` indices, or as close
+// as it gets without moving any surface by more than `max_error`, using
+// quadric error metrics (Garland-Heckbert). Edges are collapsed onto one of
+// their existing vertices, so the result indexes the same vertex buffer.
+// Vertices on open borders or UV/normal seams are never moved.
+//
+// Returns the new indices and the largest error introduced.
+pub fn simplify(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    target_index_count: usize,
+    max_error: f32,
+) -> (Vec<u32>, f32) {
+    // Vertices split at seams share a position; topology is tracked per position
+    let mut position_ids = HashMap::new();
+    let vertex_position = positions
+        .iter()
+        .map(|position| {
+            let key = position.map(f32::to_bits);
+            let next = position_ids.len();
+            *position_ids.entry(key).or_insert(next)
+        })
+        .collect::<Vec<_>>();
+    let position_count = position_ids.len();
+    let mut points = vec![Vector3::new(0.0, 0.0, 0.0); position_count];
+    for (vertex, &id) in vertex_position.iter().enumerate() {
+        points[id] = Vector3::from(positions[vertex]);
+    }
+
+    let mut triangles = indices
+        .chunks_exact(3)
+        .map(|t| [t[0], t[1], t[2]])
+        .collect::<Vec<_>>();
+    let mut alive = vec![true; triangles.len()];
+    let mut alive_count = triangles.len();
+    let corner = |vertex: u32| vertex_position[vertex as usize];
+
+    let mut incident = vec![Vec::new(); position_count];
+    let mut quadrics = vec![Quadric::default(); position_count];
+    let mut wedges: Vec<Option<u32>> = vec![None; position_count];
+    let mut locked = vec![false; position_count];
+    let mut edge_uses: HashMap<(usize, usize), u32> = HashMap::new();
+    for (index, triangle) in triangles.iter().enumerate() {
+        let ids = triangle.map(corner);
+        let (p0, p1, p2) = (points[ids[0]], points[ids[1]], points[ids[2]]);
+        let cross = (p1 - p0).cross(p2 - p0);
+        let area = cross.magnitude();
+        if area > 0.0 {
+            let normal = cross / area;
+            let quadric = Quadric::from_plane(
+                normal.x as f64,
+                normal.y as f64,
+                normal.z as f64,
+                -normal.dot(p0) as f64,
+                area as f64,
+            );
+            for &id in &ids {
+                quadrics[id].add(&quadric);
+            }
+        }
+        for (i, &id) in ids.iter().enumerate() {
+            incident[id].push(index);
+            match wedges[id] {
+                Some(vertex) if vertex != triangle[i] => locked[id] = true,
+                _ => wedges[id] = Some(triangle[i]),
+            }
+            let next = ids[(i + 1) % 3];
+            *edge_uses.entry((id.min(next), id.max(next))).or_default() += 1;
+        }
+    }
+    for (&(a, b), &uses) in &edge_uses {
+        if uses != 2 {
+            locked[a] = true;
+            locked[b] = true;
+        }
+    }
+
+    let mut removed = vec![false; position_count];
+    let mut versions = vec![0u32; position_count];
+    let max_cost = (max_error as f64) * (max_error as f64);
+
+    let neighbors = |id: usize, triangles: &[[u32; 3]], alive: &[bool], incident: &[Vec<usize>]| {
+        let mut result = incident[id]
+            .iter()
+            .filter(|&&t| alive[t])
+            .flat_map(|&t| triangles[t].map(corner))
+            .filter(|&other| other != id)
+            .collect::<Vec<_>>();
+        result.sort_unstable();
+        result.dedup();
+        result
+    };
+    let collapse = |from: usize, to: usize, quadrics: &[Quadric], versions: &[u32]| {
+        let mut quadric = quadrics[from];
+        quadric.add(&quadrics[to]);
+        Collapse {
+            cost: quadric.error(points[to]),
+            from,
+            to,
+            from_version: versions[from],
+            to_version: versions[to],
+        }
+    };
+
+    let mut heap = BinaryHeap::new();
+    for (&(a, b), _) in edge_uses.iter().filter(|(_, &uses)| uses == 2) {
+        if !locked[a] {
+            heap.push(collapse(a, b, &quadrics, &versions));
+        }
+        if !locked[b] {
+            heap.push(collapse(b, a, &quadrics, &versions));
+        }
+    }
+
+    let mut error = 0.0f64;
+    while alive_count * 3 > target_index_count {
+        let Some(candidate) = heap.pop() else {
+            break;
+        };
+        let (from, to) = (candidate.from, candidate.to);
+        if removed[from]
+            || removed[to]
+            || candidate.from_version != versions[from]
+            || candidate.to_version != versions[to]
+        {
+            continue;
+        }
+        if candidate.cost > max_cost {
+            break;
+        }
+
+        // Link condition: the edge's endpoints may only share the two vertices
+        // opposite to it, otherwise the collapse pinches the surface
+        let from_neighbors = neighbors(from, &triangles, &alive, &incident);
+        if !from_neighbors.contains(&to) {
+            continue;
+        }
+        let to_neighbors = neighbors(to, &triangles, &alive, &incident);
+        let shared = from_neighbors
+            .iter()
+            .filter(|id| to_neighbors.binary_search(id).is_ok())
+            .count();
+        if shared > 2 {
+            continue;
+        }
+
+        // Reject collapses that flip or flatten a remaining triangle
+        let mut target_vertex = None;
+        let mut flips = false;
+        for &t in incident[from].iter().filter(|&&t| alive[t]) {
+            let ids = triangles[t].map(corner);
+            if let Some(i) = ids.iter().position(|&id| id == to) {
+                target_vertex = Some(triangles[t][i]);
+                continue;
+            }
+            let moved = ids.map(|id| if id == from { points[to] } else { points[id] });
+            let before = (points[ids[1]] - points[ids[0]]).cross(points[ids[2]] - points[ids[0]]);
+            let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+            if before.dot(after) <= 1e-4 * before.magnitude2() {
+                flips = true;
+                break;
+            }
+        }
+        let Some(target_vertex) = target_vertex else {
+            continue;
+        };
+        if flips {
+            continue;
+        }
+
+        for t in std::mem::take(&mut incident[from]) {
+            if !alive[t] {
+                continue;
+            }
+            if triangles[t].iter().any(|&vertex| corner(vertex) == to) {
+                alive[t] = false;
+                alive_count -= 1;
+                continue;
+            }
+            for vertex in triangles[t].iter_mut() {
+                if vertex_position[*vertex as usize] == from {
+                    *vertex = target_vertex;
+                }
+            }
+            incident[to].push(t);
+        }
+        let from_quadric = quadrics[from];
+        quadrics[to].add(&from_quadric);
+        removed[from] = true;
+        versions[to] += 1;
+        error = error.max(candidate.cost);
+
+        for other in neighbors(to, &triangles, &alive, &incident) {
+            if !locked[to] {
+                heap.push(collapse(to, other, &quadrics, &versions));
+            }
+            if !locked[other] {
+                heap.push(collapse(other, to, &quadrics, &versions));
+            }
+        }
+    }
+
+    let indices = triangles
+        .iter()
+        .zip(&alive)
+        .filter(|(_, &alive)| alive)
+        .flat_map(|(triangle, _)| *triangle)
+        .collect();
+    (indices, error.max(0.0).sqrt() as f32)
+}
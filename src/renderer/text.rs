@@ -0,0 +1,274 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+
+use ab_glyph::{point, Font, FontArc, GlyphId, PxScale, ScaleFont};
+use anyhow::Context as _;
+use log::warn;
+
+use super::context::Context;
+use super::util::resources;
+use crate::res_path;
+
+const ATLAS_SIZE: u32 = 1024;
+// Empty texels between glyphs so linear filtering does not bleed
+const ATLAS_PADDING: u32 = 1;
+const DEFAULT_FONT: &[u8] = include_bytes!(res_path!("fonts/Hack-Regular.ttf"));
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct FontId(pub usize);
+
+#[derive(Clone, Copy, Debug)]
+pub struct TextStyle {
+    pub font: FontId,
+    // Line height in pixels
+    pub size: f32,
+    pub color: [f32; 4],
+}
+
+impl Default for TextStyle {
+    fn default() -> Self {
+        Self {
+            font: FontId(0),
+            size: 16.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct AtlasGlyph {
+    // Texel rectangle in the atlas, empty for glyphs without an outline
+    min: [u32; 2],
+    size: [u32; 2],
+    // From the pen position on the baseline to the top left of the bitmap
+    offset: [f32; 2],
+}
+
+// A glyph placed on screen with its atlas coordinates, both in pixels
+#[derive(Clone, Copy, Debug)]
+pub struct PositionedGlyph {
+    pub position: [f32; 2],
+    pub size: [f32; 2],
+    pub uv_min: [f32; 2],
+    pub uv_max: [f32; 2],
+}
+
+// Fonts and a single channel coverage atlas their glyphs are rasterized into
+// on first use, one entry per glyph and pixel size
+pub struct GlyphAtlas {
+    fonts: Vec<FontArc>,
+    glyphs: HashMap<(FontId, GlyphId, u32), AtlasGlyph>,
+    pixels: Vec<u8>,
+    // Shelf packing: glyphs fill rows left to right, rows as tall as their
+    // tallest glyph
+    cursor: [u32; 2],
+    row_height: u32,
+    dirty: bool,
+    pub texture: wgpu::Texture,
+    pub view: wgpu::TextureView,
+}
+
+impl GlyphAtlas {
+    pub fn new(context: &Context) -> Self {
+        let texture = context.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Glyph Atlas"),
+            size: wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::R8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Self {
+            fonts: vec![FontArc::try_from_slice(DEFAULT_FONT).unwrap()],
+            glyphs: HashMap::new(),
+            pixels: vec![0; (ATLAS_SIZE * ATLAS_SIZE) as usize],
+            cursor: [ATLAS_PADDING, ATLAS_PADDING],
+            row_height: 0,
+            dirty: true,
+            texture,
+            view,
+        }
+    }
+
+    // Adds a TTF or OTF font from the resource directory
+    #[allow(dead_code)]
+    pub fn load_font(&mut self, relative_path: &PathBuf) -> anyhow::Result<FontId> {
+        let path = resources::res_path(relative_path);
+        let bytes = resources::load_resource(&path)?;
+        let font = FontArc::try_from_vec(bytes)
+            .with_context(|| format!("Failed to parse font {}", path.display()))?;
+        self.fonts.push(font);
+        Ok(FontId(self.fonts.len() - 1))
+    }
+
+    // Uploads glyphs rasterized since the last upload
+    pub fn upload(&mut self, queue: &wgpu::Queue) {
+        if !self.dirty {
+            return;
+        }
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &self.pixels,
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(ATLAS_SIZE),
+                rows_per_image: Some(ATLAS_SIZE),
+            },
+            wgpu::Extent3d {
+                width: ATLAS_SIZE,
+                height: ATLAS_SIZE,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.dirty = false;
+    }
+
+    // Width and height of the text in pixels
+    pub fn measure(&mut self, text: &str, style: &TextStyle) -> [f32; 2] {
+        self.layout_text(text, [0.0, 0.0], style, |_| ())
+    }
+
+    // Lays out the text with its top left corner at `position`, breaking
+    // lines at '\n', and calls `emit` for every visible glyph. Returns the
+    // size of the text.
+    pub fn layout_text(
+        &mut self,
+        text: &str,
+        position: [f32; 2],
+        style: &TextStyle,
+        mut emit: impl FnMut(PositionedGlyph),
+    ) -> [f32; 2] {
+        let mut size = [0.0f32; 2];
+        let Some(font) = self.fonts.get(style.font.0).cloned() else {
+            warn!("Unknown font {:?}", style.font);
+            return size;
+        };
+        let scaled = font.as_scaled(PxScale::from(style.size));
+        let line_height = scaled.height() + scaled.line_gap();
+        let mut caret = [position[0], position[1] + scaled.ascent()];
+        let mut previous = None;
+        let mut lines = 1;
+
+        for c in text.chars() {
+            if c == '\n' {
+                size[0] = size[0].max(caret[0] - position[0]);
+                caret = [position[0], caret[1] + line_height];
+                previous = None;
+                lines += 1;
+                continue;
+            }
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                caret[0] += scaled.kern(previous, id);
+            }
+            previous = Some(id);
+
+            // Pen positions are snapped to whole pixels to keep glyphs crisp
+            let pen = [caret[0].round(), caret[1].round()];
+            if let Some(glyph) = self.glyph(&font, style.font, id, style.size) {
+                if glyph.size[0] > 0 && glyph.size[1] > 0 {
+                    emit(PositionedGlyph {
+                        position: [pen[0] + glyph.offset[0], pen[1] + glyph.offset[1]],
+                        size: [glyph.size[0] as f32, glyph.size[1] as f32],
+                        uv_min: [
+                            glyph.min[0] as f32 / ATLAS_SIZE as f32,
+                            glyph.min[1] as f32 / ATLAS_SIZE as f32,
+                        ],
+                        uv_max: [
+                            (glyph.min[0] + glyph.size[0]) as f32 / ATLAS_SIZE as f32,
+                            (glyph.min[1] + glyph.size[1]) as f32 / ATLAS_SIZE as f32,
+                        ],
+                    });
+                }
+            }
+            caret[0] += scaled.h_advance(id);
+        }
+        size[0] = size[0].max(caret[0] - position[0]);
+        size[1] = lines as f32 * line_height;
+        size
+    }
+
+    fn glyph(
+        &mut self,
+        font: &FontArc,
+        font_id: FontId,
+        id: GlyphId,
+        size: f32,
+    ) -> Option<AtlasGlyph> {
+        let key = (font_id, id, size.round() as u32);
+        if let Some(glyph) = self.glyphs.get(&key) {
+            return Some(*glyph);
+        }
+
+        let Some(outline) = font.outline_glyph(id.with_scale_and_position(size, point(0.0, 0.0)))
+        else {
+            // Whitespace has nothing to draw
+            let glyph = AtlasGlyph {
+                min: [0, 0],
+                size: [0, 0],
+                offset: [0.0, 0.0],
+            };
+            self.glyphs.insert(key, glyph);
+            return Some(glyph);
+        };
+        let bounds = outline.px_bounds();
+        let (width, height) = (bounds.width() as u32, bounds.height() as u32);
+        let min = self.allocate(width, height)?;
+        outline.draw(|x, y, coverage| {
+            let index = (min[1] + y) * ATLAS_SIZE + min[0] + x;
+            self.pixels[index as usize] = (coverage.clamp(0.0, 1.0) * 255.0) as u8;
+        });
+        self.dirty = true;
+
+        let glyph = AtlasGlyph {
+            min,
+            size: [width, height],
+            offset: [bounds.min.x, bounds.min.y],
+        };
+        self.glyphs.insert(key, glyph);
+        Some(glyph)
+    }
+
+    fn allocate(&mut self, width: u32, height: u32) -> Option<[u32; 2]> {
+        if self.cursor[0] + width + ATLAS_PADDING > ATLAS_SIZE {
+            self.cursor = [
+                ATLAS_PADDING,
+                self.cursor[1] + self.row_height + ATLAS_PADDING,
+            ];
+            self.row_height = 0;
+        }
+        if self.cursor[1] + height + ATLAS_PADDING > ATLAS_SIZE
+            || width + 2 * ATLAS_PADDING > ATLAS_SIZE
+        {
+            warn!("Glyph atlas is full, clearing it for the next frame");
+            self.clear();
+            return None;
+        }
+        let min = self.cursor;
+        self.cursor[0] += width + ATLAS_PADDING;
+        self.row_height = self.row_height.max(height);
+        Some(min)
+    }
+
+    // Forgets every glyph; they are rasterized again as they are used
+    pub fn clear(&mut self) {
+        self.glyphs.clear();
+        self.pixels.fill(0);
+        self.cursor = [ATLAS_PADDING, ATLAS_PADDING];
+        self.row_height = 0;
+        self.dirty = true;
+    }
+}
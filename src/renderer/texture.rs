@@ -1,31 +1,91 @@
+use super::arena::{Arena, Handle};
 use super::context::Context;
+use super::mipmap::{mip_level_count, MipmapGenerator};
+use super::shader::ShaderStore;
+use super::texture_data::{self, TextureData};
 use super::util::resources;
 
-use anyhow::Result;
+use anyhow::{bail, Context as _, Result};
 use image::GenericImageView;
 
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct SamplerSettings {
+    pub address_mode_u: wgpu::AddressMode,
+    pub address_mode_v: wgpu::AddressMode,
+    pub address_mode_w: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    // Maximum anisotropy, 1 disables anisotropic filtering
+    pub anisotropy: u16,
+}
+
+impl Default for SamplerSettings {
+    fn default() -> Self {
+        Self {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy: 16,
+        }
+    }
+}
+
+impl SamplerSettings {
+    // wgpu only accepts anisotropy between 1 and 16 and only with linear filtering
+    fn sanitized(mut self) -> Self {
+        let linear = self.mag_filter == wgpu::FilterMode::Linear
+            && self.min_filter == wgpu::FilterMode::Linear
+            && self.mipmap_filter == wgpu::FilterMode::Linear;
+        self.anisotropy = if linear {
+            self.anisotropy.clamp(1, 16)
+        } else {
+            1
+        };
+        self
+    }
+}
 
 #[allow(unused)]
 pub struct Texture {
     pub texture: wgpu::Texture,
     pub view: wgpu::TextureView,
-    pub sampler: wgpu::Sampler,
+    pub sampler: Arc<wgpu::Sampler>,
     pub bind_group: Option<wgpu::BindGroup>,
 }
 
 impl Texture {
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
+    #[allow(dead_code)]
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         bind_group_layout: &wgpu::BindGroupLayout,
         bytes: &[u8],
         label: Option<&str>,
+        sampler: Arc<wgpu::Sampler>,
+        mipmaps: Option<&MipmapGenerator>,
     ) -> Result<Self> {
         let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, bind_group_layout, &img, label)
+        Self::from_image(
+            device,
+            queue,
+            bind_group_layout,
+            &img,
+            label,
+            sampler,
+            mipmaps,
+        )
     }
 
     pub fn from_image(
@@ -34,6 +94,8 @@ impl Texture {
         bind_group_layout: &wgpu::BindGroupLayout,
         img: &image::DynamicImage,
         label: Option<&str>,
+        sampler: Arc<wgpu::Sampler>,
+        mipmaps: Option<&MipmapGenerator>,
     ) -> Result<Self> {
         let rgba = img.to_rgba8();
         let dimensions = img.dimensions();
@@ -43,14 +105,26 @@ impl Texture {
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
+        let (mip_level_count, usage) = match mipmaps {
+            Some(_) => (
+                mip_level_count(dimensions.0, dimensions.1),
+                wgpu::TextureUsages::TEXTURE_BINDING
+                    | wgpu::TextureUsages::COPY_DST
+                    | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            ),
+            None => (
+                1,
+                wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            ),
+        };
         let texture = device.create_texture(&wgpu::TextureDescriptor {
             label,
             size,
-            mip_level_count: 1,
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
             format: wgpu::TextureFormat::Rgba8UnormSrgb,
-            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            usage,
             view_formats: &[],
         });
 
@@ -70,31 +144,16 @@ impl Texture {
             size,
         );
 
-        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Nearest,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            ..Default::default()
-        });
+        if let Some(generator) = mipmaps {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Mipmap Encoder"),
+            });
+            generator.generate(device, &mut encoder, &texture)?;
+            queue.submit(std::iter::once(encoder.finish()));
+        }
 
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout: bind_group_layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&sampler),
-                },
-            ],
-            label: None,
-        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = Self::create_bind_group(device, bind_group_layout, &view, &sampler);
 
         Ok(Self {
             texture,
@@ -104,62 +163,150 @@ impl Texture {
         })
     }
 
-    pub fn create_depth_texture(
+    // Uploads pre-built mips as they are, only a lone base level gets a
+    // generated mip chain
+    pub fn from_texture_data(
         device: &wgpu::Device,
-        config: &wgpu::SurfaceConfiguration,
-        label: &str,
-    ) -> Self {
-        let size = wgpu::Extent3d {
-            width: config.width.max(1),
-            height: config.height.max(1),
-            depth_or_array_layers: 1,
+        queue: &wgpu::Queue,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        data: &TextureData,
+        label: Option<&str>,
+        sampler: Arc<wgpu::Sampler>,
+        mipmaps: Option<&MipmapGenerator>,
+    ) -> Result<Self> {
+        let mipmaps =
+            mipmaps.filter(|generator| data.levels.len() == 1 && generator.supports(data.format));
+
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST;
+        let mip_level_count = match mipmaps {
+            Some(_) => {
+                usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+                mip_level_count(data.width, data.height)
+            }
+            None => data.levels.len() as u32,
         };
+
         let desc = wgpu::TextureDescriptor {
-            label: Some(label),
-            size,
-            mip_level_count: 1,
+            label,
+            size: wgpu::Extent3d {
+                width: data.width,
+                height: data.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
             sample_count: 1,
             dimension: wgpu::TextureDimension::D2,
-            format: Self::DEPTH_FORMAT,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            format: data.format,
+            usage,
             view_formats: &[],
         };
         let texture = device.create_texture(&desc);
 
+        let (block_width, block_height) = data.format.block_dimensions();
+        let block_size = data.format.block_copy_size(None).unwrap();
+        for (level, bytes) in data.levels.iter().enumerate() {
+            // Mips smaller than a block still occupy a whole block
+            let size = desc
+                .mip_level_size(level as u32)
+                .unwrap()
+                .physical_size(data.format);
+            queue.write_texture(
+                wgpu::ImageCopyTexture {
+                    aspect: wgpu::TextureAspect::All,
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: wgpu::Origin3d::ZERO,
+                },
+                bytes,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(size.width / block_width * block_size),
+                    rows_per_image: Some(size.height / block_height),
+                },
+                size,
+            );
+        }
+
+        if let Some(generator) = mipmaps {
+            let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Mipmap Encoder"),
+            });
+            generator.generate(device, &mut encoder, &texture)?;
+            queue.submit(std::iter::once(encoder.finish()));
+        }
+
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
-            address_mode_u: wgpu::AddressMode::ClampToEdge,
-            address_mode_v: wgpu::AddressMode::ClampToEdge,
-            address_mode_w: wgpu::AddressMode::ClampToEdge,
-            mag_filter: wgpu::FilterMode::Linear,
-            min_filter: wgpu::FilterMode::Linear,
-            mipmap_filter: wgpu::FilterMode::Nearest,
-            compare: Some(wgpu::CompareFunction::LessEqual),
-            lod_min_clamp: 0.0,
-            lod_max_clamp: 100.0,
-            ..Default::default()
-        });
+        let bind_group = Self::create_bind_group(device, bind_group_layout, &view, &sampler);
 
-        Self {
+        Ok(Self {
             texture,
             view,
             sampler,
-            bind_group: None,
+            bind_group: Some(bind_group),
+        })
+    }
+
+    pub fn create_bind_group(
+        device: &wgpu::Device,
+        bind_group_layout: &wgpu::BindGroupLayout,
+        view: &wgpu::TextureView,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+            ],
+            label: None,
+        })
+    }
+}
+
+pub type TextureId = Handle<Texture>;
+
+// CPU side of a texture file, decoded off the render thread
+pub enum DecodedTexture {
+    Image(image::DynamicImage),
+    Data(TextureData),
+}
+
+// Reads and decodes a texture under `res`. Container formats the device
+// cannot sample are decompressed here.
+pub fn decode_texture(relative_path: &Path, features: wgpu::Features) -> Result<DecodedTexture> {
+    let bytes = resources::load_resource(&relative_path.to_path_buf())
+        .with_context(|| format!("Failed to read {}", relative_path.display()))?;
+    if texture_data::is_container(relative_path) {
+        let mut data = TextureData::from_container(relative_path, &bytes)?;
+        if !data.can_upload(features) {
+            data = data.decompress()?;
         }
+        Ok(DecodedTexture::Data(data))
+    } else {
+        let image = image::load_from_memory(&bytes)
+            .with_context(|| format!("Failed to decode {}", relative_path.display()))?;
+        Ok(DecodedTexture::Image(image))
     }
 }
 
 pub struct TextureStore {
     pub bind_group_layout: wgpu::BindGroupLayout,
-    pub depth_texture: Texture,
-    pub textures: HashMap<u32, Texture>,
-    next_id: u32,
+    pub textures: Arena<Texture>,
+    // Paths relative to `res` of the textures loaded from files
+    sources: HashMap<TextureId, PathBuf>,
+    // Textures with identical sampler settings share one sampler
+    samplers: HashMap<SamplerSettings, Arc<wgpu::Sampler>>,
+    pub mipmap_generator: MipmapGenerator,
 }
 
 impl TextureStore {
-    pub fn new(context: &Context) -> Self {
-        let depth_texture = Self::create_depth_texture(context);
-
+    pub fn new(context: &Context, shader_store: &ShaderStore) -> Self {
         let bind_group_layout =
             context
                 .device
@@ -185,42 +332,178 @@ impl TextureStore {
                     label: Some("texture_bind_group_layout"),
                 });
 
+        let mipmap_generator = MipmapGenerator::new(&context.device, &shader_store.blit);
+
         Self {
-            depth_texture: depth_texture,
-            bind_group_layout: bind_group_layout,
-            textures: HashMap::new(),
-            next_id: 0,
+            bind_group_layout,
+            textures: Arena::new(),
+            sources: HashMap::new(),
+            samplers: HashMap::new(),
+            mipmap_generator,
         }
     }
 
-    pub fn create_depth_texture(context: &Context) -> Texture {
-        Texture::create_depth_texture(&context.device, &context.config, "depth_texture")
+    pub fn get_sampler(
+        &mut self,
+        device: &wgpu::Device,
+        settings: SamplerSettings,
+    ) -> Arc<wgpu::Sampler> {
+        let settings = settings.sanitized();
+        self.samplers
+            .entry(settings)
+            .or_insert_with(|| {
+                Arc::new(device.create_sampler(&wgpu::SamplerDescriptor {
+                    label: Some("Texture Sampler"),
+                    address_mode_u: settings.address_mode_u,
+                    address_mode_v: settings.address_mode_v,
+                    address_mode_w: settings.address_mode_w,
+                    mag_filter: settings.mag_filter,
+                    min_filter: settings.min_filter,
+                    mipmap_filter: settings.mipmap_filter,
+                    anisotropy_clamp: settings.anisotropy,
+                    ..Default::default()
+                }))
+            })
+            .clone()
     }
 
     pub async fn load_texture_from_file(
         &mut self,
         relative_path: PathBuf,
         context: &Context<'_>,
-    ) -> u32 {
-        let path = resources::res_path(&relative_path);
-        let bytes = resources::load_resource(&path).unwrap();
-        let texture = Texture::from_bytes(
+    ) -> Result<TextureId> {
+        self.load_texture_from_file_with_sampler(relative_path, SamplerSettings::default(), context)
+            .await
+    }
+
+    pub async fn load_texture_from_file_with_sampler(
+        &mut self,
+        relative_path: PathBuf,
+        sampler_settings: SamplerSettings,
+        context: &Context<'_>,
+    ) -> Result<TextureId> {
+        let decoded = decode_texture(&relative_path, context.device.features())?;
+        let sampler = self.get_sampler(&context.device, sampler_settings);
+        let texture = self.create_texture(&decoded, &relative_path, sampler, context)?;
+        Ok(self.add_texture(texture, Some(relative_path)))
+    }
+
+    // Uploads a texture decoded by `decode_texture` without adding it
+    pub fn create_texture(
+        &self,
+        decoded: &DecodedTexture,
+        relative_path: &Path,
+        sampler: Arc<wgpu::Sampler>,
+        context: &Context<'_>,
+    ) -> Result<Texture> {
+        let label = relative_path.file_name().and_then(|name| name.to_str());
+        match decoded {
+            DecodedTexture::Image(image) => Texture::from_image(
+                &context.device,
+                &context.queue,
+                &self.bind_group_layout,
+                image,
+                label,
+                sampler,
+                Some(&self.mipmap_generator),
+            ),
+            DecodedTexture::Data(data) => Texture::from_texture_data(
+                &context.device,
+                &context.queue,
+                &self.bind_group_layout,
+                data,
+                label,
+                sampler,
+                Some(&self.mipmap_generator),
+            ),
+        }
+    }
+
+    // A single grey texel, shown while the real texture is still loading
+    pub fn create_placeholder(&mut self, context: &Context<'_>) -> Texture {
+        let image = image::DynamicImage::ImageRgba8(image::RgbaImage::from_pixel(
+            1,
+            1,
+            image::Rgba([128, 128, 128, 255]),
+        ));
+        let sampler = self.get_sampler(&context.device, SamplerSettings::default());
+        Texture::from_image(
             &context.device,
             &context.queue,
             &self.bind_group_layout,
-            &bytes,
-            path.file_name()
-                .expect("No file name in given path")
-                .to_str(),
+            &image,
+            Some("Placeholder Texture"),
+            sampler,
+            None,
         )
-        .unwrap();
-        let id = self.next_id;
-        self.next_id += 1;
-        self.textures.insert(id, texture);
+        .expect("A 1x1 texture is always valid")
+    }
+
+    pub fn add_texture(&mut self, texture: Texture, source: Option<PathBuf>) -> TextureId {
+        let id = self.textures.insert(texture);
+        if let Some(source) = source {
+            self.sources.insert(id, source);
+        }
         id
     }
 
-    pub fn get_texture(&self, id: u32) -> &Texture {
-        self.textures.get(&id).unwrap()
+    // Swaps the texture behind an id, so everything using the id picks up the
+    // new one. Returns the old texture.
+    pub fn replace_texture(&mut self, id: TextureId, texture: Texture) -> Option<Texture> {
+        let slot = self.textures.get_mut(id)?;
+        Some(std::mem::replace(slot, texture))
+    }
+
+    #[allow(dead_code)]
+    pub fn remove_texture(&mut self, id: TextureId) -> Option<Texture> {
+        self.sources.remove(&id);
+        self.textures.remove(id)
+    }
+
+    pub fn source(&self, id: TextureId) -> Option<&PathBuf> {
+        self.sources.get(&id)
+    }
+
+    // A texture loaded from `path`
+    pub fn find_by_source(&self, path: &Path) -> Option<TextureId> {
+        self.sources
+            .iter()
+            .find(|(_, source)| source.as_path() == path)
+            .map(|(&id, _)| id)
+    }
+
+    // Decodes the texture's file again and swaps it in, keeping the sampler
+    pub fn reload(&mut self, id: TextureId, context: &Context<'_>) -> Result<()> {
+        let Some(path) = self.sources.get(&id) else {
+            bail!("Texture {id} was not loaded from a file");
+        };
+        let decoded = decode_texture(path, context.device.features())?;
+        let sampler = self.get_texture(id).sampler.clone();
+        let texture = self.create_texture(&decoded, path, sampler, context)?;
+        self.replace_texture(id, texture);
+        Ok(())
+    }
+
+    // Stale ids get the first texture still loaded rather than a crash
+    pub fn get_texture(&self, id: TextureId) -> &Texture {
+        self.textures
+            .get(id)
+            .or_else(|| self.textures.values().next())
+            .expect("No textures loaded")
+    }
+
+    #[allow(dead_code)]
+    pub fn set_sampler(&mut self, id: TextureId, settings: SamplerSettings, context: &Context) {
+        let sampler = self.get_sampler(&context.device, settings);
+        let Some(texture) = self.textures.get_mut(id) else {
+            return;
+        };
+        texture.bind_group = Some(Texture::create_bind_group(
+            &context.device,
+            &self.bind_group_layout,
+            &texture.view,
+            &sampler,
+        ));
+        texture.sampler = sampler;
     }
 }
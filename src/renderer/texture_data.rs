@@ -0,0 +1,424 @@
+use anyhow::{anyhow, bail, Result};
+use ktx2::{Format, SupercompressionScheme};
+use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+
+use std::{io::Read, path::Path};
+
+// Pre-built texture data loaded from a KTX2 or DDS container, one entry in
+// `levels` per mip
+pub struct TextureData {
+    pub format: TextureFormat,
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+pub fn is_container(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            extension.eq_ignore_ascii_case("ktx2") || extension.eq_ignore_ascii_case("dds")
+        })
+}
+
+impl TextureData {
+    pub fn from_container(path: &Path, bytes: &[u8]) -> Result<Self> {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("ktx2") => Self::from_ktx2(bytes),
+            Some(extension) if extension.eq_ignore_ascii_case("dds") => Self::from_dds(bytes),
+            _ => bail!("{} is not a KTX2 or DDS file", path.display()),
+        }
+    }
+
+    pub fn from_ktx2(bytes: &[u8]) -> Result<Self> {
+        let reader = ktx2::Reader::new(bytes).map_err(|err| anyhow!("Invalid KTX2: {err:?}"))?;
+        let header = reader.header();
+
+        let supercompression = header.supercompression_scheme;
+        if header.format.is_none() || supercompression == Some(SupercompressionScheme::BasisLZ) {
+            bail!("Basis Universal textures need transcoding, which is not supported");
+        }
+        if header.face_count > 1 || header.layer_count > 1 || header.pixel_depth > 1 {
+            bail!("Only single layer 2D KTX2 textures are supported");
+        }
+
+        let format = header.format.unwrap();
+        let format =
+            ktx2_format(format).ok_or_else(|| anyhow!("Unsupported KTX2 format {format:?}"))?;
+
+        let levels = reader
+            .levels()
+            .map(|level| {
+                let length = level.uncompressed_byte_length as usize;
+                match supercompression {
+                    None => Ok(level.data.to_vec()),
+                    Some(SupercompressionScheme::Zstandard) => {
+                        let mut data = Vec::with_capacity(length);
+                        ruzstd::StreamingDecoder::new(level.data)
+                            .map_err(|err| anyhow!("Invalid zstd data: {err}"))?
+                            .read_to_end(&mut data)?;
+                        Ok(data)
+                    }
+                    Some(SupercompressionScheme::ZLIB) => {
+                        let mut data = Vec::with_capacity(length);
+                        flate2::read::ZlibDecoder::new(level.data).read_to_end(&mut data)?;
+                        Ok(data)
+                    }
+                    Some(scheme) => bail!("Unsupported KTX2 supercompression {scheme:?}"),
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Self::new(format, header.pixel_width, header.pixel_height, levels)
+    }
+
+    pub fn from_dds(bytes: &[u8]) -> Result<Self> {
+        let dds = ddsfile::Dds::read(bytes)?;
+        if dds.get_num_array_layers() > 1 || dds.get_depth() > 1 {
+            bail!("Only single layer 2D DDS textures are supported");
+        }
+
+        let format = if let Some(format) = dds.get_dxgi_format() {
+            dxgi_format(format).ok_or_else(|| anyhow!("Unsupported DDS format {format:?}"))?
+        } else if let Some(format) = dds.get_d3d_format() {
+            d3d_format(format).ok_or_else(|| anyhow!("Unsupported DDS format {format:?}"))?
+        } else {
+            bail!("DDS file has an unknown pixel format");
+        };
+
+        // All mips of the first layer are stored back to back
+        let (width, height) = (dds.get_width(), dds.get_height());
+        let mut data = dds.get_data(0)?;
+        let mut levels = Vec::new();
+        for level in 0..dds.get_num_mipmap_levels().max(1) {
+            let size = level_byte_size(format, width, height, level);
+            if data.len() < size {
+                bail!("DDS file is missing data for mip {level}");
+            }
+            let (level_data, rest) = data.split_at(size);
+            levels.push(level_data.to_vec());
+            data = rest;
+        }
+
+        Self::new(format, width, height, levels)
+    }
+
+    fn new(format: TextureFormat, width: u32, height: u32, levels: Vec<Vec<u8>>) -> Result<Self> {
+        if levels.is_empty() {
+            bail!("Texture has no image data");
+        }
+        for (level, data) in levels.iter().enumerate() {
+            if data.len() < level_byte_size(format, width, height, level as u32) {
+                bail!("Texture data for mip {level} is truncated");
+            }
+        }
+
+        Ok(Self {
+            format,
+            width,
+            height,
+            levels,
+        })
+    }
+
+    pub fn level_size(&self, level: u32) -> (u32, u32) {
+        ((self.width >> level).max(1), (self.height >> level).max(1))
+    }
+
+    // Compressed textures can only be created with a size made of whole blocks
+    pub fn can_upload(&self, features: wgpu::Features) -> bool {
+        let (block_width, block_height) = self.format.block_dimensions();
+        features.contains(self.format.required_features())
+            && self.width.is_multiple_of(block_width)
+            && self.height.is_multiple_of(block_height)
+    }
+
+    // Software fallback for devices without the compressed format. Only the
+    // BC1-BC5 family is decoded, everything else is reported as unsupported.
+    pub fn decompress(self) -> Result<Self> {
+        if !self.format.is_compressed() {
+            return Ok(self);
+        }
+
+        let srgb = self.format.is_srgb();
+        let decode_block: fn(&[u8], &mut [[u8; 4]; 16]) = match self.format {
+            TextureFormat::Bc1RgbaUnorm | TextureFormat::Bc1RgbaUnormSrgb => decode_bc1,
+            TextureFormat::Bc2RgbaUnorm | TextureFormat::Bc2RgbaUnormSrgb => decode_bc2,
+            TextureFormat::Bc3RgbaUnorm | TextureFormat::Bc3RgbaUnormSrgb => decode_bc3,
+            TextureFormat::Bc4RUnorm => decode_bc4,
+            TextureFormat::Bc5RgUnorm => decode_bc5,
+            format => bail!("No software decoder for {format:?} and the device can't sample it"),
+        };
+        let block_size = self.format.block_copy_size(None).unwrap() as usize;
+
+        let levels = self
+            .levels
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                let (width, height) = self.level_size(level as u32);
+                let blocks_wide = width.div_ceil(4) as usize;
+                let mut pixels = vec![0u8; (width * height * 4) as usize];
+                let mut block = [[0u8; 4]; 16];
+
+                for (index, block_data) in data.chunks_exact(block_size).enumerate() {
+                    let (block_x, block_y) = (index % blocks_wide * 4, index / blocks_wide * 4);
+                    if block_y >= height as usize {
+                        break;
+                    }
+                    decode_block(block_data, &mut block);
+                    for (texel, color) in block.iter().enumerate() {
+                        let (x, y) = (block_x + texel % 4, block_y + texel / 4);
+                        if x < width as usize && y < height as usize {
+                            let offset = (y * width as usize + x) * 4;
+                            pixels[offset..offset + 4].copy_from_slice(color);
+                        }
+                    }
+                }
+                pixels
+            })
+            .collect();
+
+        Ok(Self {
+            format: if srgb {
+                TextureFormat::Rgba8UnormSrgb
+            } else {
+                TextureFormat::Rgba8Unorm
+            },
+            width: self.width,
+            height: self.height,
+            levels,
+        })
+    }
+}
+
+fn level_byte_size(format: TextureFormat, width: u32, height: u32, level: u32) -> usize {
+    let (block_width, block_height) = format.block_dimensions();
+    let block_size = format.block_copy_size(None).unwrap_or(0);
+    let width = (width >> level).max(1).div_ceil(block_width);
+    let height = (height >> level).max(1).div_ceil(block_height);
+    (width * height * block_size) as usize
+}
+
+fn astc(block: AstcBlock, srgb: bool) -> TextureFormat {
+    let channel = if srgb {
+        AstcChannel::UnormSrgb
+    } else {
+        AstcChannel::Unorm
+    };
+    TextureFormat::Astc { block, channel }
+}
+
+fn ktx2_format(format: Format) -> Option<TextureFormat> {
+    Some(match format {
+        Format::R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+        Format::R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+        Format::B8G8R8A8_UNORM => TextureFormat::Bgra8Unorm,
+        Format::B8G8R8A8_SRGB => TextureFormat::Bgra8UnormSrgb,
+        Format::R16G16B16A16_SFLOAT => TextureFormat::Rgba16Float,
+        Format::BC1_RGB_UNORM_BLOCK | Format::BC1_RGBA_UNORM_BLOCK => TextureFormat::Bc1RgbaUnorm,
+        Format::BC1_RGB_SRGB_BLOCK | Format::BC1_RGBA_SRGB_BLOCK => TextureFormat::Bc1RgbaUnormSrgb,
+        Format::BC2_UNORM_BLOCK => TextureFormat::Bc2RgbaUnorm,
+        Format::BC2_SRGB_BLOCK => TextureFormat::Bc2RgbaUnormSrgb,
+        Format::BC3_UNORM_BLOCK => TextureFormat::Bc3RgbaUnorm,
+        Format::BC3_SRGB_BLOCK => TextureFormat::Bc3RgbaUnormSrgb,
+        Format::BC4_UNORM_BLOCK => TextureFormat::Bc4RUnorm,
+        Format::BC4_SNORM_BLOCK => TextureFormat::Bc4RSnorm,
+        Format::BC5_UNORM_BLOCK => TextureFormat::Bc5RgUnorm,
+        Format::BC5_SNORM_BLOCK => TextureFormat::Bc5RgSnorm,
+        Format::BC6H_UFLOAT_BLOCK => TextureFormat::Bc6hRgbUfloat,
+        Format::BC6H_SFLOAT_BLOCK => TextureFormat::Bc6hRgbFloat,
+        Format::BC7_UNORM_BLOCK => TextureFormat::Bc7RgbaUnorm,
+        Format::BC7_SRGB_BLOCK => TextureFormat::Bc7RgbaUnormSrgb,
+        Format::ETC2_R8G8B8_UNORM_BLOCK => TextureFormat::Etc2Rgb8Unorm,
+        Format::ETC2_R8G8B8_SRGB_BLOCK => TextureFormat::Etc2Rgb8UnormSrgb,
+        Format::ETC2_R8G8B8A1_UNORM_BLOCK => TextureFormat::Etc2Rgb8A1Unorm,
+        Format::ETC2_R8G8B8A1_SRGB_BLOCK => TextureFormat::Etc2Rgb8A1UnormSrgb,
+        Format::ETC2_R8G8B8A8_UNORM_BLOCK => TextureFormat::Etc2Rgba8Unorm,
+        Format::ETC2_R8G8B8A8_SRGB_BLOCK => TextureFormat::Etc2Rgba8UnormSrgb,
+        Format::EAC_R11_UNORM_BLOCK => TextureFormat::EacR11Unorm,
+        Format::EAC_R11_SNORM_BLOCK => TextureFormat::EacR11Snorm,
+        Format::EAC_R11G11_UNORM_BLOCK => TextureFormat::EacRg11Unorm,
+        Format::EAC_R11G11_SNORM_BLOCK => TextureFormat::EacRg11Snorm,
+        Format::ASTC_4x4_UNORM_BLOCK => astc(AstcBlock::B4x4, false),
+        Format::ASTC_4x4_SRGB_BLOCK => astc(AstcBlock::B4x4, true),
+        Format::ASTC_5x4_UNORM_BLOCK => astc(AstcBlock::B5x4, false),
+        Format::ASTC_5x4_SRGB_BLOCK => astc(AstcBlock::B5x4, true),
+        Format::ASTC_5x5_UNORM_BLOCK => astc(AstcBlock::B5x5, false),
+        Format::ASTC_5x5_SRGB_BLOCK => astc(AstcBlock::B5x5, true),
+        Format::ASTC_6x5_UNORM_BLOCK => astc(AstcBlock::B6x5, false),
+        Format::ASTC_6x5_SRGB_BLOCK => astc(AstcBlock::B6x5, true),
+        Format::ASTC_6x6_UNORM_BLOCK => astc(AstcBlock::B6x6, false),
+        Format::ASTC_6x6_SRGB_BLOCK => astc(AstcBlock::B6x6, true),
+        Format::ASTC_8x5_UNORM_BLOCK => astc(AstcBlock::B8x5, false),
+        Format::ASTC_8x5_SRGB_BLOCK => astc(AstcBlock::B8x5, true),
+        Format::ASTC_8x6_UNORM_BLOCK => astc(AstcBlock::B8x6, false),
+        Format::ASTC_8x6_SRGB_BLOCK => astc(AstcBlock::B8x6, true),
+        Format::ASTC_8x8_UNORM_BLOCK => astc(AstcBlock::B8x8, false),
+        Format::ASTC_8x8_SRGB_BLOCK => astc(AstcBlock::B8x8, true),
+        Format::ASTC_10x5_UNORM_BLOCK => astc(AstcBlock::B10x5, false),
+        Format::ASTC_10x5_SRGB_BLOCK => astc(AstcBlock::B10x5, true),
+        Format::ASTC_10x6_UNORM_BLOCK => astc(AstcBlock::B10x6, false),
+        Format::ASTC_10x6_SRGB_BLOCK => astc(AstcBlock::B10x6, true),
+        Format::ASTC_10x8_UNORM_BLOCK => astc(AstcBlock::B10x8, false),
+        Format::ASTC_10x8_SRGB_BLOCK => astc(AstcBlock::B10x8, true),
+        Format::ASTC_10x10_UNORM_BLOCK => astc(AstcBlock::B10x10, false),
+        Format::ASTC_10x10_SRGB_BLOCK => astc(AstcBlock::B10x10, true),
+        Format::ASTC_12x10_UNORM_BLOCK => astc(AstcBlock::B12x10, false),
+        Format::ASTC_12x10_SRGB_BLOCK => astc(AstcBlock::B12x10, true),
+        Format::ASTC_12x12_UNORM_BLOCK => astc(AstcBlock::B12x12, false),
+        Format::ASTC_12x12_SRGB_BLOCK => astc(AstcBlock::B12x12, true),
+        _ => return None,
+    })
+}
+
+fn dxgi_format(format: ddsfile::DxgiFormat) -> Option<TextureFormat> {
+    use ddsfile::DxgiFormat;
+
+    Some(match format {
+        DxgiFormat::R8G8B8A8_UNorm => TextureFormat::Rgba8Unorm,
+        DxgiFormat::R8G8B8A8_UNorm_sRGB => TextureFormat::Rgba8UnormSrgb,
+        DxgiFormat::B8G8R8A8_UNorm => TextureFormat::Bgra8Unorm,
+        DxgiFormat::B8G8R8A8_UNorm_sRGB => TextureFormat::Bgra8UnormSrgb,
+        DxgiFormat::R16G16B16A16_Float => TextureFormat::Rgba16Float,
+        DxgiFormat::BC1_UNorm => TextureFormat::Bc1RgbaUnorm,
+        DxgiFormat::BC1_UNorm_sRGB => TextureFormat::Bc1RgbaUnormSrgb,
+        DxgiFormat::BC2_UNorm => TextureFormat::Bc2RgbaUnorm,
+        DxgiFormat::BC2_UNorm_sRGB => TextureFormat::Bc2RgbaUnormSrgb,
+        DxgiFormat::BC3_UNorm => TextureFormat::Bc3RgbaUnorm,
+        DxgiFormat::BC3_UNorm_sRGB => TextureFormat::Bc3RgbaUnormSrgb,
+        DxgiFormat::BC4_UNorm => TextureFormat::Bc4RUnorm,
+        DxgiFormat::BC4_SNorm => TextureFormat::Bc4RSnorm,
+        DxgiFormat::BC5_UNorm => TextureFormat::Bc5RgUnorm,
+        DxgiFormat::BC5_SNorm => TextureFormat::Bc5RgSnorm,
+        DxgiFormat::BC6H_UF16 => TextureFormat::Bc6hRgbUfloat,
+        DxgiFormat::BC6H_SF16 => TextureFormat::Bc6hRgbFloat,
+        DxgiFormat::BC7_UNorm => TextureFormat::Bc7RgbaUnorm,
+        DxgiFormat::BC7_UNorm_sRGB => TextureFormat::Bc7RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+// Legacy DDS headers carry no color space, they are assumed to hold sRGB colors
+// like the images loaded through `image`
+fn d3d_format(format: ddsfile::D3DFormat) -> Option<TextureFormat> {
+    use ddsfile::D3DFormat;
+
+    Some(match format {
+        D3DFormat::A8B8G8R8 => TextureFormat::Rgba8UnormSrgb,
+        D3DFormat::A8R8G8B8 => TextureFormat::Bgra8UnormSrgb,
+        D3DFormat::DXT1 => TextureFormat::Bc1RgbaUnormSrgb,
+        D3DFormat::DXT2 | D3DFormat::DXT3 => TextureFormat::Bc2RgbaUnormSrgb,
+        D3DFormat::DXT4 | D3DFormat::DXT5 => TextureFormat::Bc3RgbaUnormSrgb,
+        _ => return None,
+    })
+}
+
+fn rgb565(color: u16) -> [u8; 3] {
+    let r = (color >> 11) & 0x1f;
+    let g = (color >> 5) & 0x3f;
+    let b = color & 0x1f;
+    [
+        ((r << 3) | (r >> 2)) as u8,
+        ((g << 2) | (g >> 4)) as u8,
+        ((b << 3) | (b >> 2)) as u8,
+    ]
+}
+
+fn mix(a: [u8; 3], b: [u8; 3], weight_a: u32, weight_b: u32) -> [u8; 4] {
+    let total = weight_a + weight_b;
+    let channel = |i: usize| ((a[i] as u32 * weight_a + b[i] as u32 * weight_b) / total) as u8;
+    [channel(0), channel(1), channel(2), 255]
+}
+
+// BC1 color block; BC2 and BC3 always use the four color mode
+fn decode_color_block(data: &[u8], four_color_only: bool, block: &mut [[u8; 4]; 16]) {
+    let c0 = u16::from_le_bytes([data[0], data[1]]);
+    let c1 = u16::from_le_bytes([data[2], data[3]]);
+    let indices = u32::from_le_bytes([data[4], data[5], data[6], data[7]]);
+    let (a, b) = (rgb565(c0), rgb565(c1));
+
+    let palette = if c0 > c1 || four_color_only {
+        [
+            [a[0], a[1], a[2], 255],
+            [b[0], b[1], b[2], 255],
+            mix(a, b, 2, 1),
+            mix(a, b, 1, 2),
+        ]
+    } else {
+        [
+            [a[0], a[1], a[2], 255],
+            [b[0], b[1], b[2], 255],
+            mix(a, b, 1, 1),
+            [0, 0, 0, 0],
+        ]
+    };
+
+    for (texel, color) in block.iter_mut().enumerate() {
+        *color = palette[(indices >> (texel * 2)) as usize & 0b11];
+    }
+}
+
+// Interpolated 8 bit channel used by BC3 alpha, BC4 and BC5
+fn decode_channel_block(data: &[u8]) -> [u8; 16] {
+    let (a0, a1) = (data[0] as u32, data[1] as u32);
+    let mut palette = [0u32; 8];
+    palette[0] = a0;
+    palette[1] = a1;
+    if a0 > a1 {
+        for i in 1..7 {
+            palette[i + 1] = (a0 * (7 - i as u32) + a1 * i as u32) / 7;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (a0 * (5 - i as u32) + a1 * i as u32) / 5;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+
+    let mut bits = 0u64;
+    for (i, byte) in data[2..8].iter().enumerate() {
+        bits |= (*byte as u64) << (i * 8);
+    }
+
+    let mut values = [0u8; 16];
+    for (texel, value) in values.iter_mut().enumerate() {
+        *value = palette[(bits >> (texel * 3)) as usize & 0b111] as u8;
+    }
+    values
+}
+
+fn decode_bc1(data: &[u8], block: &mut [[u8; 4]; 16]) {
+    decode_color_block(data, false, block);
+}
+
+fn decode_bc2(data: &[u8], block: &mut [[u8; 4]; 16]) {
+    decode_color_block(&data[8..], true, block);
+    for (texel, color) in block.iter_mut().enumerate() {
+        let alpha = (data[texel / 2] >> ((texel % 2) * 4)) & 0x0f;
+        color[3] = alpha * 17;
+    }
+}
+
+fn decode_bc3(data: &[u8], block: &mut [[u8; 4]; 16]) {
+    decode_color_block(&data[8..], true, block);
+    for (color, alpha) in block.iter_mut().zip(decode_channel_block(data)) {
+        color[3] = alpha;
+    }
+}
+
+// Single and dual channel formats sample as (r, 0, 0, 1) and (r, g, 0, 1)
+fn decode_bc4(data: &[u8], block: &mut [[u8; 4]; 16]) {
+    for (color, red) in block.iter_mut().zip(decode_channel_block(data)) {
+        *color = [red, 0, 0, 255];
+    }
+}
+
+fn decode_bc5(data: &[u8], block: &mut [[u8; 4]; 16]) {
+    let red = decode_channel_block(data);
+    let green = decode_channel_block(&data[8..]);
+    for (texel, color) in block.iter_mut().enumerate() {
+        *color = [red[texel], green[texel], 0, 255];
+    }
+}
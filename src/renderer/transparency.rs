@@ -0,0 +1,106 @@
+use super::{
+    context::Context, hdr::HDR_FORMAT, pipeline::init_multisampled_fullscreen_pipeline,
+    shader::ShaderStore,
+};
+
+use serde::{Deserialize, Serialize};
+
+pub const OIT_ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+pub const OIT_REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TransparencyMode {
+    // Weighted blended order independent transparency (McGuire and Bavoil),
+    // approximate where many layers overlap but needs no sorting
+    #[default]
+    WeightedBlended,
+    // Objects alpha blended back to front by their bounding sphere centers,
+    // exact between objects but not within one
+    Sorted,
+}
+
+// Resolves the weighted blended accumulation targets over the opaque scene.
+// With MSAA it blends into the multisampled scene, so the passes drawing on
+// top of it afterwards resolve a scene that includes the transparent layer.
+pub struct OitCompositor {
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl OitCompositor {
+    pub fn new(context: &Context, shader_store: &ShaderStore, sample_count: u32) -> Self {
+        let device = &context.device;
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0), texture_entry(1)],
+            label: Some("oit_composite_bind_group_layout"),
+        });
+        let pipeline = init_multisampled_fullscreen_pipeline(
+            device,
+            "OIT Composite Pipeline",
+            &[&bind_group_layout],
+            &shader_store.oit_composite,
+            "fs_main",
+            HDR_FORMAT,
+            Some(wgpu::BlendState::ALPHA_BLENDING),
+            sample_count,
+        );
+
+        Self {
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    pub fn composite(
+        &self,
+        device: &wgpu::Device,
+        encoder: &mut wgpu::CommandEncoder,
+        accum: &wgpu::TextureView,
+        revealage: &wgpu::TextureView,
+        target: &wgpu::TextureView,
+        resolve_target: Option<&wgpu::TextureView>,
+    ) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &self.bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(accum),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(revealage),
+                },
+            ],
+            label: Some("oit_composite_bind_group"),
+        });
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("OIT Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: target,
+                resolve_target,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            timestamp_writes: None,
+            occlusion_query_set: None,
+        });
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
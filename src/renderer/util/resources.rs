@@ -16,3 +16,23 @@ pub fn load_resource(relative_file_path: &PathBuf) -> Result<Vec<u8>, Error> {
     info!("Loading resource from path: {path_str}");
     fs::read(&path)
 }
+
+// Paths relative to `res` of the files in `directory` with one of the given
+// lowercase extensions, sorted by name
+pub fn list_resources(directory: &str, extensions: &[&str]) -> Vec<PathBuf> {
+    let directory = PathBuf::from(directory);
+    let Ok(entries) = fs::read_dir(res_path(&directory)) else {
+        return Vec::new();
+    };
+    let mut paths = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| directory.join(entry.file_name()))
+        .filter(|path| {
+            path.extension().is_some_and(|extension| {
+                extensions.contains(&&*extension.to_string_lossy().to_lowercase())
+            })
+        })
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths
+}